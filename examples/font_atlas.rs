@@ -0,0 +1,201 @@
+//! Offline font atlas generator.
+//!
+//! Rasterizes the printable ASCII range of a TrueType font into a single
+//! channel signed distance field page and writes an AngelCode text
+//! descriptor next to it, the format text::FontAtlas::from_bmfont reads.
+//!
+//!     cargo run --release --example font_atlas -- <font.ttf> <output stem> [size] [spread]
+//!
+//! The bundled src/font/dejavu_sans.* was made with
+//!
+//!     cargo run --release --example font_atlas -- /usr/share/fonts/truetype/dejavu/DejaVuSans.ttf src/font/dejavu_sans 32 4
+use ab_glyph::{Font, FontRef, PxScale, ScaleFont};
+use std::fmt::Write as _;
+use std::path::Path;
+
+const FIRST: u8 = 32;
+const LAST: u8 = 126;
+const PAGE_WIDTH: usize = 512;
+
+struct Cell {
+    c: char,
+    width: usize,
+    height: usize,
+    //  Signed distance in 0..255, 128 on the outline
+    pixels: Vec<u8>,
+    x_offset: i32,
+    y_offset: i32,
+    advance: f32,
+    x: usize,
+    y: usize,
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: font_atlas <font.ttf> <output stem> [size] [spread]");
+        std::process::exit(1);
+    }
+    let data = std::fs::read(&args[1]).expect("can't read the font");
+    let font = FontRef::try_from_slice(&data).expect("not a TrueType font");
+    let size: f32 = args.get(3).map_or(32., |s| s.parse().expect("size is a number"));
+    let spread: usize = args.get(4).map_or(4, |s| s.parse().expect("spread is a number"));
+    let scaled = font.as_scaled(PxScale::from(size));
+    let base = scaled.ascent().round() as i32;
+    let line_height = (scaled.ascent() - scaled.descent() + scaled.line_gap()).round() as i32;
+
+    let mut cells: Vec<Cell> = (FIRST..=LAST).map(|b| rasterize(&font, size, spread, base, b as char)).collect();
+
+    // Shelf packing, tallest first
+    let mut order: Vec<usize> = (0..cells.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(cells[i].height));
+    let (mut x, mut y, mut shelf) = (0, 0, 0);
+    for &i in order.iter() {
+        let cell = &mut cells[i];
+        if x + cell.width > PAGE_WIDTH {
+            x = 0;
+            y += shelf;
+            shelf = 0;
+        }
+        cell.x = x;
+        cell.y = y;
+        x += cell.width;
+        shelf = shelf.max(cell.height);
+    }
+    let page_height = (y + shelf).next_power_of_two();
+
+    let mut page = vec![0u8; PAGE_WIDTH * page_height];
+    for cell in cells.iter() {
+        for row in 0..cell.height {
+            let start = (cell.y + row) * PAGE_WIDTH + cell.x;
+            page[start..start + cell.width].copy_from_slice(&cell.pixels[row * cell.width..(row + 1) * cell.width]);
+        }
+    }
+
+    let stem = Path::new(&args[2]);
+    let page_file = stem.with_extension("sdf");
+    let mut fnt = String::new();
+    let name = Path::new(&args[1]).file_stem().unwrap().to_string_lossy();
+    let _ = writeln!(
+        fnt,
+        "info face=\"{}\" size={} bold=0 italic=0 charset=\"\" unicode=1 stretchH=100 smooth=1 aa=1 padding={p},{p},{p},{p} spacing=0,0",
+        name,
+        size,
+        p = spread
+    );
+    let _ = writeln!(
+        fnt,
+        "common lineHeight={} base={} scaleW={} scaleH={} pages=1 packed=0",
+        line_height, base, PAGE_WIDTH, page_height
+    );
+    let _ = writeln!(fnt, "page id=0 file=\"{}\"", page_file.file_name().unwrap().to_string_lossy());
+    let _ = writeln!(fnt, "distanceField fieldType=sdf distanceRange={}", spread * 2);
+    let _ = writeln!(fnt, "chars count={}", cells.len());
+    for cell in cells.iter() {
+        let _ = writeln!(
+            fnt,
+            "char id={} x={} y={} width={} height={} xoffset={} yoffset={} xadvance={} page=0 chnl=15",
+            cell.c as u32,
+            cell.x,
+            cell.y,
+            cell.width,
+            cell.height,
+            cell.x_offset,
+            cell.y_offset,
+            cell.advance.round()
+        );
+    }
+    let mut kernings = Vec::new();
+    for a in cells.iter() {
+        for b in cells.iter() {
+            let amount = scaled.kern(font.glyph_id(a.c), font.glyph_id(b.c)).round();
+            if amount != 0. {
+                kernings.push((a.c, b.c, amount));
+            }
+        }
+    }
+    let _ = writeln!(fnt, "kernings count={}", kernings.len());
+    for (a, b, amount) in kernings.iter() {
+        let _ = writeln!(fnt, "kerning first={} second={} amount={}", *a as u32, *b as u32, amount);
+    }
+
+    std::fs::write(stem.with_extension("fnt"), fnt).expect("can't write the descriptor");
+    std::fs::write(&page_file, &page).expect("can't write the page");
+    println!(
+        "{} glyphs, {} kerning pairs, {}x{} page",
+        cells.len(),
+        kernings.len(),
+        PAGE_WIDTH,
+        page_height
+    );
+}
+
+//--Coverage of one glyph turned into a padded distance field--
+fn rasterize(font: &FontRef, size: f32, spread: usize, base: i32, c: char) -> Cell {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let id = font.glyph_id(c);
+    let advance = scaled.h_advance(id);
+    let glyph = id.with_scale_and_position(PxScale::from(size), ab_glyph::point(0., base as f32));
+    let outlined = match font.outline_glyph(glyph) {
+        Some(outlined) => outlined,
+        None => {
+            return Cell {
+                c,
+                width: 0,
+                height: 0,
+                pixels: Vec::new(),
+                x_offset: 0,
+                y_offset: 0,
+                advance,
+                x: 0,
+                y: 0,
+            }
+        }
+    };
+    let bounds = outlined.px_bounds();
+    let (w, h) = (bounds.width() as usize, bounds.height() as usize);
+    let (width, height) = (w + spread * 2, h + spread * 2);
+    let mut coverage = vec![0f32; width * height];
+    outlined.draw(|x, y, c| coverage[(y as usize + spread) * width + x as usize + spread] = c);
+
+    // Brute force within the spread, glyphs are small
+    let inside: Vec<bool> = coverage.iter().map(|&c| c >= 0.5).collect();
+    let reach = spread as i64 + 1;
+    let mut pixels = vec![0u8; width * height];
+    for y in 0..height as i64 {
+        for x in 0..width as i64 {
+            let here = inside[(y as usize) * width + x as usize];
+            let mut nearest = (reach * reach) as f32;
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    let (sx, sy) = (x + dx, y + dy);
+                    let other = sx >= 0
+                        && sy >= 0
+                        && sx < width as i64
+                        && sy < height as i64
+                        && inside[sy as usize * width + sx as usize];
+                    if other != here {
+                        nearest = nearest.min((dx * dx + dy * dy) as f32);
+                    }
+                }
+            }
+            // The outline runs half a pixel from the nearest pixel of the other side
+            let distance = nearest.sqrt() - 0.5;
+            let signed = if here { distance } else { -distance };
+            let value = 0.5 + signed / (spread as f32 * 2.);
+            pixels[(y as usize) * width + x as usize] = (value.clamp(0., 1.) * 255.).round() as u8;
+        }
+    }
+
+    Cell {
+        c,
+        width,
+        height,
+        pixels,
+        x_offset: bounds.min.x as i32 - spread as i32,
+        y_offset: bounds.min.y as i32 - spread as i32,
+        advance,
+        x: 0,
+        y: 0,
+    }
+}
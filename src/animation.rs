@@ -0,0 +1,275 @@
+use crate::error::RenderError;
+use crate::quat;
+use crate::skin::Transform;
+
+//--How values between two keyframes are found--
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interpolation {
+    //  Straight line, spherical for rotations
+    Linear,
+    //  Hold each keyframe until the next one
+    Step,
+    //  Hermite spline, every keyframe has an in tangent, a value and an out tangent
+    CubicSpline,
+}
+
+//--Part of a joint's transform a channel drives--
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Property {
+    Translation,
+    //  Quaternion, x y z w
+    Rotation,
+    Scale,
+}
+
+impl Property {
+    pub fn components(self) -> usize {
+        match self {
+            Property::Rotation => 4,
+            _ => 3,
+        }
+    }
+}
+
+//--Keyframes of one property of one joint--
+//  <note>
+//      values holds components() floats per keyframe, three times as many
+//      with CubicSpline: in tangent, value and out tangent, like glTF.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Channel {
+    pub joint: usize,
+    pub property: Property,
+    pub interpolation: Interpolation,
+    //  Seconds, increasing
+    pub times: Vec<f32>,
+    pub values: Vec<f32>,
+}
+
+impl Channel {
+    //--Check the keyframes fit together--
+    pub fn new(joint: usize, property: Property, interpolation: Interpolation, times: Vec<f32>, values: Vec<f32>) -> Result<Self, RenderError> {
+        let per_key = property.components() * if interpolation == Interpolation::CubicSpline { 3 } else { 1 };
+        if times.is_empty() || values.len() != times.len() * per_key {
+            return Err(RenderError::Resource(format!(
+                "{} keyframe values don't match {} keyframe times",
+                values.len(),
+                times.len()
+            )));
+        }
+        if times.windows(2).any(|w| w[1] < w[0]) {
+            return Err(RenderError::Resource("keyframe times must not decrease".into()));
+        }
+        Ok(Self {
+            joint,
+            property,
+            interpolation,
+            times,
+            values,
+        })
+    }
+
+    //--Value at a time, holding the first and last keyframes outside their range--
+    //  <return> [f32; 4]  components() floats, the rest 0; rotations are normalized
+    pub fn sample(&self, time: f32) -> [f32; 4] {
+        let n = self.property.components();
+        let cubic = self.interpolation == Interpolation::CubicSpline;
+        let value = |key: usize, part: usize| -> [f32; 4] {
+            let start = if cubic { (key * 3 + part) * n } else { key * n };
+            let mut out = [0.; 4];
+            out[..n].copy_from_slice(&self.values[start..start + n]);
+            out
+        };
+        // Middle of the in tangent, value, out tangent triple
+        let at = |key: usize| value(key, if cubic { 1 } else { 0 });
+
+        let last = self.times.len() - 1;
+        let next = self.times.partition_point(|&t| t <= time);
+        if next == 0 {
+            return at(0);
+        }
+        if next > last {
+            return at(last);
+        }
+        let key = next - 1;
+        let dt = self.times[next] - self.times[key];
+        let t = if dt > 0. { (time - self.times[key]) / dt } else { 0. };
+
+        let mut out = match self.interpolation {
+            Interpolation::Step => at(key),
+            Interpolation::Linear if self.property == Property::Rotation => return quat::slerp(&at(key), &at(next), t),
+            Interpolation::Linear => {
+                let (a, b) = (at(key), at(next));
+                let mut out = [0.; 4];
+                for i in 0..n {
+                    out[i] = a[i] + (b[i] - a[i]) * t;
+                }
+                out
+            }
+            Interpolation::CubicSpline => {
+                let (p0, m0, p1, m1) = (at(key), value(key, 2), at(next), value(next, 0));
+                let (t2, t3) = (t * t, t * t * t);
+                let h00 = 2. * t3 - 3. * t2 + 1.;
+                let h10 = t3 - 2. * t2 + t;
+                let h01 = -2. * t3 + 3. * t2;
+                let h11 = t3 - t2;
+                let mut out = [0.; 4];
+                for i in 0..n {
+                    // Tangents are per second, scaled to the keyframe interval
+                    out[i] = h00 * p0[i] + h10 * dt * m0[i] + h01 * p1[i] + h11 * dt * m1[i];
+                }
+                out
+            }
+        };
+        if self.property == Property::Rotation {
+            out = quat::normalize(&out);
+        }
+        out
+    }
+}
+
+//--Named set of channels played together--
+#[derive(Clone, Debug, PartialEq)]
+pub struct Clip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    //  Last keyframe time of any channel
+    pub duration: f32,
+}
+
+impl Clip {
+    pub fn new(name: &str, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|c| c.times.last().copied())
+            .fold(0., f32::max);
+        Self {
+            name: name.to_string(),
+            channels,
+            duration,
+        }
+    }
+
+    //--Write the clip's values at a time into a pose--
+    //  <argument>
+    //      time f32             : seconds, wrap it with time % duration to loop
+    //      pose &mut [Transform] : one transform per joint, joints without channels keep theirs
+    pub fn sample(&self, time: f32, pose: &mut [Transform]) {
+        for channel in self.channels.iter() {
+            let transform = match pose.get_mut(channel.joint) {
+                Some(transform) => transform,
+                None => continue,
+            };
+            let v = channel.sample(time);
+            match channel.property {
+                Property::Translation => transform.translation = [v[0], v[1], v[2]],
+                Property::Rotation => transform.rotation = v,
+                Property::Scale => transform.scale = [v[0], v[1], v[2]],
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn translation(interpolation: Interpolation, times: Vec<f32>, values: Vec<f32>) -> Channel {
+        Channel::new(0, Property::Translation, interpolation, times, values).unwrap()
+    }
+
+    fn length(q: &[f32; 4]) -> f32 {
+        quat::dot(q, q).sqrt()
+    }
+
+    #[test]
+    fn step_holds_each_key() {
+        let channel = translation(Interpolation::Step, vec![0., 1., 2.], vec![0., 0., 0., 1., 1., 1., 2., 2., 2.]);
+        assert_eq!(channel.sample(0.), [0., 0., 0., 0.]);
+        assert_eq!(channel.sample(0.999), [0., 0., 0., 0.]);
+        assert_eq!(channel.sample(1.), [1., 1., 1., 0.]);
+        assert_eq!(channel.sample(1.5), [1., 1., 1., 0.]);
+        assert_eq!(channel.sample(2.), [2., 2., 2., 0.]);
+    }
+
+    #[test]
+    fn linear_blends_and_clamps_outside_the_keys() {
+        let channel = translation(Interpolation::Linear, vec![1., 3.], vec![0., 2., 0., 4., 2., -2.]);
+        assert_eq!(channel.sample(2.), [2., 2., -1., 0.]);
+        assert_eq!(channel.sample(-5.), [0., 2., 0., 0.]);
+        assert_eq!(channel.sample(1.), [0., 2., 0., 0.]);
+        assert_eq!(channel.sample(3.), [4., 2., -2., 0.]);
+        assert_eq!(channel.sample(10.), [4., 2., -2., 0.]);
+    }
+
+    #[test]
+    fn cubic_spline_scales_tangents_by_the_key_interval() {
+        // In tangent, value, out tangent per key: flat values, a unit out tangent on the first key
+        let values = vec![0., 0., 0., 0., 0., 0., 1., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0., 0.];
+        for dt in [1., 2., 4.] {
+            let channel = translation(Interpolation::CubicSpline, vec![0., dt], values.clone());
+            // h10(0.5) = 0.125, times the tangent scaled to the interval
+            let middle = channel.sample(dt / 2.);
+            assert!((middle[0] - 0.125 * dt).abs() < 1e-6, "{:?} over {}", middle, dt);
+            assert_eq!(channel.sample(0.), [0., 0., 0., 0.]);
+            assert_eq!(channel.sample(dt), [0., 0., 0., 0.]);
+        }
+
+        // Values are the middle of each triple
+        let values = vec![9., 9., 9., 1., 2., 3., 9., 9., 9., 9., 9., 9., 4., 5., 6., 9., 9., 9.];
+        let channel = translation(Interpolation::CubicSpline, vec![0., 1.], values);
+        assert_eq!(channel.sample(-1.), [1., 2., 3., 0.]);
+        assert_eq!(channel.sample(2.), [4., 5., 6., 0.]);
+    }
+
+    #[test]
+    fn sampled_rotations_stay_normalized() {
+        let a = quat::identity();
+        let b = quat::from_axis_angle(&[0., 1., 0.], std::f32::consts::FRAC_PI_2);
+        let linear = Channel::new(0, Property::Rotation, Interpolation::Linear, vec![0., 1.], [a, b].concat()).unwrap();
+        let halfway = quat::from_axis_angle(&[0., 1., 0.], std::f32::consts::FRAC_PI_4);
+        assert!(quat::dot(&linear.sample(0.5), &halfway) > 0.99999);
+
+        // Tangents that would pull an unnormalized spline well off the unit sphere
+        let tangent = [3., -2., 1., 5.];
+        let cubic = Channel::new(
+            0,
+            Property::Rotation,
+            Interpolation::CubicSpline,
+            vec![0., 1.],
+            [tangent, a, tangent, tangent, b, tangent].concat(),
+        )
+        .unwrap();
+        for i in 0..=20 {
+            let t = i as f32 / 20.;
+            assert!((length(&linear.sample(t)) - 1.).abs() < 1e-5);
+            assert!((length(&cubic.sample(t)) - 1.).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_keyframes() {
+        assert!(Channel::new(0, Property::Translation, Interpolation::Linear, vec![0., 1.], vec![0.; 3]).is_err());
+        assert!(Channel::new(0, Property::Rotation, Interpolation::CubicSpline, vec![0.], vec![0.; 4]).is_err());
+        assert!(Channel::new(0, Property::Scale, Interpolation::Linear, vec![], vec![]).is_err());
+        assert!(Channel::new(0, Property::Scale, Interpolation::Linear, vec![1., 0.], vec![0.; 6]).is_err());
+    }
+
+    #[test]
+    fn clips_drive_their_joints() {
+        let clip = Clip::new(
+            "walk",
+            vec![
+                translation(Interpolation::Linear, vec![0., 2.], vec![0., 0., 0., 2., 0., 0.]),
+                Channel::new(1, Property::Scale, Interpolation::Step, vec![0., 0.5], vec![1., 1., 1., 2., 2., 2.]).unwrap(),
+                // Joints the pose doesn't have are skipped
+                Channel::new(5, Property::Scale, Interpolation::Step, vec![3.], vec![1., 1., 1.]).unwrap(),
+            ],
+        );
+        assert_eq!(clip.duration, 3.);
+        let mut pose = vec![Transform::default(); 2];
+        clip.sample(1., &mut pose);
+        assert_eq!(pose[0].translation, [1., 0., 0.]);
+        assert_eq!(pose[1].scale, [2., 2., 2.]);
+        assert_eq!(pose[1].translation, [0., 0., 0.]);
+    }
+}
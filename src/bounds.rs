@@ -0,0 +1,357 @@
+use crate::mat_4::Matrix;
+use crate::vec_3;
+
+//--Axis aligned bounding box--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    //--Smallest box around 3-component positions, None without any--
+    pub fn from_positions(positions: &[f32]) -> Option<Self> {
+        let mut points = positions.chunks_exact(3);
+        let first = points.next()?;
+        let mut aabb = Aabb {
+            min: [first[0], first[1], first[2]],
+            max: [first[0], first[1], first[2]],
+        };
+        for p in points {
+            aabb.grow(&[p[0], p[1], p[2]]);
+        }
+        Some(aabb)
+    }
+
+    //--Extend the box to contain a point--
+    pub fn grow(&mut self, p: &[f32; 3]) {
+        for ((min, max), &v) in self.min.iter_mut().zip(self.max.iter_mut()).zip(p.iter()) {
+            *min = min.min(v);
+            *max = max.max(v);
+        }
+    }
+
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        let mut out = *self;
+        out.grow(&other.min);
+        out.grow(&other.max);
+        out
+    }
+
+    pub fn center(&self) -> [f32; 3] {
+        vec_3::scale(&vec_3::add(&self.min, &self.max), 0.5)
+    }
+
+    //--Half the size along each axis--
+    pub fn extents(&self) -> [f32; 3] {
+        vec_3::scale(&vec_3::sub(&self.max, &self.min), 0.5)
+    }
+
+    //--Box around this box after a transform--
+    //  <note>
+    //      Transforms the center and sums the absolute matrix columns over the
+    //      extents (Arvo), exact for the corners without visiting all eight.
+    pub fn transformed(&self, m: &Matrix) -> Aabb {
+        let v = m.get_value();
+        let center = m.transform_point(&self.center());
+        let e = self.extents();
+        let mut extents = [0.; 3];
+        for (i, extent) in extents.iter_mut().enumerate() {
+            *extent = v[i].abs() * e[0] + v[4 + i].abs() * e[1] + v[8 + i].abs() * e[2];
+        }
+        Aabb {
+            min: vec_3::sub(&center, &extents),
+            max: vec_3::add(&center, &extents),
+        }
+    }
+}
+
+//--Bounding sphere--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    //--Sphere around 3-component positions, None without any--
+    //  <note>
+    //      Ritter's approximation: starts from two distant points and grows
+    //      to take in stragglers, within a few percent of the minimal sphere.
+    pub fn from_positions(positions: &[f32]) -> Option<Self> {
+        let point = |p: &[f32]| [p[0], p[1], p[2]];
+        let first = point(positions.chunks_exact(3).next()?);
+        let farthest = |from: &[f32; 3]| {
+            positions
+                .chunks_exact(3)
+                .map(point)
+                .max_by(|a, b| distance2(a, from).total_cmp(&distance2(b, from)))
+                .unwrap_or(*from)
+        };
+        let a = farthest(&first);
+        let b = farthest(&a);
+        let mut sphere = BoundingSphere {
+            center: vec_3::lerp(&a, &b, 0.5),
+            radius: distance2(&a, &b).sqrt() / 2.,
+        };
+        for p in positions.chunks_exact(3).map(point) {
+            let d = distance2(&p, &sphere.center).sqrt();
+            if d > sphere.radius {
+                // Move the far side of the sphere out to the point
+                let radius = (sphere.radius + d) / 2.;
+                sphere.center = vec_3::add(&sphere.center, &vec_3::scale(&vec_3::sub(&p, &sphere.center), (radius - sphere.radius) / d));
+                sphere.radius = radius;
+            }
+        }
+        Some(sphere)
+    }
+
+    //--Sphere around this sphere after a transform, scaled by the largest axis scale--
+    pub fn transformed(&self, m: &Matrix) -> BoundingSphere {
+        let v = m.get_value();
+        let scale = (0..3)
+            .map(|c| vec_3::length(&[v[c * 4], v[c * 4 + 1], v[c * 4 + 2]]))
+            .fold(0., f32::max);
+        BoundingSphere {
+            center: m.transform_point(&self.center),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+fn distance2(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    let d = vec_3::sub(a, b);
+    vec_3::dot(&d, &d)
+}
+
+//--Box and sphere of one mesh in its local space--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Bounds {
+    pub aabb: Aabb,
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    pub fn from_positions(positions: &[f32]) -> Option<Self> {
+        Some(Bounds {
+            aabb: Aabb::from_positions(positions)?,
+            sphere: BoundingSphere::from_positions(positions)?,
+        })
+    }
+}
+
+//--Six clip planes of a view volume--
+//  <note>
+//      Planes are [a, b, c, d] with the normal pointing inside, so a point p
+//      is inside a plane when a*x + b*y + c*z + d >= 0.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Frustum {
+    //  Left, right, bottom, top, near, far
+    pub planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    //--Extract the planes from projection * view (Gribb/Hartmann)--
+    //  <argument>
+    //      vp &Matrix : combined projection-view matrix, planes come out in world space;
+    //                   pass projection * view * model for planes in model space
+    pub fn from_matrix(vp: &Matrix) -> Self {
+        let m = vp.get_value();
+        // Column-major, so row i is every fourth value starting at i
+        let row = |i: usize| [m[i], m[4 + i], m[8 + i], m[12 + i]];
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let add = |a: [f32; 4], b: [f32; 4]| [a[0] + b[0], a[1] + b[1], a[2] + b[2], a[3] + b[3]];
+        let sub = |a: [f32; 4], b: [f32; 4]| [a[0] - b[0], a[1] - b[1], a[2] - b[2], a[3] - b[3]];
+        let planes = [add(r3, r0), sub(r3, r0), add(r3, r1), sub(r3, r1), add(r3, r2), sub(r3, r2)].map(|p| {
+            let l = vec_3::length(&[p[0], p[1], p[2]]);
+            if l > 0. {
+                [p[0] / l, p[1] / l, p[2] / l, p[3] / l]
+            } else {
+                p
+            }
+        });
+        Frustum { planes }
+    }
+
+    fn distance(plane: &[f32; 4], p: &[f32; 3]) -> f32 {
+        plane[0] * p[0] + plane[1] * p[1] + plane[2] * p[2] + plane[3]
+    }
+
+    //--False only when the sphere is entirely outside one plane--
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| Self::distance(plane, &sphere.center) >= -sphere.radius)
+    }
+
+    //--False only when the box is entirely outside one plane--
+    //  <note>
+    //      Conservative: a box near a frustum corner can pass while being outside.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        let center = aabb.center();
+        let e = aabb.extents();
+        self.planes.iter().all(|plane| {
+            let reach = plane[0].abs() * e[0] + plane[1].abs() * e[1] + plane[2].abs() * e[2];
+            Self::distance(plane, &center) >= -reach
+        })
+    }
+
+    //--Test local bounds placed in the world by a model matrix--
+    //  <note>
+    //      The sphere test rejects most objects cheaply, the box is only
+    //      transformed when the sphere passes.
+    pub fn intersects_bounds(&self, bounds: &Bounds, model: &Matrix) -> bool {
+        self.intersects_sphere(&bounds.sphere.transformed(model)) && self.intersects_aabb(&bounds.aabb.transformed(model))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quat;
+
+    const NEAR: f32 = 1.;
+    const FAR: f32 = 100.;
+
+    //  Camera at z = 5 looking at the origin, 90 degrees high and twice as wide
+    fn view_projection() -> Matrix {
+        let mut vp = Matrix::new();
+        vp.perspective(2., std::f32::consts::FRAC_PI_2, NEAR, FAR);
+        let mut view = Matrix::new();
+        view.look_at(&[0., 0., 5.], &[0., 0., 0.], &[0., 1., 0.]);
+        vp.multiply(&view);
+        vp
+    }
+
+    //  Inside the clip volume -w <= x, y, z <= w, what GL keeps
+    fn clipped(vp: &Matrix, p: &[f32; 3]) -> Option<bool> {
+        let m = vp.get_value();
+        let clip = |row: usize| m[row] * p[0] + m[4 + row] * p[1] + m[8 + row] * p[2] + m[12 + row];
+        let w = clip(3);
+        let margin = (0..3).map(|i| w - clip(i).abs()).fold(f32::INFINITY, f32::min);
+        // Too close to a plane to tell apart from rounding
+        if margin.abs() < 1e-3 {
+            return None;
+        }
+        Some(margin < 0.)
+    }
+
+    fn point(p: [f32; 3]) -> BoundingSphere {
+        BoundingSphere { center: p, radius: 0. }
+    }
+
+    fn random_positions(count: usize) -> Vec<f32> {
+        let mut seed: u32 = 11;
+        (0..count * 3)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+                (seed >> 8) as f32 / (1 << 24) as f32 * 4. - 2.
+            })
+            .collect()
+    }
+
+    #[test]
+    fn planes_match_the_clip_volume() {
+        let vp = view_projection();
+        let frustum = Frustum::from_matrix(&vp);
+        for plane in frustum.planes.iter() {
+            assert!((vec_3::length(&[plane[0], plane[1], plane[2]]) - 1.).abs() < 1e-5);
+        }
+
+        let positions = random_positions(5000);
+        let mut inside = 0;
+        for p in positions.chunks(3) {
+            // Spread the points over and around the whole view volume
+            let p = [p[0] * 60., p[1] * 30., 5. - (p[2] + 2.) * 30.];
+            if let Some(outside) = clipped(&vp, &p) {
+                assert_eq!(frustum.intersects_sphere(&point(p)), !outside, "{:?}", p);
+                inside += !outside as usize;
+            }
+        }
+        assert!(inside > 500 && inside < 4500, "{} inside", inside);
+    }
+
+    #[test]
+    fn points_just_outside_each_plane() {
+        let frustum = Frustum::from_matrix(&view_projection());
+        // GL clips depth at -w, which this projection puts at n*f/(2f-n) in front of the eye
+        let near = NEAR * FAR / (2. * FAR - NEAR);
+        let cases = [
+            // Five units in front of the camera the volume spans x in -10..10 and y in -5..5
+            ([-9.9, 0., 0.], [-10.1, 0., 0.], 0),
+            ([9.9, 0., 0.], [10.1, 0., 0.], 1),
+            ([0., -4.9, 0.], [0., -5.1, 0.], 2),
+            ([0., 4.9, 0.], [0., 5.1, 0.], 3),
+            ([0., 0., 5. - near - 0.01], [0., 0., 5. - near + 0.01], 4),
+            ([0., 0., 5. - FAR + 0.1], [0., 0., 5. - FAR - 0.1], 5),
+        ];
+        for (inside, outside, plane) in cases.iter() {
+            assert!(frustum.intersects_sphere(&point(*inside)), "{:?}", inside);
+            assert!(!frustum.intersects_sphere(&point(*outside)), "{:?}", outside);
+            for (i, p) in frustum.planes.iter().enumerate() {
+                assert_eq!(Frustum::distance(p, outside) < 0., i == *plane, "{:?} against plane {}", outside, i);
+            }
+        }
+
+        // Behind the camera, on axis and off to the side
+        for p in [[0., 0., 6.], [0., 0., 50.], [3., 2., 5.5]] {
+            assert!(!frustum.intersects_sphere(&point(p)), "{:?}", p);
+        }
+    }
+
+    #[test]
+    fn spheres_and_boxes_reaching_into_the_volume() {
+        let frustum = Frustum::from_matrix(&view_projection());
+        let behind = |radius: f32| BoundingSphere {
+            center: [0., 0., 8.],
+            radius,
+        };
+        assert!(!frustum.intersects_sphere(&behind(1.)));
+        assert!(frustum.intersects_sphere(&behind(4.)));
+
+        let aabb = |min: [f32; 3], max: [f32; 3]| Aabb { min, max };
+        // x reaches 10 at the origin and 12 a unit further away
+        assert!(frustum.intersects_aabb(&aabb([-1., -1., -1.], [1., 1., 1.])));
+        assert!(frustum.intersects_aabb(&aabb([9., -1., -1.], [12., 1., 1.])));
+        assert!(!frustum.intersects_aabb(&aabb([13., -1., -1.], [14., 1., 1.])));
+        assert!(!frustum.intersects_aabb(&aabb([-1., -1., 6.], [1., 1., 7.])));
+
+        let bounds = Bounds::from_positions(&[-1., -1., -1., 1., 1., 1.]).unwrap();
+        let mut model = Matrix::new();
+        assert!(frustum.intersects_bounds(&bounds, &model));
+        model.set_trs(&[0., 0., 10.], &quat::identity(), &[1., 1., 1.]);
+        assert!(!frustum.intersects_bounds(&bounds, &model));
+    }
+
+    #[test]
+    fn transformed_bounds_contain_transformed_positions() {
+        let positions = random_positions(500);
+        let bounds = Bounds::from_positions(&positions).unwrap();
+        let mut model = Matrix::new();
+        model.set_trs(
+            &[3., -1., 2.],
+            &quat::from_axis_angle(&vec_3::normalize(&[1., 1., 0.]), 0.8),
+            &[2., 0.5, 1.5],
+        );
+        let (aabb, sphere) = (bounds.aabb.transformed(&model), bounds.sphere.transformed(&model));
+
+        let contains = |aabb: &Aabb, p: &[f32; 3]| (0..3).all(|i| p[i] >= aabb.min[i] - 1e-4 && p[i] <= aabb.max[i] + 1e-4);
+        for p in positions.chunks(3) {
+            let p = [p[0], p[1], p[2]];
+            assert!(contains(&bounds.aabb, &p));
+            assert!(distance2(&p, &bounds.sphere.center).sqrt() <= bounds.sphere.radius + 1e-4);
+            let q = model.transform_point(&p);
+            assert!(contains(&aabb, &q), "{:?} outside {:?}", q, aabb);
+            assert!(distance2(&q, &sphere.center).sqrt() <= sphere.radius + 1e-4, "{:?} outside {:?}", q, sphere);
+        }
+        // Ritter stays close to the minimal sphere, the cube's circumsphere here
+        assert!(bounds.sphere.radius <= 3f32.sqrt() * 2. * 1.05, "{:?}", bounds.sphere);
+    }
+
+    #[test]
+    fn no_positions_no_bounds() {
+        assert!(Aabb::from_positions(&[]).is_none());
+        assert!(BoundingSphere::from_positions(&[]).is_none());
+        assert!(Bounds::from_positions(&[]).is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use crate::bounds::Aabb;
+use crate::ray::{Ray, TriangleHit};
+use crate::vec_3;
+
+//  Triangles per leaf, small leaves trade memory for fewer triangle tests
+const LEAF_SIZE: usize = 4;
+
+//--Closest triangle a ray hits--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BvhHit {
+    //  Index of the triangle in the index buffer, indices[triangle * 3..triangle * 3 + 3]
+    pub triangle: usize,
+    pub t: f32,
+    pub barycentric: [f32; 3],
+}
+
+struct BvhNode {
+    aabb: Aabb,
+    //  Leaf: first entry in `triangles`, inner node: index of the left child, the right one follows it
+    first: usize,
+    //  Triangles in a leaf, 0 for inner nodes
+    count: usize,
+}
+
+//--Bounding volume hierarchy over the triangles of one mesh--
+//  <note>
+//      Built once from positions and indices, the triangles' corners are
+//      copied so queries don't need the mesh data.
+pub struct Bvh {
+    nodes: Vec<BvhNode>,
+    //  (triangle index, corners), grouped by leaf
+    triangles: Vec<(usize, [[f32; 3]; 3])>,
+}
+
+impl Bvh {
+    //--Build over an indexed triangle list--
+    //  <argument>
+    //      positions &[f32] : 3 floats per vertex
+    //      indices   &[u16] : triangle list
+    //  <return> Option<Bvh>  None without triangles
+    //  <note>
+    //      Nodes are split at the median centroid along their longest axis.
+    pub fn build(positions: &[f32], indices: &[u16]) -> Option<Self> {
+        let vertex = |i: u16| {
+            let i = i as usize * 3;
+            [positions[i], positions[i + 1], positions[i + 2]]
+        };
+        let triangles: Vec<(usize, [[f32; 3]; 3])> = indices
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(t, tri)| (t, [vertex(tri[0]), vertex(tri[1]), vertex(tri[2])]))
+            .collect();
+        if triangles.is_empty() {
+            return None;
+        }
+        let mut bvh = Bvh {
+            nodes: Vec::with_capacity(triangles.len() * 2 / LEAF_SIZE + 1),
+            triangles,
+        };
+        bvh.nodes.push(BvhNode {
+            aabb: bvh.bounds(0, bvh.triangles.len()),
+            first: 0,
+            count: bvh.triangles.len(),
+        });
+        bvh.split(0);
+        Some(bvh)
+    }
+
+    fn bounds(&self, first: usize, count: usize) -> Aabb {
+        let mut aabb = Aabb {
+            min: self.triangles[first].1[0],
+            max: self.triangles[first].1[0],
+        };
+        for (_, corners) in self.triangles[first..first + count].iter() {
+            for p in corners.iter() {
+                aabb.grow(p);
+            }
+        }
+        aabb
+    }
+
+    fn split(&mut self, node: usize) {
+        let (first, count) = (self.nodes[node].first, self.nodes[node].count);
+        if count <= LEAF_SIZE {
+            return;
+        }
+        let centroid = |corners: &[[f32; 3]; 3]| vec_3::scale(&vec_3::add(&corners[0], &vec_3::add(&corners[1], &corners[2])), 1. / 3.);
+
+        let mut centroids = Aabb {
+            min: centroid(&self.triangles[first].1),
+            max: centroid(&self.triangles[first].1),
+        };
+        for (_, corners) in self.triangles[first..first + count].iter() {
+            centroids.grow(&centroid(corners));
+        }
+        let size = vec_3::sub(&centroids.max, &centroids.min);
+        let axis = if size[0] >= size[1] && size[0] >= size[2] {
+            0
+        } else if size[1] >= size[2] {
+            1
+        } else {
+            2
+        };
+        if size[axis] <= 0. {
+            // Every centroid in one spot, splitting can't separate them
+            return;
+        }
+
+        let half = count / 2;
+        self.triangles[first..first + count]
+            .select_nth_unstable_by(half, |a, b| centroid(&a.1)[axis].total_cmp(&centroid(&b.1)[axis]));
+
+        let left = self.nodes.len();
+        for (start, n) in [(first, half), (first + half, count - half)] {
+            self.nodes.push(BvhNode {
+                aabb: self.bounds(start, n),
+                first: start,
+                count: n,
+            });
+        }
+        self.nodes[node].first = left;
+        self.nodes[node].count = 0;
+        self.split(left);
+        self.split(left + 1);
+    }
+
+    //--Closest triangle hit along the ray, ignoring hits beyond `max_t`--
+    pub fn intersect(&self, ray: &Ray, max_t: f32) -> Option<BvhHit> {
+        let mut best: Option<BvhHit> = None;
+        let mut limit = max_t;
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            match ray.intersect_aabb(&node.aabb) {
+                Some(t) if t <= limit => {}
+                _ => continue,
+            }
+            if node.count > 0 {
+                for (triangle, [a, b, c]) in self.triangles[node.first..node.first + node.count].iter() {
+                    if let Some(TriangleHit { t, barycentric }) = ray.intersect_triangle(a, b, c) {
+                        if t <= limit {
+                            limit = t;
+                            best = Some(BvhHit {
+                                triangle: *triangle,
+                                t,
+                                barycentric,
+                            });
+                        }
+                    }
+                }
+                continue;
+            }
+            // Visit the nearer child first so the farther one is more likely culled by `limit`
+            let (left, right) = (node.first, node.first + 1);
+            let entry = |child: usize| ray.intersect_aabb(&self.nodes[child].aabb).unwrap_or(f32::INFINITY);
+            if entry(left) <= entry(right) {
+                stack.push(right);
+                stack.push(left);
+            } else {
+                stack.push(left);
+                stack.push(right);
+            }
+        }
+        best
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes;
+
+    fn corners(positions: &[f32], triangle: &[u16]) -> [[f32; 3]; 3] {
+        let vertex = |i: u16| {
+            let i = i as usize * 3;
+            [positions[i], positions[i + 1], positions[i + 2]]
+        };
+        [vertex(triangle[0]), vertex(triangle[1]), vertex(triangle[2])]
+    }
+
+    //  Closest hit over every triangle, no acceleration
+    fn brute_force(positions: &[f32], indices: &[u16], ray: &Ray) -> Option<BvhHit> {
+        let mut best: Option<BvhHit> = None;
+        for (triangle, corner_indices) in indices.chunks(3).enumerate() {
+            let [a, b, c] = corners(positions, corner_indices);
+            if let Some(hit) = ray.intersect_triangle(&a, &b, &c) {
+                if best.is_none_or(|b| hit.t < b.t) {
+                    best = Some(BvhHit {
+                        triangle,
+                        t: hit.t,
+                        barycentric: hit.barycentric,
+                    });
+                }
+            }
+        }
+        best
+    }
+
+    //  Rays from a shell around the torus aimed at jittered points near its center
+    fn rays() -> Vec<Ray> {
+        let mut seed: u32 = 7;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32 * 2. - 1.
+        };
+        (0..2000)
+            .map(|_| {
+                let origin = vec_3::scale(&vec_3::normalize(&[random(), random(), random()]), 6.);
+                let target = [random() * 3., random(), random() * 3.];
+                Ray::new(origin, vec_3::normalize(&vec_3::sub(&target, &origin)))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_brute_force_on_a_torus() {
+        let (positions, _, _, indices) = shapes::torus(24, 24, 0.5, 2.);
+        let bvh = Bvh::build(&positions, &indices).unwrap();
+        assert!(bvh.nodes.len() > 1);
+
+        let mut hits = 0;
+        for ray in rays() {
+            match (brute_force(&positions, &indices, &ray), bvh.intersect(&ray, f32::INFINITY)) {
+                (None, None) => {}
+                (Some(expected), Some(actual)) => {
+                    hits += 1;
+                    assert!((expected.t - actual.t).abs() < 1e-4, "{:?} vs {:?}", expected, actual);
+                    // On a shared edge either triangle is a valid answer
+                    let [a, b, c] = corners(&positions, &indices[actual.triangle * 3..actual.triangle * 3 + 3]);
+                    let hit = ray.intersect_triangle(&a, &b, &c).unwrap();
+                    assert_eq!(hit.t, actual.t);
+                    assert_eq!(hit.barycentric, actual.barycentric);
+
+                    assert!(bvh.intersect(&ray, expected.t + 1e-3).is_some());
+                    assert!(bvh.intersect(&ray, expected.t * 0.99).is_none());
+                }
+                (expected, actual) => panic!("brute force {:?} but BVH {:?} for {:?}", expected, actual, ray),
+            }
+        }
+        // The rays both hit and miss
+        assert!(hits > 200 && hits < 1800, "{} hits", hits);
+    }
+
+    #[test]
+    fn empty_meshes_have_no_bvh() {
+        assert!(Bvh::build(&[], &[]).is_none());
+    }
+}
@@ -0,0 +1,521 @@
+use crate::error::RenderError;
+use crate::input::{InputEvent, MouseButton};
+use crate::mat_4::Matrix;
+use crate::quat;
+use crate::vec_3;
+use std::collections::HashSet;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    //  `fovy` is the vertical field of view in radians
+    Perspective { fovy: f32, near: f32, far: f32 },
+    //  `height` is the visible extent along Y, the width follows the aspect
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+//--Camera attached to a scene node--
+//  <note>
+//      The view matrix is the inverse of the node's world matrix,
+//      the camera looks down its local -Z axis.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    pub projection: Projection,
+}
+
+impl Camera {
+    //--Perspective camera, `fovy` in radians--
+    pub fn perspective(fovy: f32, near: f32, far: f32) -> Self {
+        Self {
+            projection: Projection::Perspective { fovy, near, far },
+        }
+    }
+
+    pub fn orthographic(height: f32, near: f32, far: f32) -> Self {
+        Self {
+            projection: Projection::Orthographic { height, near, far },
+        }
+    }
+
+    //--Create projection matrix--
+    //  <argument>
+    //      aspect f32 : width divided by height of the viewport
+    pub fn projection_matrix(&self, aspect: f32) -> Matrix {
+        let mut m = Matrix::new();
+        match self.projection {
+            Projection::Perspective { fovy, near, far } => m.perspective(aspect, fovy, near, far),
+            Projection::Orthographic { height, near, far } => {
+                let h = height / 2.;
+                let w = h * aspect;
+                m.orthographic(-w, w, -h, h, near, far)
+            }
+        };
+        m
+    }
+
+    //--Derive view matrix from the world matrix of a camera node--
+    pub fn view_from_world(world: &Matrix) -> Result<Matrix, RenderError> {
+        let mut view = *world;
+        view.inverse()
+            .map_err(|_| RenderError::Math("camera matrix is not invertible".into()))?;
+        Ok(view)
+    }
+}
+
+//--Drives a camera pose from an input event stream--
+//  <note>
+//      Events are fed as they arrive, update() integrates held keys once per frame.
+pub trait CameraController {
+    fn handle_event(&mut self, event: &InputEvent);
+
+    fn update(&mut self, dt: f32);
+
+    fn position(&self) -> [f32; 3];
+
+    fn rotation(&self) -> [f32; 4];
+}
+
+fn yaw_pitch_rotation(yaw: f32, pitch: f32) -> [f32; 4] {
+    quat::multiply(
+        &quat::from_axis_angle(&[0., 1., 0.], yaw),
+        &quat::from_axis_angle(&[1., 0., 0.], pitch),
+    )
+}
+
+//--Yaw and pitch that make the camera look along a direction--
+fn yaw_pitch_towards(dir: &[f32; 3]) -> (f32, f32) {
+    let d = vec_3::normalize(dir);
+    ((-d[0]).atan2(-d[2]), d[1].clamp(-1., 1.).asin())
+}
+
+const PITCH_LIMIT: f32 = std::f32::consts::FRAC_PI_2 - 0.01;
+
+//--Rotate around a target point--
+//  <note>
+//      Left drag rotates, right or middle drag pans, wheel zooms,
+//      pinch zooms and twist rotates.
+pub struct OrbitController {
+    pub target: [f32; 3],
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub rotate_speed: f32,
+    pub pan_speed: f32,
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+    rotating: bool,
+    panning: bool,
+}
+
+impl OrbitController {
+    pub fn new(target: &[f32; 3], distance: f32) -> Self {
+        Self {
+            target: *target,
+            distance,
+            yaw: 0.,
+            pitch: 0.,
+            rotate_speed: 0.005,
+            pan_speed: 0.001,
+            zoom_speed: 0.001,
+            min_distance: 0.1,
+            max_distance: 1000.,
+            rotating: false,
+            panning: false,
+        }
+    }
+
+    //--Place the camera at `eye`, orbiting `target`--
+    pub fn look_at(&mut self, eye: &[f32; 3], target: &[f32; 3]) -> &mut Self {
+        let offset = vec_3::sub(eye, target);
+        let (yaw, pitch) = yaw_pitch_towards(&vec_3::scale(&offset, -1.));
+        self.target = *target;
+        self.distance = vec_3::length(&offset).clamp(self.min_distance, self.max_distance);
+        self.yaw = yaw;
+        self.pitch = (-pitch).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        self
+    }
+
+    pub fn rotate(&mut self, d_yaw: f32, d_pitch: f32) {
+        self.yaw += d_yaw;
+        self.pitch = (self.pitch + d_pitch).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+    }
+
+    //--Move the target in the view plane--
+    pub fn pan(&mut self, dx: f32, dy: f32) {
+        let rotation = self.rotation();
+        let right = quat::rotate(&rotation, &[1., 0., 0.]);
+        let up = quat::rotate(&rotation, &[0., 1., 0.]);
+        let offset = vec_3::add(&vec_3::scale(&right, -dx), &vec_3::scale(&up, dy));
+        self.target = vec_3::add(&self.target, &vec_3::scale(&offset, self.distance));
+    }
+
+    //--Zoom exponentially so each wheel notch feels the same at any distance--
+    pub fn zoom(&mut self, delta: f32) {
+        self.distance = (self.distance * delta.exp()).clamp(self.min_distance, self.max_distance);
+    }
+}
+
+impl CameraController for OrbitController {
+    fn handle_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::PointerDown { button, .. } => match button {
+                MouseButton::Left => self.rotating = true,
+                MouseButton::Middle | MouseButton::Right => self.panning = true,
+            },
+            InputEvent::PointerUp { button, .. } => match button {
+                MouseButton::Left => self.rotating = false,
+                MouseButton::Middle | MouseButton::Right => self.panning = false,
+            },
+            InputEvent::PointerMove { dx, dy, .. } => {
+                if self.rotating {
+                    self.rotate(-dx * self.rotate_speed, dy * self.rotate_speed);
+                } else if self.panning {
+                    self.pan(dx * self.pan_speed, dy * self.pan_speed);
+                }
+            }
+            InputEvent::Wheel { delta } => self.zoom(delta * self.zoom_speed),
+            InputEvent::Gesture { scale, rotation, .. } => {
+                // Spreading the fingers zooms in, twisting turns the model with them
+                if *scale > 0. {
+                    self.zoom(-scale.ln());
+                }
+                self.rotate(-rotation, 0.);
+            }
+            _ => (),
+        }
+    }
+
+    fn update(&mut self, _dt: f32) {}
+
+    fn position(&self) -> [f32; 3] {
+        let offset = [
+            self.pitch.cos() * self.yaw.sin(),
+            self.pitch.sin(),
+            self.pitch.cos() * self.yaw.cos(),
+        ];
+        vec_3::add(&self.target, &vec_3::scale(&offset, self.distance))
+    }
+
+    fn rotation(&self) -> [f32; 4] {
+        yaw_pitch_rotation(self.yaw, -self.pitch)
+    }
+}
+
+//--Walk on the XZ plane with WASD, look around with the mouse--
+//  <note>
+//      Mouse look is active while the left button is held,
+//      or always when `always_look` is set (e.g. under pointer lock).
+pub struct FirstPersonController {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub speed: f32,
+    pub sensitivity: f32,
+    pub always_look: bool,
+    looking: bool,
+    keys: HashSet<String>,
+}
+
+impl FirstPersonController {
+    pub fn new(position: &[f32; 3]) -> Self {
+        Self {
+            position: *position,
+            yaw: 0.,
+            pitch: 0.,
+            speed: 5.,
+            sensitivity: 0.003,
+            always_look: false,
+            looking: false,
+            keys: HashSet::new(),
+        }
+    }
+
+    pub fn look_at(&mut self, target: &[f32; 3]) -> &mut Self {
+        let (yaw, pitch) = yaw_pitch_towards(&vec_3::sub(target, &self.position));
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        self
+    }
+}
+
+//--Shared look and key handling of the first-person and fly controllers--
+fn handle_look_event(
+    event: &InputEvent,
+    look: (&mut f32, &mut f32, &mut bool),
+    always_look: bool,
+    sensitivity: f32,
+    keys: &mut HashSet<String>,
+) {
+    let (yaw, pitch, looking) = look;
+    match event {
+        InputEvent::PointerDown { button: MouseButton::Left, .. } => *looking = true,
+        InputEvent::PointerUp { button: MouseButton::Left, .. } => *looking = false,
+        InputEvent::PointerMove { dx, dy, .. } if *looking || always_look => {
+            *yaw -= dx * sensitivity;
+            *pitch = (*pitch - dy * sensitivity).clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        }
+        InputEvent::KeyDown { code } => {
+            keys.insert(code.clone());
+        }
+        InputEvent::KeyUp { code } => {
+            keys.remove(code);
+        }
+        _ => (),
+    }
+}
+
+//--Movement direction from held keys, in the camera's local axes--
+//  <return> [right, up, back]
+fn key_axes(keys: &HashSet<String>) -> [f32; 3] {
+    let axis = |positive: &str, negative: &str| {
+        keys.contains(positive) as i32 as f32 - keys.contains(negative) as i32 as f32
+    };
+    [
+        axis("KeyD", "KeyA"),
+        axis("KeyE", "KeyQ"),
+        axis("KeyS", "KeyW"),
+    ]
+}
+
+impl CameraController for FirstPersonController {
+    fn handle_event(&mut self, event: &InputEvent) {
+        handle_look_event(
+            event,
+            (&mut self.yaw, &mut self.pitch, &mut self.looking),
+            self.always_look,
+            self.sensitivity,
+            &mut self.keys,
+        );
+    }
+
+    fn update(&mut self, dt: f32) {
+        let axes = key_axes(&self.keys);
+        let yaw = quat::from_axis_angle(&[0., 1., 0.], self.yaw);
+        let right = quat::rotate(&yaw, &[1., 0., 0.]);
+        let back = quat::rotate(&yaw, &[0., 0., 1.]);
+        let horizontal = vec_3::add(&vec_3::scale(&right, axes[0]), &vec_3::scale(&back, axes[2]));
+        let step = vec_3::scale(&vec_3::normalize(&horizontal), self.speed * dt);
+        self.position = vec_3::add(&self.position, &step);
+    }
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn rotation(&self) -> [f32; 4] {
+        yaw_pitch_rotation(self.yaw, self.pitch)
+    }
+}
+
+//--Free flight along the view direction--
+//  <note>
+//      WASD moves in the view plane, Q and E move down and up
+//      along the camera's own axes, mouse look as in FirstPersonController.
+pub struct FlyController {
+    pub position: [f32; 3],
+    pub yaw: f32,
+    pub pitch: f32,
+    pub speed: f32,
+    pub boost: f32,
+    pub sensitivity: f32,
+    pub always_look: bool,
+    looking: bool,
+    keys: HashSet<String>,
+}
+
+impl FlyController {
+    pub fn new(position: &[f32; 3]) -> Self {
+        Self {
+            position: *position,
+            yaw: 0.,
+            pitch: 0.,
+            speed: 5.,
+            boost: 4.,
+            sensitivity: 0.003,
+            always_look: false,
+            looking: false,
+            keys: HashSet::new(),
+        }
+    }
+
+    pub fn look_at(&mut self, target: &[f32; 3]) -> &mut Self {
+        let (yaw, pitch) = yaw_pitch_towards(&vec_3::sub(target, &self.position));
+        self.yaw = yaw;
+        self.pitch = pitch.clamp(-PITCH_LIMIT, PITCH_LIMIT);
+        self
+    }
+}
+
+impl CameraController for FlyController {
+    fn handle_event(&mut self, event: &InputEvent) {
+        handle_look_event(
+            event,
+            (&mut self.yaw, &mut self.pitch, &mut self.looking),
+            self.always_look,
+            self.sensitivity,
+            &mut self.keys,
+        );
+    }
+
+    fn update(&mut self, dt: f32) {
+        let axes = key_axes(&self.keys);
+        let direction = vec_3::normalize(&quat::rotate(&self.rotation(), &axes));
+        let speed = if self.keys.contains("ShiftLeft") || self.keys.contains("ShiftRight") {
+            self.speed * self.boost
+        } else {
+            self.speed
+        };
+        self.position = vec_3::add(&self.position, &vec_3::scale(&direction, speed * dt));
+    }
+
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+
+    fn rotation(&self) -> [f32; 4] {
+        yaw_pitch_rotation(self.yaw, self.pitch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_near(a: &[f32; 3], b: &[f32; 3]) {
+        for i in 0..3 {
+            assert!((a[i] - b[i]).abs() < EPSILON, "{:?} != {:?}", a, b);
+        }
+    }
+
+    fn forward(controller: &dyn CameraController) -> [f32; 3] {
+        quat::rotate(&controller.rotation(), &[0., 0., -1.])
+    }
+
+    fn key(controller: &mut dyn CameraController, code: &str, down: bool) {
+        let code = code.to_string();
+        controller.handle_event(&if down { InputEvent::KeyDown { code } } else { InputEvent::KeyUp { code } });
+    }
+
+    fn drag(controller: &mut dyn CameraController, button: MouseButton, dx: f32, dy: f32) {
+        controller.handle_event(&InputEvent::PointerDown { button, x: 0., y: 0. });
+        controller.handle_event(&InputEvent::PointerMove { x: dx, y: dy, dx, dy });
+        controller.handle_event(&InputEvent::PointerUp { button, x: dx, y: dy });
+    }
+
+    #[test]
+    fn orbit_looks_at_target_from_distance() {
+        let mut orbit = OrbitController::new(&[1., 2., 3.], 5.);
+        orbit.rotate(0.7, 0.3);
+        let offset = vec_3::sub(&orbit.position(), &orbit.target);
+        assert!((vec_3::length(&offset) - 5.).abs() < EPSILON);
+        assert_near(&forward(&orbit), &vec_3::normalize(&vec_3::scale(&offset, -1.)));
+
+        // The target sits on the view axis, `distance` in front of the eye
+        let mut world = Matrix::new();
+        world.set_trs(&orbit.position(), &orbit.rotation(), &[1., 1., 1.]);
+        let view = Camera::view_from_world(&world).unwrap();
+        assert_near(&view.transform_point(&orbit.target), &[0., 0., -5.]);
+    }
+
+    #[test]
+    fn orbit_look_at_round_trips() {
+        let mut orbit = OrbitController::new(&[0., 0., 0.], 1.);
+        orbit.look_at(&[3., 4., -2.], &[1., 1., 1.]);
+        assert_near(&orbit.position(), &[3., 4., -2.]);
+        assert_near(&orbit.target, &[1., 1., 1.]);
+    }
+
+    #[test]
+    fn orbit_clamps_pitch_and_distance() {
+        let mut orbit = OrbitController::new(&[0., 0., 0.], 10.);
+        orbit.rotate(0., 10.);
+        assert_eq!(orbit.pitch, PITCH_LIMIT);
+        orbit.rotate(0., -20.);
+        assert_eq!(orbit.pitch, -PITCH_LIMIT);
+
+        orbit.zoom(-100.);
+        assert_eq!(orbit.distance, orbit.min_distance);
+        orbit.zoom(100.);
+        assert_eq!(orbit.distance, orbit.max_distance);
+
+        // Each notch scales the distance by the same factor
+        orbit.distance = 10.;
+        orbit.handle_event(&InputEvent::Wheel { delta: 100. });
+        let ratio = orbit.distance / 10.;
+        orbit.handle_event(&InputEvent::Wheel { delta: 100. });
+        assert!((orbit.distance / 10. - ratio * ratio).abs() < EPSILON);
+    }
+
+    #[test]
+    fn orbit_pan_keeps_view_direction() {
+        let mut orbit = OrbitController::new(&[0., 0., 0.], 4.);
+        orbit.rotate(0.4, -0.2);
+        let before = forward(&orbit);
+        drag(&mut orbit, MouseButton::Right, 100., 0.);
+        assert_near(&forward(&orbit), &before);
+        // Dragging right moves the target to the camera's left
+        let right = quat::rotate(&orbit.rotation(), &[1., 0., 0.]);
+        let moved = vec_3::dot(&orbit.target, &right);
+        assert!((moved + 100. * orbit.pan_speed * 4.).abs() < EPSILON);
+    }
+
+    #[test]
+    fn first_person_walks_horizontally() {
+        let mut walker = FirstPersonController::new(&[0., 1., 0.]);
+        walker.look_at(&[1., 0., -1.]);
+        assert_near(&forward(&walker), &vec_3::normalize(&[1., -1., -1.]));
+
+        key(&mut walker, "KeyW", true);
+        walker.update(0.5);
+        let step = vec_3::sub(&walker.position, &[0., 1., 0.]);
+        assert!(step[1].abs() < EPSILON);
+        assert!((vec_3::length(&step) - walker.speed * 0.5).abs() < EPSILON);
+        assert_near(&vec_3::normalize(&step), &vec_3::normalize(&[1., 0., -1.]));
+
+        // Diagonals aren't faster, released keys stop
+        key(&mut walker, "KeyD", true);
+        let before = walker.position;
+        walker.update(1.);
+        assert!((vec_3::length(&vec_3::sub(&walker.position, &before)) - walker.speed).abs() < EPSILON);
+        key(&mut walker, "KeyW", false);
+        key(&mut walker, "KeyD", false);
+        let before = walker.position;
+        walker.update(1.);
+        assert_eq!(walker.position, before);
+    }
+
+    #[test]
+    fn first_person_looks_only_while_dragging() {
+        let mut walker = FirstPersonController::new(&[0., 0., 0.]);
+        walker.handle_event(&InputEvent::PointerMove { x: 0., y: 0., dx: 50., dy: 0. });
+        assert_eq!(walker.yaw, 0.);
+
+        drag(&mut walker, MouseButton::Left, 50., 1e6);
+        assert!((walker.yaw + 50. * walker.sensitivity).abs() < EPSILON);
+        assert_eq!(walker.pitch, -PITCH_LIMIT);
+
+        walker.always_look = true;
+        walker.handle_event(&InputEvent::PointerMove { x: 0., y: 0., dx: -50., dy: 0. });
+        assert!(walker.yaw.abs() < EPSILON);
+    }
+
+    #[test]
+    fn fly_moves_along_view_and_boosts() {
+        let mut fly = FlyController::new(&[0., 0., 0.]);
+        fly.look_at(&[0., 1., -1.]);
+        key(&mut fly, "KeyW", true);
+        fly.update(1.);
+        assert_near(&fly.position, &vec_3::scale(&vec_3::normalize(&[0., 1., -1.]), fly.speed));
+
+        // E rises along the camera's up axis
+        key(&mut fly, "KeyW", false);
+        key(&mut fly, "KeyE", true);
+        key(&mut fly, "ShiftLeft", true);
+        let before = fly.position;
+        fly.update(1.);
+        let up = quat::rotate(&fly.rotation(), &[0., 1., 0.]);
+        assert_near(&vec_3::sub(&fly.position, &before), &vec_3::scale(&up, fly.speed * fly.boost));
+    }
+}
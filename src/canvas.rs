@@ -0,0 +1,199 @@
+use crate::error::RenderError;
+use std::cell::Cell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{HtmlCanvasElement, ResizeObserver, ResizeObserverEntry};
+
+//--Canvas to render into--
+#[derive(Clone, Debug)]
+pub enum CanvasTarget {
+    Element(HtmlCanvasElement),
+    //  Value of the element's id attribute, without '#'
+    Id(String),
+    //  Any CSS selector, the first match is used
+    Selector(String),
+}
+
+impl CanvasTarget {
+    //--Target from a JS value--
+    //  <note>
+    //      Accepts a canvas element or a string. Plain names such as "canvas"
+    //      are ids, anything else ("#canvas", ".preview canvas") is a selector.
+    pub fn from_js(value: &JsValue) -> Result<Self, RenderError> {
+        if let Some(canvas) = value.dyn_ref::<HtmlCanvasElement>() {
+            return Ok(CanvasTarget::Element(canvas.clone()));
+        }
+        match value.as_string() {
+            Some(s) => Ok(CanvasTarget::from(s.as_str())),
+            None => Err(RenderError::Context("expected a canvas element, an id or a CSS selector".into())),
+        }
+    }
+
+    //--Look the canvas up in the document--
+    pub fn resolve(&self) -> Result<HtmlCanvasElement, RenderError> {
+        let element = match self {
+            CanvasTarget::Element(canvas) => return Ok(canvas.clone()),
+            CanvasTarget::Id(id) => document()?.get_element_by_id(id),
+            CanvasTarget::Selector(selector) => document()?
+                .query_selector(selector)
+                .map_err(|_| RenderError::Context(format!("invalid selector '{}'", selector)))?,
+        };
+        element
+            .ok_or_else(|| RenderError::Context(format!("canvas '{}' doesn't exist", self.describe())))?
+            .dyn_into::<HtmlCanvasElement>()
+            .map_err(|_| RenderError::Context(format!("'{}' is not a canvas", self.describe())))
+    }
+
+    fn describe(&self) -> String {
+        match self {
+            CanvasTarget::Element(canvas) => canvas.id(),
+            CanvasTarget::Id(id) => format!("#{}", id),
+            CanvasTarget::Selector(selector) => selector.clone(),
+        }
+    }
+}
+
+impl From<&str> for CanvasTarget {
+    //  Plain identifiers are ids, anything else is treated as a selector
+    fn from(s: &str) -> Self {
+        let is_id = !s.is_empty() && s.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_');
+        if is_id {
+            CanvasTarget::Id(s.to_string())
+        } else {
+            CanvasTarget::Selector(s.to_string())
+        }
+    }
+}
+
+impl From<HtmlCanvasElement> for CanvasTarget {
+    fn from(canvas: HtmlCanvasElement) -> Self {
+        CanvasTarget::Element(canvas)
+    }
+}
+
+fn document() -> Result<web_sys::Document, RenderError> {
+    web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or_else(|| RenderError::Context("document doesn't exist".into()))
+}
+
+//--Drawing buffer size for a CSS size--
+//  <argument>
+//      css_width, css_height f64 : layout size in CSS pixels
+//      pixel_ratio           f64 : window.devicePixelRatio
+//      max_pixel_ratio       f64 : cap, keeps fill rate in check on dense screens
+//  <return> (u32, u32)  width and height in device pixels, at least 1x1
+pub fn drawing_buffer_size(css_width: f64, css_height: f64, pixel_ratio: f64, max_pixel_ratio: f64) -> (u32, u32) {
+    let ratio = pixel_ratio.min(max_pixel_ratio).max(0.);
+    let w = (css_width * ratio).round().max(1.);
+    let h = (css_height * ratio).round().max(1.);
+    (w as u32, h as u32)
+}
+
+//--Canvas whose drawing buffer follows its CSS size--
+//  <note>
+//      A ResizeObserver records the CSS size, update() applies it once per frame
+//      so the canvas is never resized in the middle of drawing. The device pixel
+//      ratio is re-read on every update to catch zoom and monitor changes.
+pub struct ResponsiveCanvas {
+    canvas: HtmlCanvasElement,
+    observer: ResizeObserver,
+    _callback: Closure<dyn FnMut(js_sys::Array)>,
+    css_size: Rc<Cell<(f64, f64)>>,
+    max_pixel_ratio: f64,
+    pixel_ratio: f64,
+    width: u32,
+    height: u32,
+}
+
+impl ResponsiveCanvas {
+    //--Observe a canvas--
+    //  <argument>
+    //      max_pixel_ratio f64 : upper bound for devicePixelRatio, e.g. 2.0
+    pub fn new(canvas: &HtmlCanvasElement, max_pixel_ratio: f64) -> Result<Self, RenderError> {
+        let css_size = Rc::new(Cell::new((
+            canvas.client_width() as f64,
+            canvas.client_height() as f64,
+        )));
+
+        let size = css_size.clone();
+        let callback = Closure::wrap(Box::new(move |entries: js_sys::Array| {
+            if let Some(entry) = entries.iter().last() {
+                let rect = entry.unchecked_into::<ResizeObserverEntry>().content_rect();
+                size.set((rect.width(), rect.height()));
+            }
+        }) as Box<dyn FnMut(js_sys::Array)>);
+        let observer = ResizeObserver::new(callback.as_ref().unchecked_ref())
+            .map_err(|_| RenderError::Context("ResizeObserver is not supported in this browser".into()))?;
+        observer.observe(canvas);
+
+        Ok(Self {
+            canvas: canvas.clone(),
+            observer,
+            _callback: callback,
+            css_size,
+            max_pixel_ratio,
+            pixel_ratio: 0.,
+            width: canvas.width(),
+            height: canvas.height(),
+        })
+    }
+
+    pub fn set_max_pixel_ratio(&mut self, max_pixel_ratio: f64) {
+        self.max_pixel_ratio = max_pixel_ratio;
+        // Forces the next update to recompute the size
+        self.pixel_ratio = 0.;
+    }
+
+    //--Apply a pending resize to the drawing buffer and viewport--
+    //  <return> bool  true if the size changed
+    pub fn update(&mut self, gl: &GL) -> bool {
+        let pixel_ratio = web_sys::window().map_or(1., |w| w.device_pixel_ratio());
+        let (css_width, css_height) = self.css_size.get();
+        if css_width <= 0. || css_height <= 0. {
+            // Hidden (display: none), keep the last size
+            return false;
+        }
+        let (width, height) = drawing_buffer_size(css_width, css_height, pixel_ratio, self.max_pixel_ratio);
+        if width == self.width && height == self.height && pixel_ratio == self.pixel_ratio {
+            return false;
+        }
+
+        self.pixel_ratio = pixel_ratio;
+        let changed = width != self.width || height != self.height;
+        if changed {
+            self.canvas.set_width(width);
+            self.canvas.set_height(height);
+            self.width = width;
+            self.height = height;
+        }
+        gl.viewport(0, 0, width as i32, height as i32);
+        changed
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    //--Device pixels per CSS pixel actually in use--
+    pub fn pixel_ratio(&self) -> f64 {
+        self.pixel_ratio.min(self.max_pixel_ratio)
+    }
+
+    //--Width divided by height, for the projection matrix--
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height.max(1) as f32
+    }
+}
+
+impl Drop for ResponsiveCanvas {
+    fn drop(&mut self) {
+        self.observer.disconnect();
+    }
+}
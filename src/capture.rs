@@ -0,0 +1,256 @@
+use crate::error::RenderError;
+use crate::frame_loop::{FrameClock, FrameTime};
+use crate::png;
+use crate::webgl;
+use wasm_bindgen::JsCast;
+use web_sys::WebGlRenderingContext as GL;
+
+//--Pixels read back from a render--
+#[derive(Clone, Debug, PartialEq)]
+pub struct Capture {
+    pub width: u32,
+    pub height: u32,
+    //  RGBA, rows from top to bottom
+    pub pixels: Vec<u8>,
+}
+
+impl Capture {
+    pub fn to_png(&self) -> Result<Vec<u8>, RenderError> {
+        png::encode_rgba(self.width, self.height, &self.pixels)
+    }
+}
+
+//--Render a frame offscreen and read it back--
+//  <argument>
+//      width, height u32 : size of the capture in pixels
+//      draw          F   : draws the frame, including the clear
+//  <note>
+//      The target is bound with a matching viewport while `draw` runs, so
+//      nothing reaches the canvas and preserveDrawingBuffer isn't needed.
+//      Renderer globals such as viewportSize still follow the drawing buffer,
+//      capture at its size for screen space labels to match. The default
+//      framebuffer and viewport are restored afterwards.
+pub fn capture_frame<F>(gl: &GL, width: u32, height: u32, draw: F) -> Result<Capture, RenderError>
+where
+    F: FnOnce() -> Result<(), RenderError>,
+{
+    if width == 0 || height == 0 {
+        return Err(RenderError::Resource("capture size must not be 0".into()));
+    }
+    let (framebuffer, texture, depth) = webgl::create_render_target(gl, width, height)?;
+    gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
+    gl.viewport(0, 0, width as i32, height as i32);
+
+    let mut pixels = vec![0u8; width as usize * height as usize * 4];
+    let result = draw().and_then(|_| {
+        gl.read_pixels_with_opt_u8_array(
+            0,
+            0,
+            width as i32,
+            height as i32,
+            GL::RGBA,
+            GL::UNSIGNED_BYTE,
+            Some(&mut pixels),
+        )
+        .map_err(|_| RenderError::Resource("failed to read back the capture".into()))
+    });
+
+    gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+    gl.viewport(0, 0, gl.drawing_buffer_width(), gl.drawing_buffer_height());
+    gl.delete_framebuffer(Some(&framebuffer));
+    gl.delete_texture(Some(&texture));
+    gl.delete_renderbuffer(Some(&depth));
+    result?;
+
+    // GL rows start at the bottom
+    png::flip_rows(&mut pixels, width as usize * 4);
+    Ok(Capture { width, height, pixels })
+}
+
+//--Fixed virtual time steps for capturing an animation frame by frame--
+//  <note>
+//      The first frame is at time 0, each following one `step` seconds later,
+//      however long rendering and encoding take.
+pub struct FrameSequence {
+    clock: FrameClock,
+    frames: u32,
+    index: u32,
+}
+
+impl FrameSequence {
+    //  <argument>
+    //      frames u32 : frames to capture
+    //      step   f64 : seconds between frames, e.g. 1 / 30
+    pub fn new(frames: u32, step: f64) -> Self {
+        Self {
+            clock: FrameClock::new(step),
+            frames,
+            index: 0,
+        }
+    }
+
+    //--Time of the next frame, None once every frame was handed out--
+    pub fn next_frame(&mut self) -> Option<FrameTime> {
+        if self.index >= self.frames {
+            return None;
+        }
+        self.index += 1;
+        Some(if self.index == 1 { self.clock.tick(0.) } else { self.clock.step() })
+    }
+
+    //--Frames handed out so far, the number of the current one starting at 1--
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.index >= self.frames
+    }
+}
+
+//--Uncompressed ZIP archive, to download many captures at once--
+//  <note>
+//      Entries are stored as is, PNGs are compressed already. Offsets are
+//      32 bits, archives must stay below 4 GiB.
+#[derive(Default)]
+pub struct ZipArchive {
+    bytes: Vec<u8>,
+    //  Central directory records, written by finish()
+    directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipArchive {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, filename: &str, data: &[u8]) {
+        let offset = self.bytes.len() as u32;
+        let crc = png::crc32(data);
+        // Version 2.0, no flags, stored, 1980-01-01 00:00
+        let common = |out: &mut Vec<u8>| {
+            for value in [20u16, 0, 0, 0, 0x21] {
+                out.extend_from_slice(&value.to_le_bytes());
+            }
+            out.extend_from_slice(&crc.to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            out.extend_from_slice(&(filename.len() as u16).to_le_bytes());
+            // No extra field
+            out.extend_from_slice(&0u16.to_le_bytes());
+        };
+
+        self.bytes.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        common(&mut self.bytes);
+        self.bytes.extend_from_slice(filename.as_bytes());
+        self.bytes.extend_from_slice(data);
+
+        self.directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        // Made by version 2.0
+        self.directory.extend_from_slice(&20u16.to_le_bytes());
+        common(&mut self.directory);
+        // No comment, disk 0, no attributes
+        self.directory.extend_from_slice(&[0; 10]);
+        self.directory.extend_from_slice(&offset.to_le_bytes());
+        self.directory.extend_from_slice(filename.as_bytes());
+        self.entries += 1;
+    }
+
+    //--Append the central directory, the result is the file contents--
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = self.bytes;
+        let directory_offset = out.len() as u32;
+        out.extend_from_slice(&self.directory);
+        out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        // Single disk
+        out.extend_from_slice(&[0; 4]);
+        out.extend_from_slice(&self.entries.to_le_bytes());
+        out.extend_from_slice(&self.entries.to_le_bytes());
+        out.extend_from_slice(&(self.directory.len() as u32).to_le_bytes());
+        out.extend_from_slice(&directory_offset.to_le_bytes());
+        // No comment
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out
+    }
+}
+
+//--Save bytes through the browser's download prompt--
+//  <argument>
+//      bytes     &[u8] : file contents
+//      mime_type &str  : e.g. "image/png"
+//      filename  &str  : suggested name
+pub fn download(bytes: &[u8], mime_type: &str, filename: &str) -> Result<(), RenderError> {
+    let fail = |what: &str| RenderError::Resource(format!("failed to download {}: {}", filename, what));
+    let document = web_sys::window()
+        .and_then(|w| w.document())
+        .ok_or_else(|| RenderError::Context("document doesn't exist".into()))?;
+
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(bytes));
+    let options = web_sys::BlobPropertyBag::new();
+    options.set_type(mime_type);
+    let blob = web_sys::Blob::new_with_u8_array_sequence_and_options(&parts, &options).map_err(|_| fail("blob"))?;
+    let url = web_sys::Url::create_object_url_with_blob(&blob).map_err(|_| fail("object URL"))?;
+
+    let anchor = document
+        .create_element("a")
+        .map_err(|_| fail("link"))?
+        .dyn_into::<web_sys::HtmlAnchorElement>()
+        .map_err(|_| fail("link"))?;
+    anchor.set_href(&url);
+    anchor.set_download(filename);
+    anchor.click();
+    // The click starts the download synchronously, the URL isn't needed afterwards
+    let _ = web_sys::Url::revoke_object_url(&url);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    fn u16_at(bytes: &[u8], at: usize) -> usize {
+        u16::from_le_bytes(bytes[at..at + 2].try_into().unwrap()) as usize
+    }
+
+    fn u32_at(bytes: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn zip_directory_points_at_stored_entries() {
+        let files: [(&str, &[u8]); 3] = [("a.png", b"first"), ("frames/b.png", b""), ("c.png", &[0xff; 300])];
+        let mut archive = ZipArchive::new();
+        for (name, data) in files.iter() {
+            archive.add(name, data);
+        }
+        let zip = archive.finish();
+
+        // End of central directory record, without a comment it's the last 22 bytes
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(&zip, end), 0x0605_4b50);
+        assert_eq!(u16_at(&zip, end + 10), files.len());
+        let size = u32_at(&zip, end + 12) as usize;
+        let mut record = u32_at(&zip, end + 16) as usize;
+        assert_eq!(record + size, end);
+
+        for (name, data) in files.iter() {
+            assert_eq!(u32_at(&zip, record), 0x0201_4b50);
+            let crc = u32_at(&zip, record + 16);
+            let name_len = u16_at(&zip, record + 28);
+            assert_eq!(&zip[record + 46..record + 46 + name_len], name.as_bytes());
+            assert_eq!(crc, png::crc32(data));
+
+            // The local header repeats the entry and precedes the raw data
+            let local = u32_at(&zip, record + 42) as usize;
+            assert_eq!(u32_at(&zip, local), 0x0403_4b50);
+            assert_eq!(u16_at(&zip, local + 8), 0, "stored");
+            assert_eq!(u32_at(&zip, local + 14), crc);
+            assert_eq!(u32_at(&zip, local + 22) as usize, data.len());
+            let start = local + 30 + u16_at(&zip, local + 26);
+            assert_eq!(&zip[start..start + data.len()], *data);
+            record += 46 + name_len;
+        }
+    }
+}
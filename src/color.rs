@@ -0,0 +1,527 @@
+use crate::error::RenderError;
+
+//--sRGB transfer function, encoded 0..1 to linear light--
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+//--Inverse of srgb_to_linear--
+pub fn linear_to_srgb(c: f32) -> f32 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+//--sRGB encoded color with straight alpha--
+//  <note>
+//      Components are 0..1 as written in CSS, hex or a color picker.
+//      Shaders light in linear space, pass linear() to uniforms and
+//      vertex colors rather than the raw components.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rgba {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Default for Rgba {
+    fn default() -> Self {
+        Self::WHITE
+    }
+}
+
+impl From<[f32; 4]> for Rgba {
+    fn from(c: [f32; 4]) -> Self {
+        Self::new(c[0], c[1], c[2], c[3])
+    }
+}
+
+impl From<Rgba> for [f32; 4] {
+    fn from(c: Rgba) -> Self {
+        c.to_array()
+    }
+}
+
+impl Rgba {
+    pub const WHITE: Self = Self::new(1., 1., 1., 1.);
+    pub const BLACK: Self = Self::new(0., 0., 0., 1.);
+    pub const TRANSPARENT: Self = Self::new(0., 0., 0., 0.);
+
+    pub const fn new(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Self { r, g, b, a }
+    }
+
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+        Self::new(r, g, b, 1.)
+    }
+
+    pub fn to_array(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+
+    pub fn with_alpha(self, a: f32) -> Self {
+        Self { a, ..self }
+    }
+
+    //--Components clamped to 0..1--
+    pub fn clamped(&self) -> Self {
+        Self::new(
+            self.r.clamp(0., 1.),
+            self.g.clamp(0., 1.),
+            self.b.clamp(0., 1.),
+            self.a.clamp(0., 1.),
+        )
+    }
+
+    //--Linear light RGBA for shader uniforms and vertex colors, alpha is unchanged--
+    pub fn linear(&self) -> [f32; 4] {
+        [srgb_to_linear(self.r), srgb_to_linear(self.g), srgb_to_linear(self.b), self.a]
+    }
+
+    pub fn from_linear(c: &[f32; 4]) -> Self {
+        Self::new(linear_to_srgb(c[0]), linear_to_srgb(c[1]), linear_to_srgb(c[2]), c[3])
+    }
+
+    //--Create color from hue, saturation and value--
+    //  <argument>
+    //      h f32 : hue in degrees, wrapped into 0..360 so negative hues work
+    //      s f32 : saturation, clamped to 0..1
+    //      v f32 : value, clamped to 0..1
+    //      a f32 : alpha, clamped to 0..1
+    pub fn from_hsv(h: f32, s: f32, v: f32, a: f32) -> Self {
+        let s = s.clamp(0., 1.);
+        let v = v.clamp(0., 1.);
+        let c = v * s;
+        let (r, g, b) = hue_rgb(h, c);
+        let m = v - c;
+        Self::new(r + m, g + m, b + m, a.clamp(0., 1.))
+    }
+
+    //--Hue in degrees 0..360, saturation and value 0..1--
+    //  <note>
+    //      Grays report a hue of 0.
+    pub fn to_hsv(self) -> (f32, f32, f32) {
+        let (max, min, h) = self.max_min_hue();
+        let s = if max > 0. { (max - min) / max } else { 0. };
+        (h, s, max)
+    }
+
+    //--Create color from hue, saturation and lightness--
+    //  <argument>
+    //      h f32 : hue in degrees, wrapped into 0..360
+    //      s f32 : saturation, clamped to 0..1
+    //      l f32 : lightness, clamped to 0..1
+    //      a f32 : alpha, clamped to 0..1
+    pub fn from_hsl(h: f32, s: f32, l: f32, a: f32) -> Self {
+        let s = s.clamp(0., 1.);
+        let l = l.clamp(0., 1.);
+        let c = (1. - (2. * l - 1.).abs()) * s;
+        let (r, g, b) = hue_rgb(h, c);
+        let m = l - c / 2.;
+        Self::new(r + m, g + m, b + m, a.clamp(0., 1.))
+    }
+
+    //--Hue in degrees 0..360, saturation and lightness 0..1--
+    pub fn to_hsl(self) -> (f32, f32, f32) {
+        let (max, min, h) = self.max_min_hue();
+        let l = (max + min) / 2.;
+        let d = max - min;
+        let s = if d > 0. { d / (1. - (2. * l - 1.).abs()) } else { 0. };
+        (h, s.min(1.), l)
+    }
+
+    fn max_min_hue(&self) -> (f32, f32, f32) {
+        let max = self.r.max(self.g).max(self.b);
+        let min = self.r.min(self.g).min(self.b);
+        let d = max - min;
+        let h = if d <= 0. {
+            0.
+        } else if max == self.r {
+            60. * ((self.g - self.b) / d).rem_euclid(6.)
+        } else if max == self.g {
+            60. * ((self.b - self.r) / d + 2.)
+        } else {
+            60. * ((self.r - self.g) / d + 4.)
+        };
+        (max, min, h)
+    }
+
+    //--Parse #rgb, #rgba, #rrggbb or #rrggbbaa, the # is optional--
+    pub fn from_hex(hex: &str) -> Result<Self, RenderError> {
+        let digits = hex.trim().trim_start_matches('#');
+        let invalid = || RenderError::Resource(format!("invalid hex color {:?}", hex));
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let nibble = |i: usize| u8::from_str_radix(&digits[i..i + 1], 16).map(|v| v * 17);
+        let byte = |i: usize| u8::from_str_radix(&digits[i..i + 2], 16);
+        let channels = match digits.len() {
+            3 | 4 => (0..digits.len()).map(nibble).collect::<Result<Vec<u8>, _>>(),
+            6 | 8 => (0..digits.len() / 2).map(|i| byte(i * 2)).collect::<Result<Vec<u8>, _>>(),
+            _ => return Err(invalid()),
+        }
+        .map_err(|_| invalid())?;
+        let alpha = channels.get(3).copied().unwrap_or(255);
+        Ok(Self::from_bytes([channels[0], channels[1], channels[2], alpha]))
+    }
+
+    //--#rrggbb, or #rrggbbaa when not opaque--
+    pub fn to_hex(self) -> String {
+        let [r, g, b, a] = self.to_bytes();
+        if a == 255 {
+            format!("#{:02x}{:02x}{:02x}", r, g, b)
+        } else {
+            format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a)
+        }
+    }
+
+    pub fn from_bytes(c: [u8; 4]) -> Self {
+        Self::new(c[0] as f32 / 255., c[1] as f32 / 255., c[2] as f32 / 255., c[3] as f32 / 255.)
+    }
+
+    //--Components clamped and rounded to 8 bits--
+    pub fn to_bytes(self) -> [u8; 4] {
+        let byte = |c: f32| (c.clamp(0., 1.) * 255.).round() as u8;
+        [byte(self.r), byte(self.g), byte(self.b), byte(self.a)]
+    }
+
+    //--Parse a CSS color--
+    //  <note>
+    //      Accepts hex, rgb()/rgba(), hsl()/hsla() with comma or space separated
+    //      arguments, and the basic named colors.
+    pub fn parse_css(css: &str) -> Result<Self, RenderError> {
+        let s = css.trim().to_ascii_lowercase();
+        let invalid = || RenderError::Resource(format!("invalid CSS color {:?}", css));
+        if s.starts_with('#') {
+            return Self::from_hex(&s);
+        }
+        if let Some(&(_, hex)) = NAMED_COLORS.iter().find(|(name, _)| *name == s) {
+            return Self::from_hex(hex);
+        }
+
+        let open = s.find('(').ok_or_else(invalid)?;
+        if !s.ends_with(')') {
+            return Err(invalid());
+        }
+        let function = s[..open].trim();
+        let args: Vec<&str> = s[open + 1..s.len() - 1]
+            .split(|c: char| c == ',' || c == '/' || c.is_whitespace())
+            .filter(|a| !a.is_empty())
+            .collect();
+        if args.len() != 3 && args.len() != 4 {
+            return Err(invalid());
+        }
+        let alpha = match args.get(3) {
+            Some(a) => css_number(a, 1.).ok_or_else(invalid)?,
+            None => 1.,
+        };
+        match function {
+            "rgb" | "rgba" => {
+                let mut c = [0.; 3];
+                for (c, arg) in c.iter_mut().zip(args.iter()) {
+                    *c = css_number(arg, 255.).ok_or_else(invalid)? / 255.;
+                }
+                Ok(Self::new(c[0], c[1], c[2], alpha).clamped())
+            }
+            "hsl" | "hsla" => {
+                let h = css_number(args[0].trim_end_matches("deg"), 1.).ok_or_else(invalid)?;
+                // Percentages, bare numbers count as percent too
+                let s = css_number(args[1], 100.).ok_or_else(invalid)? / 100.;
+                let l = css_number(args[2], 100.).ok_or_else(invalid)? / 100.;
+                Ok(Self::from_hsl(h, s, l, alpha.clamp(0., 1.)))
+            }
+            _ => Err(invalid()),
+        }
+    }
+
+    //--Blend in linear light--
+    pub fn mix(&self, other: &Rgba, t: f32) -> Self {
+        let a = self.linear();
+        let b = other.linear();
+        Self::from_linear(&[0, 1, 2, 3].map(|i| a[i] + (b[i] - a[i]) * t))
+    }
+}
+
+//--RGB of a fully saturated hue with chroma `c`, before adding the minimum--
+fn hue_rgb(h: f32, c: f32) -> (f32, f32, f32) {
+    let h = h.rem_euclid(360.) / 60.;
+    let x = c * (1. - (h % 2. - 1.).abs());
+    match h as u32 {
+        0 => (c, x, 0.),
+        1 => (x, c, 0.),
+        2 => (0., c, x),
+        3 => (0., x, c),
+        4 => (x, 0., c),
+        _ => (c, 0., x),
+    }
+}
+
+//--Number or percentage, `percent_of` is what 100% maps to--
+fn css_number(arg: &str, percent_of: f32) -> Option<f32> {
+    match arg.strip_suffix('%') {
+        Some(p) => p.parse::<f32>().ok().map(|p| p / 100. * percent_of),
+        None => arg.parse::<f32>().ok(),
+    }
+}
+
+const NAMED_COLORS: [(&str, &str); 21] = [
+    ("transparent", "#00000000"),
+    ("black", "#000000"),
+    ("white", "#ffffff"),
+    ("red", "#ff0000"),
+    ("lime", "#00ff00"),
+    ("green", "#008000"),
+    ("blue", "#0000ff"),
+    ("yellow", "#ffff00"),
+    ("cyan", "#00ffff"),
+    ("aqua", "#00ffff"),
+    ("magenta", "#ff00ff"),
+    ("fuchsia", "#ff00ff"),
+    ("gray", "#808080"),
+    ("grey", "#808080"),
+    ("silver", "#c0c0c0"),
+    ("maroon", "#800000"),
+    ("olive", "#808000"),
+    ("navy", "#000080"),
+    ("purple", "#800080"),
+    ("teal", "#008080"),
+    ("orange", "#ffa500"),
+];
+
+//--Colors at positions 0..1, sampled in linear light--
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gradient {
+    stops: Vec<(f32, Rgba)>,
+}
+
+impl Gradient {
+    //--Evenly spaced stops--
+    pub fn new(colors: &[Rgba]) -> Self {
+        let n = colors.len().max(2) - 1;
+        Self {
+            stops: colors.iter().enumerate().map(|(i, c)| (i as f32 / n as f32, *c)).collect(),
+        }
+    }
+
+    //--Stops at explicit positions, sorted by position--
+    pub fn with_stops(stops: &[(f32, Rgba)]) -> Self {
+        let mut stops = stops.to_vec();
+        stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+        Self { stops }
+    }
+
+    //--Color at `t`, the end colors extend past the first and last stop--
+    pub fn sample(&self, t: f32) -> Rgba {
+        let (first, last) = match (self.stops.first(), self.stops.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => return Rgba::BLACK,
+        };
+        if t <= first.0 {
+            return first.1;
+        }
+        if t >= last.0 {
+            return last.1;
+        }
+        let i = self.stops.iter().position(|(p, _)| *p > t).unwrap_or(self.stops.len() - 1);
+        let (p0, c0) = &self.stops[i - 1];
+        let (p1, c1) = &self.stops[i];
+        c0.mix(c1, (t - p0) / (p1 - p0))
+    }
+}
+
+//--Scalar to color mapping for data visualization--
+#[derive(Clone, Debug, PartialEq)]
+pub enum Colormap {
+    //  Perceptually uniform, dark blue to yellow
+    Viridis,
+    //  Improved rainbow, dark blue through green to dark red
+    Turbo,
+    Gradient(Gradient),
+}
+
+impl Colormap {
+    //--Color at `t`, clamped to 0..1--
+    pub fn sample(&self, t: f32) -> Rgba {
+        let t = t.clamp(0., 1.);
+        match self {
+            Colormap::Viridis => {
+                // Polynomial fit of matplotlib's viridis
+                let c = evaluate(&VIRIDIS, t);
+                Rgba::rgb(c[0], c[1], c[2]).clamped()
+            }
+            Colormap::Turbo => {
+                // Polynomial approximation published with the Turbo colormap
+                let c = evaluate(&TURBO, t);
+                Rgba::rgb(c[0], c[1], c[2]).clamped()
+            }
+            Colormap::Gradient(gradient) => gradient.sample(t),
+        }
+    }
+
+    //--Map one scalar per vertex to a flat linear RGBA color attribute--
+    //  <argument>
+    //      values &[f32]              : one value per vertex
+    //      range  Option<(f32, f32)>  : value mapped to 0 and 1, the values' min and max without one
+    pub fn vertex_colors(&self, values: &[f32], range: Option<(f32, f32)>) -> Vec<f32> {
+        let (min, max) = range.unwrap_or_else(|| {
+            values
+                .iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(lo, hi), &v| (lo.min(v), hi.max(v)))
+        });
+        let span = if max > min { max - min } else { 1. };
+        let mut colors = Vec::with_capacity(values.len() * 4);
+        for &v in values {
+            colors.extend_from_slice(&self.sample((v - min) / span).linear());
+        }
+        colors
+    }
+}
+
+//  Polynomial coefficients per channel, lowest power first
+const VIRIDIS: [[f32; 3]; 7] = [
+    [0.277_727_33, 0.005_407_344_5, 0.334_099_8],
+    [0.105_093_04, 1.404_613_5, 1.384_590_2],
+    [-0.330_861_83, 0.214_847_56, 0.095_095_16],
+    [-4.634_230_6, -5.799_101, -19.332_441],
+    [6.228_27, 14.179_933, 56.690_55],
+    [4.776_385, -13.745_145, -65.353_03],
+    [-5.435_456, 4.645_852_6, 26.312_435],
+];
+
+const TURBO: [[f32; 3]; 6] = [
+    [0.135_721_38, 0.091_402_61, 0.106_673_3],
+    [4.615_392_6, 2.194_188_4, 12.641_946],
+    [-42.660_324, 4.842_966_6, -60.582_05],
+    [132.131_08, -14.185_033, 110.362_77],
+    [-152.942_4, 4.277_299, -89.903_11],
+    [59.286_38, 2.829_566, 27.348_25],
+];
+
+fn evaluate(coefficients: &[[f32; 3]], t: f32) -> [f32; 3] {
+    // Horner's scheme, highest power first
+    coefficients
+        .iter()
+        .rev()
+        .fold([0.; 3], |c, k| [c[0] * t + k[0], c[1] * t + k[1], c[2] * t + k[2]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn assert_close(a: Rgba, b: Rgba) {
+        let (a, b) = (a.to_array(), b.to_array());
+        for i in 0..4 {
+            assert!((a[i] - b[i]).abs() < EPSILON, "{:?} != {:?}", a, b);
+        }
+    }
+
+    //  Every 8-bit level plus the break points of the transfer function
+    fn levels() -> impl Iterator<Item = f32> {
+        (0..=255).map(|i| i as f32 / 255.).chain([0.04045, 0.0031308, 1.])
+    }
+
+    //  Grid over the RGB cube including grays and the primaries
+    fn cube() -> Vec<Rgba> {
+        let steps = [0., 0.1, 0.25, 0.5, 0.75, 0.9, 1.];
+        let mut colors = Vec::new();
+        for &r in &steps {
+            for &g in &steps {
+                for &b in &steps {
+                    colors.push(Rgba::rgb(r, g, b));
+                }
+            }
+        }
+        colors
+    }
+
+    #[test]
+    fn srgb_round_trips() {
+        for c in levels() {
+            assert!((linear_to_srgb(srgb_to_linear(c)) - c).abs() < EPSILON, "{}", c);
+            assert!((srgb_to_linear(linear_to_srgb(c)) - c).abs() < EPSILON, "{}", c);
+        }
+        assert_eq!(srgb_to_linear(0.), 0.);
+        assert!((srgb_to_linear(1.) - 1.).abs() < 1e-6);
+        // Mid gray is about a fifth of the light
+        assert!((srgb_to_linear(0.5) - 0.21404).abs() < EPSILON);
+
+        let color = Rgba::new(0.2, 0.5, 0.8, 0.3);
+        assert_close(Rgba::from_linear(&color.linear()), color);
+    }
+
+    #[test]
+    fn hsv_round_trips() {
+        for color in cube() {
+            let (h, s, v) = color.to_hsv();
+            assert!((0. ..360.).contains(&h) && (0. ..=1.).contains(&s) && (0. ..=1.).contains(&v));
+            assert_close(Rgba::from_hsv(h, s, v, 1.), color);
+        }
+        assert_close(Rgba::from_hsv(120., 1., 1., 1.), Rgba::rgb(0., 1., 0.));
+        // Hues wrap
+        assert_close(Rgba::from_hsv(-120., 1., 1., 1.), Rgba::from_hsv(240., 1., 1., 1.));
+        assert_close(Rgba::from_hsv(720., 1., 0.5, 1.), Rgba::rgb(0.5, 0., 0.));
+    }
+
+    #[test]
+    fn hsl_round_trips() {
+        for color in cube() {
+            let (h, s, l) = color.to_hsl();
+            assert!((0. ..360.).contains(&h) && (0. ..=1.).contains(&s) && (0. ..=1.).contains(&l));
+            assert_close(Rgba::from_hsl(h, s, l, 1.), color);
+        }
+        assert_close(Rgba::from_hsl(0., 1., 0.5, 1.), Rgba::rgb(1., 0., 0.));
+        assert_close(Rgba::from_hsl(210., 0.5, 0.25, 1.), Rgba::rgb(0.125, 0.25, 0.375));
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        for c in 0..=255u8 {
+            let bytes = [c, 255 - c, c / 2, c];
+            let color = Rgba::from_bytes(bytes);
+            assert_eq!(Rgba::from_hex(&color.to_hex()).unwrap().to_bytes(), bytes);
+        }
+        assert_eq!(Rgba::from_hex("#ff8000").unwrap().to_hex(), "#ff8000");
+        assert_eq!(Rgba::from_hex("f80").unwrap().to_bytes(), [255, 136, 0, 255]);
+        assert_eq!(Rgba::from_hex("#f808").unwrap().to_bytes(), [255, 136, 0, 136]);
+        assert_eq!(Rgba::from_hex(" #11223344 ").unwrap().to_bytes(), [0x11, 0x22, 0x33, 0x44]);
+        for bad in ["", "#12", "#12345", "#gggggg", "#1234567", "#ffé"] {
+            assert!(Rgba::from_hex(bad).is_err(), "{:?}", bad);
+        }
+    }
+
+    #[test]
+    fn parses_css() {
+        let orange = Rgba::rgb(1., 165. / 255., 0.);
+        for css in [
+            "orange",
+            "  ORANGE ",
+            "#ffa500",
+            "rgb(255, 165, 0)",
+            "rgb(255 165 0)",
+            "rgba(100%, 64.70588%, 0%, 1)",
+            "rgb(255 165 0 / 100%)",
+            "hsl(38.82353, 100%, 50%)",
+            "hsl(38.82353deg 100 50)",
+        ] {
+            assert_close(Rgba::parse_css(css).unwrap(), orange);
+        }
+        assert_close(Rgba::parse_css("rgba(0, 0, 255, 0.5)").unwrap(), Rgba::new(0., 0., 1., 0.5));
+        assert_close(Rgba::parse_css("hsla(120, 100%, 25%, 50%)").unwrap(), Rgba::new(0., 0.5, 0., 0.5));
+        assert_close(Rgba::parse_css("transparent").unwrap(), Rgba::TRANSPARENT);
+        // Out of range components are clamped
+        assert_close(Rgba::parse_css("rgb(300, -5, 0)").unwrap(), Rgba::rgb(1., 0., 0.));
+        for bad in ["", "rgb(1, 2)", "rgb(1, 2, 3", "cmyk(1, 2, 3)", "rgb(a, b, c)", "notacolor"] {
+            assert!(Rgba::parse_css(bad).is_err(), "{:?}", bad);
+        }
+    }
+}
@@ -0,0 +1,305 @@
+use crate::error::RenderError;
+use std::cell::RefCell;
+use std::rc::{Rc, Weak};
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{HtmlCanvasElement, WebglLoseContext};
+
+//--GPU resource that can be rebuilt from data it keeps on the CPU--
+//  <note>
+//      Generic over the context so the restore bookkeeping can run natively
+//      against a mock backend.
+pub trait Restorable<C = GL> {
+    //--Recreate the GPU objects on a fresh context--
+    fn restore(&self, gl: &C) -> Result<(), RenderError>;
+}
+
+//--Weak list of every resource living on one context--
+//  <note>
+//      Resources dropped by their owners disappear from the list on the next
+//      track or restore, so replacing resources doesn't grow it.
+pub struct ResourceTracker<C = GL> {
+    resources: Vec<Weak<dyn Restorable<C>>>,
+}
+
+impl<C> ResourceTracker<C> {
+    pub fn new() -> Self {
+        Self { resources: Vec::new() }
+    }
+
+    pub fn track<T: Restorable<C> + 'static>(&mut self, resource: &Rc<T>) {
+        let weak: Weak<dyn Restorable<C>> = Rc::downgrade(resource) as Weak<dyn Restorable<C>>;
+        self.resources.retain(|r| r.strong_count() > 0);
+        self.resources.push(weak);
+    }
+
+    //--Recreate every live resource--
+    //  <return> usize  number of resources restored
+    //  <note>
+    //      Keeps going after a failure so one broken shader doesn't take the
+    //      whole scene down, the first error is returned.
+    pub fn restore_all(&mut self, gl: &C) -> Result<usize, RenderError> {
+        self.resources.retain(|r| r.strong_count() > 0);
+        let mut first_error = None;
+        let mut restored = 0;
+        for resource in self.resources.iter().filter_map(|r| r.upgrade()) {
+            match resource.restore(gl) {
+                Ok(()) => restored += 1,
+                Err(e) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(restored),
+        }
+    }
+
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ContextEvent {
+    Lost,
+    Restored,
+}
+
+//--Lost/restored bookkeeping, independent of the browser--
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ContextState {
+    lost: bool,
+    restore_pending: bool,
+}
+
+impl ContextState {
+    pub fn handle_event(&mut self, event: ContextEvent) {
+        match event {
+            ContextEvent::Lost => {
+                self.lost = true;
+                self.restore_pending = false;
+            }
+            ContextEvent::Restored => {
+                self.lost = false;
+                self.restore_pending = true;
+            }
+        }
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.lost
+    }
+
+    //--True once after each restore, the caller recreates its resources then--
+    pub fn take_restored(&mut self) -> bool {
+        std::mem::replace(&mut self.restore_pending, false)
+    }
+}
+
+type Handler = Rc<RefCell<Option<Box<dyn FnMut(ContextEvent)>>>>;
+type Listener = (&'static str, Closure<dyn FnMut(web_sys::Event)>);
+
+//--Watches a canvas for webglcontextlost and webglcontextrestored--
+//  <note>
+//      Poll is_lost()/take_restored() from the frame loop, or install a handler
+//      to pause and resume the loop. The lost event is prevented so the
+//      browser is allowed to restore the context.
+pub struct ContextMonitor {
+    canvas: HtmlCanvasElement,
+    state: Rc<RefCell<ContextState>>,
+    handler: Handler,
+    listeners: Vec<Listener>,
+    lose_context: Option<WebglLoseContext>,
+}
+
+impl ContextMonitor {
+    pub fn attach(canvas: &HtmlCanvasElement, gl: &GL) -> Result<Self, RenderError> {
+        let state = Rc::new(RefCell::new(ContextState::default()));
+        let handler: Handler = Rc::new(RefCell::new(None));
+
+        let mut listeners = Vec::new();
+        for &(name, event) in [
+            ("webglcontextlost", ContextEvent::Lost),
+            ("webglcontextrestored", ContextEvent::Restored),
+        ]
+        .iter()
+        {
+            let state = state.clone();
+            let handler = handler.clone();
+            let closure = Closure::wrap(Box::new(move |e: web_sys::Event| {
+                if event == ContextEvent::Lost {
+                    e.prevent_default();
+                }
+                state.borrow_mut().handle_event(event);
+                if let Some(handler) = handler.borrow_mut().as_mut() {
+                    handler(event);
+                }
+            }) as Box<dyn FnMut(web_sys::Event)>);
+            canvas
+                .add_event_listener_with_callback(name, closure.as_ref().unchecked_ref())
+                .map_err(|_| RenderError::Context(format!("failed to listen for {}", name)))?;
+            listeners.push((name, closure));
+        }
+
+        // Extensions can't be queried while lost, so fetch it up front
+        let lose_context = gl
+            .get_extension("WEBGL_lose_context")
+            .ok()
+            .flatten()
+            .map(|ext| ext.unchecked_into::<WebglLoseContext>());
+
+        Ok(Self {
+            canvas: canvas.clone(),
+            state,
+            handler,
+            listeners,
+            lose_context,
+        })
+    }
+
+    //--Call `handler` after the state was updated for each event--
+    pub fn set_handler<F: FnMut(ContextEvent) + 'static>(&self, handler: F) {
+        *self.handler.borrow_mut() = Some(Box::new(handler));
+    }
+
+    pub fn is_lost(&self) -> bool {
+        self.state.borrow().is_lost()
+    }
+
+    pub fn take_restored(&self) -> bool {
+        self.state.borrow_mut().take_restored()
+    }
+
+    //--Force a context loss through WEBGL_lose_context, for testing--
+    pub fn simulate_loss(&self) -> Result<(), RenderError> {
+        self.lose_context
+            .as_ref()
+            .ok_or_else(|| RenderError::Context("WEBGL_lose_context is not supported".into()))?
+            .lose_context();
+        Ok(())
+    }
+
+    //--Restore a context lost through simulate_loss--
+    pub fn simulate_restore(&self) -> Result<(), RenderError> {
+        self.lose_context
+            .as_ref()
+            .ok_or_else(|| RenderError::Context("WEBGL_lose_context is not supported".into()))?
+            .restore_context();
+        Ok(())
+    }
+}
+
+impl Drop for ContextMonitor {
+    fn drop(&mut self) {
+        for (name, closure) in self.listeners.iter() {
+            let _ = self
+                .canvas
+                .remove_event_listener_with_callback(name, closure.as_ref().unchecked_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //  Records restores in call order and fails the resources named in `broken`
+    #[derive(Default)]
+    struct MockContext {
+        log: RefCell<Vec<&'static str>>,
+        broken: Vec<&'static str>,
+    }
+
+    struct MockResource(&'static str);
+
+    impl Restorable<MockContext> for MockResource {
+        fn restore(&self, gl: &MockContext) -> Result<(), RenderError> {
+            gl.log.borrow_mut().push(self.0);
+            if gl.broken.contains(&self.0) {
+                return Err(RenderError::Shader(format!("{} failed", self.0)));
+            }
+            Ok(())
+        }
+    }
+
+    fn live(tracker: &ResourceTracker<MockContext>) -> usize {
+        tracker.resources.iter().filter(|r| r.strong_count() > 0).count()
+    }
+
+    fn track_all(tracker: &mut ResourceTracker<MockContext>, names: &[&'static str]) -> Vec<Rc<MockResource>> {
+        names
+            .iter()
+            .map(|&name| {
+                let resource = Rc::new(MockResource(name));
+                tracker.track(&resource);
+                resource
+            })
+            .collect()
+    }
+
+    #[test]
+    fn restores_in_tracking_order() {
+        let mut tracker = ResourceTracker::new();
+        let _resources = track_all(&mut tracker, &["texture", "program", "mesh"]);
+        let gl = MockContext::default();
+        assert_eq!(tracker.restore_all(&gl).unwrap(), 3);
+        assert_eq!(*gl.log.borrow(), ["texture", "program", "mesh"]);
+    }
+
+    #[test]
+    fn skips_dropped_resources() {
+        let mut tracker = ResourceTracker::new();
+        let mut resources = track_all(&mut tracker, &["texture", "program", "mesh"]);
+        resources.remove(1);
+        assert_eq!(live(&tracker), 2);
+        let gl = MockContext::default();
+        assert_eq!(tracker.restore_all(&gl).unwrap(), 2);
+        assert_eq!(*gl.log.borrow(), ["texture", "mesh"]);
+
+        resources.clear();
+        assert_eq!(live(&tracker), 0);
+        assert_eq!(tracker.restore_all(&gl).unwrap(), 0);
+
+        // Replacing a resource over and over doesn't pile up dead entries,
+        // only the one dropped since the last track is left
+        for _ in 0..100 {
+            resources = track_all(&mut tracker, &["label"]);
+            assert!(tracker.resources.len() <= 2);
+        }
+        resources.clear();
+        let _mesh = track_all(&mut tracker, &["mesh"]);
+        assert_eq!(tracker.resources.len(), 1);
+    }
+
+    #[test]
+    fn keeps_restoring_after_a_failure() {
+        let mut tracker = ResourceTracker::new();
+        let _resources = track_all(&mut tracker, &["texture", "program", "shadow", "mesh"]);
+        let gl = MockContext {
+            broken: vec!["program", "shadow"],
+            ..MockContext::default()
+        };
+        match tracker.restore_all(&gl) {
+            Err(RenderError::Shader(message)) => assert_eq!(message, "program failed"),
+            other => panic!("expected the first shader error, got {:?}", other),
+        }
+        assert_eq!(*gl.log.borrow(), ["texture", "program", "shadow", "mesh"]);
+
+        // The failed resources stay tracked for the next restore
+        assert_eq!(live(&tracker), 4);
+        assert_eq!(tracker.restore_all(&MockContext::default()).unwrap(), 4);
+    }
+
+    #[test]
+    fn reports_each_restore_once() {
+        let mut state = ContextState::default();
+        assert!(!state.take_restored());
+        state.handle_event(ContextEvent::Lost);
+        assert!(state.is_lost());
+        assert!(!state.take_restored());
+        state.handle_event(ContextEvent::Restored);
+        assert!(!state.is_lost());
+        assert!(state.take_restored());
+        assert!(!state.take_restored());
+    }
+}
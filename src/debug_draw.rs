@@ -0,0 +1,280 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::context::ResourceTracker;
+use crate::error::RenderError;
+use crate::mat_4::Matrix;
+use crate::material::{ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::render_state::{CullFace, RenderState, StateCache, Winding};
+use crate::vec_3;
+use std::rc::Rc;
+use web_sys::WebGlBuffer;
+use web_sys::WebGlRenderingContext as GL;
+
+//  Floats per vertex: position and RGBA color
+const VERTEX_FLOATS: usize = 7;
+//  Segments of a full circle in sphere()
+const CIRCLE_SEGMENTS: usize = 32;
+
+pub const RED: [f32; 4] = [1., 0.2, 0.2, 1.];
+pub const GREEN: [f32; 4] = [0.2, 1., 0.2, 1.];
+pub const BLUE: [f32; 4] = [0.3, 0.5, 1., 1.];
+pub const YELLOW: [f32; 4] = [1., 1., 0.2, 1.];
+pub const GRAY: [f32; 4] = [0.5, 0.5, 0.5, 1.];
+
+//--Immediate-mode line drawing for debugging--
+//  <note>
+//      Shapes are queued in world space during a frame and drawn with one
+//      GL::LINES call by flush(), which empties the queue. Colors are written
+//      as given. flush() changes GL state behind the renderer, invalidate the
+//      renderer's state afterwards.
+pub struct DebugDraw {
+    //  Line list, VERTEX_FLOATS per vertex
+    vertices: Vec<f32>,
+    program: Rc<ShaderProgram>,
+    //  Dynamic buffer and how many floats it has room for
+    buffer: Option<(WebGlBuffer, usize)>,
+    state: RenderState,
+    state_cache: StateCache,
+}
+
+impl DebugDraw {
+    pub fn new(gl: &GL) -> Result<Self, RenderError> {
+        Ok(Self {
+            vertices: Vec::new(),
+            program: Rc::new(ShaderProgram::new(
+                gl,
+                include_str!("shader/debug.vert"),
+                include_str!("shader/debug.frag"),
+            )?),
+            buffer: None,
+            state: RenderState::OPAQUE.with_cull_face(CullFace::None, Winding::CounterClockwise),
+            state_cache: StateCache::new(),
+        })
+    }
+
+    //--Register the program for context restore--
+    //  <note>
+    //      Call invalidate() after a restore as well, the buffer is rebuilt on the next flush.
+    pub fn track(&self, resources: &mut ResourceTracker) {
+        resources.track(&self.program);
+    }
+
+    //--Forget the buffer and cached state, after a context restore--
+    pub fn invalidate(&mut self) {
+        self.buffer = None;
+        self.state_cache.invalidate();
+    }
+
+    //--Hide lines behind geometry, on by default; off draws them on top--
+    pub fn set_depth_test(&mut self, enabled: bool) -> &mut Self {
+        self.state = self.state.with_depth(enabled, enabled, self.state.depth_func);
+        self
+    }
+
+    //--Queued line segments--
+    pub fn len(&self) -> usize {
+        self.vertices.len() / (VERTEX_FLOATS * 2)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub fn line(&mut self, a: &[f32; 3], b: &[f32; 3], color: [f32; 4]) -> &mut Self {
+        for p in [a, b] {
+            self.vertices.extend_from_slice(p);
+            self.vertices.extend_from_slice(&color);
+        }
+        self
+    }
+
+    //--Line with a four-pronged head at `to`--
+    pub fn arrow(&mut self, from: &[f32; 3], to: &[f32; 3], color: [f32; 4]) -> &mut Self {
+        let d = vec_3::sub(to, from);
+        let length = vec_3::length(&d);
+        self.line(from, to, color);
+        if length == 0. {
+            return self;
+        }
+        let dir = vec_3::scale(&d, 1. / length);
+        // Any vector not parallel to the shaft gives the head's plane
+        let helper = if dir[1].abs() < 0.9 { [0., 1., 0.] } else { [1., 0., 0.] };
+        let side = vec_3::normalize(&vec_3::cross(&dir, &helper));
+        let up = vec_3::cross(&side, &dir);
+        let head = length * 0.15;
+        let base = vec_3::sub(to, &vec_3::scale(&dir, head));
+        for prong in [side, up, vec_3::scale(&side, -1.), vec_3::scale(&up, -1.)] {
+            self.line(to, &vec_3::add(&base, &vec_3::scale(&prong, head * 0.4)), color);
+        }
+        self
+    }
+
+    //--Box edges, transformed by a model matrix so rotated boxes stay boxes--
+    pub fn aabb(&mut self, aabb: &Aabb, model: &Matrix, color: [f32; 4]) -> &mut Self {
+        let corner = |i: usize| {
+            let pick = |axis: usize| if i & (1 << axis) != 0 { aabb.max[axis] } else { aabb.min[axis] };
+            model.transform_point(&[pick(0), pick(1), pick(2)])
+        };
+        self.cube(corner, color)
+    }
+
+    //--Three great circles around the sphere--
+    pub fn sphere(&mut self, sphere: &BoundingSphere, color: [f32; 4]) -> &mut Self {
+        let point = |axes: (usize, usize), angle: f32| {
+            let mut p = sphere.center;
+            p[axes.0] += sphere.radius * angle.cos();
+            p[axes.1] += sphere.radius * angle.sin();
+            p
+        };
+        for axes in [(0, 1), (1, 2), (2, 0)] {
+            for i in 0..CIRCLE_SEGMENTS {
+                let step = std::f32::consts::PI * 2. / CIRCLE_SEGMENTS as f32;
+                self.line(&point(axes, i as f32 * step), &point(axes, (i + 1) as f32 * step), color);
+            }
+        }
+        self
+    }
+
+    //--Edges of the volume a view projection matrix sees, e.g. another camera's--
+    pub fn frustum(&mut self, view_projection: &Matrix, color: [f32; 4]) -> Result<&mut Self, RenderError> {
+        let mut inverse = *view_projection;
+        inverse
+            .inverse()
+            .map_err(|_| RenderError::Math("view projection matrix is not invertible".into()))?;
+        let corner = |i: usize| {
+            let ndc = |axis: usize| if i & (1 << axis) != 0 { 1. } else { -1. };
+            inverse.transform_point(&[ndc(0), ndc(1), ndc(2)])
+        };
+        Ok(self.cube(corner, color))
+    }
+
+    //--Twelve edges between eight corners indexed by their x, y, z bits--
+    fn cube(&mut self, corner: impl Fn(usize) -> [f32; 3], color: [f32; 4]) -> &mut Self {
+        let corners: Vec<[f32; 3]> = (0..8).map(corner).collect();
+        for i in 0..8 {
+            for axis in 0..3 {
+                let j = i | (1 << axis);
+                if j != i {
+                    self.line(&corners[i], &corners[j], color);
+                }
+            }
+        }
+        self
+    }
+
+    //--X, Y and Z axes of a transform in red, green and blue--
+    //  <note>
+    //      Drawn along the matrix' columns, so scale and handedness show up as-is.
+    pub fn axes(&mut self, matrix: &Matrix, size: f32) -> &mut Self {
+        let origin = matrix.get_translation();
+        for (axis, color) in [([size, 0., 0.], RED), ([0., size, 0.], GREEN), ([0., 0., size], BLUE)] {
+            let tip = vec_3::add(&origin, &matrix.transform_direction(&axis));
+            self.arrow(&origin, &tip, color);
+        }
+        self
+    }
+
+    //--Square grid on the XZ plane centered on the origin--
+    //  <argument>
+    //      size      f32 : edge length
+    //      divisions u32 : cells along each edge
+    pub fn grid(&mut self, size: f32, divisions: u32, color: [f32; 4]) -> &mut Self {
+        let half = size / 2.;
+        let divisions = divisions.max(1);
+        for i in 0..=divisions {
+            let t = -half + size * i as f32 / divisions as f32;
+            self.line(&[t, 0., -half], &[t, 0., half], color);
+            self.line(&[-half, 0., t], &[half, 0., t], color);
+        }
+        self
+    }
+
+    //--Vertex normals of a mesh as lines from each vertex--
+    //  <note>
+    //      Normals go through the inverse transpose of the model matrix like in
+    //      the shaders, so a bad transform shows as normals leaving the surface.
+    //      Meshes without 3-component position and normal attributes are skipped.
+    pub fn normals(&mut self, mesh: &Mesh, model: &Matrix, length: f32, color: [f32; 4]) -> &mut Self {
+        let attribute = |name: &str| mesh.attributes.iter().find(|a| a.name == name && a.size == 3);
+        let (positions, normals) = match (attribute("position"), attribute("normal")) {
+            (Some(p), Some(n)) => (&p.data, &n.data),
+            _ => return self,
+        };
+        let mut normal_matrix = *model;
+        if normal_matrix.inverse().is_err() {
+            return self;
+        }
+        normal_matrix.transpose();
+        for (p, n) in positions.chunks_exact(3).zip(normals.chunks_exact(3)) {
+            let start = model.transform_point(&[p[0], p[1], p[2]]);
+            let direction = vec_3::normalize(&normal_matrix.transform_direction(&[n[0], n[1], n[2]]));
+            self.line(&start, &vec_3::add(&start, &vec_3::scale(&direction, length)), color);
+        }
+        self
+    }
+
+    //--Draw every queued line and empty the queue--
+    pub fn flush(&mut self, gl: &GL, view: &Matrix, projection: &Matrix) -> Result<(), RenderError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        if let Err(e) = self.upload(gl) {
+            // Don't let lines pile up frame after frame
+            self.clear();
+            return Err(e);
+        }
+
+        let mut vp_matrix = *projection;
+        vp_matrix.multiply(view);
+        self.state_cache.apply(gl, &self.state);
+        gl.use_program(Some(&self.program.program()));
+        self.program.set_uniform(gl, "vpMatrix", &Uniform::Mat4(vp_matrix.get_value()));
+
+        let stride = (VERTEX_FLOATS * 4) as i32;
+        let mut bound = Vec::with_capacity(2);
+        for (name, size, offset) in [("position", 3, 0), ("color", 4, 12)] {
+            let location = self.program.attrib_location(gl, name);
+            if location < 0 {
+                continue;
+            }
+            let location = location as u32;
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(location, size, GL::FLOAT, false, stride, offset);
+            bound.push(location);
+        }
+        gl.draw_arrays(GL::LINES, 0, (self.len() * 2) as i32);
+        for location in bound {
+            gl.disable_vertex_attrib_array(location);
+        }
+
+        self.clear();
+        Ok(())
+    }
+
+    //--Copy the queue into the buffer, growing it in powers of two--
+    fn upload(&mut self, gl: &GL) -> Result<(), RenderError> {
+        let needed = self.vertices.len();
+        if self.buffer.as_ref().is_none_or(|(_, capacity)| *capacity < needed) {
+            let buffer = gl
+                .create_buffer()
+                .ok_or_else(|| RenderError::Buffer("failed to create debug line buffer".into()))?;
+            let capacity = needed.next_power_of_two();
+            gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
+            gl.buffer_data_with_i32(GL::ARRAY_BUFFER, (capacity * 4) as i32, GL::DYNAMIC_DRAW);
+            if let Some((old, _)) = self.buffer.replace((buffer, capacity)) {
+                gl.delete_buffer(Some(&old));
+            }
+        }
+        let (buffer, _) = self.buffer.as_ref().ok_or_else(|| RenderError::Buffer("debug line buffer is missing".into()))?;
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+        unsafe {
+            let view = js_sys::Float32Array::view(&self.vertices);
+            gl.buffer_sub_data_with_i32_and_array_buffer_view(GL::ARRAY_BUFFER, 0, &view);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,723 @@
+use crate::camera::{self, CameraController, OrbitController};
+use crate::canvas::ResponsiveCanvas;
+use crate::capture::{self, FrameSequence, ZipArchive};
+use crate::color::{Colormap, Gradient, Rgba};
+use crate::context::{ContextMonitor, ResourceTracker};
+use crate::debug_draw::{self, DebugDraw};
+use crate::error::{ErrorReporter, RenderError};
+use crate::frame_loop::{FrameLoop, FrameTime};
+use crate::input::{Input, InputState, MouseButton};
+use crate::instancing::{self, Instance, InstanceBatch};
+use crate::loader::MeshData;
+use crate::mat_4::Matrix;
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::particles::{ColorOverLife, Curve, Emitter, EmitterShape, ParticleSystem};
+use crate::quat;
+use crate::render_state::{
+    BlendMode, CompareFunc, CullFace, PolygonOffset, RenderState, Scissor, StencilOp, StencilState, Winding,
+};
+use crate::renderer::Renderer;
+use crate::scene::{Light, LightKind, NodeId, Renderable, Scene};
+use crate::shading;
+use crate::shapes;
+use crate::text::{Font, Label, LabelMode, LabelStyle, TextAlign, TextAnchor, TextLayout};
+use crate::vertex_cache;
+use crate::webgl;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+use web_sys::HtmlCanvasElement;
+use web_sys::WebGlRenderingContext as GL;
+
+//  Render states R cycles the torus through, see torus_state()
+const TORUS_STATES: [&str; 9] = [
+    "opaque",
+    "additive",
+    "multiply",
+    "premultiplied alpha",
+    "inside out",
+    "clockwise winding",
+    "depth only",
+    "left half",
+    "pushed back",
+];
+
+//  Written by the torus where it's drawn, the outline is kept out of it
+const OUTLINE_MASK: StencilState = StencilState {
+    func: CompareFunc::Always,
+    reference: 1,
+    read_mask: 0xff,
+    write_mask: 0xff,
+    fail: StencilOp::Keep,
+    depth_fail: StencilOp::Keep,
+    pass: StencilOp::Replace,
+};
+
+//--Spinning torus with sparks, moons and a title, driven by the keyboard--
+//  <note>
+//      B bursts sparks off the torus, C clears them, O adds a moon, K clears them,
+//      V cycles colormaps, X halves the triangles, R cycles render states,
+//      U outlines the torus, I shows the input readout, N/G/F/H/M toggle
+//      debug views, P saves a PNG and T a turntable ZIP.
+pub struct Demo {
+    gl: GL,
+    errors: ErrorReporter,
+    resources: ResourceTracker,
+    renderer: Renderer,
+    scene: Scene,
+    camera: NodeId,
+    torus: NodeId,
+    outline: NodeId,
+    //  Outline's mesh and material, on its node while U has it shown
+    shell: Renderable,
+    outlined: bool,
+    hud: NodeId,
+    //  Full resolution torus, V and recoloring start from it
+    torus_data: MeshData,
+    radii: Vec<f32>,
+    colormaps: [Colormap; 4],
+    colormap: usize,
+    torus_states: usize,
+    torus_stats: String,
+    sparks: ParticleSystem,
+    moons: Rc<InstanceBatch>,
+    readout: Rc<Label>,
+    debug: DebugDraw,
+    show_normals: bool,
+    show_bounds: bool,
+    frozen_frustum: Option<Matrix>,
+    lines_on_top: bool,
+    input: Input,
+    responsive: ResponsiveCanvas,
+    controller: OrbitController,
+    turntable: Option<(FrameSequence, ZipArchive)>,
+    context: ContextMonitor,
+}
+
+impl Demo {
+    //--Build the demo on a canvas and run it on its own animation loop--
+    pub fn start(canvas: &HtmlCanvasElement, errors: ErrorReporter) -> Result<(), JsValue> {
+        let mut demo = Self::new(canvas, errors)?;
+        FrameLoop::start(0., move |time| demo.frame(time));
+        Ok(())
+    }
+
+    fn new(canvas: &HtmlCanvasElement, errors: ErrorReporter) -> Result<Self, JsValue> {
+        let gl = webgl::get_webgl_context(canvas)?;
+        let mut resources = ResourceTracker::new();
+        let mut scene = Scene::new();
+
+        let program = Rc::new(ShaderProgram::new(
+            &gl,
+            include_str!("shader/vertex.vert"),
+            include_str!("shader/fragment.frag"),
+        )?);
+        resources.track(&program);
+        let torus_data = torus_data();
+        let radii = torus_data.positions.chunks_exact(3).map(|p| p[0].hypot(p[2])).collect();
+        let torus = scene.add_node("torus");
+        let outline = scene.add_node("outline");
+        scene.set_parent(outline, Some(torus))?;
+        scene.set_scale(outline, &[1.05, 1.05, 1.05]);
+        let mesh = Rc::new(Mesh::from_data(&gl, &torus_data)?);
+        resources.track(&mesh);
+        let outline_mesh = Rc::new(Mesh::from_data(&gl, &outline_data(&torus_data))?);
+        resources.track(&outline_mesh);
+        scene.node_mut(torus).unwrap().renderable = Some(Renderable {
+            mesh,
+            material: Rc::new(lit_material(&program, Rgba::rgb(0.1, 0.1, 0.1))),
+        });
+        let mut outline_material = lit_material(&program, Rgba::rgb(0.5, 0.3, 0.));
+        outline_material.set_state(RenderState::OVERLAY.with_stencil(Some(StencilState {
+            func: CompareFunc::NotEqual,
+            write_mask: 0,
+            pass: StencilOp::Keep,
+            ..OUTLINE_MASK
+        })));
+        let shell = Renderable {
+            mesh: outline_mesh,
+            material: Rc::new(outline_material),
+        };
+
+        let camera = scene.add_node("camera");
+        scene.node_mut(camera).unwrap().camera = Some(camera::Camera::perspective(45f32.to_radians(), 0.1, 100.));
+        let light = scene.add_node("light");
+        scene.set_rotation(light, &quat::from_rotation_arc(&[0., 0., 1.], &[-0.5, 0.5, 0.5]));
+        scene.node_mut(light).unwrap().light = Some(Light {
+            kind: LightKind::Directional,
+            color: [1., 1., 1.],
+            intensity: 1.,
+        });
+
+        let sparks = sparks(&gl)?;
+        sparks.track(&mut resources);
+        let node = scene.add_node("sparks");
+        scene.node_mut(node).unwrap().instances = Some(sparks.batch().clone());
+        let moons = moons(&gl, &mut resources)?;
+        let node = scene.add_node("moons");
+        scene.node_mut(node).unwrap().instances = Some(moons.clone());
+
+        let font = Rc::new(Font::builtin(&gl)?);
+        font.track(&mut resources);
+        let title = title(&gl, &font)?;
+        resources.track(&title);
+        let node = scene.add_node("title");
+        scene.set_translation(node, &[0., 3.5, 0.]);
+        scene.node_mut(node).unwrap().label = Some(title);
+        //Pinned to the top left corner by following the camera, see update_hud()
+        let readout = Rc::new(Label::new(
+            &gl,
+            &font,
+            "",
+            LabelStyle {
+                mode: LabelMode::Screen,
+                size: 16.,
+                align: TextAlign::Left,
+                anchor: TextAnchor::Top,
+                offset: [12., -12.],
+                depth_test: false,
+                ..Default::default()
+            },
+        )?);
+        resources.track(&readout);
+        let hud = scene.add_node("hud");
+        scene.set_parent(hud, Some(camera))?;
+
+        let mut renderer = Renderer::new();
+        let shading_program = Rc::new(shading::program(&gl)?);
+        resources.track(&shading_program);
+        renderer.set_shading_program(shading_program);
+        let debug = DebugDraw::new(&gl)?;
+        debug.track(&mut resources);
+
+        let mut demo = Self {
+            errors,
+            resources,
+            renderer,
+            scene,
+            camera,
+            torus,
+            outline,
+            shell,
+            outlined: false,
+            hud,
+            torus_data,
+            radii,
+            colormaps: colormaps(),
+            colormap: 0,
+            torus_states: 0,
+            torus_stats: String::new(),
+            sparks,
+            moons,
+            readout,
+            debug,
+            show_normals: false,
+            show_bounds: false,
+            frozen_frustum: None,
+            lines_on_top: false,
+            input: Input::attach(canvas)?,
+            responsive: ResponsiveCanvas::new(canvas, 2.)?,
+            controller: OrbitController::new(&[0., 0., 0.], 15.),
+            turntable: None,
+            //Skip frames while the context is lost, rebuild buffers and programs once it's back
+            context: ContextMonitor::attach(canvas, &gl)?,
+            gl,
+        };
+        demo.torus_stats = demo.stats(&demo.torus_mesh());
+        Ok(demo)
+    }
+
+    fn frame(&mut self, time: &FrameTime) {
+        if self.context.is_lost() {
+            return;
+        }
+        if self.context.take_restored() {
+            self.errors.check(self.resources.restore_all(&self.gl));
+            self.renderer.invalidate_state();
+            self.debug.invalidate();
+        }
+
+        //Follow the canvas' CSS size, then move the camera
+        self.responsive.update(&self.gl);
+        for event in self.input.poll().iter() {
+            self.controller.handle_event(event);
+        }
+        self.handle_keys();
+        let screenshot = self.input.state().was_key_pressed("KeyP");
+
+        //While capturing the turntable, time advances by its fixed step instead of the clock
+        let sequence_time = self.turntable.as_mut().and_then(|(sequence, _)| sequence.next_frame());
+        self.animate(sequence_time.as_ref().unwrap_or(time));
+        let result = self.update_torus_state();
+        self.errors.check(result);
+        let result = self.update_hud();
+        self.errors.check(result);
+
+        if screenshot || self.turntable.is_some() {
+            self.save_frame();
+        }
+        let aspect = self.responsive.aspect();
+        let result = self.draw(aspect);
+        self.errors.check(result);
+        if self.turntable.as_ref().is_some_and(|(sequence, _)| sequence.is_done()) {
+            if let Some((_, frames)) = self.turntable.take() {
+                self.errors
+                    .check(capture::download(&frames.finish(), "application/zip", "turntable.zip"));
+            }
+        }
+
+        //Context redrawn
+        self.gl.flush();
+    }
+
+    fn handle_keys(&mut self) {
+        let input = &self.input;
+        let pressed = |code: &str| input.state().was_key_pressed(code);
+        let (burst, next_colormap, halve) = (pressed("KeyB"), pressed("KeyV"), pressed("KeyX"));
+        let (outline, hud, freeze) = (pressed("KeyU"), pressed("KeyI"), pressed("KeyF"));
+        if pressed("KeyN") {
+            self.show_normals = !self.show_normals;
+        }
+        if pressed("KeyG") {
+            self.show_bounds = !self.show_bounds;
+        }
+        if pressed("KeyH") {
+            self.lines_on_top = !self.lines_on_top;
+            self.debug.set_depth_test(!self.lines_on_top);
+        }
+        if pressed("KeyM") {
+            let next = self.renderer.shading().next();
+            self.renderer.set_shading(next);
+        }
+        if pressed("KeyR") {
+            self.torus_states = (self.torus_states + 1) % TORUS_STATES.len();
+        }
+        if pressed("KeyC") {
+            self.sparks.clear();
+        }
+        if pressed("KeyO") {
+            self.moons.push(moon(self.moons.len()));
+        }
+        if pressed("KeyK") {
+            self.moons.clear();
+        }
+        if pressed("KeyT") && self.turntable.is_none() {
+            self.turntable = Some((FrameSequence::new(60, 0.1), ZipArchive::new()));
+        }
+
+        if freeze {
+            self.frozen_frustum = match self.frozen_frustum {
+                Some(_) => None,
+                None => self.view_projection(),
+            };
+        }
+        if burst {
+            self.burst_from_surface();
+        }
+        if next_colormap {
+            self.colormap = (self.colormap + 1) % (self.colormaps.len() + 1);
+            let mut data = self.torus_data.clone();
+            if let Some(map) = self.colormap.checked_sub(1).map(|i| &self.colormaps[i]) {
+                data.colors = map.vertex_colors(&self.radii, None);
+            }
+            let result = self.set_torus_mesh(&data);
+            self.errors.check(result);
+        }
+        if halve {
+            let result = self.halve_torus();
+            self.errors.check(result);
+        }
+        if outline {
+            self.set_outline(!self.outlined);
+        }
+        if hud {
+            let node = self.scene.node_mut(self.hud).unwrap();
+            node.label = match node.label {
+                Some(_) => None,
+                None => Some(self.readout.clone()),
+            };
+        }
+    }
+
+    //--Camera's view projection, for freezing its frustum--
+    fn view_projection(&mut self) -> Option<Matrix> {
+        let world = self.scene.world_matrix(self.camera)?;
+        let view = camera::Camera::view_from_world(&world).ok()?;
+        let camera = self.scene.node(self.camera)?.camera?;
+        let mut view_projection = camera.projection_matrix(self.responsive.aspect());
+        view_projection.multiply(&view);
+        Some(view_projection)
+    }
+
+    //--Shed a burst of sparks off the torus' surface--
+    fn burst_from_surface(&mut self) {
+        let world = match self.scene.world_matrix(self.torus) {
+            Some(world) => world,
+            None => return,
+        };
+        let mut surface = MeshData {
+            positions: self.torus_data.positions.clone(),
+            indices: self.torus_data.indices.clone(),
+            ..Default::default()
+        };
+        for p in surface.positions.chunks_exact_mut(3) {
+            let q = world.transform_point(&[p[0], p[1], p[2]]);
+            p.copy_from_slice(&q);
+        }
+        if let Some(shape) = self.errors.check(EmitterShape::mesh_surface(&surface)) {
+            let center = std::mem::replace(&mut self.sparks.emitter.shape, shape);
+            self.sparks.burst(300);
+            self.sparks.emitter.shape = center;
+        }
+    }
+
+    fn torus_mesh(&self) -> Rc<Mesh> {
+        self.scene.node(self.torus).and_then(|n| n.renderable.as_ref()).unwrap().mesh.clone()
+    }
+
+    //--Weld, then decimate the current torus to half its triangles, flat shaded to show the facets--
+    fn halve_torus(&mut self) -> Result<(), RenderError> {
+        let mut data = self.torus_mesh().to_data();
+        data.weld(1e-4);
+        data.remove_degenerates(0.);
+        let target = data.indices.len() / 6;
+        if target < 8 {
+            return Ok(());
+        }
+        data.decimate(target);
+        data.flat_normals()?;
+        data.optimize();
+        self.set_torus_mesh(&data)
+    }
+
+    //--Replace the torus and its outline--
+    fn set_torus_mesh(&mut self, data: &MeshData) -> Result<(), RenderError> {
+        let mesh = Rc::new(Mesh::from_data(&self.gl, data)?);
+        self.resources.track(&mesh);
+        let outline = Rc::new(Mesh::from_data(&self.gl, &outline_data(data))?);
+        self.resources.track(&outline);
+        self.torus_stats = self.stats(&mesh);
+        if let Some(renderable) = self.scene.node_mut(self.torus).and_then(|n| n.renderable.as_mut()) {
+            renderable.mesh = mesh;
+        }
+        self.shell.mesh = outline;
+        self.set_outline(self.outlined);
+        Ok(())
+    }
+
+    //--Size and vertex cache efficiency of a mesh, for the readout--
+    fn stats(&self, mesh: &Mesh) -> String {
+        let count = mesh.vertex_count() as usize;
+        let cache = vertex_cache::analyze_vertex_cache(&mesh.index, count, vertex_cache::FIFO_CACHE_SIZE);
+        format!(
+            "torus {} triangles, {} vertices, ACMR {:.2}, ATVR {:.2}",
+            mesh.index.len() / 3,
+            count,
+            cache.acmr,
+            cache.atvr
+        )
+    }
+
+    fn set_outline(&mut self, shown: bool) {
+        self.outlined = shown;
+        if let Some(node) = self.scene.node_mut(self.outline) {
+            node.renderable = if shown { Some(self.shell.clone()) } else { None };
+        }
+    }
+
+    //--State of the torus: the render state R selected, masking the outline while it's shown--
+    fn torus_state(&self) -> RenderState {
+        let opaque = RenderState::OPAQUE;
+        let state = match TORUS_STATES[self.torus_states] {
+            "additive" => opaque
+                .with_blend(BlendMode::Additive)
+                .with_depth(true, false, CompareFunc::LessEqual),
+            "multiply" => opaque.with_blend(BlendMode::Multiply),
+            "premultiplied alpha" => RenderState::TRANSPARENT.with_blend(BlendMode::PremultipliedAlpha),
+            "inside out" => opaque.with_cull_face(CullFace::Front, Winding::CounterClockwise),
+            "clockwise winding" => opaque.with_cull_face(CullFace::Back, Winding::Clockwise),
+            //Invisible but still hiding what's behind it
+            "depth only" => opaque.with_color_mask([false; 4]),
+            "left half" => opaque.with_scissor(Some(Scissor {
+                x: 0,
+                y: 0,
+                width: self.gl.drawing_buffer_width() / 2,
+                height: self.gl.drawing_buffer_height(),
+            })),
+            //Far enough for the debug axes to show through the tube
+            "pushed back" => opaque.with_polygon_offset(Some(PolygonOffset {
+                factor: 0.,
+                units: 20000.,
+            })),
+            _ => opaque,
+        };
+        state.with_stencil(if self.outlined { Some(OUTLINE_MASK) } else { None })
+    }
+
+    //--Swap the torus material when its state changed--
+    fn update_torus_state(&mut self) -> Result<(), RenderError> {
+        let state = self.torus_state();
+        let renderable = self
+            .scene
+            .node_mut(self.torus)
+            .and_then(|n| n.renderable.as_mut())
+            .ok_or_else(|| RenderError::Resource("the torus has no mesh".into()))?;
+        if renderable.material.state != state {
+            let mut material = (*renderable.material).clone();
+            material.set_state(state);
+            renderable.material = Rc::new(material);
+        }
+        Ok(())
+    }
+
+    //--Spin the torus, orbit the moons and simulate the sparks--
+    fn animate(&mut self, time: &FrameTime) {
+        //One turn every 6 seconds, independent of the refresh rate
+        let rad = (time.elapsed % 6.) as f32 / 6. * std::f32::consts::PI * 2.;
+
+        self.controller.update(time.delta);
+        self.scene.set_translation(self.camera, &self.controller.position());
+        self.scene.set_rotation(self.camera, &self.controller.rotation());
+
+        //Simulate particles, then face them to the camera
+        self.sparks.update(time.delta);
+        let result = self
+            .sparks
+            .write_instances(&self.controller.position(), &self.controller.rotation());
+        self.errors.check(result);
+
+        //Moons spread by the golden angle, circling the torus once per turn of it
+        for index in 0..self.moons.len() {
+            if let Some(mut instance) = self.moons.get(index) {
+                let angle = index as f32 * 2.4 - rad;
+                let position = [4.5 * angle.cos(), 0.5 * (angle * 3.).sin(), 4.5 * angle.sin()];
+                instance.model.set_trs(&position, &quat::from_axis_angle(&[1., 0., 0.], angle), &[1., 1., 1.]);
+                self.moons.set(index, instance);
+            }
+        }
+
+        self.scene.set_rotation(
+            self.torus,
+            &quat::multiply(
+                &quat::from_axis_angle(&[0., 1., 0.], rad),
+                &quat::from_axis_angle(&[0., 0., 1.], rad),
+            ),
+        );
+    }
+
+    //--Keep the readout in the top left corner and its text current--
+    fn update_hud(&mut self) -> Result<(), RenderError> {
+        if self.scene.node(self.hud).is_some_and(|n| n.label.is_none()) {
+            return Ok(());
+        }
+        //A unit in front of the camera, at the edge of its 45° field of view
+        let half_height = (22.5f32).to_radians().tan();
+        let aspect = self.responsive.aspect();
+        self.scene.set_translation(self.hud, &[-half_height * aspect, half_height, -1.]);
+
+        let text = format!(
+            "{}\n{}\nstate {}",
+            readout(self.input.state()),
+            self.torus_stats,
+            TORUS_STATES[self.torus_states]
+        );
+        if self.readout.text() != text {
+            self.readout.set_text(&self.gl, &text)?;
+        }
+        Ok(())
+    }
+
+    //--Draw the scene, then debug lines over it--
+    fn draw(&mut self, aspect: f32) -> Result<(), RenderError> {
+        self.renderer.clear(&self.gl, &[0.0, 0.0, 0.0, 1.0]);
+        self.scene.render(&self.gl, &mut self.renderer, self.camera, aspect)?;
+        self.debug.grid(20., 20, debug_draw::GRAY);
+        if let Some(world) = self.scene.world_matrix(self.torus) {
+            self.debug.axes(&world, 3.);
+        }
+        if self.show_normals {
+            self.scene.debug_normals(&mut self.debug, 0.3, debug_draw::YELLOW);
+        }
+        if self.show_bounds {
+            let bounds = self.torus_mesh().bounds;
+            if let (Some(world), Some(bounds)) = (self.scene.world_matrix(self.torus), bounds) {
+                self.debug.aabb(&bounds.aabb, &world, debug_draw::GREEN);
+                self.debug.sphere(&bounds.sphere.transformed(&world), debug_draw::BLUE);
+            }
+        }
+        if let Some(view_projection) = &self.frozen_frustum {
+            self.debug.frustum(view_projection, debug_draw::RED)?;
+        }
+        let flushed = self.scene.flush_debug(&self.gl, &mut self.debug, self.camera, aspect);
+        self.renderer.invalidate_state();
+        flushed
+    }
+
+    //--Save the frame as PNG, or add it to the turntable's ZIP--
+    fn save_frame(&mut self) {
+        let gl = self.gl.clone();
+        let (width, height) = (gl.drawing_buffer_width() as u32, gl.drawing_buffer_height() as u32);
+        let aspect = self.responsive.aspect();
+        let png = capture::capture_frame(&gl, width, height, || self.draw(aspect)).and_then(|frame| frame.to_png());
+        let saved = png.and_then(|png| match &mut self.turntable {
+            Some((sequence, frames)) => {
+                frames.add(&format!("turntable_{:04}.png", sequence.index()), &png);
+                Ok(())
+            }
+            None => capture::download(&png, "image/png", "frame.png"),
+        });
+        self.errors.check(saved);
+    }
+}
+
+fn torus_data() -> MeshData {
+    let (positions, normals, colors, indices) = shapes::torus(32, 32, 1.0, 2.0);
+    let mut data = MeshData {
+        positions,
+        normals,
+        colors,
+        indices,
+        ..Default::default()
+    };
+    data.optimize();
+    data
+}
+
+//--The torus in one flat color, for the outline shell--
+fn outline_data(torus: &MeshData) -> MeshData {
+    let color = Rgba::rgb(1., 0.6, 0.).linear();
+    MeshData {
+        colors: color.repeat(torus.vertex_count()),
+        ..torus.clone()
+    }
+}
+
+fn lit_material(program: &Rc<ShaderProgram>, ambient: Rgba) -> Material {
+    let mut material = Material::new(program.clone());
+    material
+        .set("ambientColor", Uniform::Vec4(ambient.linear()))
+        .set("baseColor", Uniform::Vec4(Rgba::WHITE.linear()));
+    material
+}
+
+//--Colors cycled with V after the rainbow, mapping the distance from the torus' axis--
+fn colormaps() -> [Colormap; 4] {
+    [
+        Colormap::Viridis,
+        Colormap::Turbo,
+        Colormap::Gradient(Gradient::new(&[Rgba::BLACK, Rgba::rgb(1., 0.3, 0.), Rgba::WHITE])),
+        Colormap::Gradient(Gradient::with_stops(&[
+            (0., Rgba::rgb(0.2, 0.3, 1.)),
+            (0.5, Rgba::WHITE),
+            (1., Rgba::rgb(1., 0.2, 0.1)),
+        ])),
+    ]
+}
+
+//--Rainbow sparks bursting from the center of the torus--
+fn sparks(gl: &GL) -> Result<ParticleSystem, RenderError> {
+    ParticleSystem::new(
+        gl,
+        Emitter {
+            shape: EmitterShape::Sphere { radius: 0.5, volume: true },
+            rate: 120.,
+            speed: (3., 5.),
+            spread: 0.3,
+            gravity: [0., -4., 0.],
+            drag: 0.2,
+            color: ColorOverLife::Hue {
+                hue_start: 0.,
+                hue_end: 300.,
+                saturation: 1.,
+                value: 1.,
+                alpha: Curve::linear(1., 0.),
+            },
+            //Full size for most of their life, shrinking away at the end
+            size: Curve::constant(0.15).key(0.7, 0.15).key(1., 0.),
+            ..Default::default()
+        },
+        BlendMode::Additive,
+    )
+}
+
+//--Small tori orbiting the big one in a single instanced draw--
+fn moons(gl: &GL, resources: &mut ResourceTracker) -> Result<Rc<InstanceBatch>, RenderError> {
+    let program = Rc::new(instancing::instanced_program(gl)?);
+    resources.track(&program);
+    let (positions, normals, colors, indices) = shapes::torus(12, 12, 0.08, 0.2);
+    let mesh = Rc::new(Mesh::from_data(
+        gl,
+        &MeshData {
+            positions,
+            normals,
+            colors,
+            indices,
+            ..Default::default()
+        },
+    )?);
+    resources.track(&mesh);
+    let moons = Rc::new(InstanceBatch::new(mesh, Rc::new(lit_material(&program, Rgba::rgb(0.1, 0.1, 0.1)))));
+    resources.track(&moons);
+    for index in 0..8 {
+        moons.push(moon(index));
+    }
+    Ok(moons)
+}
+
+fn moon(index: usize) -> Instance {
+    Instance {
+        color: Rgba::from_hsv(index as f32 * 137.5 % 360., 0.6, 1., 1.).linear(),
+        ..Default::default()
+    }
+}
+
+//--Name floating above the torus, as wide as its outer radius--
+fn title(gl: &GL, font: &Rc<Font>) -> Result<Rc<Label>, RenderError> {
+    let width = font.atlas.measure("Torus", &TextLayout::default())[0];
+    Ok(Rc::new(Label::new(
+        gl,
+        font,
+        "Torus",
+        LabelStyle {
+            size: 3. / width.max(f32::EPSILON),
+            ..Default::default()
+        },
+    )?))
+}
+
+//--Pointer, wheel, gesture, button and modifier state of the frame--
+fn readout(input: &InputState) -> String {
+    let [x, y] = input.pointer_position();
+    let [dx, dy] = input.pointer_delta();
+    let (pinch, twist) = input.gesture();
+    let button = |b| phase(input.is_button_down(b), input.was_button_pressed(b), input.was_button_released(b));
+    let key = |code| phase(input.is_key_down(code), input.was_key_pressed(code), input.was_key_released(code));
+    format!(
+        "pointer {:.0}, {:.0}  delta {:.0}, {:.0}  wheel {:.0}\npinch {:.2}  twist {:.2}  locked {}\nleft {}  middle {}  right {}  shift {}",
+        x,
+        y,
+        dx,
+        dy,
+        input.wheel_delta(),
+        pinch,
+        twist,
+        input.is_pointer_locked(),
+        button(MouseButton::Left),
+        button(MouseButton::Middle),
+        button(MouseButton::Right),
+        key("ShiftLeft"),
+    )
+}
+
+//--Name of a key or button state, a change within the frame wins over the held state--
+fn phase(down: bool, pressed: bool, released: bool) -> &'static str {
+    if pressed {
+        "pressed"
+    } else if released {
+        "released"
+    } else if down {
+        "down"
+    } else {
+        "up"
+    }
+}
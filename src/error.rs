@@ -0,0 +1,111 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use wasm_bindgen::JsValue;
+
+//--Every way rendering can fail--
+#[derive(Clone, Debug, PartialEq)]
+pub enum RenderError {
+    //  No canvas, no WebGL, lost context or a missing browser API
+    Context(String),
+    //  Compile or link failure, carries the driver's info log
+    Shader(String),
+    //  Vertex or index buffer creation
+    Buffer(String),
+    //  Textures, meshes, scene nodes and loaded assets
+    Resource(String),
+    //  Degenerate input such as a singular matrix
+    Math(String),
+}
+
+impl RenderError {
+    //--Short name of the variant, exposed to JavaScript as error.kind--
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RenderError::Context(_) => "context",
+            RenderError::Shader(_) => "shader",
+            RenderError::Buffer(_) => "buffer",
+            RenderError::Resource(_) => "resource",
+            RenderError::Math(_) => "math",
+        }
+    }
+
+    pub fn message(&self) -> &str {
+        match self {
+            RenderError::Context(m)
+            | RenderError::Shader(m)
+            | RenderError::Buffer(m)
+            | RenderError::Resource(m)
+            | RenderError::Math(m) => m,
+        }
+    }
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} error: {}", self.kind(), self.message())
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+//--JavaScript Error with name "RenderError" and a kind property--
+impl From<RenderError> for JsValue {
+    fn from(error: RenderError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("RenderError");
+        let _ = js_sys::Reflect::set(&js_error, &JsValue::from_str("kind"), &JsValue::from_str(error.kind()));
+        js_error.into()
+    }
+}
+
+type Handler = Rc<RefCell<Box<dyn FnMut(&RenderError)>>>;
+type Callback = Rc<RefCell<Option<Handler>>>;
+
+//--Destination for errors raised inside the frame loop--
+//  <note>
+//      Frames can't return errors to anyone, so they are handed to a callback.
+//      Without one the error is logged to the console. Clones share the callback.
+#[derive(Clone, Default)]
+pub struct ErrorReporter {
+    callback: Callback,
+}
+
+impl ErrorReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_callback<F: FnMut(&RenderError) + 'static>(&self, callback: F) {
+        *self.callback.borrow_mut() = Some(Rc::new(RefCell::new(Box::new(callback))));
+    }
+
+    //--Fall back to logging--
+    pub fn clear_callback(&self) {
+        self.callback.borrow_mut().take();
+    }
+
+    //  <note>
+    //      The callback runs without the slot borrowed, so it may replace or
+    //      clear itself. An error it raises itself is logged instead.
+    pub fn report(&self, error: &RenderError) {
+        let handler = self.callback.borrow().clone();
+        let called = handler
+            .and_then(|h| h.try_borrow_mut().ok().map(|mut callback| callback(error)))
+            .is_some();
+        if !called {
+            web_sys::console::error_1(&JsValue::from_str(&error.to_string()));
+        }
+    }
+
+    //--Report the error of a result, if any--
+    pub fn check<T>(&self, result: Result<T, RenderError>) -> Option<T> {
+        match result {
+            Ok(value) => Some(value),
+            Err(e) => {
+                self.report(&e);
+                None
+            }
+        }
+    }
+}
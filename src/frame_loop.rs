@@ -0,0 +1,382 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+
+//--Timing information passed to the frame callback--
+//  <note>
+//      Run the simulation `fixed_steps` times with `fixed_step` seconds each,
+//      then render, blending states with `alpha` if needed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameTime {
+    //  Seconds since the previous frame, clamped to FrameClock::max_delta
+    pub delta: f32,
+    //  Seconds of unpaused time since the loop started
+    pub elapsed: f64,
+    //  Frames delivered so far, starting at 1
+    pub frame: u64,
+    pub fixed_steps: u32,
+    pub fixed_step: f32,
+    //  Leftover accumulator as a fraction of fixed_step, 0..1
+    pub alpha: f32,
+}
+
+//--Turns requestAnimationFrame timestamps into frame times--
+//  <note>
+//      Pure bookkeeping, the browser side lives in FrameLoop.
+#[derive(Clone, Debug)]
+pub struct FrameClock {
+    //  Fixed update interval in seconds
+    pub fixed_step: f64,
+    //  Upper bound for a single delta, avoids a burst of updates after a stall
+    pub max_delta: f64,
+    //  Upper bound for fixed steps in one frame
+    pub max_fixed_steps: u32,
+    last: Option<f64>,
+    elapsed: f64,
+    accumulator: f64,
+    frame: u64,
+}
+
+impl FrameClock {
+    pub fn new(fixed_step: f64) -> Self {
+        Self {
+            fixed_step,
+            max_delta: 0.25,
+            max_fixed_steps: 8,
+            last: None,
+            elapsed: 0.,
+            accumulator: 0.,
+            frame: 0,
+        }
+    }
+
+    //--Advance to a timestamp--
+    //  <argument>
+    //      timestamp f64 : milliseconds, as passed to requestAnimationFrame
+    pub fn tick(&mut self, timestamp: f64) -> FrameTime {
+        let delta = match self.last {
+            Some(last) => ((timestamp - last) / 1000.).max(0.).min(self.max_delta),
+            None => 0.,
+        };
+        self.last = Some(timestamp);
+        self.advance(delta)
+    }
+
+    //--Advance by exactly one fixed step, 1/60 s without one, used while paused--
+    pub fn step(&mut self) -> FrameTime {
+        self.last = None;
+        let step = if self.fixed_step > 0. { self.fixed_step } else { 1. / 60. };
+        self.advance(step)
+    }
+
+    //--Forget the last timestamp, the next tick has a delta of 0--
+    //  <note>
+    //      Call after a pause so the time spent paused isn't simulated.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+
+    fn advance(&mut self, delta: f64) -> FrameTime {
+        self.elapsed += delta;
+        self.frame += 1;
+
+        let mut fixed_steps = 0;
+        if self.fixed_step > 0. {
+            self.accumulator += delta;
+            while self.accumulator >= self.fixed_step && fixed_steps < self.max_fixed_steps {
+                self.accumulator -= self.fixed_step;
+                fixed_steps += 1;
+            }
+            // Too far behind, drop the backlog instead of spiralling
+            if fixed_steps == self.max_fixed_steps {
+                self.accumulator = self.accumulator.min(self.fixed_step);
+            }
+        }
+
+        FrameTime {
+            delta: delta as f32,
+            elapsed: self.elapsed,
+            frame: self.frame,
+            fixed_steps,
+            fixed_step: self.fixed_step as f32,
+            alpha: if self.fixed_step > 0. {
+                (self.accumulator / self.fixed_step) as f32
+            } else {
+                0.
+            },
+        }
+    }
+}
+
+struct Control {
+    clock: FrameClock,
+    request: Option<i32>,
+    paused: bool,
+    hidden: bool,
+    //  Set by the owner, e.g. while the context is lost, independent of pause()
+    suspended: bool,
+    step_pending: bool,
+    cancelled: bool,
+}
+
+type Callback = Rc<RefCell<Box<dyn FnMut(&FrameTime)>>>;
+type RafClosure = Rc<RefCell<Option<Closure<dyn FnMut(f64)>>>>;
+type Listener = Rc<RefCell<Option<Closure<dyn FnMut()>>>>;
+
+//--requestAnimationFrame loop with pause, single stepping and cancellation--
+//  <note>
+//      The loop is suspended while the document is hidden and resumes with a
+//      zero delta, so nothing is simulated for the hidden period.
+//      Dropping a handle leaves the loop running, call cancel() to stop it.
+#[derive(Clone)]
+pub struct FrameLoop {
+    control: Rc<RefCell<Control>>,
+    raf: RafClosure,
+    visibility: Listener,
+}
+
+impl FrameLoop {
+    //--Start calling `callback` once per animation frame--
+    //  <argument>
+    //      fixed_step f64 : interval of FrameTime::fixed_steps in seconds, 0 disables them
+    pub fn start<F>(fixed_step: f64, callback: F) -> Self
+    where
+        F: FnMut(&FrameTime) + 'static,
+    {
+        let hidden = document().is_some_and(|d| d.hidden());
+        let frame_loop = FrameLoop {
+            control: Rc::new(RefCell::new(Control {
+                clock: FrameClock::new(fixed_step),
+                request: None,
+                paused: false,
+                hidden,
+                suspended: false,
+                step_pending: false,
+                cancelled: false,
+            })),
+            raf: Rc::new(RefCell::new(None)),
+            visibility: Rc::new(RefCell::new(None)),
+        };
+        let callback: Callback = Rc::new(RefCell::new(Box::new(callback)));
+
+        let handle = frame_loop.clone();
+        *frame_loop.raf.borrow_mut() = Some(Closure::wrap(Box::new(move |timestamp: f64| {
+            let time = {
+                let mut control = handle.control.borrow_mut();
+                control.request = None;
+                if control.cancelled || control.suspended {
+                    return;
+                }
+                if control.step_pending {
+                    control.step_pending = false;
+                    control.clock.step()
+                } else if control.paused || control.hidden {
+                    return;
+                } else {
+                    control.clock.tick(timestamp)
+                }
+            };
+            // Not borrowed while the callback runs, so it may pause or cancel the loop
+            (callback.borrow_mut())(&time);
+            handle.schedule();
+        }) as Box<dyn FnMut(f64)>));
+
+        let handle = frame_loop.clone();
+        let on_visibility = Closure::wrap(Box::new(move || {
+            let hidden = document().is_some_and(|d| d.hidden());
+            {
+                let mut control = handle.control.borrow_mut();
+                control.hidden = hidden;
+                control.clock.reset();
+            }
+            handle.schedule();
+        }) as Box<dyn FnMut()>);
+        if let Some(document) = document() {
+            let _ = document.add_event_listener_with_callback("visibilitychange", on_visibility.as_ref().unchecked_ref());
+        }
+        *frame_loop.visibility.borrow_mut() = Some(on_visibility);
+
+        frame_loop.schedule();
+        frame_loop
+    }
+
+    //--Request the next frame if the loop should be running--
+    fn schedule(&self) {
+        let mut control = self.control.borrow_mut();
+        let wanted = !control.suspended && (control.step_pending || !(control.paused || control.hidden));
+        if control.cancelled || control.request.is_some() || !wanted {
+            return;
+        }
+        if let (Some(window), Some(closure)) = (web_sys::window(), self.raf.borrow().as_ref()) {
+            control.request = window.request_animation_frame(closure.as_ref().unchecked_ref()).ok();
+        }
+    }
+
+    fn cancel_request(&self) {
+        if let Some(id) = self.control.borrow_mut().request.take() {
+            if let Some(window) = web_sys::window() {
+                let _ = window.cancel_animation_frame(id);
+            }
+        }
+    }
+
+    pub fn pause(&self) {
+        self.control.borrow_mut().paused = true;
+        if !self.control.borrow().step_pending {
+            self.cancel_request();
+        }
+    }
+
+    pub fn resume(&self) {
+        {
+            let mut control = self.control.borrow_mut();
+            if !control.paused {
+                return;
+            }
+            control.paused = false;
+            control.clock.reset();
+        }
+        self.schedule();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.control.borrow().paused
+    }
+
+    //--Hold the loop without touching the paused flag--
+    //  <note>
+    //      pause() and resume() calls made while suspended take effect once
+    //      it's lifted, a pending step() is delivered then too.
+    pub fn set_suspended(&self, suspended: bool) {
+        {
+            let mut control = self.control.borrow_mut();
+            if control.suspended == suspended {
+                return;
+            }
+            control.suspended = suspended;
+            control.clock.reset();
+        }
+        if suspended {
+            self.cancel_request();
+        } else {
+            self.schedule();
+        }
+    }
+
+    //--Deliver a single frame of one fixed step while paused--
+    pub fn step(&self) {
+        {
+            let mut control = self.control.borrow_mut();
+            if !control.paused {
+                return;
+            }
+            control.step_pending = true;
+        }
+        self.schedule();
+    }
+
+    //--Stop the loop for good--
+    //  <note>
+    //      Drops the frame closure and with it everything the callback captured,
+    //      which breaks the closure -> loop -> closure reference cycle.
+    pub fn cancel(&self) {
+        self.cancel_request();
+        self.control.borrow_mut().cancelled = true;
+        if let Some(closure) = self.visibility.borrow_mut().take() {
+            if let Some(document) = document() {
+                let _ = document
+                    .remove_event_listener_with_callback("visibilitychange", closure.as_ref().unchecked_ref());
+            }
+        }
+        self.raf.borrow_mut().take();
+    }
+}
+
+fn document() -> Option<web_sys::Document> {
+    web_sys::window().and_then(|w| w.document())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //  A power of two keeps the accumulator exact
+    const STEP: f64 = 1. / 64.;
+
+    fn ms(seconds: f64) -> f64 {
+        seconds * 1000.
+    }
+
+    #[test]
+    fn fixed_steps_accumulate() {
+        let mut clock = FrameClock::new(STEP);
+        let first = clock.tick(ms(1.));
+        assert_eq!((first.delta, first.fixed_steps, first.frame), (0., 0, 1));
+
+        let time = clock.tick(ms(1. + STEP * 2.5));
+        assert_eq!(time.fixed_steps, 2);
+        assert_eq!(time.alpha, 0.5);
+        assert_eq!(time.fixed_step, STEP as f32);
+
+        // The leftover half step completes with the next one
+        let time = clock.tick(ms(1. + STEP * 3.));
+        assert_eq!((time.fixed_steps, time.alpha), (1, 0.));
+        assert_eq!(time.elapsed, STEP * 3.);
+        assert_eq!(time.frame, 3);
+    }
+
+    #[test]
+    fn stalls_are_clamped_and_backlog_dropped() {
+        let mut clock = FrameClock::new(STEP);
+        clock.max_delta = 1.;
+        clock.tick(0.);
+
+        // Half a second is 32 steps, only max_fixed_steps run and the rest is dropped
+        let time = clock.tick(ms(0.5));
+        assert_eq!(time.fixed_steps, clock.max_fixed_steps);
+        assert!(time.alpha <= 1.);
+        let time = clock.tick(ms(0.5));
+        assert!(time.fixed_steps <= 1, "{} steps after the stall", time.fixed_steps);
+
+        // Deltas never exceed max_delta, nor go backwards
+        let time = clock.tick(ms(10.));
+        assert_eq!(time.delta, 1.);
+        let time = clock.tick(ms(5.));
+        assert_eq!(time.delta, 0.);
+    }
+
+    #[test]
+    fn step_advances_one_fixed_step() {
+        let mut clock = FrameClock::new(STEP);
+        clock.tick(ms(1.));
+        let time = clock.step();
+        assert_eq!((time.delta, time.fixed_steps, time.frame), (STEP as f32, 1, 2));
+        assert_eq!(time.elapsed, STEP);
+
+        // The tick after a step doesn't count the time since the last tick
+        let time = clock.tick(ms(30.));
+        assert_eq!((time.delta, time.fixed_steps), (0., 0));
+
+        // Without a fixed step, steps are 1/60 s and run no fixed updates
+        let mut clock = FrameClock::new(0.);
+        let time = clock.step();
+        assert_eq!((time.delta, time.fixed_steps, time.alpha), ((1. / 60.) as f32, 0, 0.));
+    }
+
+    #[test]
+    fn reset_skips_the_paused_time() {
+        let mut clock = FrameClock::new(STEP);
+        clock.tick(ms(1.));
+        let before = clock.tick(ms(1.016)).elapsed;
+
+        // Paused for a minute
+        clock.reset();
+        let time = clock.tick(ms(61.));
+        assert_eq!((time.delta, time.fixed_steps), (0., 0));
+        assert_eq!(time.elapsed, before);
+
+        let time = clock.tick(ms(61.) + 16.);
+        assert!((time.delta - 0.016).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,438 @@
+use crate::bounds::Frustum;
+use crate::context::ResourceTracker;
+use crate::error::RenderError;
+use crate::instancing::{InstanceBatch, Instancing};
+use crate::mat_4::Matrix;
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::render_state::{RenderState, StateCache};
+use crate::webgl;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{WebGlBuffer, WebGlFramebuffer, WebGlRenderbuffer, WebGlTexture};
+
+//  IDs are written as 24 bit RGB plus one, 0 is left for the background
+pub const MAX_IDS: u32 = 0xff_ffff;
+
+//--What an ID pass found under the cursor--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdHit {
+    //  ID returned by submit(), or first ID + instance index for batches
+    pub id: u32,
+    //  World space position reconstructed from the depth buffer
+    pub position: [f32; 3],
+    //  Window space depth, 1 at the far plane. The near plane is at 0.5 for
+    //  perspective projections, which map it to NDC z 0, and at 0 for orthographic
+    //  ones. Not linear in distance, use `position` to measure.
+    pub depth: f32,
+}
+
+struct IdDraw {
+    mesh: Rc<Mesh>,
+    instances: Option<Rc<InstanceBatch>>,
+    model: Matrix,
+    //  Opaque state with the material's culling, so hidden faces stay unpickable
+    state: RenderState,
+    first_id: u32,
+}
+
+//--Offscreen target covering only the pick window--
+struct IdTarget {
+    size: u32,
+    framebuffer: WebGlFramebuffer,
+    //  Kept alive with the framebuffer they're attached to
+    _texture: WebGlTexture,
+    _depth: WebGlRenderbuffer,
+}
+
+//--GPU picking by drawing objects in unique colors--
+//  <note>
+//      Draws go through vertex.vert (mvpMatrix) and id_instanced.vert with a
+//      projection narrowed to the pick window, so the target is only
+//      (2 * radius + 1) pixels square whatever the canvas size. A second pass
+//      packs depth into color, WebGL1 can't read the depth buffer back.
+//      The pass changes GL state behind the renderer, the caller should
+//      invalidate the renderer's state afterwards.
+pub struct IdPass {
+    program: Rc<ShaderProgram>,
+    instanced_program: Rc<ShaderProgram>,
+    queue: Vec<IdDraw>,
+    next_id: u32,
+    radius: u32,
+    target: Option<IdTarget>,
+    //  Floats 0, 1, 2, ... for instanceIndex, and how many it holds
+    indices: Option<(WebGlBuffer, usize)>,
+    enabled: Vec<u32>,
+    state_cache: StateCache,
+    instancing: Option<Instancing>,
+}
+
+impl IdPass {
+    //--Compile the ID programs--
+    //  <argument>
+    //      radius u32 : pick radius in pixels, 0 only tests the pixel under the cursor
+    pub fn new(gl: &GL, radius: u32) -> Result<Self, RenderError> {
+        let frag = include_str!("shader/id.frag");
+        Ok(Self {
+            program: Rc::new(ShaderProgram::new(gl, include_str!("shader/vertex.vert"), frag)?),
+            instanced_program: Rc::new(ShaderProgram::new(gl, include_str!("shader/id_instanced.vert"), frag)?),
+            queue: Vec::new(),
+            next_id: 0,
+            radius,
+            target: None,
+            indices: None,
+            enabled: Vec::new(),
+            state_cache: StateCache::new(),
+            instancing: None,
+        })
+    }
+
+    //--Register the programs for context restore--
+    //  <note>
+    //      Call invalidate() after a restore as well, the target is rebuilt on the next pick.
+    pub fn track(&self, resources: &mut ResourceTracker) {
+        resources.track(&self.program);
+        resources.track(&self.instanced_program);
+    }
+
+    //--Forget GL objects and cached state, after a context restore--
+    pub fn invalidate(&mut self) {
+        self.target = None;
+        self.indices = None;
+        self.enabled.clear();
+        self.state_cache.invalidate();
+        self.instancing = None;
+    }
+
+    //--Pixels around the cursor that count as a hit, the closest object pixel wins--
+    pub fn set_radius(&mut self, radius: u32) -> &mut Self {
+        self.radius = radius;
+        self
+    }
+
+    //--Queue a mesh for the next pick--
+    //  <return> Option<u32>  ID reported when it's hit, None once MAX_IDS are used up
+    pub fn submit(&mut self, mesh: &Rc<Mesh>, material: &Material, model: &Matrix) -> Option<u32> {
+        let id = self.allocate(1)?;
+        self.queue.push(IdDraw {
+            mesh: mesh.clone(),
+            instances: None,
+            model: *model,
+            state: pick_state(material),
+            first_id: id,
+        });
+        Some(id)
+    }
+
+    //--Queue an instance batch for the next pick--
+    //  <return> Option<u32>  ID of the first instance, instance i is reported as first + i
+    pub fn submit_instances(&mut self, batch: &Rc<InstanceBatch>, model: &Matrix) -> Option<u32> {
+        let id = self.allocate(batch.len() as u32)?;
+        self.queue.push(IdDraw {
+            mesh: batch.mesh.clone(),
+            instances: Some(batch.clone()),
+            model: *model,
+            state: pick_state(&batch.material),
+            first_id: id,
+        });
+        Some(id)
+    }
+
+    fn allocate(&mut self, count: u32) -> Option<u32> {
+        let id = self.next_id;
+        if MAX_IDS - id < count {
+            return None;
+        }
+        self.next_id += count;
+        Some(id)
+    }
+
+    //--Draw the queued objects around a point and read back the closest one--
+    //  <argument>
+    //      view, projection &Matrix : camera the scene is seen through
+    //      x, y             f32     : position from the top left corner of the viewport
+    //      width, height    f32     : viewport size in the same units, the radius is in these units too
+    //  <note>
+    //      The queue is cleared and the default framebuffer and viewport are restored.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pick(
+        &mut self,
+        gl: &GL,
+        view: &Matrix,
+        projection: &Matrix,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> Result<Option<IdHit>, RenderError> {
+        let result = self.pick_queued(gl, view, projection, [x, y], [width, height]);
+
+        for &location in self.enabled.iter() {
+            gl.disable_vertex_attrib_array(location);
+        }
+        self.enabled.clear();
+        gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+        gl.viewport(0, 0, gl.drawing_buffer_width(), gl.drawing_buffer_height());
+        self.queue.clear();
+        self.next_id = 0;
+        result
+    }
+
+    fn pick_queued(
+        &mut self,
+        gl: &GL,
+        view: &Matrix,
+        projection: &Matrix,
+        point: [f32; 2],
+        viewport: [f32; 2],
+    ) -> Result<Option<IdHit>, RenderError> {
+        if self.queue.is_empty() || viewport[0] <= 0. || viewport[1] <= 0. {
+            return Ok(None);
+        }
+        let size = self.radius * 2 + 1;
+        self.prepare(gl, size)?;
+
+        // Window center and half size in normalized device coordinates
+        let center = [point[0] / viewport[0] * 2. - 1., 1. - point[1] / viewport[1] * 2.];
+        let half = [size as f32 / viewport[0], size as f32 / viewport[1]];
+        let mut vp_matrix = *projection;
+        vp_matrix.multiply(view);
+        // Scale and shift clip space so the window fills the target
+        let mut pick_matrix = Matrix::new();
+        let mut m = pick_matrix.get_value();
+        m[0] = 1. / half[0];
+        m[5] = 1. / half[1];
+        m[12] = -center[0] / half[0];
+        m[13] = -center[1] / half[1];
+        pick_matrix.set_value(&m).multiply(&vp_matrix);
+
+        let frustum = Frustum::from_matrix(&pick_matrix);
+        self.queue.retain(|draw| is_visible(draw, &frustum));
+
+        let ids = self.draw_pass(gl, &pick_matrix, size, false)?;
+        let pixel = match closest_pixel(&ids, size, self.radius) {
+            Some(pixel) => pixel,
+            None => return Ok(None),
+        };
+        let offset = (pixel.1 * size as usize + pixel.0) * 4;
+        let id = decode_id(&ids[offset..offset + 3]);
+
+        let depths = self.draw_pass(gl, &pick_matrix, size, true)?;
+        let depth = unpack_depth(&depths[offset..offset + 4]);
+
+        let ndc = [
+            center[0] + half[0] * ((pixel.0 as f32 + 0.5) * 2. / size as f32 - 1.),
+            center[1] + half[1] * ((pixel.1 as f32 + 0.5) * 2. / size as f32 - 1.),
+            depth * 2. - 1.,
+        ];
+        vp_matrix
+            .inverse()
+            .map_err(|_| RenderError::Math("view projection matrix is not invertible".into()))?;
+        Ok(Some(IdHit {
+            id,
+            position: vp_matrix.transform_point(&ndc),
+            depth,
+        }))
+    }
+
+    //--Create the target and index buffer if missing or too small--
+    fn prepare(&mut self, gl: &GL, size: u32) -> Result<(), RenderError> {
+        if self.target.as_ref().is_none_or(|t| t.size != size) {
+            let (framebuffer, texture, depth) = webgl::create_render_target(gl, size, size)?;
+            self.target = Some(IdTarget {
+                size,
+                framebuffer,
+                _texture: texture,
+                _depth: depth,
+            });
+        }
+
+        let needed = self.queue.iter().filter_map(|d| d.instances.as_ref()).map(|b| b.len()).max().unwrap_or(0);
+        if needed > 0 && self.indices.as_ref().is_none_or(|(_, len)| *len < needed) {
+            let len = needed.next_power_of_two();
+            let data: Vec<f32> = (0..len).map(|i| i as f32).collect();
+            self.indices = Some((webgl::create_vbo_vector(gl, &data)?, len));
+        }
+        if self.instancing.is_none() {
+            self.instancing = Some(Instancing::detect(gl));
+        }
+        Ok(())
+    }
+
+    //--Clear the target, draw every queued object and read the pixels back--
+    //  <argument>
+    //      depth bool : write packed depth instead of IDs
+    fn draw_pass(&mut self, gl: &GL, vp_matrix: &Matrix, size: u32, depth: bool) -> Result<Vec<u8>, RenderError> {
+        let target = self.target.as_ref().ok_or_else(|| RenderError::Resource("ID target is missing".into()))?;
+        gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&target.framebuffer));
+        gl.viewport(0, 0, size as i32, size as i32);
+        self.state_cache.apply(gl, &RenderState::OPAQUE);
+        gl.clear_color(0., 0., 0., 0.);
+        gl.clear_depth(1.);
+        gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT);
+
+        let instancing = self.instancing.clone().unwrap_or(Instancing::Unsupported);
+        let depth_pass = Uniform::Float(if depth { 1. } else { 0. });
+        let mut mvp_matrix = Matrix::new();
+        let mut current = None;
+        for draw in self.queue.iter() {
+            self.state_cache.apply(gl, &draw.state);
+            let program = match draw.instances {
+                Some(_) => &self.instanced_program,
+                None => &self.program,
+            };
+            if current != Some(program.id) {
+                gl.use_program(Some(&program.program()));
+                program.set_uniform(gl, "depthPass", &depth_pass);
+                current = Some(program.id);
+            }
+
+            match &draw.instances {
+                Some(batch) => {
+                    program.set_uniform(gl, "instanced", &Uniform::Float(1.));
+                    program.set_uniform(gl, "vpMatrix", &Uniform::Mat4(vp_matrix.get_value()));
+                    program.set_uniform(gl, "modelMatrix", &Uniform::Mat4(draw.model.get_value()));
+                    program.set_uniform(gl, "baseId", &Uniform::Float((draw.first_id + 1) as f32));
+                    let indices = self.indices.as_ref().map(|(buffer, _)| buffer);
+                    batch.draw_indexed(gl, program, &instancing, &mut self.enabled, indices)?;
+                }
+                None => {
+                    mvp_matrix.substitution(vp_matrix).multiply(&draw.model);
+                    program.set_uniform(gl, "instanced", &Uniform::Float(0.));
+                    program.set_uniform(gl, "mvpMatrix", &Uniform::Mat4(mvp_matrix.get_value()));
+                    program.set_uniform(gl, "idColor", &Uniform::Vec4(encode_id(draw.first_id)));
+                    draw.mesh.bind(gl, program, &mut self.enabled);
+                    draw.mesh.draw(gl);
+                }
+            }
+        }
+
+        let mut pixels = vec![0u8; (size * size * 4) as usize];
+        gl.read_pixels_with_opt_u8_array(0, 0, size as i32, size as i32, GL::RGBA, GL::UNSIGNED_BYTE, Some(&mut pixels))
+            .map_err(|_| RenderError::Resource("failed to read back the ID target".into()))?;
+        Ok(pixels)
+    }
+}
+
+//--Opaque state keeping the material's face culling--
+fn pick_state(material: &Material) -> RenderState {
+    RenderState::OPAQUE.with_cull_face(material.state.cull_face, material.state.front_face)
+}
+
+//--Conservative frustum test against the pick window, same rules as the renderer--
+fn is_visible(draw: &IdDraw, frustum: &Frustum) -> bool {
+    match &draw.instances {
+        Some(batch) => batch
+            .bounds()
+            .is_none_or(|aabb| frustum.intersects_aabb(&aabb.transformed(&draw.model))),
+        None => draw
+            .mesh
+            .bounds
+            .as_ref()
+            .is_none_or(|bounds| frustum.intersects_bounds(bounds, &draw.model)),
+    }
+}
+
+//--ID as the color id.frag writes, plus one so 0 stays the background--
+fn encode_id(id: u32) -> [f32; 4] {
+    let [r, g, b, _] = (id + 1).to_le_bytes();
+    [r as f32 / 255., g as f32 / 255., b as f32 / 255., 1.]
+}
+
+//--Inverse of encode_id() for a pixel read back from a non-background pixel--
+fn decode_id(rgb: &[u8]) -> u32 {
+    u32::from_le_bytes([rgb[0], rgb[1], rgb[2], 0]) - 1
+}
+
+//--Pixel with an ID nearest to the center, within the radius--
+//  <return> Option<(usize, usize)>  column and row from the bottom
+fn closest_pixel(pixels: &[u8], size: u32, radius: u32) -> Option<(usize, usize)> {
+    let size = size as usize;
+    let r = radius as i64;
+    (0..size * size)
+        .filter(|&p| pixels[p * 4..p * 4 + 3].iter().any(|&c| c != 0))
+        .map(|p| (p % size, p / size))
+        .map(|(i, j)| ((i as i64 - r).pow(2) + (j as i64 - r).pow(2), (i, j)))
+        .filter(|&(d, _)| d <= r * r)
+        .min_by_key(|&(d, _)| d)
+        .map(|(_, pixel)| pixel)
+}
+
+//--Inverse of packDepth() in id.frag--
+fn unpack_depth(rgba: &[u8]) -> f32 {
+    let weights = [1., 1. / 255., 1. / 65025., 1. / 16_581_375.];
+    rgba.iter().zip(weights.iter()).map(|(&c, w)| c as f32 / 255. * w).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //  Float to unorm8 conversion of the color attachment
+    fn quantize(color: &[f32]) -> Vec<u8> {
+        color.iter().map(|c| (c.clamp(0., 1.) * 255.).round() as u8).collect()
+    }
+
+    //  packDepth() from id.frag
+    fn pack_depth(depth: f32) -> [f32; 4] {
+        let scales = [1., 255., 65025., 16_581_375.];
+        let mut enc = [0.; 4];
+        for (e, s) in enc.iter_mut().zip(scales.iter()) {
+            *e = (depth * s).fract();
+        }
+        [enc[0] - enc[1] / 255., enc[1] - enc[2] / 255., enc[2] - enc[3] / 255., enc[3]]
+    }
+
+    #[test]
+    fn ids_round_trip_through_the_color_target() {
+        for &id in [0, 1, 254, 255, 256, 65_534, 65_535, 0x12_3456, MAX_IDS - 1].iter() {
+            let rgb = quantize(&encode_id(id)[..3]);
+            assert!(rgb.iter().any(|&c| c != 0), "{} looks like the background", id);
+            assert_eq!(decode_id(&rgb), id);
+        }
+        // The last ID fills all three channels
+        assert_eq!(quantize(&encode_id(MAX_IDS - 1)), [255, 255, 255, 255]);
+        assert_eq!(quantize(&encode_id(0)), [1, 0, 0, 255]);
+    }
+
+    #[test]
+    fn depth_round_trips_through_pack_depth() {
+        assert_eq!(unpack_depth(&quantize(&pack_depth(0.))), 0.);
+        let mut seed = 7u32;
+        for _ in 0..1000 {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            let depth = (seed >> 8) as f32 / (1 << 24) as f32;
+            let unpacked = unpack_depth(&quantize(&pack_depth(depth)));
+            // Far finer than a single 8-bit channel could hold
+            assert!((unpacked - depth).abs() < 1e-5, "{} came back as {}", depth, unpacked);
+        }
+    }
+
+    fn pixels(size: u32, hits: &[(usize, usize)]) -> Vec<u8> {
+        let mut pixels = vec![0; (size * size * 4) as usize];
+        for &(i, j) in hits.iter() {
+            let p = (j * size as usize + i) * 4;
+            pixels[p..p + 4].copy_from_slice(&[7, 0, 0, 255]);
+        }
+        pixels
+    }
+
+    #[test]
+    fn closest_pixel_prefers_the_center() {
+        // 5x5 window around (2, 2), the corner is sqrt(8) away, outside a radius of 2
+        assert_eq!(closest_pixel(&pixels(5, &[(0, 0), (2, 4), (3, 2)]), 5, 2), Some((3, 2)));
+        assert_eq!(closest_pixel(&pixels(5, &[(0, 0), (2, 4)]), 5, 2), Some((2, 4)));
+        assert_eq!(closest_pixel(&pixels(5, &[(0, 0), (4, 4)]), 5, 2), None);
+        assert_eq!(closest_pixel(&pixels(5, &[(2, 2), (2, 3)]), 5, 2), Some((2, 2)));
+        assert_eq!(closest_pixel(&pixels(1, &[(0, 0)]), 1, 0), Some((0, 0)));
+    }
+
+    #[test]
+    fn background_alpha_is_not_a_hit() {
+        let mut background = pixels(3, &[]);
+        for p in background.chunks_mut(4) {
+            p[3] = 255;
+        }
+        assert_eq!(closest_pixel(&background, 3, 1), None);
+    }
+}
@@ -0,0 +1,692 @@
+use crate::error::RenderError;
+use std::cell::{Cell, RefCell};
+use std::collections::HashSet;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use web_sys::{EventTarget, HtmlCanvasElement, KeyboardEvent, PointerEvent, WheelEvent};
+
+//--Platform-neutral input events--
+//  <note>
+//      Positions and deltas are in CSS pixels, keys use KeyboardEvent.code
+//      names ("KeyW", "Space", "ShiftLeft", ...), so the same stream can be
+//      produced by the browser or written by hand in native code.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum InputEvent {
+    PointerDown { button: MouseButton, x: f32, y: f32 },
+    PointerUp { button: MouseButton, x: f32, y: f32 },
+    PointerMove { x: f32, y: f32, dx: f32, dy: f32 },
+    //  Positive delta scrolls away from the user (zoom out)
+    Wheel { delta: f32 },
+    KeyDown { code: String },
+    KeyUp { code: String },
+    //  Two-finger gesture since the previous event: distance ratio,
+    //  rotation in radians (clockwise on screen) and the midpoint
+    Gesture { scale: f32, rotation: f32, x: f32, y: f32 },
+    PointerLockChange { locked: bool },
+}
+
+//--Input state accumulated over one frame--
+//  <note>
+//      begin_frame() clears the just-pressed/released sets and deltas,
+//      then every event of the frame is fed to handle_event().
+#[derive(Default)]
+pub struct InputState {
+    keys_down: HashSet<String>,
+    keys_pressed: HashSet<String>,
+    keys_released: HashSet<String>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    pointer: [f32; 2],
+    pointer_delta: [f32; 2],
+    wheel_delta: f32,
+    gesture_scale: f32,
+    gesture_rotation: f32,
+    pointer_locked: bool,
+}
+
+impl InputState {
+    pub fn new() -> Self {
+        Self {
+            gesture_scale: 1.,
+            ..Default::default()
+        }
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.pointer_delta = [0., 0.];
+        self.wheel_delta = 0.;
+        self.gesture_scale = 1.;
+        self.gesture_rotation = 0.;
+    }
+
+    pub fn handle_event(&mut self, event: &InputEvent) {
+        match event {
+            InputEvent::PointerDown { button, x, y } => {
+                if self.buttons_down.insert(*button) {
+                    self.buttons_pressed.insert(*button);
+                }
+                self.pointer = [*x, *y];
+            }
+            InputEvent::PointerUp { button, x, y } => {
+                if self.buttons_down.remove(button) {
+                    self.buttons_released.insert(*button);
+                }
+                self.pointer = [*x, *y];
+            }
+            InputEvent::PointerMove { x, y, dx, dy } => {
+                self.pointer = [*x, *y];
+                self.pointer_delta[0] += dx;
+                self.pointer_delta[1] += dy;
+            }
+            InputEvent::Wheel { delta } => self.wheel_delta += delta,
+            InputEvent::KeyDown { code } => {
+                if self.keys_down.insert(code.clone()) {
+                    self.keys_pressed.insert(code.clone());
+                }
+            }
+            InputEvent::KeyUp { code } => {
+                if self.keys_down.remove(code) {
+                    self.keys_released.insert(code.clone());
+                }
+            }
+            InputEvent::Gesture { scale, rotation, .. } => {
+                self.gesture_scale *= scale;
+                self.gesture_rotation += rotation;
+            }
+            InputEvent::PointerLockChange { locked } => self.pointer_locked = *locked,
+        }
+    }
+
+    //--Release every held key and button, e.g. when the page loses focus--
+    //  <return> the KeyUp/PointerUp events that were applied
+    //  <note>
+    //      Browsers don't send keyup or pointerup to a page without focus,
+    //      without this keys pressed while switching away stay held.
+    pub fn release_all(&mut self) -> Vec<InputEvent> {
+        let mut keys: Vec<String> = self.keys_down.iter().cloned().collect();
+        keys.sort();
+        let mut buttons: Vec<MouseButton> = self.buttons_down.iter().copied().collect();
+        buttons.sort_by_key(|b| *b as u8);
+        let [x, y] = self.pointer;
+        let events: Vec<InputEvent> = keys
+            .into_iter()
+            .map(|code| InputEvent::KeyUp { code })
+            .chain(buttons.into_iter().map(|button| InputEvent::PointerUp { button, x, y }))
+            .collect();
+        for event in events.iter() {
+            self.handle_event(event);
+        }
+        events
+    }
+
+    pub fn is_key_down(&self, code: &str) -> bool {
+        self.keys_down.contains(code)
+    }
+
+    pub fn was_key_pressed(&self, code: &str) -> bool {
+        self.keys_pressed.contains(code)
+    }
+
+    pub fn was_key_released(&self, code: &str) -> bool {
+        self.keys_released.contains(code)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn was_button_pressed(&self, button: MouseButton) -> bool {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn was_button_released(&self, button: MouseButton) -> bool {
+        self.buttons_released.contains(&button)
+    }
+
+    pub fn pointer_position(&self) -> [f32; 2] {
+        self.pointer
+    }
+
+    pub fn pointer_delta(&self) -> [f32; 2] {
+        self.pointer_delta
+    }
+
+    pub fn wheel_delta(&self) -> f32 {
+        self.wheel_delta
+    }
+
+    //--Accumulated pinch scale and twist of the frame--
+    pub fn gesture(&self) -> (f32, f32) {
+        (self.gesture_scale, self.gesture_rotation)
+    }
+
+    pub fn is_pointer_locked(&self) -> bool {
+        self.pointer_locked
+    }
+}
+
+//--Turns touch pointers into pinch/rotate gestures--
+//  <note>
+//      With one active pointer moves pass through unchanged,
+//      with two they become Gesture events. A second pointer landing
+//      releases the left button so the first finger's drag ends.
+#[derive(Default)]
+pub struct GestureTracker {
+    pointers: Vec<(i32, [f32; 2])>,
+}
+
+impl GestureTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    //--Track a new pointer--
+    //  <return> PointerDown for the first pointer, a synthetic left PointerUp for the second
+    pub fn pointer_down(&mut self, id: i32, button: Option<MouseButton>, x: f32, y: f32) -> Option<InputEvent> {
+        self.pointers.retain(|(p, _)| *p != id);
+        self.pointers.push((id, [x, y]));
+        match self.pointers.len() {
+            1 => button.map(|button| InputEvent::PointerDown { button, x, y }),
+            2 => Some(InputEvent::PointerUp {
+                button: MouseButton::Left,
+                x,
+                y,
+            }),
+            _ => None,
+        }
+    }
+
+    //--Stop tracking a pointer--
+    //  <return> PointerUp when the last pointer lifts
+    pub fn pointer_up(&mut self, id: i32, button: Option<MouseButton>, x: f32, y: f32) -> Option<InputEvent> {
+        let was_active = self.pointers.len();
+        self.pointers.retain(|(p, _)| *p != id);
+        if was_active == 1 && self.pointers.is_empty() {
+            button.map(|button| InputEvent::PointerUp { button, x, y })
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.pointers.clear();
+    }
+
+    //--Track a move and turn it into an event--
+    //  <return> None if the pointer isn't tracked
+    pub fn pointer_move(&mut self, id: i32, x: f32, y: f32) -> Option<InputEvent> {
+        let index = self.pointers.iter().position(|(p, _)| *p == id)?;
+        let before = self.pointers.clone();
+        let previous = before[index].1;
+        self.pointers[index].1 = [x, y];
+
+        if self.pointers.len() < 2 || index > 1 {
+            return Some(InputEvent::PointerMove {
+                x,
+                y,
+                dx: x - previous[0],
+                dy: y - previous[1],
+            });
+        }
+
+        let span = |p: &[(i32, [f32; 2])]| {
+            let (a, b) = (p[0].1, p[1].1);
+            let (dx, dy) = (b[0] - a[0], b[1] - a[1]);
+            ((dx * dx + dy * dy).sqrt(), dy.atan2(dx), [(a[0] + b[0]) / 2., (a[1] + b[1]) / 2.])
+        };
+        let (d0, a0, _) = span(&before);
+        let (d1, a1, center) = span(&self.pointers);
+        let mut rotation = a1 - a0;
+        if rotation > std::f32::consts::PI {
+            rotation -= 2. * std::f32::consts::PI;
+        } else if rotation < -std::f32::consts::PI {
+            rotation += 2. * std::f32::consts::PI;
+        }
+        Some(InputEvent::Gesture {
+            scale: if d0 > 0. { d1 / d0 } else { 1. },
+            rotation,
+            x: center[0],
+            y: center[1],
+        })
+    }
+}
+
+type Listener = (EventTarget, &'static str, Closure<dyn FnMut(web_sys::Event)>);
+
+//  Release stands for the page losing focus, poll() expands it using the held keys
+enum Queued {
+    Event(InputEvent),
+    Release,
+}
+
+//--Browser input attached to a canvas--
+//  <note>
+//      Listeners push InputEvents into a queue, poll() drains it once per frame.
+//      Keyboard input needs the canvas to have focus, it gets a tabindex and
+//      is focused on pointer down. Held keys and buttons are released on blur
+//      and when the page is hidden. Listeners are removed on drop.
+pub struct Input {
+    canvas: HtmlCanvasElement,
+    queue: Rc<RefCell<Vec<Queued>>>,
+    state: InputState,
+    listeners: Vec<Listener>,
+}
+
+impl Input {
+    pub fn attach(canvas: &HtmlCanvasElement) -> Result<Self, RenderError> {
+        let document = canvas
+            .owner_document()
+            .ok_or_else(|| RenderError::Context("canvas is not in a document".into()))?;
+        if !canvas.has_attribute("tabindex") {
+            canvas.set_tab_index(0);
+        }
+        let _ = canvas.style().set_property("touch-action", "none");
+
+        let queue = Rc::new(RefCell::new(Vec::new()));
+        let gestures = Rc::new(RefCell::new(GestureTracker::new()));
+        let mut input = Self {
+            canvas: canvas.clone(),
+            queue: queue.clone(),
+            state: InputState::new(),
+            listeners: Vec::new(),
+        };
+
+        let target: EventTarget = canvas.clone().into();
+        {
+            let (queue, gestures, canvas) = (queue.clone(), gestures.clone(), canvas.clone());
+            input.listen(&target, "pointerdown", move |event| {
+                let event: PointerEvent = event.unchecked_into();
+                let (x, y) = (event.offset_x() as f32, event.offset_y() as f32);
+                let _ = canvas.focus();
+                let _ = canvas.set_pointer_capture(event.pointer_id());
+                let button = mouse_button(event.button());
+                if let Some(event) = gestures.borrow_mut().pointer_down(event.pointer_id(), button, x, y) {
+                    queue.borrow_mut().push(Queued::Event(event));
+                }
+            })?;
+        }
+        {
+            let (queue, queue_lock, gestures) = (queue.clone(), queue.clone(), gestures.clone());
+            let locked = Rc::new(Cell::new(false));
+            let locked_move = locked.clone();
+            input.listen(&target, "pointermove", move |event| {
+                let event: PointerEvent = event.unchecked_into();
+                let (x, y) = (event.offset_x() as f32, event.offset_y() as f32);
+                let mut gestures = gestures.borrow_mut();
+                let tracked = gestures.pointer_move(event.pointer_id(), x, y);
+                let event = match tracked {
+                    // Under pointer lock the position is frozen, use the raw movement
+                    _ if locked_move.get() => Some(InputEvent::PointerMove {
+                        x,
+                        y,
+                        dx: event.movement_x() as f32,
+                        dy: event.movement_y() as f32,
+                    }),
+                    Some(tracked) => Some(tracked),
+                    None if event.pointer_type() == "mouse" => Some(InputEvent::PointerMove {
+                        x,
+                        y,
+                        dx: event.movement_x() as f32,
+                        dy: event.movement_y() as f32,
+                    }),
+                    None => None,
+                };
+                if let Some(event) = event {
+                    queue.borrow_mut().push(Queued::Event(event));
+                }
+            })?;
+
+            let document_lock = document.clone();
+            let canvas_element: web_sys::Element = canvas.clone().into();
+            input.listen(&document.clone().into(), "pointerlockchange", move |_| {
+                let element = document_lock.pointer_lock_element();
+                let is_locked = element.is_some_and(|e| e == canvas_element);
+                locked.set(is_locked);
+                queue_lock
+                    .borrow_mut()
+                    .push(Queued::Event(InputEvent::PointerLockChange { locked: is_locked }));
+            })?;
+        }
+        for &name in ["pointerup", "pointercancel"].iter() {
+            let (queue, gestures) = (queue.clone(), gestures.clone());
+            input.listen(&target, name, move |event| {
+                let event: PointerEvent = event.unchecked_into();
+                let (x, y) = (event.offset_x() as f32, event.offset_y() as f32);
+                let button = mouse_button(event.button());
+                if let Some(event) = gestures.borrow_mut().pointer_up(event.pointer_id(), button, x, y) {
+                    queue.borrow_mut().push(Queued::Event(event));
+                }
+            })?;
+        }
+        {
+            let queue = queue.clone();
+            input.listen(&target, "wheel", move |event| {
+                event.prevent_default();
+                let event: WheelEvent = event.unchecked_into();
+                // Lines and pages are converted to pixels like browsers do for mice
+                let scale = match event.delta_mode() {
+                    WheelEvent::DOM_DELTA_LINE => 16.,
+                    WheelEvent::DOM_DELTA_PAGE => 800.,
+                    _ => 1.,
+                };
+                queue.borrow_mut().push(Queued::Event(InputEvent::Wheel {
+                    delta: (event.delta_y() * scale) as f32,
+                }));
+            })?;
+        }
+        input.listen(&target, "contextmenu", |event| event.prevent_default())?;
+        for &(name, down) in [("keydown", true), ("keyup", false)].iter() {
+            let queue = queue.clone();
+            input.listen(&target, name, move |event| {
+                let event: KeyboardEvent = event.unchecked_into();
+                if event.repeat() {
+                    return;
+                }
+                let code = event.code();
+                queue.borrow_mut().push(Queued::Event(if down {
+                    InputEvent::KeyDown { code }
+                } else {
+                    InputEvent::KeyUp { code }
+                }));
+            })?;
+        }
+        // Key and pointer ups go to whatever has focus, so release everything when leaving
+        let window: EventTarget = web_sys::window()
+            .ok_or_else(|| RenderError::Context("no window".into()))?
+            .into();
+        for target in [&target, &window].iter() {
+            let (queue, gestures) = (queue.clone(), gestures.clone());
+            input.listen(target, "blur", move |_| {
+                gestures.borrow_mut().clear();
+                queue.borrow_mut().push(Queued::Release);
+            })?;
+        }
+        {
+            let document_hidden = document.clone();
+            input.listen(&document.into(), "visibilitychange", move |_| {
+                if document_hidden.hidden() {
+                    gestures.borrow_mut().clear();
+                    queue.borrow_mut().push(Queued::Release);
+                }
+            })?;
+        }
+
+        Ok(input)
+    }
+
+    fn listen<F: FnMut(web_sys::Event) + 'static>(
+        &mut self,
+        target: &EventTarget,
+        name: &'static str,
+        f: F,
+    ) -> Result<(), RenderError> {
+        let closure = Closure::wrap(Box::new(f) as Box<dyn FnMut(web_sys::Event)>);
+        target
+            .add_event_listener_with_callback(name, closure.as_ref().unchecked_ref())
+            .map_err(|_| RenderError::Context(format!("failed to listen to {}", name)))?;
+        self.listeners.push((target.clone(), name, closure));
+        Ok(())
+    }
+
+    //--Start a new frame and return the events received since the last one--
+    pub fn poll(&mut self) -> Vec<InputEvent> {
+        let queued: Vec<Queued> = self.queue.borrow_mut().drain(..).collect();
+        self.state.begin_frame();
+        let mut events = Vec::with_capacity(queued.len());
+        for queued in queued {
+            match queued {
+                Queued::Event(event) => {
+                    self.state.handle_event(&event);
+                    events.push(event);
+                }
+                Queued::Release => events.extend(self.state.release_all()),
+            }
+        }
+        events
+    }
+
+    pub fn state(&self) -> &InputState {
+        &self.state
+    }
+
+    //--Ask for pointer lock, browsers only grant it from a user gesture--
+    pub fn request_pointer_lock(&self) {
+        self.canvas.request_pointer_lock();
+    }
+
+    pub fn exit_pointer_lock(&self) {
+        if let Some(document) = self.canvas.owner_document() {
+            document.exit_pointer_lock();
+        }
+    }
+}
+
+impl Drop for Input {
+    fn drop(&mut self) {
+        for (target, name, closure) in self.listeners.iter() {
+            let _ = target.remove_event_listener_with_callback(name, closure.as_ref().unchecked_ref());
+        }
+    }
+}
+
+fn mouse_button(button: i16) -> Option<MouseButton> {
+    match button {
+        0 => Some(MouseButton::Left),
+        1 => Some(MouseButton::Middle),
+        2 => Some(MouseButton::Right),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_down(code: &str) -> InputEvent {
+        InputEvent::KeyDown { code: code.into() }
+    }
+
+    fn key_up(code: &str) -> InputEvent {
+        InputEvent::KeyUp { code: code.into() }
+    }
+
+    #[test]
+    fn presses_and_releases_last_one_frame() {
+        let mut state = InputState::new();
+        state.begin_frame();
+        state.handle_event(&key_down("KeyW"));
+        state.handle_event(&InputEvent::PointerDown {
+            button: MouseButton::Right,
+            x: 4.,
+            y: 8.,
+        });
+        assert!(state.is_key_down("KeyW") && state.was_key_pressed("KeyW"));
+        assert!(state.was_button_pressed(MouseButton::Right));
+        assert_eq!(state.pointer_position(), [4., 8.]);
+
+        // Held over the next frame without being pressed again
+        state.begin_frame();
+        state.handle_event(&key_down("KeyW"));
+        assert!(state.is_key_down("KeyW") && !state.was_key_pressed("KeyW"));
+        assert!(state.is_button_down(MouseButton::Right) && !state.was_button_pressed(MouseButton::Right));
+
+        state.begin_frame();
+        state.handle_event(&key_up("KeyW"));
+        state.handle_event(&key_up("KeyA"));
+        assert!(!state.is_key_down("KeyW") && state.was_key_released("KeyW"));
+        assert!(!state.was_key_released("KeyA"));
+        state.begin_frame();
+        assert!(!state.was_key_released("KeyW"));
+    }
+
+    #[test]
+    fn deltas_accumulate_within_a_frame() {
+        let mut state = InputState::new();
+        state.begin_frame();
+        for &(x, dx) in [(1., 1.), (3., 2.), (6., 3.)].iter() {
+            state.handle_event(&InputEvent::PointerMove { x, y: 0., dx, dy: -1. });
+        }
+        state.handle_event(&InputEvent::Wheel { delta: 16. });
+        state.handle_event(&InputEvent::Wheel { delta: -4. });
+        state.handle_event(&InputEvent::Gesture {
+            scale: 2.,
+            rotation: 0.5,
+            x: 0.,
+            y: 0.,
+        });
+        state.handle_event(&InputEvent::Gesture {
+            scale: 1.5,
+            rotation: -0.25,
+            x: 0.,
+            y: 0.,
+        });
+        assert_eq!(state.pointer_position(), [6., 0.]);
+        assert_eq!(state.pointer_delta(), [6., -3.]);
+        assert_eq!(state.wheel_delta(), 12.);
+        assert_eq!(state.gesture(), (3., 0.25));
+
+        state.begin_frame();
+        assert_eq!(state.pointer_delta(), [0., 0.]);
+        assert_eq!(state.wheel_delta(), 0.);
+        assert_eq!(state.gesture(), (1., 0.));
+        assert_eq!(state.pointer_position(), [6., 0.]);
+    }
+
+    #[test]
+    fn release_all_lets_go_of_held_input() {
+        let mut state = InputState::new();
+        state.begin_frame();
+        state.handle_event(&key_down("ShiftLeft"));
+        state.handle_event(&key_down("KeyD"));
+        state.handle_event(&InputEvent::PointerDown {
+            button: MouseButton::Left,
+            x: 10.,
+            y: 20.,
+        });
+        state.begin_frame();
+        let released = state.release_all();
+        assert_eq!(
+            released,
+            [
+                key_up("KeyD"),
+                key_up("ShiftLeft"),
+                InputEvent::PointerUp {
+                    button: MouseButton::Left,
+                    x: 10.,
+                    y: 20.,
+                },
+            ]
+        );
+        assert!(!state.is_key_down("KeyD") && state.was_key_released("ShiftLeft"));
+        assert!(!state.is_button_down(MouseButton::Left) && state.was_button_released(MouseButton::Left));
+        assert!(state.release_all().is_empty());
+    }
+
+    fn feed(state: &mut InputState, event: Option<InputEvent>) -> Option<InputEvent> {
+        if let Some(event) = event.as_ref() {
+            state.handle_event(event);
+        }
+        event
+    }
+
+    #[test]
+    fn single_pointer_moves_pass_through() {
+        let mut tracker = GestureTracker::new();
+        assert_eq!(tracker.pointer_move(1, 5., 5.), None);
+        assert_eq!(
+            tracker.pointer_down(1, Some(MouseButton::Left), 10., 10.),
+            Some(InputEvent::PointerDown {
+                button: MouseButton::Left,
+                x: 10.,
+                y: 10.,
+            })
+        );
+        assert_eq!(
+            tracker.pointer_move(1, 13., 6.),
+            Some(InputEvent::PointerMove {
+                x: 13.,
+                y: 6.,
+                dx: 3.,
+                dy: -4.,
+            })
+        );
+        assert_eq!(
+            tracker.pointer_up(1, Some(MouseButton::Left), 13., 6.),
+            Some(InputEvent::PointerUp {
+                button: MouseButton::Left,
+                x: 13.,
+                y: 6.,
+            })
+        );
+        assert_eq!(tracker.pointer_move(1, 0., 0.), None);
+    }
+
+    #[test]
+    fn second_finger_turns_a_drag_into_a_gesture() {
+        let mut tracker = GestureTracker::new();
+        let mut state = InputState::new();
+        state.begin_frame();
+
+        feed(&mut state, tracker.pointer_down(1, Some(MouseButton::Left), 0., 0.));
+        feed(&mut state, tracker.pointer_move(1, 2., 0.));
+        assert_eq!(
+            feed(&mut state, tracker.pointer_down(2, Some(MouseButton::Left), 4., 0.)),
+            Some(InputEvent::PointerUp {
+                button: MouseButton::Left,
+                x: 4.,
+                y: 0.,
+            })
+        );
+
+        // Spreading and turning the second finger a quarter turn around the first
+        match feed(&mut state, tracker.pointer_move(2, 2., 4.)) {
+            Some(InputEvent::Gesture { scale, rotation, x, y }) => {
+                assert!((scale - 2.).abs() < 1e-6);
+                assert!((rotation - std::f32::consts::FRAC_PI_2).abs() < 1e-6);
+                assert_eq!([x, y], [2., 2.]);
+            }
+            other => panic!("expected a gesture, got {:?}", other),
+        }
+        // A third finger is tracked but doesn't take part in the gesture
+        assert_eq!(tracker.pointer_down(3, Some(MouseButton::Left), 9., 9.), None);
+        assert!(matches!(tracker.pointer_move(3, 10., 9.), Some(InputEvent::PointerMove { .. })));
+
+        // Only lifting the last finger reports an up, the button is already released by then
+        assert_eq!(tracker.pointer_up(3, Some(MouseButton::Left), 10., 9.), None);
+        assert_eq!(tracker.pointer_up(2, Some(MouseButton::Left), 2., 4.), None);
+        assert!(matches!(tracker.pointer_move(1, 3., 0.), Some(InputEvent::PointerMove { .. })));
+        state.begin_frame();
+        assert!(feed(&mut state, tracker.pointer_up(1, Some(MouseButton::Left), 3., 0.)).is_some());
+
+        assert!(!state.is_button_down(MouseButton::Left));
+        assert!(!state.was_button_released(MouseButton::Left));
+    }
+
+    #[test]
+    fn rotation_wraps_across_the_negative_x_axis() {
+        let mut tracker = GestureTracker::new();
+        tracker.pointer_down(1, None, 0., 0.);
+        tracker.pointer_down(2, None, -1., 0.1);
+        match tracker.pointer_move(2, -1., -0.1) {
+            Some(InputEvent::Gesture { rotation, .. }) => assert!(rotation.abs() < 0.3, "{}", rotation),
+            other => panic!("expected a gesture, got {:?}", other),
+        }
+
+        tracker.clear();
+        assert_eq!(tracker.pointer_move(1, 1., 1.), None);
+    }
+}
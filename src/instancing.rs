@@ -0,0 +1,351 @@
+use crate::bounds::Aabb;
+use crate::context::Restorable;
+use crate::error::RenderError;
+use crate::mat_4::Matrix;
+use crate::material::{Material, ShaderProgram};
+use crate::mesh::Mesh;
+use crate::webgl;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use wasm_bindgen::JsCast;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{AngleInstancedArrays, WebGl2RenderingContext, WebGlBuffer};
+
+//--Per-instance attributes, read by shader/instanced.vert--
+//      instanceModel0..3 vec4 : columns of the instance's model matrix
+//      instanceColor     vec4 : multiplied with the vertex color
+const MODEL_ATTRIBUTES: [&str; 4] = ["instanceModel0", "instanceModel1", "instanceModel2", "instanceModel3"];
+const COLOR_ATTRIBUTE: &str = "instanceColor";
+//  Optional float attribute with the instance's index, see draw_indexed()
+const INDEX_ATTRIBUTE: &str = "instanceIndex";
+//  Floats per instance: a 4x4 matrix and an RGBA color
+const INSTANCE_FLOATS: usize = 20;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Instance {
+    //  Relative to the model matrix the batch is submitted with
+    pub model: Matrix,
+    pub color: [f32; 4],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            model: Matrix::new(),
+            color: [1., 1., 1., 1.],
+        }
+    }
+}
+
+//--Program for instanced draws, shader/instanced.vert with the default fragment shader--
+pub fn instanced_program(gl: &GL) -> Result<ShaderProgram, RenderError> {
+    ShaderProgram::new(
+        gl,
+        include_str!("shader/instanced.vert"),
+        include_str!("shader/fragment.frag"),
+    )
+}
+
+//--How the context draws instances--
+#[derive(Clone)]
+pub enum Instancing {
+    //  WebGL2 context, instancing is core
+    Native(WebGl2RenderingContext),
+    //  WebGL1 with ANGLE_instanced_arrays
+    Angle(AngleInstancedArrays),
+    //  One draw per instance with the instance attributes set as constants
+    Unsupported,
+}
+
+impl Instancing {
+    pub fn detect(gl: &GL) -> Self {
+        if let Some(gl2) = webgl::webgl2(gl) {
+            return Instancing::Native(gl2.clone());
+        }
+        match gl.get_extension("ANGLE_instanced_arrays") {
+            Ok(Some(ext)) => Instancing::Angle(ext.unchecked_into()),
+            _ => Instancing::Unsupported,
+        }
+    }
+
+    pub fn is_supported(&self) -> bool {
+        !matches!(self, Instancing::Unsupported)
+    }
+
+    fn divisor(&self, location: u32, divisor: u32) {
+        match self {
+            Instancing::Native(gl2) => gl2.vertex_attrib_divisor(location, divisor),
+            Instancing::Angle(ext) => ext.vertex_attrib_divisor_angle(location, divisor),
+            Instancing::Unsupported => {}
+        }
+    }
+
+    fn draw_elements(&self, count: i32, instances: i32) {
+        match self {
+            Instancing::Native(gl2) => {
+                gl2.draw_elements_instanced_with_i32(GL::TRIANGLES, count, GL::UNSIGNED_SHORT, 0, instances)
+            }
+            Instancing::Angle(ext) => {
+                ext.draw_elements_instanced_angle_with_i32(GL::TRIANGLES, count, GL::UNSIGNED_SHORT, 0, instances)
+            }
+            Instancing::Unsupported => {}
+        }
+    }
+}
+
+//--Many copies of one mesh drawn with one material--
+//  <note>
+//      The material's program has to read the instance attributes, see
+//      shader/instanced.vert. Instances live on the CPU and are uploaded
+//      to a single interleaved buffer when they changed since the last draw,
+//      unless the buffer is handed to the GPU through gpu_buffer().
+pub struct InstanceBatch {
+    pub mesh: Rc<Mesh>,
+    pub material: Rc<Material>,
+    instances: RefCell<Vec<Instance>>,
+    buffer: RefCell<Option<WebGlBuffer>>,
+    //  Instances the buffer has room for
+    capacity: Cell<usize>,
+    dirty: Cell<bool>,
+    //  Instances written into the buffer on the GPU, None while they live on the CPU
+    gpu_count: Cell<Option<usize>>,
+}
+
+impl InstanceBatch {
+    pub fn new(mesh: Rc<Mesh>, material: Rc<Material>) -> Self {
+        Self {
+            mesh,
+            material,
+            instances: RefCell::new(Vec::new()),
+            buffer: RefCell::new(None),
+            capacity: Cell::new(0),
+            dirty: Cell::new(true),
+            gpu_count: Cell::new(None),
+        }
+    }
+
+    //--Append an instance--
+    //  <return> usize  index of the instance
+    pub fn push(&self, instance: Instance) -> usize {
+        let mut instances = self.instances.borrow_mut();
+        instances.push(instance);
+        self.dirty.set(true);
+        self.gpu_count.set(None);
+        instances.len() - 1
+    }
+
+    //--Replace an instance, out of range indices are ignored--
+    pub fn set(&self, index: usize, instance: Instance) {
+        if let Some(slot) = self.instances.borrow_mut().get_mut(index) {
+            *slot = instance;
+            self.dirty.set(true);
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<Instance> {
+        self.instances.borrow().get(index).copied()
+    }
+
+    //--Replace every instance at once--
+    pub fn set_all(&self, instances: Vec<Instance>) {
+        *self.instances.borrow_mut() = instances;
+        self.dirty.set(true);
+        self.gpu_count.set(None);
+    }
+
+    pub fn clear(&self) {
+        self.instances.borrow_mut().clear();
+        self.dirty.set(true);
+        self.gpu_count.set(None);
+    }
+
+    pub fn len(&self) -> usize {
+        self.gpu_count.get().unwrap_or_else(|| self.instances.borrow().len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    //--Box around every instance, relative to the batch's model matrix--
+    //  <return> Option<Aabb>  None when empty, written on the GPU or the mesh has no bounds
+    pub fn bounds(&self) -> Option<Aabb> {
+        let local = self.mesh.bounds?.aabb;
+        self.instances
+            .borrow()
+            .iter()
+            .map(|instance| local.transformed(&instance.model))
+            .reduce(|a, b| a.union(&b))
+    }
+
+    //--Buffer for `count` instances written on the GPU, e.g. by transform feedback--
+    //  <return> WebGlBuffer  20 floats per instance, the model matrix's columns then the color
+    //  <note>
+    //      CPU instances are dropped, the batch draws `count` instances until
+    //      push(), set_all() or clear() hand it back to the CPU.
+    pub fn gpu_buffer(&self, gl: &GL, count: usize) -> Result<WebGlBuffer, RenderError> {
+        self.instances.borrow_mut().clear();
+        self.reserve(gl, count)?;
+        gl.bind_buffer(GL::ARRAY_BUFFER, None);
+        self.gpu_count.set(Some(count));
+        self.dirty.set(false);
+        self.buffer
+            .borrow()
+            .clone()
+            .ok_or_else(|| RenderError::Buffer("instance buffer is missing".into()))
+    }
+
+    //--Upload instance data if it changed--
+    fn upload(&self, gl: &GL) -> Result<(), RenderError> {
+        if !self.dirty.get() && self.buffer.borrow().is_some() {
+            return Ok(());
+        }
+        let instances = self.instances.borrow();
+        let mut data = Vec::with_capacity(instances.len() * INSTANCE_FLOATS);
+        for instance in instances.iter() {
+            data.extend_from_slice(&instance.model.get_value());
+            data.extend_from_slice(&instance.color);
+        }
+
+        self.reserve(gl, instances.len())?;
+        unsafe {
+            let view = js_sys::Float32Array::view(&data);
+            gl.buffer_sub_data_with_i32_and_array_buffer_view(GL::ARRAY_BUFFER, 0, &view);
+        }
+        self.dirty.set(false);
+        Ok(())
+    }
+
+    //--Create the buffer or grow it to hold `count` instances, leaving it bound--
+    fn reserve(&self, gl: &GL, count: usize) -> Result<(), RenderError> {
+        if self.buffer.borrow().is_none() {
+            let buffer = gl
+                .create_buffer()
+                .ok_or_else(|| RenderError::Buffer("failed to create instance buffer".into()))?;
+            *self.buffer.borrow_mut() = Some(buffer);
+            self.capacity.set(0);
+        }
+        gl.bind_buffer(GL::ARRAY_BUFFER, self.buffer.borrow().as_ref());
+        if count > self.capacity.get() {
+            // Grow in powers of two so batches that gain a few instances per frame don't reallocate every frame
+            let capacity = count.next_power_of_two();
+            gl.buffer_data_with_i32(GL::ARRAY_BUFFER, (capacity * INSTANCE_FLOATS * 4) as i32, GL::DYNAMIC_DRAW);
+            self.capacity.set(capacity);
+        }
+        Ok(())
+    }
+
+    //--Draw every instance--
+    //  <argument>
+    //      program   &ShaderProgram : program in use, the material's
+    //      instancing &Instancing   : detected once per context
+    //      enabled   &mut Vec<u32>  : attribute arrays enabled by the renderer
+    //  <note>
+    //      Divisors are reset afterwards so plain meshes can reuse the locations.
+    pub fn draw(
+        &self,
+        gl: &GL,
+        program: &ShaderProgram,
+        instancing: &Instancing,
+        enabled: &mut Vec<u32>,
+    ) -> Result<(), RenderError> {
+        self.draw_indexed(gl, program, instancing, enabled, None)
+    }
+
+    //--Draw every instance, also feeding each one's index to instanceIndex--
+    //  <argument>
+    //      indices Option<&WebGlBuffer> : floats 0, 1, 2, ... at least len() long,
+    //                                     unused when instancing is unsupported
+    //  <note>
+    //      Used by the ID pass to tell instances apart.
+    pub fn draw_indexed(
+        &self,
+        gl: &GL,
+        program: &ShaderProgram,
+        instancing: &Instancing,
+        enabled: &mut Vec<u32>,
+        indices: Option<&WebGlBuffer>,
+    ) -> Result<(), RenderError> {
+        let count = self.len();
+        if count == 0 {
+            return Ok(());
+        }
+        self.mesh.bind(gl, program, enabled);
+
+        let model: Vec<i32> = MODEL_ATTRIBUTES
+            .iter()
+            .map(|name| program.attrib_location(gl, name))
+            .collect();
+        let color = program.attrib_location(gl, COLOR_ATTRIBUTE);
+        let index = match indices {
+            Some(_) => program.attrib_location(gl, INDEX_ATTRIBUTE),
+            None => -1,
+        };
+
+        if !instancing.is_supported() {
+            // Constant attribute values stand in for the per-instance arrays
+            for &location in model.iter().chain([color, index].iter()).filter(|&&l| l >= 0) {
+                gl.disable_vertex_attrib_array(location as u32);
+            }
+            for (i, instance) in self.instances.borrow().iter().enumerate() {
+                let m = instance.model.get_value();
+                for (column, &location) in model.iter().enumerate() {
+                    if location >= 0 {
+                        gl.vertex_attrib4fv_with_f32_array(location as u32, &m[column * 4..column * 4 + 4]);
+                    }
+                }
+                if color >= 0 {
+                    gl.vertex_attrib4fv_with_f32_array(color as u32, &instance.color);
+                }
+                if index >= 0 {
+                    gl.vertex_attrib1f(index as u32, i as f32);
+                }
+                self.mesh.draw(gl);
+            }
+            return Ok(());
+        }
+
+        self.upload(gl)?;
+        let stride = (INSTANCE_FLOATS * 4) as i32;
+        let mut bound = Vec::with_capacity(6);
+        for (i, &location) in model.iter().chain(std::iter::once(&color)).enumerate() {
+            if location < 0 {
+                continue;
+            }
+            let location = location as u32;
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(location, 4, GL::FLOAT, false, stride, (i * 16) as i32);
+            instancing.divisor(location, 1);
+            bound.push(location);
+        }
+        if let (Some(buffer), true) = (indices, index >= 0) {
+            let location = index as u32;
+            gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(location, 1, GL::FLOAT, false, 0, 0);
+            instancing.divisor(location, 1);
+            bound.push(location);
+        }
+
+        instancing.draw_elements(self.mesh.index_count, count as i32);
+
+        for location in bound {
+            instancing.divisor(location, 0);
+            gl.disable_vertex_attrib_array(location);
+            enabled.retain(|&l| l != location);
+        }
+        Ok(())
+    }
+}
+
+impl Restorable for InstanceBatch {
+    //  The buffer is rebuilt from the instances on the next draw, GPU-written
+    //  instances are gone until their writer fills a new buffer
+    fn restore(&self, _gl: &GL) -> Result<(), RenderError> {
+        *self.buffer.borrow_mut() = None;
+        self.capacity.set(0);
+        self.dirty.set(true);
+        self.gpu_count.set(None);
+        Ok(())
+    }
+}
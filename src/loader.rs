@@ -0,0 +1,375 @@
+use crate::animation::{Channel, Clip, Interpolation, Property};
+use crate::error::RenderError;
+use crate::mat_4::Matrix;
+use crate::pbr::PbrMaterial;
+use crate::skin::{Joint, Skeleton, Transform};
+use crate::vec_3;
+use std::collections::HashMap;
+
+//--Triangle mesh kept on the CPU--
+//  <note>
+//      colors is RGBA per vertex, white when the source has none.
+//      tex_coords (2 per vertex) and tangents (4 per vertex, w is the
+//      bitangent sign) are optional and either empty or complete, as are
+//      joints and weights (4 influences per vertex) of skinned meshes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshData {
+    pub positions: Vec<f32>,
+    pub normals: Vec<f32>,
+    pub colors: Vec<f32>,
+    pub tex_coords: Vec<f32>,
+    pub tangents: Vec<f32>,
+    //  Skeleton joint indices, stored as floats for the vertex attribute
+    pub joints: Vec<f32>,
+    pub weights: Vec<f32>,
+    pub indices: Vec<u16>,
+}
+
+impl MeshData {
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len() / 3
+    }
+
+    //--Append another mesh, offsetting its indices--
+    pub fn append(&mut self, other: &MeshData) -> Result<(), RenderError> {
+        let base = self.vertex_count();
+        if base + other.vertex_count() > u16::MAX as usize + 1 {
+            return Err(RenderError::Resource("mesh has more than 65536 vertices".into()));
+        }
+        let count = other.vertex_count();
+        self.positions.extend_from_slice(&other.positions);
+        self.normals.extend_from_slice(&other.normals);
+        self.colors.extend_from_slice(&other.colors);
+        append_optional(&mut self.tex_coords, base, &other.tex_coords, count, 2);
+        append_optional(&mut self.tangents, base, &other.tangents, count, 4);
+        append_optional(&mut self.joints, base, &other.joints, count, 4);
+        append_optional(&mut self.weights, base, &other.weights, count, 4);
+        self.indices.extend(other.indices.iter().map(|i| i + base as u16));
+        Ok(())
+    }
+}
+
+//--Append an optional attribute, zero filling whichever side lacks it--
+fn append_optional(dst: &mut Vec<f32>, dst_count: usize, src: &[f32], src_count: usize, size: usize) {
+    if dst.is_empty() && src.is_empty() {
+        return;
+    }
+    dst.resize(dst_count * size, 0.);
+    if src.is_empty() {
+        dst.resize((dst_count + src_count) * size, 0.);
+    } else {
+        dst.extend_from_slice(src);
+    }
+}
+
+//--Triangles sharing one glTF material--
+pub struct ModelPart {
+    pub mesh: MeshData,
+    //  Index into Model::materials, None for the glTF default material
+    pub material: Option<usize>,
+}
+
+//--PNG or JPEG file embedded in a glTF--
+pub struct EncodedImage {
+    pub bytes: Vec<u8>,
+    pub mime_type: String,
+}
+
+//--Static glTF scene with its materials--
+//  <note>
+//      PbrMaterial texture references index `textures`, which names the image
+//      each glTF texture samples. Samplers are ignored, textures repeat when
+//      their size allows it.
+pub struct Model {
+    pub parts: Vec<ModelPart>,
+    pub materials: Vec<PbrMaterial>,
+    pub images: Vec<EncodedImage>,
+    //  Index into `images` per glTF texture
+    pub textures: Vec<usize>,
+}
+
+//--Load every triangle primitive of a binary glTF (.glb), one part per material--
+//  <argument>
+//      bytes &[u8] : .glb file, or a .gltf whose buffers are all embedded
+//  <note>
+//      Node transforms of the default scene are baked into the vertices.
+//      Buffers and images referenced by external URIs are not supported.
+//      Only TEXCOORD_0 is read, textures on other sets sample it as well.
+pub fn load_gltf_model(bytes: &[u8]) -> Result<Model, RenderError> {
+    let gltf = parse_gltf(bytes)?;
+    let blob = gltf.blob.as_deref();
+    let scene = default_scene(&gltf)?;
+
+    let mut parts: Vec<ModelPart> = Vec::new();
+    let mut stack: Vec<(gltf::Node, Matrix)> = scene.nodes().map(|n| (n, Matrix::new())).collect();
+    while let Some((node, parent)) = stack.pop() {
+        let mut world = parent;
+        let mut local = Matrix::new();
+        local.set_value(&flatten(&node.transform().matrix()));
+        world.multiply(&local);
+
+        if let Some(mesh) = node.mesh() {
+            for primitive in mesh.primitives() {
+                if primitive.mode() != gltf::mesh::Mode::Triangles {
+                    continue;
+                }
+                let material = primitive.material().index();
+                let part = match parts.iter().position(|p| p.material == material) {
+                    Some(i) => &mut parts[i],
+                    None => {
+                        parts.push(ModelPart {
+                            mesh: MeshData::default(),
+                            material,
+                        });
+                        parts.last_mut().unwrap()
+                    }
+                };
+                let reader = primitive.reader(|_| blob);
+                part.mesh.append(&read_primitive(&reader, &world)?)?;
+            }
+        }
+        for child in node.children() {
+            stack.push((child, world));
+        }
+    }
+
+    if parts.is_empty() {
+        return Err(RenderError::Resource("glTF has no triangle meshes".into()));
+    }
+    for part in parts.iter_mut() {
+        // Baked vertices no longer follow any skeleton
+        part.mesh.joints.clear();
+        part.mesh.weights.clear();
+    }
+
+    let mut images = Vec::new();
+    for image in gltf.images() {
+        match image.source() {
+            gltf::image::Source::View { view, mime_type } => {
+                let buffer = blob.ok_or_else(|| RenderError::Resource("image buffer is missing".into()))?;
+                let bytes = buffer
+                    .get(view.offset()..view.offset() + view.length())
+                    .ok_or_else(|| RenderError::Resource("image exceeds its buffer".into()))?;
+                images.push(EncodedImage {
+                    bytes: bytes.to_vec(),
+                    mime_type: mime_type.to_string(),
+                });
+            }
+            gltf::image::Source::Uri { uri, .. } => {
+                return Err(RenderError::Resource(format!("external image '{}' is not supported, use a .glb file", uri)));
+            }
+        }
+    }
+
+    Ok(Model {
+        parts,
+        materials: gltf.materials().map(|m| PbrMaterial::from_gltf(&m)).collect(),
+        images,
+        textures: gltf.textures().map(|t| t.source().index()).collect(),
+    })
+}
+
+//--Skinned mesh with its skeleton and animation clips--
+pub struct SkinnedModel {
+    pub mesh: MeshData,
+    pub skeleton: Skeleton,
+    pub clips: Vec<Clip>,
+}
+
+//--Load the first skinned mesh of a binary glTF (.glb) and the clips animating its joints--
+//  <note>
+//      Vertices stay in bind pose mesh space, the mesh node's own transform
+//      is ignored as glTF requires; transforms above the root joints become
+//      Skeleton::root. Channels targeting other nodes or morph weights are
+//      skipped. Only the first set of joints and weights is read.
+pub fn load_gltf_skinned(bytes: &[u8]) -> Result<SkinnedModel, RenderError> {
+    let gltf = parse_gltf(bytes)?;
+    let blob = gltf.blob.as_deref();
+    let scene = default_scene(&gltf)?;
+
+    // Parent and world matrix of every node in the scene
+    let mut parents: HashMap<usize, usize> = HashMap::new();
+    let mut worlds: HashMap<usize, Matrix> = HashMap::new();
+    let mut skinned = None;
+    let mut stack: Vec<(gltf::Node, Matrix)> = scene.nodes().map(|n| (n, Matrix::new())).collect();
+    while let Some((node, parent)) = stack.pop() {
+        let mut world = parent;
+        let mut local = Matrix::new();
+        local.set_value(&flatten(&node.transform().matrix()));
+        world.multiply(&local);
+        worlds.insert(node.index(), world);
+        if let (Some(mesh), Some(skin), None) = (node.mesh(), node.skin(), &skinned) {
+            skinned = Some((mesh, skin));
+        }
+        for child in node.children() {
+            parents.insert(child.index(), node.index());
+            stack.push((child, world));
+        }
+    }
+    let (mesh, skin) = skinned.ok_or_else(|| RenderError::Resource("glTF has no skinned mesh".into()))?;
+
+    let mut data = MeshData::default();
+    for primitive in mesh.primitives() {
+        if primitive.mode() != gltf::mesh::Mode::Triangles {
+            continue;
+        }
+        let reader = primitive.reader(|_| blob);
+        data.append(&read_primitive(&reader, &Matrix::new())?)?;
+    }
+    if data.indices.is_empty() || data.joints.is_empty() || data.weights.is_empty() {
+        return Err(RenderError::Resource("skinned mesh has no triangles with joints and weights".into()));
+    }
+
+    let joint_of: HashMap<usize, usize> = skin.joints().enumerate().map(|(i, node)| (node.index(), i)).collect();
+    let inverse_binds: Vec<[[f32; 4]; 4]> = skin
+        .reader(|_| blob)
+        .read_inverse_bind_matrices()
+        .map(|m| m.collect())
+        .unwrap_or_default();
+    let mut root = Matrix::new();
+    let mut joints = Vec::with_capacity(joint_of.len());
+    for (i, node) in skin.joints().enumerate() {
+        let parent = parents.get(&node.index()).and_then(|p| joint_of.get(p).copied());
+        if parent.is_none() {
+            if let Some(world) = parents.get(&node.index()).and_then(|p| worlds.get(p)) {
+                root = *world;
+            }
+        }
+        let (translation, rotation, scale) = node.transform().decomposed();
+        let mut inverse_bind = Matrix::new();
+        if let Some(m) = inverse_binds.get(i) {
+            inverse_bind.set_value(&flatten(m));
+        }
+        joints.push(Joint {
+            name: node.name().map_or_else(|| format!("joint{}", i), str::to_string),
+            parent,
+            rest: Transform {
+                translation,
+                rotation,
+                scale,
+            },
+            inverse_bind,
+        });
+    }
+    let skeleton = Skeleton::new(joints, root)?;
+
+    let mut clips = Vec::new();
+    for (index, animation) in gltf.animations().enumerate() {
+        let mut channels = Vec::new();
+        for channel in animation.channels() {
+            let joint = match joint_of.get(&channel.target().node().index()) {
+                Some(&joint) => joint,
+                None => continue,
+            };
+            let reader = channel.reader(|_| blob);
+            let (times, outputs) = match (reader.read_inputs(), reader.read_outputs()) {
+                (Some(times), Some(outputs)) => (times.collect::<Vec<f32>>(), outputs),
+                _ => continue,
+            };
+            let (property, values): (Property, Vec<f32>) = match outputs {
+                gltf::animation::util::ReadOutputs::Translations(t) => (Property::Translation, t.flatten().collect()),
+                gltf::animation::util::ReadOutputs::Rotations(r) => (Property::Rotation, r.into_f32().flatten().collect()),
+                gltf::animation::util::ReadOutputs::Scales(s) => (Property::Scale, s.flatten().collect()),
+                gltf::animation::util::ReadOutputs::MorphTargetWeights(_) => continue,
+            };
+            let interpolation = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::Linear => Interpolation::Linear,
+                gltf::animation::Interpolation::Step => Interpolation::Step,
+                gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+            };
+            channels.push(Channel::new(joint, property, interpolation, times, values)?);
+        }
+        if !channels.is_empty() {
+            let name = animation.name().map_or_else(|| format!("clip{}", index), str::to_string);
+            clips.push(Clip::new(&name, channels));
+        }
+    }
+
+    Ok(SkinnedModel {
+        mesh: data,
+        skeleton,
+        clips,
+    })
+}
+
+//--Parse a .glb or self-contained .gltf, rejecting external buffers--
+fn parse_gltf(bytes: &[u8]) -> Result<gltf::Gltf, RenderError> {
+    let gltf = gltf::Gltf::from_slice(bytes).map_err(|e| RenderError::Resource(format!("invalid glTF: {}", e)))?;
+    for buffer in gltf.buffers() {
+        if let gltf::buffer::Source::Uri(uri) = buffer.source() {
+            return Err(RenderError::Resource(format!(
+                "external buffer '{}' is not supported, use a .glb file",
+                uri
+            )));
+        }
+    }
+    Ok(gltf)
+}
+
+fn default_scene(gltf: &gltf::Gltf) -> Result<gltf::Scene<'_>, RenderError> {
+    gltf.default_scene()
+        .or_else(|| gltf.scenes().next())
+        .ok_or_else(|| RenderError::Resource("glTF has no scene".into()))
+}
+
+fn read_primitive<'a, 's, F>(reader: &gltf::mesh::Reader<'a, 's, F>, world: &Matrix) -> Result<MeshData, RenderError>
+where
+    F: Clone + Fn(gltf::Buffer<'a>) -> Option<&'s [u8]>,
+{
+    let positions: Vec<[f32; 3]> = reader
+        .read_positions()
+        .ok_or_else(|| RenderError::Resource("primitive has no positions".into()))?
+        .collect();
+    if positions.len() > u16::MAX as usize + 1 {
+        return Err(RenderError::Resource("mesh has more than 65536 vertices".into()));
+    }
+
+    let mut normal_matrix = *world;
+    if normal_matrix.inverse().is_err() {
+        normal_matrix.set_identity();
+    }
+    normal_matrix.transpose();
+
+    let mut data = MeshData::default();
+    for p in positions.iter() {
+        data.positions.extend_from_slice(&world.transform_point(p));
+    }
+    data.colors = match reader.read_colors(0) {
+        Some(colors) => colors.into_rgba_f32().flatten().collect(),
+        None => vec![1.; positions.len() * 4],
+    };
+    data.indices = match reader.read_indices() {
+        Some(indices) => indices.into_u32().map(|i| i as u16).collect(),
+        None => (0..positions.len() as u32).map(|i| i as u16).collect(),
+    };
+    match reader.read_normals() {
+        Some(normals) => {
+            for n in normals {
+                data.normals.extend_from_slice(&vec_3::normalize(&normal_matrix.transform_direction(&n)));
+            }
+        }
+        None => data.smooth_normals(),
+    }
+    if let Some(tex_coords) = reader.read_tex_coords(0) {
+        data.tex_coords = tex_coords.into_f32().flatten().collect();
+    }
+    if let (Some(joints), Some(weights)) = (reader.read_joints(0), reader.read_weights(0)) {
+        data.joints = joints.into_u16().flatten().map(|j| j as f32).collect();
+        data.weights = weights.into_f32().flatten().collect();
+    }
+    if let Some(tangents) = reader.read_tangents() {
+        for t in tangents {
+            let xyz = vec_3::normalize(&world.transform_direction(&[t[0], t[1], t[2]]));
+            data.tangents.extend_from_slice(&[xyz[0], xyz[1], xyz[2], t[3]]);
+        }
+    }
+    Ok(data)
+}
+
+fn flatten(m: &[[f32; 4]; 4]) -> [f32; 16] {
+    let mut out = [0.; 16];
+    for (i, column) in m.iter().enumerate() {
+        out[i * 4..i * 4 + 4].copy_from_slice(column);
+    }
+    out
+}
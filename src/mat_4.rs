@@ -1,3 +1,6 @@
+use crate::error::RenderError;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Matrix {
     value: [f32; 16],
 }
@@ -187,12 +190,13 @@ impl Matrix {
         self
     }
 
+    //--Rotate around the X axis, clockwise looking down +X like rotate_around_y/z--
     pub fn rotate_around_x(&mut self, rad: f32) -> &mut Self {
         let r_c = rad.cos();
         let r_s = rad.sin();
         let x_mut = Matrix {
             value: [
-                r_c, 0., r_s, 0., 0., 1., 0., 0., -r_s, 0., r_c, 0., 0., 0., 0., 1.,
+                1., 0., 0., 0., 0., r_c, -r_s, 0., 0., r_s, r_c, 0., 0., 0., 0., 1.,
             ],
         };
         self.multiply(&x_mut);
@@ -257,7 +261,7 @@ impl Matrix {
     //--Create perspective projections matrix--
     //  <argument>
     //      aspect f32  ratio parameter is the width divided by the height
-    //      fovy   f32  field of view y-axis
+    //      fovy   f32  field of view y-axis, in radians
     //      near   f32  near clipping plane
     //      far    f32  far clipping plane
     //  <note>
@@ -276,16 +280,48 @@ impl Matrix {
         self
     }
 
+    //--Create orthographic projections matrix--
+    //  <argument>
+    //      left, right f32  horizontal clipping planes
+    //      bottom, top f32  vertical clipping planes
+    //      near, far   f32  depth clipping planes
+    //  <note>
+    //      Right-Handed Coordinate System!
+    pub fn orthographic(&mut self, left: f32, right: f32, bottom: f32, top: f32, near: f32, far: f32) -> &mut Self {
+        let w = right - left;
+        let h = top - bottom;
+        let d = far - near;
+        self.value = [
+            2. / w, 0., 0., 0.,
+            0., 2. / h, 0., 0.,
+            0., 0., -2. / d, 0.,
+            -(right + left) / w, -(top + bottom) / h, -(far + near) / d, 1.,
+        ];
+        self
+    }
+
     //--inverse the matrix
-    pub fn inverse(&mut self) -> Result<&mut Self, i8> {
+    //  <note>
+    //      Gauss-Jordan elimination with partial pivoting,
+    //      fails only if the matrix is singular.
+    pub fn inverse(&mut self) -> Result<&mut Self, RenderError> {
         const SIZE: usize = 4;
         let mut inv = Matrix::identity();
         let mut buf: f32;
         let mut a = self.value;
 
         for i in 0..SIZE {
-            if a[i * SIZE + i] == 0. {
-                return Err(-1);
+            let pivot = (i..SIZE)
+                .max_by(|&p, &q| a[p * SIZE + i].abs().total_cmp(&a[q * SIZE + i].abs()))
+                .unwrap();
+            if a[pivot * SIZE + i] == 0. {
+                return Err(RenderError::Math("matrix is singular".into()));
+            }
+            if pivot != i {
+                for k in 0..SIZE {
+                    a.swap(i * SIZE + k, pivot * SIZE + k);
+                    inv.swap(i * SIZE + k, pivot * SIZE + k);
+                }
             }
             buf = 1. / a[i * SIZE + i];
             for j in 0..SIZE {
@@ -307,6 +343,54 @@ impl Matrix {
         Ok(self)
     }
 
+    //--Set translation * rotation * scale in the matrix--
+    //  <argument>
+    //      t &[f32; 3] : translation
+    //      r &[f32; 4] : unit quaternion [x, y, z, w]
+    //      s &[f32; 3] : scale
+    pub fn set_trs(&mut self, t: &[f32; 3], r: &[f32; 4], s: &[f32; 3]) -> &mut Self {
+        let (x, y, z, w) = (r[0], r[1], r[2], r[3]);
+        let (xx, yy, zz) = (x * x, y * y, z * z);
+        let (xy, xz, yz) = (x * y, x * z, y * z);
+        let (wx, wy, wz) = (w * x, w * y, w * z);
+        self.value = [
+            (1. - 2. * (yy + zz)) * s[0], 2. * (xy + wz) * s[0], 2. * (xz - wy) * s[0], 0.,
+            2. * (xy - wz) * s[1], (1. - 2. * (xx + zz)) * s[1], 2. * (yz + wx) * s[1], 0.,
+            2. * (xz + wy) * s[2], 2. * (yz - wx) * s[2], (1. - 2. * (xx + yy)) * s[2], 0.,
+            t[0], t[1], t[2], 1.,
+        ];
+        self
+    }
+
+    //--Transform a point (w = 1), dividing by w--
+    pub fn transform_point(&self, p: &[f32; 3]) -> [f32; 3] {
+        let m = &self.value;
+        let x = m[0] * p[0] + m[4] * p[1] + m[8] * p[2] + m[12];
+        let y = m[1] * p[0] + m[5] * p[1] + m[9] * p[2] + m[13];
+        let z = m[2] * p[0] + m[6] * p[1] + m[10] * p[2] + m[14];
+        let w = m[3] * p[0] + m[7] * p[1] + m[11] * p[2] + m[15];
+        if w != 0. && w != 1. {
+            [x / w, y / w, z / w]
+        } else {
+            [x, y, z]
+        }
+    }
+
+    //--Transform a direction (w = 0)--
+    pub fn transform_direction(&self, d: &[f32; 3]) -> [f32; 3] {
+        let m = &self.value;
+        [
+            m[0] * d[0] + m[4] * d[1] + m[8] * d[2],
+            m[1] * d[0] + m[5] * d[1] + m[9] * d[2],
+            m[2] * d[0] + m[6] * d[1] + m[10] * d[2],
+        ]
+    }
+
+    //--Get the translation part--
+    pub fn get_translation(&self) -> [f32; 3] {
+        [self.value[12], self.value[13], self.value[14]]
+    }
+
     fn identity() -> [f32; 16] {
         [
             1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1., 0., 0., 0., 0., 1.,
@@ -0,0 +1,228 @@
+use crate::context::Restorable;
+use crate::error::RenderError;
+use crate::render_state::RenderState;
+use crate::texture::Texture;
+use crate::webgl;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{WebGlProgram, WebGlUniformLocation};
+
+static NEXT_PROGRAM_ID: AtomicU32 = AtomicU32::new(0);
+static NEXT_MATERIAL_ID: AtomicU32 = AtomicU32::new(0);
+
+//--Linked program with cached attribute and uniform locations--
+//  <note>
+//      The sources are kept to relink after a context loss, along with the
+//      extensions they need, which a restored context has forgotten, and
+//      the outputs captured by transform feedback.
+pub struct ShaderProgram {
+    pub id: u32,
+    vert_source: String,
+    frag_source: String,
+    extensions: Vec<String>,
+    feedback: Vec<String>,
+    program: RefCell<WebGlProgram>,
+    attributes: RefCell<HashMap<String, i32>>,
+    uniforms: RefCell<HashMap<String, Option<WebGlUniformLocation>>>,
+}
+
+impl ShaderProgram {
+    //--Compile and link program--
+    //  <argument>
+    //      vert_source &str : vertex shader source
+    //      frag_source &str : fragment shader source
+    pub fn new(gl: &GL, vert_source: &str, frag_source: &str) -> Result<Self, RenderError> {
+        Self::with_extensions(gl, vert_source, frag_source, &[])
+    }
+
+    //--Compile and link program after enabling the extensions its sources use--
+    //  <argument>
+    //      extensions &[&str] : e.g. "OES_standard_derivatives", unsupported ones are
+    //                           skipped, the sources test their #ifdef
+    pub fn with_extensions(gl: &GL, vert_source: &str, frag_source: &str, extensions: &[&str]) -> Result<Self, RenderError> {
+        for name in extensions.iter() {
+            let _ = gl.get_extension(name);
+        }
+        Ok(Self {
+            id: NEXT_PROGRAM_ID.fetch_add(1, Ordering::Relaxed),
+            vert_source: vert_source.to_string(),
+            frag_source: frag_source.to_string(),
+            extensions: extensions.iter().map(|e| e.to_string()).collect(),
+            feedback: Vec::new(),
+            program: RefCell::new(webgl::link_program(gl, vert_source, frag_source)?),
+            attributes: RefCell::new(HashMap::new()),
+            uniforms: RefCell::new(HashMap::new()),
+        })
+    }
+
+    //--Compile and link a WebGL2 program whose outputs are captured by transform feedback--
+    //  <argument>
+    //      varyings &[&str] : vertex shader outputs, written interleaved in this order
+    pub fn with_feedback(gl: &GL, vert_source: &str, frag_source: &str, varyings: &[&str]) -> Result<Self, RenderError> {
+        Ok(Self {
+            id: NEXT_PROGRAM_ID.fetch_add(1, Ordering::Relaxed),
+            vert_source: vert_source.to_string(),
+            frag_source: frag_source.to_string(),
+            extensions: Vec::new(),
+            feedback: varyings.iter().map(|v| v.to_string()).collect(),
+            program: RefCell::new(webgl::link_feedback_program(gl, vert_source, frag_source, varyings)?),
+            attributes: RefCell::new(HashMap::new()),
+            uniforms: RefCell::new(HashMap::new()),
+        })
+    }
+
+    //--Current GL object, changes after a restore--
+    pub fn program(&self) -> WebGlProgram {
+        self.program.borrow().clone()
+    }
+
+    //--Get attribute location, -1 if the program doesn't use it--
+    pub fn attrib_location(&self, gl: &GL, name: &str) -> i32 {
+        if let Some(&location) = self.attributes.borrow().get(name) {
+            return location;
+        }
+        let location = gl.get_attrib_location(&self.program.borrow(), name);
+        self.attributes.borrow_mut().insert(name.to_string(), location);
+        location
+    }
+
+    //--Get uniform location, None if the program doesn't use it--
+    pub fn uniform_location(&self, gl: &GL, name: &str) -> Option<WebGlUniformLocation> {
+        if let Some(location) = self.uniforms.borrow().get(name) {
+            return location.clone();
+        }
+        let location = gl.get_uniform_location(&self.program.borrow(), name);
+        self.uniforms.borrow_mut().insert(name.to_string(), location.clone());
+        location
+    }
+
+    //--Set a uniform by name--
+    pub fn set_uniform(&self, gl: &GL, name: &str, value: &Uniform) {
+        let location = match self.uniform_location(gl, name) {
+            Some(location) => location,
+            None => return,
+        };
+        let location = Some(&location);
+        match value {
+            Uniform::Float(v) => gl.uniform1f(location, *v),
+            Uniform::Vec2(v) => gl.uniform2fv_with_f32_array(location, v),
+            Uniform::Vec3(v) => gl.uniform3fv_with_f32_array(location, v),
+            Uniform::Vec4(v) => gl.uniform4fv_with_f32_array(location, v),
+            Uniform::Mat4(v) => gl.uniform_matrix4fv_with_f32_array(location, false, v),
+            Uniform::Int(v) => gl.uniform1i(location, *v),
+        }
+    }
+}
+
+impl Restorable for ShaderProgram {
+    fn restore(&self, gl: &GL) -> Result<(), RenderError> {
+        for name in self.extensions.iter() {
+            let _ = gl.get_extension(name);
+        }
+        let feedback: Vec<&str> = self.feedback.iter().map(|v| v.as_str()).collect();
+        *self.program.borrow_mut() = webgl::link_feedback_program(gl, &self.vert_source, &self.frag_source, &feedback)?;
+        self.attributes.borrow_mut().clear();
+        self.uniforms.borrow_mut().clear();
+        Ok(())
+    }
+}
+
+//--Value of a single uniform--
+#[derive(Clone, Debug, PartialEq)]
+pub enum Uniform {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec3([f32; 3]),
+    Vec4([f32; 4]),
+    Mat4([f32; 16]),
+    Int(i32),
+}
+
+//--Program plus the parameters and fixed-function state of one object--
+//  <note>
+//      Cloning keeps the program and parameters but gets a new id,
+//      so per-object tweaks don't get batched with the original.
+pub struct Material {
+    pub id: u32,
+    pub program: Rc<ShaderProgram>,
+    pub params: Vec<(String, Uniform)>,
+    pub textures: Vec<(String, Rc<Texture>)>,
+    pub state: RenderState,
+}
+
+impl Clone for Material {
+    fn clone(&self) -> Self {
+        Self {
+            id: NEXT_MATERIAL_ID.fetch_add(1, Ordering::Relaxed),
+            program: self.program.clone(),
+            params: self.params.clone(),
+            textures: self.textures.clone(),
+            state: self.state,
+        }
+    }
+}
+
+impl Material {
+    //--Create opaque material--
+    //  <argument>
+    //      program Rc<ShaderProgram> : program shared between materials
+    pub fn new(program: Rc<ShaderProgram>) -> Self {
+        Self {
+            id: NEXT_MATERIAL_ID.fetch_add(1, Ordering::Relaxed),
+            program,
+            params: Vec::new(),
+            textures: Vec::new(),
+            state: RenderState::OPAQUE,
+        }
+    }
+
+    //--Set a parameter, replacing any previous value--
+    pub fn set(&mut self, name: &str, value: Uniform) -> &mut Self {
+        match self.params.iter_mut().find(|(n, _)| n == name) {
+            Some(param) => param.1 = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Uniform> {
+        self.params.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    //--Set a sampler parameter, replacing any previous texture--
+    pub fn set_texture(&mut self, name: &str, texture: Rc<Texture>) -> &mut Self {
+        match self.textures.iter_mut().find(|(n, _)| n == name) {
+            Some(slot) => slot.1 = texture,
+            None => self.textures.push((name.to_string(), texture)),
+        }
+        self
+    }
+
+    //--Blended materials are drawn after opaque ones, back to front--
+    pub fn set_state(&mut self, state: RenderState) -> &mut Self {
+        self.state = state;
+        self
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.state.is_transparent()
+    }
+
+    //--Apply parameters and textures--
+    //  <note>
+    //      The program has to be in use already, render state is
+    //      applied by the renderer through its state cache.
+    pub fn apply(&self, gl: &GL) {
+        for (name, value) in self.params.iter() {
+            self.program.set_uniform(gl, name, value);
+        }
+        for (unit, (name, texture)) in self.textures.iter().enumerate() {
+            gl.active_texture(GL::TEXTURE0 + unit as u32);
+            texture.bind(gl);
+            self.program.set_uniform(gl, name, &Uniform::Int(unit as i32));
+        }
+    }
+}
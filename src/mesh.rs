@@ -0,0 +1,211 @@
+use crate::bounds::Bounds;
+use crate::bvh::Bvh;
+use crate::context::Restorable;
+use crate::error::RenderError;
+use crate::loader::MeshData;
+use crate::material::ShaderProgram;
+use crate::webgl;
+use std::cell::{OnceCell, RefCell};
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::WebGlBuffer;
+
+//--Vertex buffer bound to the attribute of the same name--
+//  <note>
+//      The data is kept to rebuild the buffer after a context loss.
+pub struct VertexAttribute {
+    pub name: String,
+    pub data: Vec<f32>,
+    pub size: i32,
+    buffer: RefCell<WebGlBuffer>,
+}
+
+//--Indexed triangle mesh uploaded to the GPU--
+pub struct Mesh {
+    pub attributes: Vec<VertexAttribute>,
+    pub index: Vec<u16>,
+    pub index_count: i32,
+    //  Local space bounds of the position attribute, None without one
+    pub bounds: Option<Bounds>,
+    ibo: RefCell<WebGlBuffer>,
+    //  Triangle hierarchy for ray queries, built on first use
+    bvh: OnceCell<Option<Bvh>>,
+    //  Copy with a vertex per corner for the wireframe view, built on first use
+    unwelded: RefCell<Option<Rc<Mesh>>>,
+}
+
+impl Mesh {
+    //--Upload vertex attributes and indices--
+    //  <argument>
+    //      attributes &[(&str, &[f32], i32)] : attribute name, data and components per vertex
+    //      index      &[u16]                 : triangle list
+    pub fn new(gl: &GL, attributes: &[(&str, &[f32], i32)], index: &[u16]) -> Result<Self, RenderError> {
+        let mut vbo = Vec::with_capacity(attributes.len());
+        for (name, data, size) in attributes.iter() {
+            vbo.push(VertexAttribute {
+                name: name.to_string(),
+                data: data.to_vec(),
+                size: *size,
+                buffer: RefCell::new(webgl::create_vbo_vector(gl, data)?),
+            });
+        }
+
+        let bounds = attributes
+            .iter()
+            .find(|(name, _, size)| *name == "position" && *size == 3)
+            .and_then(|(_, data, _)| Bounds::from_positions(data));
+
+        Ok(Self {
+            attributes: vbo,
+            index: index.to_vec(),
+            index_count: index.len() as i32,
+            bounds,
+            ibo: RefCell::new(webgl::create_ibo_vector(gl, index)?),
+            bvh: OnceCell::new(),
+            unwelded: RefCell::new(None),
+        })
+    }
+
+    //--Upload CPU-side mesh data as position, normal and color attributes--
+    //  <note>
+    //      Texture coordinates, tangents and skinning data are uploaded as
+    //      texCoord0, tangent, joints and weights when the data has them.
+    pub fn from_data(gl: &GL, data: &MeshData) -> Result<Self, RenderError> {
+        let mut attributes = vec![("position", &data.positions[..], 3), ("normal", &data.normals[..], 3), ("color", &data.colors[..], 4)];
+        if !data.tex_coords.is_empty() {
+            attributes.push(("texCoord0", &data.tex_coords[..], 2));
+        }
+        if !data.tangents.is_empty() {
+            attributes.push(("tangent", &data.tangents[..], 4));
+        }
+        if !data.joints.is_empty() && !data.weights.is_empty() {
+            attributes.push(("joints", &data.joints[..], 4));
+            attributes.push(("weights", &data.weights[..], 4));
+        }
+        Self::new(gl, &attributes, &data.indices)
+    }
+
+    //--CPU-side copy of the attributes from_data uploads, for reprocessing--
+    pub fn to_data(&self) -> MeshData {
+        let attribute = |name: &str| {
+            self.attributes
+                .iter()
+                .find(|a| a.name == name)
+                .map_or_else(Vec::new, |a| a.data.clone())
+        };
+        MeshData {
+            positions: attribute("position"),
+            normals: attribute("normal"),
+            colors: attribute("color"),
+            tex_coords: attribute("texCoord0"),
+            tangents: attribute("tangent"),
+            joints: attribute("joints"),
+            weights: attribute("weights"),
+            indices: self.index.clone(),
+        }
+    }
+
+    //--Bind buffers to the attributes the program uses--
+    //  <argument>
+    //      enabled &mut Vec<u32> : attribute arrays enabled by the previous mesh,
+    //                              updated to the ones enabled by this mesh
+    pub fn bind(&self, gl: &GL, program: &ShaderProgram, enabled: &mut Vec<u32>) {
+        let mut now_enabled = Vec::with_capacity(self.attributes.len());
+        for attribute in self.attributes.iter() {
+            let location = program.attrib_location(gl, &attribute.name);
+            if location < 0 {
+                continue;
+            }
+            let location = location as u32;
+            gl.bind_buffer(GL::ARRAY_BUFFER, Some(&attribute.buffer.borrow()));
+            gl.enable_vertex_attrib_array(location);
+            gl.vertex_attrib_pointer_with_i32(location, attribute.size, GL::FLOAT, false, 0, 0);
+            now_enabled.push(location);
+        }
+        for location in enabled.iter() {
+            if !now_enabled.contains(location) {
+                gl.disable_vertex_attrib_array(*location);
+            }
+        }
+        *enabled = now_enabled;
+
+        gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&self.ibo.borrow()));
+    }
+
+    //--Triangle BVH over the position attribute, None without positions or triangles--
+    //  <note>
+    //      Built the first time it's asked for, so meshes that are never picked
+    //      don't pay for it.
+    pub fn bvh(&self) -> Option<&Bvh> {
+        self.bvh
+            .get_or_init(|| {
+                let positions = self.attributes.iter().find(|a| a.name == "position" && a.size == 3)?;
+                Bvh::build(&positions.data, &self.index)
+            })
+            .as_ref()
+    }
+
+    //--Vertices in the first attribute--
+    pub fn vertex_count(&self) -> i32 {
+        self.attributes.first().map_or(0, |a| a.data.len() as i32 / a.size.max(1))
+    }
+
+    //--Same triangles with no shared vertices and a barycentric attribute--
+    //  <note>
+    //      Every corner gets (1, 0, 0), (0, 1, 0) or (0, 0, 1), which the
+    //      wireframe shading mode turns into edges. The copy has no indices
+    //      so it isn't bound by the 16-bit index limit, and is cached until
+    //      the next context restore.
+    pub fn unwelded(&self, gl: &GL) -> Result<Rc<Mesh>, RenderError> {
+        if let Some(mesh) = self.unwelded.borrow().as_ref() {
+            return Ok(mesh.clone());
+        }
+        let corners = self.index.len() / 3 * 3;
+        let mut expanded: Vec<(&str, Vec<f32>, i32)> = self
+            .attributes
+            .iter()
+            .map(|a| {
+                let size = a.size as usize;
+                let data = self.index[..corners]
+                    .iter()
+                    .flat_map(|&i| a.data[i as usize * size..(i as usize + 1) * size].iter().copied())
+                    .collect();
+                (a.name.as_str(), data, a.size)
+            })
+            .collect();
+        let barycentric = (0..corners)
+            .flat_map(|corner| {
+                let mut b = [0.; 3];
+                b[corner % 3] = 1.;
+                b
+            })
+            .collect();
+        expanded.push(("barycentric", barycentric, 3));
+
+        let attributes: Vec<(&str, &[f32], i32)> = expanded.iter().map(|(name, data, size)| (*name, &data[..], *size)).collect();
+        let mesh = Rc::new(Mesh::new(gl, &attributes, &[])?);
+        *self.unwelded.borrow_mut() = Some(mesh.clone());
+        Ok(mesh)
+    }
+
+    //--Draw the bound buffers, meshes without indices as a plain triangle list--
+    pub fn draw(&self, gl: &GL) {
+        if self.index.is_empty() {
+            gl.draw_arrays(GL::TRIANGLES, 0, self.vertex_count());
+            return;
+        }
+        gl.draw_elements_with_i32(GL::TRIANGLES, self.index_count, GL::UNSIGNED_SHORT, 0);
+    }
+}
+
+impl Restorable for Mesh {
+    fn restore(&self, gl: &GL) -> Result<(), RenderError> {
+        for attribute in self.attributes.iter() {
+            *attribute.buffer.borrow_mut() = webgl::create_vbo_vector(gl, &attribute.data)?;
+        }
+        *self.ibo.borrow_mut() = webgl::create_ibo_vector(gl, &self.index)?;
+        // Rebuilt on its next use
+        *self.unwelded.borrow_mut() = None;
+        Ok(())
+    }
+}
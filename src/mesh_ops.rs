@@ -0,0 +1,842 @@
+use crate::error::RenderError;
+use crate::loader::MeshData;
+use crate::vec_3;
+use crate::vertex_cache;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+//  Index buffers are u16
+const MAX_VERTICES: usize = u16::MAX as usize + 1;
+//  Weight of the planes that hold open borders in place during decimation
+const BOUNDARY_WEIGHT: f64 = 1000.;
+//  Collapses that turn a face normal by more than ~78 degrees are rejected
+const MIN_NORMAL_DOT: f64 = 0.2;
+
+fn too_many_vertices() -> RenderError {
+    RenderError::Resource("mesh has more than 65536 vertices".into())
+}
+
+//--Processing passes over CPU-side meshes--
+//  <note>
+//      Run these on imported or scanned data before uploading it with
+//      Mesh::from_data. Passes that move or merge vertices leave any
+//      tangents stale, regenerate them last.
+impl MeshData {
+    fn position(&self, i: usize) -> [f32; 3] {
+        [self.positions[i * 3], self.positions[i * 3 + 1], self.positions[i * 3 + 2]]
+    }
+
+    //--New mesh made of copies of the given vertices, without indices--
+    fn gather(&self, sources: &[usize]) -> MeshData {
+        fn pick(data: &[f32], sources: &[usize], size: usize) -> Vec<f32> {
+            if data.is_empty() {
+                return Vec::new();
+            }
+            let mut out = Vec::with_capacity(sources.len() * size);
+            for &i in sources {
+                out.extend_from_slice(&data[i * size..i * size + size]);
+            }
+            out
+        }
+        MeshData {
+            positions: pick(&self.positions, sources, 3),
+            normals: pick(&self.normals, sources, 3),
+            colors: pick(&self.colors, sources, 4),
+            tex_coords: pick(&self.tex_coords, sources, 2),
+            tangents: pick(&self.tangents, sources, 4),
+            joints: pick(&self.joints, sources, 4),
+            weights: pick(&self.weights, sources, 4),
+            indices: Vec::new(),
+        }
+    }
+
+    //--Give every triangle its own vertices carrying the face normal--
+    pub fn flat_normals(&mut self) -> Result<(), RenderError> {
+        let corners = self.indices.len() / 3 * 3;
+        if corners > MAX_VERTICES {
+            return Err(too_many_vertices());
+        }
+        let sources: Vec<usize> = self.indices[..corners].iter().map(|&i| i as usize).collect();
+        let mut flat = self.gather(&sources);
+        flat.indices = (0..corners).map(|i| i as u16).collect();
+        flat.normals = Vec::with_capacity(corners * 3);
+        for tri in sources.chunks_exact(3) {
+            let n = vec_3::normalize(&self.face_normal(tri[0], tri[1], tri[2]));
+            for _ in 0..3 {
+                flat.normals.extend_from_slice(&n);
+            }
+        }
+        *self = flat;
+        Ok(())
+    }
+
+    //--Angle weighted vertex normals--
+    //  <note>
+    //      Vertices at the same position share a normal even when a texture
+    //      or color seam splits them, so seams don't show up in the shading.
+    pub fn smooth_normals(&mut self) {
+        let count = self.vertex_count();
+        let mut groups: HashMap<[u32; 3], usize> = HashMap::new();
+        let group: Vec<usize> = (0..count)
+            .map(|i| {
+                // Adding 0 turns -0 into 0 so both land in the same group
+                let key = self.position(i).map(|c| (c + 0.).to_bits());
+                let next = groups.len();
+                *groups.entry(key).or_insert(next)
+            })
+            .collect();
+
+        let mut sums = vec![[0f32; 3]; groups.len()];
+        for tri in self.indices.chunks_exact(3) {
+            let p = [tri[0], tri[1], tri[2]].map(|i| self.position(i as usize));
+            let n = vec_3::normalize(&vec_3::cross(&vec_3::sub(&p[1], &p[0]), &vec_3::sub(&p[2], &p[0])));
+            for (k, &i) in tri.iter().enumerate() {
+                let e1 = vec_3::normalize(&vec_3::sub(&p[(k + 1) % 3], &p[k]));
+                let e2 = vec_3::normalize(&vec_3::sub(&p[(k + 2) % 3], &p[k]));
+                let angle = vec_3::dot(&e1, &e2).clamp(-1., 1.).acos();
+                let sum = &mut sums[group[i as usize]];
+                *sum = vec_3::add(sum, &vec_3::scale(&n, angle));
+            }
+        }
+
+        self.normals = Vec::with_capacity(count * 3);
+        for g in group {
+            self.normals.extend_from_slice(&vec_3::normalize(&sums[g]));
+        }
+    }
+
+    //--Unnormalized normal of a triangle, twice its area long--
+    fn face_normal(&self, a: usize, b: usize, c: usize) -> [f32; 3] {
+        let p = self.position(a);
+        vec_3::cross(&vec_3::sub(&self.position(b), &p), &vec_3::sub(&self.position(c), &p))
+    }
+
+    //--MikkTSpace tangents from positions, normals and texture coordinates--
+    //  <note>
+    //      Matches the tangent space normal maps are baked in. Vertices whose
+    //      corners receive different tangents are split. Smooth normals are
+    //      computed first if the mesh has none.
+    pub fn generate_tangents(&mut self) -> Result<(), RenderError> {
+        if self.tex_coords.len() != self.vertex_count() * 2 {
+            return Err(RenderError::Resource("tangents need texture coordinates".into()));
+        }
+        if self.normals.len() != self.positions.len() {
+            self.smooth_normals();
+        }
+        self.indices.truncate(self.indices.len() / 3 * 3);
+
+        let mut geometry = TangentGeometry {
+            data: self,
+            corners: vec![[0.; 4]; self.indices.len()],
+        };
+        if !mikktspace::generate_tangents(&mut geometry) {
+            return Err(RenderError::Math("tangent generation failed".into()));
+        }
+        let corners = geometry.corners;
+
+        let count = self.vertex_count();
+        let mut sources: Vec<usize> = (0..count).collect();
+        let mut tangents: Vec<Option<[f32; 4]>> = vec![None; count];
+        let mut copies: HashMap<usize, Vec<usize>> = HashMap::new();
+        let same = |a: &[f32; 4], b: &[f32; 4]| a[3] == b[3] && vec_3::dot(&[a[0], a[1], a[2]], &[b[0], b[1], b[2]]) > 0.9999;
+        let mut indices = self.indices.clone();
+        for (index, tangent) in indices.iter_mut().zip(corners.iter()) {
+            let v = *index as usize;
+            match &tangents[v] {
+                None => tangents[v] = Some(*tangent),
+                Some(existing) if same(existing, tangent) => {}
+                Some(_) => {
+                    let split = copies.entry(v).or_default();
+                    let found = split.iter().copied().find(|&c| tangents[c].as_ref().is_some_and(|t| same(t, tangent)));
+                    let copy = match found {
+                        Some(copy) => copy,
+                        None => {
+                            sources.push(v);
+                            tangents.push(Some(*tangent));
+                            split.push(sources.len() - 1);
+                            sources.len() - 1
+                        }
+                    };
+                    *index = copy as u16;
+                }
+            }
+        }
+        if sources.len() > MAX_VERTICES {
+            return Err(too_many_vertices());
+        }
+
+        let mut out = self.gather(&sources);
+        out.indices = indices;
+        out.tangents = tangents.iter().flat_map(|t| t.unwrap_or([1., 0., 0., 1.])).collect();
+        *self = out;
+        Ok(())
+    }
+
+    //--Merge vertices whose attributes all match within `epsilon`--
+    //  <return> usize  number of vertices removed
+    //  <note>
+    //      Positions are compared by distance, every other attribute per component.
+    //      To weld a triangle soup with per-face normals, clear the normals first
+    //      and recompute them with smooth_normals afterwards.
+    pub fn weld(&mut self, epsilon: f32) -> usize {
+        let count = self.vertex_count();
+        let cell = epsilon.max(1e-6);
+        let cell_of = |p: &[f32; 3]| p.map(|c| (c / cell).floor() as i64);
+        let mut grid: HashMap<[i64; 3], Vec<usize>> = HashMap::new();
+        let mut sources = Vec::new();
+        let mut remap = vec![0usize; count];
+
+        for (i, slot) in remap.iter_mut().enumerate() {
+            let p = self.position(i);
+            let c = cell_of(&p);
+            let mut found = None;
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        if let Some(candidates) = grid.get(&[c[0] + dx, c[1] + dy, c[2] + dz]) {
+                            if let Some(target) = candidates.iter().copied().find(|&r| self.vertices_match(i, sources[r], epsilon)) {
+                                found = Some(target);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
+            }
+            *slot = match found {
+                Some(target) => target,
+                None => {
+                    sources.push(i);
+                    grid.entry(c).or_default().push(sources.len() - 1);
+                    sources.len() - 1
+                }
+            };
+        }
+
+        let mut out = self.gather(&sources);
+        out.indices = self.indices.iter().map(|&i| remap[i as usize] as u16).collect();
+        *self = out;
+        count - sources.len()
+    }
+
+    fn vertices_match(&self, a: usize, b: usize, epsilon: f32) -> bool {
+        let close = |data: &[f32], size: usize| {
+            data.is_empty()
+                || data[a * size..a * size + size]
+                    .iter()
+                    .zip(data[b * size..b * size + size].iter())
+                    .all(|(x, y)| (x - y).abs() <= epsilon)
+        };
+        vec_3::length(&vec_3::sub(&self.position(a), &self.position(b))) <= epsilon
+            && close(&self.normals, 3)
+            && close(&self.colors, 4)
+            && close(&self.tex_coords, 2)
+            && close(&self.tangents, 4)
+            && close(&self.joints, 4)
+            && close(&self.weights, 4)
+    }
+
+    //--Drop triangles with repeated vertices or an area of at most `min_area`--
+    //  <return> usize  number of triangles removed
+    //  <note>
+    //      Vertices no longer referenced by any triangle are removed as well.
+    pub fn remove_degenerates(&mut self, min_area: f32) -> usize {
+        let before = self.indices.len() / 3;
+        let mut kept = Vec::with_capacity(self.indices.len());
+        for tri in self.indices.chunks_exact(3) {
+            let (a, b, c) = (tri[0] as usize, tri[1] as usize, tri[2] as usize);
+            if a == b || b == c || a == c {
+                continue;
+            }
+            if vec_3::length(&self.face_normal(a, b, c)) / 2. <= min_area {
+                continue;
+            }
+            kept.extend_from_slice(tri);
+        }
+        self.indices = kept;
+        self.remove_unused_vertices();
+        before - self.indices.len() / 3
+    }
+
+    //--Drop vertices no triangle references, keeping the order of the rest--
+    //  <return> usize  number of vertices removed
+    pub fn remove_unused_vertices(&mut self) -> usize {
+        let count = self.vertex_count();
+        let mut used = vec![false; count];
+        for &i in self.indices.iter() {
+            used[i as usize] = true;
+        }
+        let mut remap = vec![0u16; count];
+        let mut sources = Vec::with_capacity(count);
+        for (i, _) in used.iter().enumerate().filter(|(_, &u)| u) {
+            remap[i] = sources.len() as u16;
+            sources.push(i);
+        }
+        let mut out = self.gather(&sources);
+        out.indices = self.indices.iter().map(|&i| remap[i as usize]).collect();
+        *self = out;
+        count - sources.len()
+    }
+
+    //--Reorder triangles and vertices for the GPU--
+    //  <note>
+    //      Triangles are sorted for the post-transform vertex cache and then in
+    //      clusters for less overdraw, vertices follow the order the triangles
+    //      use them. Unused vertices are dropped. Run it last, right before upload.
+    pub fn optimize(&mut self) {
+        let vertex_count = self.vertex_count();
+        vertex_cache::optimize_vertex_cache(&mut self.indices, vertex_count);
+        vertex_cache::optimize_overdraw(&mut self.indices, &self.positions, 1.05);
+        let order = vertex_cache::optimize_vertex_fetch(&mut self.indices, vertex_count);
+        let mut out = self.gather(&order);
+        out.indices = std::mem::take(&mut self.indices);
+        *self = out;
+    }
+
+    //--Reduce the triangle count with quadric error metric edge collapses--
+    //  <argument>
+    //      target_triangles usize : stop once at most this many triangles are left
+    //  <return> usize  triangles left, more than the target if no collapse was valid
+    //  <note>
+    //      Open borders are held in place by extra boundary planes. Vertices split
+    //      by a seam are not connected, so weld the mesh first to let collapses
+    //      cross seams. Normals are recomputed with smooth_normals if the mesh had
+    //      any, tangents are dropped.
+    pub fn decimate(&mut self, target_triangles: usize) -> usize {
+        let mut faces: Vec<[usize; 3]> = self
+            .indices
+            .chunks_exact(3)
+            .map(|t| [t[0] as usize, t[1] as usize, t[2] as usize])
+            .collect();
+        if faces.len() <= target_triangles {
+            return faces.len();
+        }
+
+        let count = self.vertex_count();
+        let mut positions: Vec<[f64; 3]> = (0..count).map(|i| self.position(i).map(f64::from)).collect();
+        let mut quadrics = vec![Quadric::default(); count];
+        let mut vertex_faces: Vec<Vec<usize>> = vec![Vec::new(); count];
+        let mut edge_faces: HashMap<(usize, usize), u32> = HashMap::new();
+
+        for (f, face) in faces.iter().enumerate() {
+            let n = cross64(&sub64(&positions[face[1]], &positions[face[0]]), &sub64(&positions[face[2]], &positions[face[0]]));
+            let double_area = length64(&n);
+            if double_area > 0. {
+                let n = scale64(&n, 1. / double_area);
+                let plane = Quadric::plane(&n, -dot64(&n, &positions[face[0]]), double_area / 2.);
+                for &v in face.iter() {
+                    quadrics[v].add(&plane);
+                }
+            }
+            for (k, &v) in face.iter().enumerate() {
+                vertex_faces[v].push(f);
+                *edge_faces.entry(edge_key(v, face[(k + 1) % 3])).or_insert(0) += 1;
+            }
+        }
+
+        // Planes through each border edge, perpendicular to its face
+        for face in faces.iter() {
+            let n = normalize64(&cross64(&sub64(&positions[face[1]], &positions[face[0]]), &sub64(&positions[face[2]], &positions[face[0]])));
+            for k in 0..3 {
+                let (a, b) = (face[k], face[(k + 1) % 3]);
+                if edge_faces[&edge_key(a, b)] != 1 {
+                    continue;
+                }
+                let edge = sub64(&positions[b], &positions[a]);
+                let side = normalize64(&cross64(&edge, &n));
+                let plane = Quadric::plane(&side, -dot64(&side, &positions[a]), BOUNDARY_WEIGHT * dot64(&edge, &edge));
+                quadrics[a].add(&plane);
+                quadrics[b].add(&plane);
+            }
+        }
+
+        let mut version = vec![0u32; count];
+        let mut removed = vec![false; count];
+        let mut face_alive = vec![true; faces.len()];
+        let mut alive = faces.len();
+        let mut heap = BinaryHeap::new();
+        for &(a, b) in edge_faces.keys() {
+            heap.push(Collapse::new(a, b, &positions, &quadrics, &version));
+        }
+
+        while alive > target_triangles {
+            let collapse = match heap.pop() {
+                Some(collapse) => collapse,
+                None => break,
+            };
+            let (a, b) = (collapse.a, collapse.b);
+            if removed[a] || removed[b] || version[a] != collapse.versions[0] || version[b] != collapse.versions[1] {
+                continue;
+            }
+            if !can_collapse(a, b, &collapse.position, &faces, &face_alive, &vertex_faces, &positions) {
+                continue;
+            }
+
+            // Move a to the optimal position and hand it every face of b
+            positions[a] = collapse.position;
+            let q = quadrics[b];
+            quadrics[a].add(&q);
+            removed[b] = true;
+            for f in std::mem::take(&mut vertex_faces[b]) {
+                if !face_alive[f] {
+                    continue;
+                }
+                if faces[f].contains(&a) {
+                    face_alive[f] = false;
+                    alive -= 1;
+                    continue;
+                }
+                for v in faces[f].iter_mut().filter(|v| **v == b) {
+                    *v = a;
+                }
+                vertex_faces[a].push(f);
+            }
+            vertex_faces[a].retain(|&f| face_alive[f]);
+            version[a] += 1;
+
+            let mut neighbours: Vec<usize> = vertex_faces[a].iter().flat_map(|&f| faces[f]).filter(|&v| v != a).collect();
+            neighbours.sort_unstable();
+            neighbours.dedup();
+            for n in neighbours {
+                heap.push(Collapse::new(a, n, &positions, &quadrics, &version));
+            }
+        }
+
+        faces = faces.iter().zip(face_alive.iter()).filter(|(_, &alive)| alive).map(|(f, _)| *f).collect();
+        self.indices = faces.iter().flatten().map(|&v| v as u16).collect();
+        for (i, p) in positions.iter().enumerate() {
+            self.positions[i * 3..i * 3 + 3].copy_from_slice(&p.map(|c| c as f32));
+        }
+        self.tangents.clear();
+        self.remove_unused_vertices();
+        if !self.normals.is_empty() {
+            self.smooth_normals();
+        }
+        alive
+    }
+}
+
+//--Feeds MeshData to the MikkTSpace generator and collects one tangent per corner--
+struct TangentGeometry<'a> {
+    data: &'a MeshData,
+    corners: Vec<[f32; 4]>,
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.data.indices[face * 3 + vert] as usize
+    }
+}
+
+impl mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.data.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.data.position(self.vertex(face, vert))
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        let i = self.vertex(face, vert) * 3;
+        [self.data.normals[i], self.data.normals[i + 1], self.data.normals[i + 2]]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        let i = self.vertex(face, vert) * 2;
+        [self.data.tex_coords[i], self.data.tex_coords[i + 1]]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.corners[face * 3 + vert] = tangent;
+    }
+}
+
+//--Symmetric 4x4 error quadric, upper triangle only--
+//  <note>
+//      [a00 a01 a02 a11 a12 a22 b0 b1 b2 c], the error of a point p is
+//      p·Ap + 2b·p + c.
+#[derive(Clone, Copy, Debug, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    //--Squared distance to the plane n·p + d = 0, times `weight`--
+    fn plane(n: &[f64; 3], d: f64, weight: f64) -> Self {
+        let [x, y, z] = *n;
+        Quadric([x * x, x * y, x * z, y * y, y * z, z * z, x * d, y * d, z * d, d * d].map(|v| v * weight))
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(other.0.iter()) {
+            *a += b;
+        }
+    }
+
+    fn error(&self, p: &[f64; 3]) -> f64 {
+        let q = &self.0;
+        let [x, y, z] = *p;
+        q[0] * x * x + 2. * q[1] * x * y + 2. * q[2] * x * z + q[3] * y * y + 2. * q[4] * y * z + q[5] * z * z
+            + 2. * (q[6] * x + q[7] * y + q[8] * z)
+            + q[9]
+    }
+
+    //--Point of least error, None when the quadric is singular--
+    fn minimum(&self) -> Option<[f64; 3]> {
+        let q = &self.0;
+        let (a, b, c, d, e, f) = (q[0], q[1], q[2], q[3], q[4], q[5]);
+        let det = a * (d * f - e * e) - b * (b * f - c * e) + c * (b * e - c * d);
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        // Solve A p = -b with the adjugate of the symmetric matrix
+        let r = [-q[6], -q[7], -q[8]];
+        let inv = [
+            [d * f - e * e, c * e - b * f, b * e - c * d],
+            [c * e - b * f, a * f - c * c, b * c - a * e],
+            [b * e - c * d, b * c - a * e, a * d - b * b],
+        ];
+        Some(inv.map(|row| dot64(&row, &r) / det))
+    }
+}
+
+//--Candidate edge collapse, ordered so the heap pops the cheapest first--
+struct Collapse {
+    cost: f64,
+    a: usize,
+    b: usize,
+    position: [f64; 3],
+    //  Vertex versions when the candidate was made, stale ones are skipped
+    versions: [u32; 2],
+}
+
+impl Collapse {
+    fn new(a: usize, b: usize, positions: &[[f64; 3]], quadrics: &[Quadric], version: &[u32]) -> Self {
+        let mut q = quadrics[a];
+        q.add(&quadrics[b]);
+        let (pa, pb) = (positions[a], positions[b]);
+        let mid = [(pa[0] + pb[0]) / 2., (pa[1] + pb[1]) / 2., (pa[2] + pb[2]) / 2.];
+        let (position, cost) = q
+            .minimum()
+            .into_iter()
+            .chain([pa, pb, mid])
+            .map(|p| (p, q.error(&p)))
+            .min_by(|x, y| x.1.total_cmp(&y.1))
+            .unwrap_or((mid, 0.));
+        Self {
+            cost,
+            a,
+            b,
+            position,
+            versions: [version[a], version[b]],
+        }
+    }
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+//--Reject collapses that break the manifold or fold a face over--
+fn can_collapse(
+    a: usize,
+    b: usize,
+    target: &[f64; 3],
+    faces: &[[usize; 3]],
+    face_alive: &[bool],
+    vertex_faces: &[Vec<usize>],
+    positions: &[[f64; 3]],
+) -> bool {
+    let live = |v: usize| vertex_faces[v].iter().copied().filter(move |&f| face_alive[f]);
+
+    // Link condition: a and b may only share the neighbours of the faces they span
+    let neighbours = |v: usize| {
+        let mut n: Vec<usize> = live(v).flat_map(|f| faces[f]).filter(|&u| u != v).collect();
+        n.sort_unstable();
+        n.dedup();
+        n
+    };
+    let shared_faces = live(a).filter(|&f| faces[f].contains(&b)).count();
+    let nb = neighbours(b);
+    let shared_neighbours = neighbours(a).iter().filter(|u| nb.binary_search(u).is_ok()).count();
+    if shared_neighbours > shared_faces {
+        return false;
+    }
+
+    for v in [a, b] {
+        for f in live(v).filter(|&f| !(faces[f].contains(&a) && faces[f].contains(&b))) {
+            let p = faces[f].map(|u| positions[u]);
+            let moved = faces[f].map(|u| if u == v { *target } else { positions[u] });
+            let before = cross64(&sub64(&p[1], &p[0]), &sub64(&p[2], &p[0]));
+            let after = cross64(&sub64(&moved[1], &moved[0]), &sub64(&moved[2], &moved[0]));
+            let (lb, la) = (length64(&before), length64(&after));
+            if la == 0. || (lb > 0. && dot64(&before, &after) < MIN_NORMAL_DOT * lb * la) {
+                return false;
+            }
+        }
+    }
+    true
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    (a.min(b), a.max(b))
+}
+
+fn sub64(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+fn scale64(a: &[f64; 3], s: f64) -> [f64; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+fn dot64(a: &[f64; 3], b: &[f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+fn cross64(a: &[f64; 3], b: &[f64; 3]) -> [f64; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
+
+fn length64(a: &[f64; 3]) -> f64 {
+    dot64(a, a).sqrt()
+}
+
+fn normalize64(a: &[f64; 3]) -> [f64; 3] {
+    let l = length64(a);
+    if l == 0. {
+        *a
+    } else {
+        scale64(a, 1. / l)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes;
+
+    fn torus(rows: u16, columns: u16) -> MeshData {
+        let (positions, normals, colors, indices) = shapes::torus(rows, columns, 0.5, 2.);
+        MeshData {
+            positions,
+            normals,
+            colors,
+            indices,
+            ..Default::default()
+        }
+    }
+
+    //  u around the ring, v around the tube, 0 and 1 meet at the seams
+    fn torus_tex_coords(rows: u16, columns: u16) -> Vec<f32> {
+        (0..=rows)
+            .flat_map(|i| (0..=columns).flat_map(move |ii| [ii as f32 / columns as f32, i as f32 / rows as f32]))
+            .collect()
+    }
+
+    //  Two triangles of the unit quad, each with its own three corners
+    fn split_quad() -> MeshData {
+        MeshData {
+            positions: vec![0., 0., 0., 1., 0., 0., 1., 1., 0., 0., 0., 0., 1., 1., 0., 0., 1., 0.],
+            normals: [0., 0., 1.].repeat(6),
+            indices: vec![0, 1, 2, 3, 4, 5],
+            ..Default::default()
+        }
+    }
+
+    //  `n` x `n` quads over -1..1 in the XZ plane, facing up
+    fn grid(n: u16) -> MeshData {
+        let mut data = MeshData::default();
+        for z in 0..=n {
+            for x in 0..=n {
+                data.positions.extend_from_slice(&[x as f32 / n as f32 * 2. - 1., 0., z as f32 / n as f32 * 2. - 1.]);
+            }
+        }
+        for z in 0..n {
+            for x in 0..n {
+                let i = z * (n + 1) + x;
+                data.indices.extend_from_slice(&[i, i + n + 1, i + 1, i + 1, i + n + 1, i + n + 2]);
+            }
+        }
+        data
+    }
+
+    fn corners(data: &MeshData, tri: &[u16]) -> [[f32; 3]; 3] {
+        [tri[0], tri[1], tri[2]].map(|i| data.position(i as usize))
+    }
+
+    //  Corner positions of every triangle, in a comparable order
+    fn triangle_positions(data: &MeshData) -> Vec<[[u32; 3]; 3]> {
+        let mut triangles: Vec<[[u32; 3]; 3]> = data
+            .indices
+            .chunks(3)
+            .map(|tri| corners(data, tri).map(|p| p.map(|c| (c * 1e4).round().to_bits())))
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    //  Edges used by a single triangle
+    fn border_edges(data: &MeshData) -> Vec<(u16, u16)> {
+        let mut count: HashMap<(u16, u16), u32> = HashMap::new();
+        for tri in data.indices.chunks(3) {
+            for k in 0..3 {
+                let (a, b) = (tri[k], tri[(k + 1) % 3]);
+                *count.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+            }
+        }
+        count.into_iter().filter(|&(_, n)| n == 1).map(|(edge, _)| edge).collect()
+    }
+
+    //  Sign of each face normal against the direction away from the tube's center
+    fn torus_facing(data: &MeshData) -> Vec<f32> {
+        data.indices
+            .chunks(3)
+            .map(|tri| {
+                let [a, b, c] = corners(data, tri);
+                let n = vec_3::cross(&vec_3::sub(&b, &a), &vec_3::sub(&c, &a));
+                let centroid = vec_3::scale(&vec_3::add(&a, &vec_3::add(&b, &c)), 1. / 3.);
+                let ring = vec_3::scale(&vec_3::normalize(&[centroid[0], 0., centroid[2]]), 2.);
+                vec_3::dot(&n, &vec_3::sub(&centroid, &ring)).signum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn weld_merges_torus_seams() {
+        let mut data = torus(12, 16);
+        let before = triangle_positions(&data);
+        let removed = data.weld(1e-4);
+
+        // One row and one column of duplicates close the tube and the ring
+        assert_eq!(removed, 12 + 16 + 1);
+        assert_eq!(data.vertex_count(), 12 * 16);
+        assert_eq!(data.indices.len(), 12 * 16 * 6);
+        assert!(data.indices.iter().all(|&i| (i as usize) < data.vertex_count()));
+        assert_eq!(triangle_positions(&data), before);
+    }
+
+    #[test]
+    fn weld_keeps_texture_seams() {
+        let mut data = torus(12, 16);
+        data.tex_coords = torus_tex_coords(12, 16);
+        assert_eq!(data.weld(1e-4), 0);
+    }
+
+    #[test]
+    fn weld_joins_split_quad() {
+        let mut data = split_quad();
+        assert_eq!(data.weld(1e-5), 2);
+        assert_eq!(data.vertex_count(), 4);
+        assert_eq!(data.indices.len(), 6);
+        assert_eq!(border_edges(&data).len(), 4);
+    }
+
+    #[test]
+    fn remove_degenerates_drops_collapsed_triangles() {
+        let mut data = split_quad();
+        data.positions.extend_from_slice(&[2., 0., 0., 3., 0., 0., 4., 0., 0.]);
+        data.normals.extend_from_slice(&[0., 0., 1.].repeat(3));
+        data.indices.extend_from_slice(&[6, 7, 8, 0, 0, 1]);
+        assert_eq!(data.remove_degenerates(0.), 2);
+        assert_eq!(data.indices.len(), 6);
+        assert_eq!(data.vertex_count(), 6);
+    }
+
+    #[test]
+    fn decimate_reaches_target_without_flipping_torus_faces() {
+        let mut data = torus(16, 16);
+        data.weld(1e-4);
+        let facing = torus_facing(&data)[0];
+        assert!(torus_facing(&data).iter().all(|&s| s == facing));
+
+        let left = data.decimate(200);
+        assert!(left <= 200, "{} triangles left", left);
+        assert_eq!(data.indices.len(), left * 3);
+        assert!(torus_facing(&data).iter().all(|&s| s == facing));
+        assert_eq!(data.normals.len(), data.positions.len());
+        // Still closed, every edge is shared by two triangles
+        assert!(border_edges(&data).is_empty());
+    }
+
+    #[test]
+    fn decimate_keeps_grid_border() {
+        let mut data = grid(16);
+        let left = data.decimate(64);
+        assert!(left <= 64, "{} triangles left", left);
+
+        for tri in data.indices.chunks(3) {
+            let [a, b, c] = corners(&data, tri);
+            assert!(vec_3::cross(&vec_3::sub(&b, &a), &vec_3::sub(&c, &a))[1] > 0.);
+        }
+        // Border edges stay on the outline and still run all the way around it
+        let on_side = |p: &[f32; 3], axis: usize| (p[axis].abs() - 1.).abs() < 1e-4;
+        let mut perimeter = 0.;
+        for (a, b) in border_edges(&data) {
+            let (pa, pb) = (data.position(a as usize), data.position(b as usize));
+            let shared = (0..3).step_by(2).any(|axis| on_side(&pa, axis) && on_side(&pb, axis) && pa[axis] == pb[axis]);
+            assert!(shared, "{:?} - {:?} left the border", pa, pb);
+            perimeter += vec_3::length(&vec_3::sub(&pb, &pa));
+        }
+        assert!((perimeter - 8.).abs() < 1e-3, "perimeter {}", perimeter);
+    }
+
+    #[test]
+    fn tangents_are_unit_and_orthogonal_on_a_torus() {
+        let mut data = torus(12, 16);
+        data.tex_coords = torus_tex_coords(12, 16);
+        data.generate_tangents().unwrap();
+        assert_eq!(data.tangents.len(), data.vertex_count() * 4);
+        for (t, n) in data.tangents.chunks(4).zip(data.normals.chunks(3)) {
+            let tangent = [t[0], t[1], t[2]];
+            assert!((vec_3::length(&tangent) - 1.).abs() < 1e-3, "{:?}", t);
+            assert!(vec_3::dot(&tangent, &[n[0], n[1], n[2]]).abs() < 1e-3, "{:?} against {:?}", t, n);
+            assert!(t[3] == 1. || t[3] == -1.);
+        }
+    }
+
+    #[test]
+    fn tangents_split_at_mirrored_tex_coords() {
+        // Two quads sharing the x = 0 edge, u runs away from it on both sides
+        let mut data = MeshData {
+            positions: vec![-1., 0., 0., 0., 0., 0., 0., 1., 0., -1., 1., 0., 1., 0., 0., 1., 1., 0.],
+            normals: [0., 0., 1.].repeat(6),
+            tex_coords: vec![0., 0., 1., 0., 1., 1., 0., 1., 0., 0., 0., 1.],
+            indices: vec![0, 1, 2, 0, 2, 3, 1, 4, 5, 1, 5, 2],
+            ..Default::default()
+        };
+        data.generate_tangents().unwrap();
+        assert_eq!(data.vertex_count(), 8);
+
+        let tangent = |corner: usize| {
+            let i = data.indices[corner] as usize * 4;
+            [data.tangents[i], data.tangents[i + 1], data.tangents[i + 2], data.tangents[i + 3]]
+        };
+        for corner in 0..6 {
+            let t = tangent(corner);
+            assert!(t[0] > 0.999 && t[3] == 1., "left corner {} has {:?}", corner, t);
+        }
+        for corner in 6..12 {
+            let t = tangent(corner);
+            assert!(t[0] < -0.999 && t[3] == -1., "right corner {} has {:?}", corner, t);
+        }
+    }
+}
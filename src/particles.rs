@@ -0,0 +1,674 @@
+use crate::color::Rgba;
+use crate::context::{ResourceTracker, Restorable};
+use crate::error::RenderError;
+use crate::instancing::{Instance, InstanceBatch};
+use crate::loader::MeshData;
+use crate::mat_4::Matrix;
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::quat;
+use crate::render_state::{BlendMode, CullFace, RenderState, Winding};
+use crate::vec_3;
+use crate::webgl;
+use std::cell::RefCell;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{WebGl2RenderingContext, WebGlBuffer, WebGlVertexArrayObject};
+
+//  Floats per particle in the transform feedback buffers: position, velocity, age, lifetime
+const STATE_FLOATS: usize = 8;
+//  Samples of the size and color curves handed to shader/particle_expand.vert
+const LIFE_SAMPLES: usize = 16;
+
+//--Value that can be blended between curve keys--
+pub trait Lerp: Copy {
+    fn lerp(a: &Self, b: &Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(a: &Self, b: &Self, t: f32) -> Self {
+        a + (b - a) * t
+    }
+}
+
+impl Lerp for [f32; 4] {
+    fn lerp(a: &Self, b: &Self, t: f32) -> Self {
+        [
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+            a[3] + (b[3] - a[3]) * t,
+        ]
+    }
+}
+
+//--Piecewise linear curve over a particle's normalized age--
+//  <note>
+//      Keys are (time, value) with time in 0..1, kept sorted by time.
+//      Before the first key and after the last one the curve is flat.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Curve<T: Lerp> {
+    keys: Vec<(f32, T)>,
+}
+
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Self { keys: vec![(0., value)] }
+    }
+
+    //--Blend from `start` at birth to `end` at death--
+    pub fn linear(start: T, end: T) -> Self {
+        Self {
+            keys: vec![(0., start), (1., end)],
+        }
+    }
+
+    //--Add a key, replacing one at the same time--
+    pub fn key(mut self, time: f32, value: T) -> Self {
+        let time = time.clamp(0., 1.);
+        match self.keys.iter().position(|(t, _)| *t >= time) {
+            Some(i) if self.keys[i].0 == time => self.keys[i].1 = value,
+            Some(i) => self.keys.insert(i, (time, value)),
+            None => self.keys.push((time, value)),
+        }
+        self
+    }
+
+    pub fn sample(&self, time: f32) -> T {
+        let last = self.keys.len() - 1;
+        if time <= self.keys[0].0 {
+            return self.keys[0].1;
+        }
+        if time >= self.keys[last].0 {
+            return self.keys[last].1;
+        }
+        let i = self.keys.iter().position(|(t, _)| *t > time).unwrap_or(last);
+        let (t0, v0) = &self.keys[i - 1];
+        let (t1, v1) = &self.keys[i];
+        T::lerp(v0, v1, (time - t0) / (t1 - t0))
+    }
+}
+
+//--Color of a particle over its lifetime--
+#[derive(Clone, Debug, PartialEq)]
+pub enum ColorOverLife {
+    Gradient(Curve<[f32; 4]>),
+    //  Hue sweeps `hue_start`..`hue_end` degrees through Rgba::from_hsv, alpha follows its own curve
+    Hue {
+        hue_start: f32,
+        hue_end: f32,
+        saturation: f32,
+        value: f32,
+        alpha: Curve<f32>,
+    },
+}
+
+impl ColorOverLife {
+    pub fn sample(&self, time: f32) -> [f32; 4] {
+        match self {
+            ColorOverLife::Gradient(curve) => curve.sample(time),
+            ColorOverLife::Hue {
+                hue_start,
+                hue_end,
+                saturation,
+                value,
+                alpha,
+            } => {
+                let a = alpha.sample(time);
+                let h = hue_start + (hue_end - hue_start) * time.clamp(0., 1.);
+                Rgba::from_hsv(h, *saturation, *value, a).to_array()
+            }
+        }
+    }
+}
+
+//--Where new particles appear and which way they start moving--
+#[derive(Clone, Debug, PartialEq)]
+pub enum EmitterShape {
+    //  At the origin, in a random direction
+    Point,
+    //  On the surface of a sphere, or inside it with `volume`, moving outwards
+    Sphere { radius: f32, volume: bool },
+    //  On the triangles of a mesh, moving along the face normal
+    MeshSurface {
+        triangles: Vec<[[f32; 3]; 3]>,
+        //  Running sum of triangle areas, for area-weighted sampling
+        areas: Vec<f32>,
+    },
+}
+
+impl EmitterShape {
+    //--Emit from the surface of loaded mesh data--
+    pub fn mesh_surface(data: &MeshData) -> Result<Self, RenderError> {
+        let vertex = |i: u16| {
+            let i = i as usize * 3;
+            [data.positions[i], data.positions[i + 1], data.positions[i + 2]]
+        };
+        let mut triangles = Vec::with_capacity(data.indices.len() / 3);
+        let mut areas = Vec::with_capacity(data.indices.len() / 3);
+        let mut total = 0.;
+        for tri in data.indices.chunks_exact(3) {
+            let t = [vertex(tri[0]), vertex(tri[1]), vertex(tri[2])];
+            let area = vec_3::length(&vec_3::cross(&vec_3::sub(&t[1], &t[0]), &vec_3::sub(&t[2], &t[0]))) / 2.;
+            if area <= 0. {
+                continue;
+            }
+            total += area;
+            triangles.push(t);
+            areas.push(total);
+        }
+        if triangles.is_empty() {
+            return Err(RenderError::Resource("emitter mesh has no surface".into()));
+        }
+        Ok(EmitterShape::MeshSurface { triangles, areas })
+    }
+
+    //--Pick a spawn position and a unit direction--
+    fn sample(&self, rng: &mut Rng) -> ([f32; 3], [f32; 3]) {
+        match self {
+            EmitterShape::Point => ([0., 0., 0.], rng.unit_vector()),
+            EmitterShape::Sphere { radius, volume } => {
+                let dir = rng.unit_vector();
+                // cbrt keeps the density uniform across the volume
+                let r = if *volume { radius * rng.next().cbrt() } else { *radius };
+                (vec_3::scale(&dir, r), dir)
+            }
+            EmitterShape::MeshSurface { triangles, areas } => {
+                let total = areas[areas.len() - 1];
+                let target = rng.next() * total;
+                let i = areas.partition_point(|&a| a < target).min(triangles.len() - 1);
+                let [a, b, c] = &triangles[i];
+                let (mut u, mut v) = (rng.next(), rng.next());
+                if u + v > 1. {
+                    u = 1. - u;
+                    v = 1. - v;
+                }
+                let ab = vec_3::sub(b, a);
+                let ac = vec_3::sub(c, a);
+                let position = vec_3::add(a, &vec_3::add(&vec_3::scale(&ab, u), &vec_3::scale(&ac, v)));
+                (position, vec_3::normalize(&vec_3::cross(&ab, &ac)))
+            }
+        }
+    }
+}
+
+//--Spawn and motion parameters--
+#[derive(Clone, Debug, PartialEq)]
+pub struct Emitter {
+    pub shape: EmitterShape,
+    //  Particles per second
+    pub rate: f32,
+    //  Seconds, picked uniformly per particle
+    pub lifetime: (f32, f32),
+    //  Initial speed along the shape's direction, picked uniformly per particle
+    pub speed: (f32, f32),
+    //  Blend between the shape's direction (0) and a random one (1)
+    pub spread: f32,
+    //  Acceleration in units per second squared
+    pub gravity: [f32; 3],
+    //  Fraction of velocity lost per second
+    pub drag: f32,
+    pub size: Curve<f32>,
+    pub color: ColorOverLife,
+    //  Emission stops while this many particles are alive
+    pub max_particles: usize,
+}
+
+impl Default for Emitter {
+    fn default() -> Self {
+        Self {
+            shape: EmitterShape::Point,
+            rate: 50.,
+            lifetime: (1., 2.),
+            speed: (1., 2.),
+            spread: 0.,
+            gravity: [0., -9.8, 0.],
+            drag: 0.,
+            size: Curve::linear(0.2, 0.),
+            color: ColorOverLife::Gradient(Curve::linear([1., 1., 1., 1.], [1., 1., 1., 0.])),
+            max_particles: 1000,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Particle {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+impl Particle {
+    //--Age as a fraction of the lifetime, 0..1--
+    pub fn life(&self) -> f32 {
+        (self.age / self.lifetime).min(1.)
+    }
+}
+
+//--Xorshift generator, good enough for spawn jitter--
+#[derive(Clone, Debug)]
+struct Rng(u32);
+
+impl Rng {
+    fn new(seed: u32) -> Self {
+        Rng(seed.max(1))
+    }
+
+    //--Uniform in 0..1--
+    fn next(&mut self) -> f32 {
+        let mut x = self.0;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.0 = x;
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn range(&mut self, (min, max): (f32, f32)) -> f32 {
+        min + (max - min) * self.next()
+    }
+
+    fn unit_vector(&mut self) -> [f32; 3] {
+        let z = self.next() * 2. - 1.;
+        let a = self.next() * std::f32::consts::PI * 2.;
+        let r = (1. - z * z).max(0.).sqrt();
+        [r * a.cos(), r * a.sin(), z]
+    }
+}
+
+//--Particles simulated over time and drawn as instanced billboards--
+//  <note>
+//      Particles live in the space of the scene node the batch is attached to,
+//      keep that node unrotated and unscaled for the quads to face the camera.
+//      On WebGL2 additive particles stay on the GPU, see GpuSimulation, and
+//      only spawning runs in Rust. Otherwise they are simulated on the CPU and
+//      re-uploaded every frame, alpha blended ones have to be sorted there anyway.
+pub struct ParticleSystem {
+    pub emitter: Emitter,
+    particles: Vec<Particle>,
+    //  Fractional particles owed by the emission rate
+    pending: f32,
+    rng: Rng,
+    batch: Rc<InstanceBatch>,
+    blend: BlendMode,
+    gpu: Option<Rc<GpuSimulation>>,
+}
+
+impl ParticleSystem {
+    //--Create the quad, program and batch--
+    //  <argument>
+    //      blend BlendMode : Additive or Alpha, Alpha sorts particles back to front
+    pub fn new(gl: &GL, emitter: Emitter, blend: BlendMode) -> Result<Self, RenderError> {
+        let program = Rc::new(particle_program(gl)?);
+        let mesh = Rc::new(Mesh::new(
+            gl,
+            &[
+                ("position", &[-0.5, -0.5, 0., 0.5, -0.5, 0., 0.5, 0.5, 0., -0.5, 0.5, 0.], 3),
+                ("texCoord", &[0., 0., 1., 0., 1., 1., 0., 1.], 2),
+            ],
+            &[0, 1, 2, 0, 2, 3],
+        )?);
+        let mut material = Material::new(program);
+        material.set_state(
+            RenderState::TRANSPARENT
+                .with_blend(blend)
+                .with_cull_face(CullFace::None, Winding::CounterClockwise),
+        );
+        let gpu = match (webgl::webgl2(gl), blend) {
+            (Some(_), BlendMode::Additive) => Some(Rc::new(GpuSimulation::new(gl, emitter.max_particles)?)),
+            _ => None,
+        };
+        Ok(Self {
+            emitter,
+            particles: Vec::new(),
+            pending: 0.,
+            rng: Rng::new(0x9e37_79b9),
+            batch: Rc::new(InstanceBatch::new(mesh, Rc::new(material))),
+            blend,
+            gpu,
+        })
+    }
+
+    //--Batch to attach to a scene node or submit to the renderer--
+    pub fn batch(&self) -> &Rc<InstanceBatch> {
+        &self.batch
+    }
+
+    //--Register the GPU resources for context restore--
+    pub fn track(&self, resources: &mut ResourceTracker) {
+        resources.track(&self.batch);
+        resources.track(&self.batch.mesh);
+        resources.track(&self.batch.material.program);
+        if let Some(gpu) = &self.gpu {
+            resources.track(gpu);
+            resources.track(&gpu.update);
+            resources.track(&gpu.expand);
+        }
+    }
+
+    //--Spawn `count` particles at once, ignoring the rate--
+    pub fn burst(&mut self, count: usize) {
+        let max = self.emitter.max_particles;
+        let free = match &self.gpu {
+            Some(gpu) => gpu.free_slots(max),
+            None => max.saturating_sub(self.particles.len()),
+        };
+        let spawned: Vec<Particle> = (0..count.min(free)).map(|_| self.spawn()).collect();
+        match &self.gpu {
+            Some(gpu) => gpu.spawn(&spawned),
+            None => self.particles.extend(spawned),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.pending = 0.;
+        if let Some(gpu) = &self.gpu {
+            gpu.clear();
+        }
+    }
+
+    fn spawn(&mut self) -> Particle {
+        let (position, dir) = self.emitter.shape.sample(&mut self.rng);
+        let dir = if self.emitter.spread > 0. {
+            let random = self.rng.unit_vector();
+            vec_3::normalize(&vec_3::lerp(&dir, &random, self.emitter.spread.min(1.)))
+        } else {
+            dir
+        };
+        Particle {
+            position,
+            velocity: vec_3::scale(&dir, self.rng.range(self.emitter.speed)),
+            age: 0.,
+            lifetime: self.rng.range(self.emitter.lifetime).max(f32::EPSILON),
+        }
+    }
+
+    //--Advance the simulation--
+    //  <argument>
+    //      dt f32 : seconds, FrameTime::fixed_step when stepping at a fixed rate
+    pub fn update(&mut self, dt: f32) {
+        if dt <= 0. {
+            return;
+        }
+        let damping = (1. - self.emitter.drag * dt).max(0.);
+        if let Some(gpu) = &self.gpu {
+            gpu.step(dt, &self.emitter.gravity, damping, self.emitter.max_particles);
+        }
+        let gravity = vec_3::scale(&self.emitter.gravity, dt);
+        self.particles.retain_mut(|p| {
+            p.age += dt;
+            if p.age >= p.lifetime {
+                return false;
+            }
+            p.velocity = vec_3::scale(&vec_3::add(&p.velocity, &gravity), damping);
+            p.position = vec_3::add(&p.position, &vec_3::scale(&p.velocity, dt));
+            true
+        });
+
+        self.pending += self.emitter.rate * dt;
+        let count = self.pending.floor();
+        self.pending -= count;
+        self.burst(count as usize);
+    }
+
+    //--Write the particles into the batch as camera-facing quads--
+    //  <argument>
+    //      eye      &[f32; 3] : camera position, used to sort alpha blended particles
+    //      rotation &[f32; 4] : camera orientation, the quads take it over
+    //  <note>
+    //      On the GPU the instances are written by transform feedback, one per slot.
+    pub fn write_instances(&mut self, eye: &[f32; 3], rotation: &[f32; 4]) -> Result<(), RenderError> {
+        if let Some(gpu) = &self.gpu {
+            return gpu.expand(&self.batch, &self.emitter, rotation);
+        }
+        if self.blend != BlendMode::Additive {
+            // Additive blending is order independent, everything else draws far to near
+            let forward = quat::rotate(rotation, &[0., 0., -1.]);
+            let depth = |p: &Particle| vec_3::dot(&vec_3::sub(&p.position, eye), &forward);
+            self.particles
+                .sort_by(|a, b| depth(b).partial_cmp(&depth(a)).unwrap_or(std::cmp::Ordering::Equal));
+        }
+        let instances = self
+            .particles
+            .iter()
+            .map(|p| {
+                let life = p.life();
+                let size = self.emitter.size.sample(life);
+                let mut model = Matrix::new();
+                model.set_trs(&p.position, rotation, &[size, size, size]);
+                Instance {
+                    model,
+                    color: self.emitter.color.sample(life),
+                }
+            })
+            .collect();
+        self.batch.set_all(instances);
+        Ok(())
+    }
+}
+
+//--Particle state in a pair of WebGL2 buffers, advanced by transform feedback--
+//  <note>
+//      The update pass reads one buffer and writes the other, the expand pass
+//      then writes the batch's instances straight from the state. The CPU
+//      only keeps how long each slot has left, to spawn into free ones.
+struct GpuSimulation {
+    gl: GL,
+    gl2: WebGl2RenderingContext,
+    update: Rc<ShaderProgram>,
+    expand: Rc<ShaderProgram>,
+    buffers: RefCell<GpuBuffers>,
+    //  Seconds each slot has left to live, free slots are <= 0
+    remaining: RefCell<Vec<f32>>,
+}
+
+struct GpuBuffers {
+    state: [WebGlBuffer; 2],
+    //  Attribute setup of the update and expand passes
+    arrays: [WebGlVertexArrayObject; 2],
+    //  State buffer holding the latest step
+    current: usize,
+}
+
+impl GpuSimulation {
+    fn new(gl: &GL, capacity: usize) -> Result<Self, RenderError> {
+        let gl2 = webgl::webgl2(gl)
+            .ok_or_else(|| RenderError::Context("transform feedback needs WebGL2".into()))?
+            .clone();
+        let feedback = include_str!("shader/feedback.frag");
+        let update = ShaderProgram::with_feedback(
+            gl,
+            include_str!("shader/particle_update.vert"),
+            feedback,
+            &["outPosition", "outVelocity", "outAge", "outLifetime"],
+        )?;
+        let expand = ShaderProgram::with_feedback(
+            gl,
+            include_str!("shader/particle_expand.vert"),
+            feedback,
+            &["outModel0", "outModel1", "outModel2", "outModel3", "outColor"],
+        )?;
+        let buffers = GpuBuffers::new(&gl2, capacity)?;
+        Ok(Self {
+            gl: gl.clone(),
+            gl2,
+            update: Rc::new(update),
+            expand: Rc::new(expand),
+            buffers: RefCell::new(buffers),
+            remaining: RefCell::new(vec![0.; capacity]),
+        })
+    }
+
+    fn free_slots(&self, capacity: usize) -> usize {
+        self.resize(capacity);
+        self.remaining.borrow().iter().filter(|&&r| r <= 0.).count()
+    }
+
+    //--Reallocate the buffers when max_particles changed, dropping every particle--
+    fn resize(&self, capacity: usize) {
+        if self.remaining.borrow().len() != capacity {
+            *self.remaining.borrow_mut() = vec![0.; capacity];
+            self.clear();
+        }
+    }
+
+    fn clear(&self) {
+        let buffers = self.buffers.borrow();
+        let size = (self.remaining.borrow().len() * STATE_FLOATS * 4) as i32;
+        for buffer in buffers.state.iter() {
+            // Zeroed state has no lifetime left, every slot is dead
+            self.gl.bind_buffer(GL::ARRAY_BUFFER, Some(buffer));
+            self.gl.buffer_data_with_i32(GL::ARRAY_BUFFER, size, WebGl2RenderingContext::DYNAMIC_COPY);
+        }
+        self.gl.bind_buffer(GL::ARRAY_BUFFER, None);
+        self.remaining.borrow_mut().iter_mut().for_each(|r| *r = 0.);
+    }
+
+    //--Write new particles into free slots of the current state--
+    fn spawn(&self, particles: &[Particle]) {
+        let buffers = self.buffers.borrow();
+        let mut remaining = self.remaining.borrow_mut();
+        self.gl.bind_buffer(GL::ARRAY_BUFFER, Some(&buffers.state[buffers.current]));
+        let mut slots = (0..remaining.len()).filter(|&i| remaining[i] <= 0.).collect::<Vec<_>>().into_iter();
+        for (p, slot) in particles.iter().zip(&mut slots) {
+            let state = [
+                p.position[0],
+                p.position[1],
+                p.position[2],
+                p.velocity[0],
+                p.velocity[1],
+                p.velocity[2],
+                p.age,
+                p.lifetime,
+            ];
+            let view = js_sys::Float32Array::from(&state[..]);
+            self.gl
+                .buffer_sub_data_with_i32_and_array_buffer_view(GL::ARRAY_BUFFER, (slot * STATE_FLOATS * 4) as i32, &view);
+            remaining[slot] = p.lifetime - p.age;
+        }
+        self.gl.bind_buffer(GL::ARRAY_BUFFER, None);
+    }
+
+    //--Advance every slot by `dt` seconds, see shader/particle_update.vert--
+    fn step(&self, dt: f32, gravity: &[f32; 3], damping: f32, capacity: usize) {
+        self.resize(capacity);
+        let gl = &self.gl;
+        gl.use_program(Some(&self.update.program()));
+        self.update.set_uniform(gl, "dt", &Uniform::Float(dt));
+        self.update.set_uniform(gl, "gravity", &Uniform::Vec3(*gravity));
+        self.update.set_uniform(gl, "damping", &Uniform::Float(damping));
+
+        let mut buffers = self.buffers.borrow_mut();
+        let (source, target) = (buffers.current, 1 - buffers.current);
+        self.feedback(&self.update, &buffers.arrays[0], &buffers.state[source], &buffers.state[target], capacity);
+        buffers.current = target;
+        self.remaining.borrow_mut().iter_mut().for_each(|r| *r -= dt);
+    }
+
+    //--Write one camera-facing instance per slot into the batch, see shader/particle_expand.vert--
+    fn expand(&self, batch: &InstanceBatch, emitter: &Emitter, rotation: &[f32; 4]) -> Result<(), RenderError> {
+        let capacity = self.remaining.borrow().len();
+        if capacity == 0 {
+            batch.clear();
+            return Ok(());
+        }
+        let target = batch.gpu_buffer(&self.gl, capacity)?;
+
+        let gl = &self.gl;
+        gl.use_program(Some(&self.expand.program()));
+        let mut billboard = Matrix::new();
+        billboard.set_trs(&[0., 0., 0.], rotation, &[1., 1., 1.]);
+        self.expand.set_uniform(gl, "billboard", &Uniform::Mat4(billboard.get_value()));
+        let life = |i: usize| i as f32 / (LIFE_SAMPLES - 1) as f32;
+        let sizes: Vec<f32> = (0..LIFE_SAMPLES).map(|i| emitter.size.sample(life(i))).collect();
+        let colors: Vec<f32> = (0..LIFE_SAMPLES).flat_map(|i| emitter.color.sample(life(i))).collect();
+        if let Some(location) = self.expand.uniform_location(gl, "lifeSize") {
+            gl.uniform1fv_with_f32_array(Some(&location), &sizes);
+        }
+        if let Some(location) = self.expand.uniform_location(gl, "lifeColor") {
+            gl.uniform4fv_with_f32_array(Some(&location), &colors);
+        }
+
+        let buffers = self.buffers.borrow();
+        self.feedback(&self.expand, &buffers.arrays[1], &buffers.state[buffers.current], &target, capacity);
+        Ok(())
+    }
+
+    //--Run `program` once per slot of `source`, capturing its outputs in `target`--
+    fn feedback(
+        &self,
+        program: &ShaderProgram,
+        array: &WebGlVertexArrayObject,
+        source: &WebGlBuffer,
+        target: &WebGlBuffer,
+        capacity: usize,
+    ) {
+        let (gl, gl2) = (&self.gl, &self.gl2);
+        // Own vertex array, so attributes the renderer left enabled aren't validated against this draw
+        gl2.bind_vertex_array(Some(array));
+        gl.bind_buffer(GL::ARRAY_BUFFER, Some(source));
+        let stride = (STATE_FLOATS * 4) as i32;
+        for (name, size, offset) in [("position", 3, 0), ("velocity", 3, 12), ("age", 1, 24), ("lifetime", 1, 28)] {
+            let location = program.attrib_location(gl, name);
+            if location >= 0 {
+                gl.enable_vertex_attrib_array(location as u32);
+                gl.vertex_attrib_pointer_with_i32(location as u32, size, GL::FLOAT, false, stride, offset);
+            }
+        }
+        gl.bind_buffer(GL::ARRAY_BUFFER, None);
+
+        gl2.bind_buffer_base(WebGl2RenderingContext::TRANSFORM_FEEDBACK_BUFFER, 0, Some(target));
+        gl.enable(WebGl2RenderingContext::RASTERIZER_DISCARD);
+        gl2.begin_transform_feedback(GL::POINTS);
+        gl.draw_arrays(GL::POINTS, 0, capacity as i32);
+        gl2.end_transform_feedback();
+        gl.disable(WebGl2RenderingContext::RASTERIZER_DISCARD);
+        gl2.bind_buffer_base(WebGl2RenderingContext::TRANSFORM_FEEDBACK_BUFFER, 0, None);
+        gl2.bind_vertex_array(None);
+    }
+}
+
+impl GpuBuffers {
+    fn new(gl2: &WebGl2RenderingContext, capacity: usize) -> Result<Self, RenderError> {
+        let buffer = || {
+            let buffer = gl2
+                .create_buffer()
+                .ok_or_else(|| RenderError::Buffer("failed to create particle state buffer".into()))?;
+            gl2.bind_buffer(GL::ARRAY_BUFFER, Some(&buffer));
+            gl2.buffer_data_with_i32(
+                GL::ARRAY_BUFFER,
+                (capacity * STATE_FLOATS * 4) as i32,
+                WebGl2RenderingContext::DYNAMIC_COPY,
+            );
+            Ok(buffer)
+        };
+        let array = || {
+            gl2.create_vertex_array()
+                .ok_or_else(|| RenderError::Buffer("failed to create particle vertex array".into()))
+        };
+        let state = [buffer()?, buffer()?];
+        gl2.bind_buffer(GL::ARRAY_BUFFER, None);
+        Ok(Self {
+            state,
+            arrays: [array()?, array()?],
+            current: 0,
+        })
+    }
+}
+
+impl Restorable for GpuSimulation {
+    //  The state was on the lost context, every particle is gone
+    fn restore(&self, _gl: &GL) -> Result<(), RenderError> {
+        let capacity = self.remaining.borrow().len();
+        *self.buffers.borrow_mut() = GpuBuffers::new(&self.gl2, capacity)?;
+        self.remaining.borrow_mut().iter_mut().for_each(|r| *r = 0.);
+        Ok(())
+    }
+}
+
+//--Program for particle batches, unlit round sprites--
+pub fn particle_program(gl: &GL) -> Result<ShaderProgram, RenderError> {
+    ShaderProgram::new(gl, include_str!("shader/particle.vert"), include_str!("shader/particle.frag"))
+}
@@ -0,0 +1,556 @@
+use crate::context::ResourceTracker;
+use crate::error::RenderError;
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::render_state::{CullFace, RenderState, Winding};
+use crate::texture::{Texture, TextureSource};
+use crate::vec_3;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+const PI: f32 = std::f32::consts::PI;
+//  Brightest gamma encoded value of the RGBM environment, RGBM_RANGE in pbr.frag
+const RGBM_RANGE: f32 = 8.;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AlphaMode {
+    Opaque,
+    Mask,
+    Blend,
+}
+
+//--Reference to a texture in the asset's texture list--
+//  <note>
+//      Same as glTF textureInfo: `index` into the textures array
+//      and the TEXCOORD_n set used to sample it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextureRef {
+    pub index: usize,
+    pub tex_coord: u32,
+}
+
+//--glTF metallic-roughness material--
+//  <note>
+//      Field names and defaults follow the glTF 2.0 specification,
+//      so a material can be read from an asset without any remapping.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PbrMaterial {
+    pub base_color_factor: [f32; 4],
+    pub base_color_texture: Option<TextureRef>,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub metallic_roughness_texture: Option<TextureRef>,
+    pub normal_texture: Option<TextureRef>,
+    pub normal_scale: f32,
+    pub occlusion_texture: Option<TextureRef>,
+    pub occlusion_strength: f32,
+    pub emissive_factor: [f32; 3],
+    pub emissive_texture: Option<TextureRef>,
+    pub alpha_mode: AlphaMode,
+    pub alpha_cutoff: f32,
+    pub double_sided: bool,
+}
+
+impl Default for PbrMaterial {
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1., 1., 1., 1.],
+            base_color_texture: None,
+            metallic_factor: 1.,
+            roughness_factor: 1.,
+            metallic_roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.,
+            occlusion_texture: None,
+            occlusion_strength: 1.,
+            emissive_factor: [0., 0., 0.],
+            emissive_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            alpha_cutoff: 0.5,
+            double_sided: false,
+        }
+    }
+}
+
+impl PbrMaterial {
+    //--Create material from a glTF material--
+    //  <argument>
+    //      material &gltf::Material : material of a loaded document
+    pub fn from_gltf(material: &gltf::Material) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        let texture_ref = |texture: gltf::Texture, tex_coord: u32| TextureRef {
+            index: texture.index(),
+            tex_coord,
+        };
+
+        Self {
+            base_color_factor: pbr.base_color_factor(),
+            base_color_texture: pbr
+                .base_color_texture()
+                .map(|info| texture_ref(info.texture(), info.tex_coord())),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            metallic_roughness_texture: pbr
+                .metallic_roughness_texture()
+                .map(|info| texture_ref(info.texture(), info.tex_coord())),
+            normal_texture: material
+                .normal_texture()
+                .map(|info| texture_ref(info.texture(), info.tex_coord())),
+            normal_scale: material.normal_texture().map_or(1., |info| info.scale()),
+            occlusion_texture: material
+                .occlusion_texture()
+                .map(|info| texture_ref(info.texture(), info.tex_coord())),
+            occlusion_strength: material.occlusion_texture().map_or(1., |info| info.strength()),
+            emissive_factor: material.emissive_factor(),
+            emissive_texture: material
+                .emissive_texture()
+                .map(|info| texture_ref(info.texture(), info.tex_coord())),
+            alpha_mode: match material.alpha_mode() {
+                gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+                gltf::material::AlphaMode::Mask => AlphaMode::Mask,
+                gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+            },
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            double_sided: material.double_sided(),
+        }
+    }
+
+    //--Material drawing with the PBR program--
+    //  <argument>
+    //      pbr      &PbrResources  : program, fallback textures and image based lighting
+    //      textures &[Rc<Texture>] : textures of the asset, indexed by TextureRef::index
+    //  <note>
+    //      Slots without a texture, or with an index outside `textures`, sample
+    //      white or a flat normal so the factors apply unchanged.
+    pub fn to_material(&self, pbr: &PbrResources, textures: &[Rc<Texture>]) -> Material {
+        let mut material = Material::new(pbr.program.clone());
+        let alpha_mode = match self.alpha_mode {
+            AlphaMode::Opaque => 0.,
+            AlphaMode::Mask => 1.,
+            AlphaMode::Blend => 2.,
+        };
+        let tex_coord = |texture: &Option<TextureRef>| texture.map_or(0., |t| t.tex_coord.min(1) as f32);
+        material
+            .set("baseColorFactor", Uniform::Vec4(self.base_color_factor))
+            .set("metallicFactor", Uniform::Float(self.metallic_factor))
+            .set("roughnessFactor", Uniform::Float(self.roughness_factor))
+            .set("normalScale", Uniform::Float(self.normal_scale))
+            .set("occlusionStrength", Uniform::Float(self.occlusion_strength))
+            .set("emissiveFactor", Uniform::Vec3(self.emissive_factor))
+            .set("alphaMode", Uniform::Float(alpha_mode))
+            .set("alphaCutoff", Uniform::Float(self.alpha_cutoff))
+            .set(
+                "texCoordSets",
+                Uniform::Vec4([
+                    tex_coord(&self.base_color_texture),
+                    tex_coord(&self.metallic_roughness_texture),
+                    tex_coord(&self.normal_texture),
+                    tex_coord(&self.occlusion_texture),
+                ]),
+            )
+            .set("emissiveTexCoord", Uniform::Float(tex_coord(&self.emissive_texture)))
+            .set("environmentMaxLod", Uniform::Float(pbr.max_lod));
+        for (i, coefficient) in pbr.irradiance.iter().enumerate() {
+            material.set(&format!("irradianceSH[{}]", i), Uniform::Vec3(*coefficient));
+        }
+
+        let slots = [
+            ("baseColorTexture", &self.base_color_texture, &pbr.white),
+            ("metallicRoughnessTexture", &self.metallic_roughness_texture, &pbr.white),
+            ("normalTexture", &self.normal_texture, &pbr.flat_normal),
+            ("occlusionTexture", &self.occlusion_texture, &pbr.white),
+            ("emissiveTexture", &self.emissive_texture, &pbr.white),
+        ];
+        for (name, texture, fallback) in slots.iter() {
+            let texture = texture.and_then(|t| textures.get(t.index)).unwrap_or(fallback);
+            material.set_texture(name, texture.clone());
+        }
+        material
+            .set_texture("environmentMap", pbr.environment.clone())
+            .set_texture("brdfLut", pbr.brdf_lut.clone())
+            .set_state(self.render_state());
+        material
+    }
+
+    //--Render state implied by alpha mode and double sidedness--
+    pub fn render_state(&self) -> RenderState {
+        let state = if self.alpha_mode == AlphaMode::Blend {
+            RenderState::TRANSPARENT
+        } else {
+            RenderState::OPAQUE
+        };
+        if self.double_sided {
+            state.with_cull_face(CullFace::None, Winding::CounterClockwise)
+        } else {
+            state
+        }
+    }
+}
+
+//--Program, fallback textures and image based lighting shared by PBR materials--
+//  <note>
+//      The program takes the renderer's mvpMatrix, modelMatrix and normalMatrix,
+//      and the lightDirection, lightColor and cameraPosition globals a scene sets.
+//      Meshes need position and normal, texCoord0 and tangent are optional;
+//      without tangents normal maps are ignored.
+pub struct PbrResources {
+    pub program: Rc<ShaderProgram>,
+    white: Rc<Texture>,
+    flat_normal: Rc<Texture>,
+    environment: Rc<Texture>,
+    brdf_lut: Rc<Texture>,
+    max_lod: f32,
+    irradiance: [[f32; 3]; 9],
+}
+
+impl PbrResources {
+    //--Compile the program and prefilter an environment on the CPU--
+    //  <argument>
+    //      environment &Environment : source radiance, size must be a power of two
+    //      samples     u32          : GGX samples per texel
+    pub fn new(gl: &GL, environment: &Environment, samples: u32) -> Result<Self, RenderError> {
+        // textureCubeLodEXT picks the roughness level, the shader falls back to a bias
+        let program = ShaderProgram::with_extensions(
+            gl,
+            include_str!("shader/pbr.vert"),
+            include_str!("shader/pbr.frag"),
+            &["EXT_shader_texture_lod"],
+        )?;
+        let levels = environment.prefilter(samples);
+        let lut_size = 64;
+        Ok(Self {
+            program: Rc::new(program),
+            white: Rc::new(Texture::image_2d(gl, 1, 1, vec![255, 255, 255, 255])?),
+            flat_normal: Rc::new(Texture::image_2d(gl, 1, 1, vec![128, 128, 255, 255])?),
+            max_lod: (levels.len() - 1) as f32,
+            environment: Rc::new(Texture::new(
+                gl,
+                TextureSource::Cube {
+                    size: environment.size as u32,
+                    levels,
+                },
+            )?),
+            brdf_lut: Rc::new(Texture::image_2d(gl, lut_size, lut_size, brdf_lut(lut_size as usize, 256))?),
+            irradiance: environment.irradiance_sh(),
+        })
+    }
+
+    //--Rebuild the program and textures on context restore--
+    pub fn track(&self, resources: &mut ResourceTracker) {
+        resources.track(&self.program);
+        for texture in [&self.white, &self.flat_normal, &self.environment, &self.brdf_lut].iter() {
+            resources.track(texture);
+        }
+    }
+}
+
+//--Radiance cube map kept on the CPU--
+//  <note>
+//      Faces are in +X, -X, +Y, -Y, +Z, -Z order with linear RGB texels,
+//      row 0 being t = 0 as in glTexImage2D.
+pub struct Environment {
+    pub size: usize,
+    pub faces: [Vec<[f32; 3]>; 6],
+}
+
+impl Environment {
+    //--Create environment by evaluating radiance for every texel direction--
+    pub fn from_fn<F: Fn([f32; 3]) -> [f32; 3]>(size: usize, radiance: F) -> Self {
+        let face = |f: usize| {
+            let mut texels = Vec::with_capacity(size * size);
+            for j in 0..size {
+                for i in 0..size {
+                    let s = (i as f32 + 0.5) / size as f32;
+                    let t = (j as f32 + 0.5) / size as f32;
+                    texels.push(radiance(cube_direction(f, s, t)));
+                }
+            }
+            texels
+        };
+        Self {
+            size,
+            faces: [face(0), face(1), face(2), face(3), face(4), face(5)],
+        }
+    }
+
+    //--Outdoor light: sky gradient over a dim ground, with a sun brighter than white--
+    //  <argument>
+    //      size usize     : edge length, a power of two
+    //      sun  &[f32; 3] : direction towards the sun
+    pub fn sky(size: usize, sun: &[f32; 3]) -> Self {
+        let sun = vec_3::normalize(sun);
+        Self::from_fn(size, |d| {
+            let up = d[1];
+            let base = if up >= 0. {
+                vec_3::lerp(&[0.9, 0.9, 0.85], &[0.3, 0.5, 0.9], up.powf(0.5))
+            } else {
+                vec_3::lerp(&[0.4, 0.37, 0.33], &[0.15, 0.13, 0.11], (-up).powf(0.5))
+            };
+            // Narrow falloff spread over a few texels so every mip level sees it
+            let glow = vec_3::dot(&d, &sun).max(0.).powf(64.) * 24.;
+            vec_3::add(&base, &[glow, glow * 0.95, glow * 0.85])
+        })
+    }
+
+    //--Bilinear radiance lookup--
+    pub fn sample(&self, dir: &[f32; 3]) -> [f32; 3] {
+        let (face, s, t) = cube_lookup(dir);
+        let texels = &self.faces[face];
+        let n = self.size;
+        let x = (s * n as f32 - 0.5).max(0.);
+        let y = (t * n as f32 - 0.5).max(0.);
+        let x0 = (x as usize).min(n - 1);
+        let y0 = (y as usize).min(n - 1);
+        let x1 = (x0 + 1).min(n - 1);
+        let y1 = (y0 + 1).min(n - 1);
+        let fx = x - x0 as f32;
+        let fy = y - y0 as f32;
+        let top = vec_3::lerp(&texels[y0 * n + x0], &texels[y0 * n + x1], fx);
+        let bottom = vec_3::lerp(&texels[y1 * n + x0], &texels[y1 * n + x1], fx);
+        vec_3::lerp(&top, &bottom, fy)
+    }
+
+    //--Prefilter radiance with the GGX lobe for every mip level--
+    //  <return> Vec<[Vec<u8>; 6]>  RGBM encoded faces, level l uses roughness l / (levels - 1)
+    //  <note>
+    //      The chain goes down to 1x1 so the cube map is mipmap complete.
+    //      RGBM keeps radiance above 1 in 8 bits: rgb * a * RGBM_RANGE is the
+    //      gamma 2.2 encoded value. Filtering blends encoded texels, which is
+    //      close enough for the smooth prefiltered levels.
+    pub fn prefilter(&self, samples: u32) -> Vec<[Vec<u8>; 6]> {
+        let levels = self.size.trailing_zeros() as usize + 1;
+        let mut result = Vec::with_capacity(levels);
+        for level in 0..levels {
+            let size = (self.size >> level).max(1);
+            let roughness = if levels > 1 {
+                level as f32 / (levels - 1) as f32
+            } else {
+                0.
+            };
+            let face = |f: usize| {
+                let mut data = Vec::with_capacity(size * size * 4);
+                for j in 0..size {
+                    for i in 0..size {
+                        let s = (i as f32 + 0.5) / size as f32;
+                        let t = (j as f32 + 0.5) / size as f32;
+                        let n = cube_direction(f, s, t);
+                        let c = if level == 0 {
+                            self.sample(&n)
+                        } else {
+                            self.convolve_ggx(&n, roughness, samples)
+                        };
+                        data.extend_from_slice(&encode_rgbm(&c));
+                    }
+                }
+                data
+            };
+            result.push([face(0), face(1), face(2), face(3), face(4), face(5)]);
+        }
+        result
+    }
+
+    fn convolve_ggx(&self, n: &[f32; 3], roughness: f32, samples: u32) -> [f32; 3] {
+        let mut color = [0.; 3];
+        let mut weight = 0.;
+        for i in 0..samples {
+            let h = importance_sample_ggx(hammersley(i, samples), n, roughness);
+            let l = vec_3::sub(&vec_3::scale(&h, 2. * vec_3::dot(n, &h)), n);
+            let n_dot_l = vec_3::dot(n, &l);
+            if n_dot_l > 0. {
+                color = vec_3::add(&color, &vec_3::scale(&self.sample(&l), n_dot_l));
+                weight += n_dot_l;
+            }
+        }
+        if weight > 0. {
+            vec_3::scale(&color, weight.recip())
+        } else {
+            self.sample(n)
+        }
+    }
+
+    //--Project the cosine-convolved radiance onto 9 SH coefficients--
+    //  <note>
+    //      Coefficients already include the Lambert 1/PI, so the shader
+    //      gets irradiance for a white diffuse surface directly.
+    pub fn irradiance_sh(&self) -> [[f32; 3]; 9] {
+        let mut sh = [[0.; 3]; 9];
+        let n = self.size;
+        for (f, texels) in self.faces.iter().enumerate() {
+            for j in 0..n {
+                for i in 0..n {
+                    let s = (i as f32 + 0.5) / n as f32;
+                    let t = (j as f32 + 0.5) / n as f32;
+                    let d = cube_direction(f, s, t);
+                    let weight = texel_solid_angle(i, j, n);
+                    let c = texels[j * n + i];
+                    for (k, y) in sh_basis(&d).iter().enumerate() {
+                        for ch in 0..3 {
+                            sh[k][ch] += c[ch] * y * weight;
+                        }
+                    }
+                }
+            }
+        }
+
+        // Cosine lobe convolution (PI, 2PI/3, PI/4 per band) and Lambert 1/PI
+        let band = [1., 2. / 3., 2. / 3., 2. / 3., 0.25, 0.25, 0.25, 0.25, 0.25];
+        for (coefficient, a) in sh.iter_mut().zip(band.iter()) {
+            for ch in coefficient.iter_mut() {
+                *ch *= a;
+            }
+        }
+        sh
+    }
+}
+
+//--Generate the split-sum BRDF lookup table--
+//  <argument>
+//      size    usize : edge length, u = dot(N, V) and v = roughness
+//      samples u32   : GGX samples per texel
+//  <return> Vec<u8>  RGBA texels with scale in R and bias in G
+pub fn brdf_lut(size: usize, samples: u32) -> Vec<u8> {
+    let mut data = Vec::with_capacity(size * size * 4);
+    for j in 0..size {
+        for i in 0..size {
+            let n_dot_v = (i as f32 + 0.5) / size as f32;
+            let roughness = (j as f32 + 0.5) / size as f32;
+            let (scale, bias) = integrate_brdf(n_dot_v, roughness, samples);
+            data.extend_from_slice(&[
+                (scale.clamp(0., 1.) * 255.).round() as u8,
+                (bias.clamp(0., 1.) * 255.).round() as u8,
+                0,
+                255,
+            ]);
+        }
+    }
+    data
+}
+
+fn integrate_brdf(n_dot_v: f32, roughness: f32, samples: u32) -> (f32, f32) {
+    let v = [(1. - n_dot_v * n_dot_v).sqrt(), 0., n_dot_v];
+    let n = [0., 0., 1.];
+    let mut scale = 0.;
+    let mut bias = 0.;
+    for i in 0..samples {
+        let h = importance_sample_ggx(hammersley(i, samples), &n, roughness);
+        let v_dot_h = vec_3::dot(&v, &h);
+        let l = vec_3::sub(&vec_3::scale(&h, 2. * v_dot_h), &v);
+        let n_dot_l = l[2].max(0.);
+        let n_dot_h = h[2].max(0.);
+        if n_dot_l > 0. {
+            let g = geometry_smith(n_dot_v, n_dot_l, roughness);
+            let g_vis = g * v_dot_h.max(0.) / (n_dot_h * n_dot_v);
+            let fc = (1. - v_dot_h.max(0.)).powi(5);
+            scale += (1. - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    (scale / samples as f32, bias / samples as f32)
+}
+
+//--Smith-Schlick geometry term with the IBL remapping k = alpha / 2--
+fn geometry_smith(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.;
+    let g_v = n_dot_v / (n_dot_v * (1. - k) + k);
+    let g_l = n_dot_l / (n_dot_l * (1. - k) + k);
+    g_v * g_l
+}
+
+fn hammersley(i: u32, n: u32) -> (f32, f32) {
+    (i as f32 / n as f32, i.reverse_bits() as f32 * 2.328_306_4e-10)
+}
+
+fn importance_sample_ggx(xi: (f32, f32), n: &[f32; 3], roughness: f32) -> [f32; 3] {
+    let a = roughness * roughness;
+    let phi = 2. * PI * xi.0;
+    let cos_theta = ((1. - xi.1) / (1. + (a * a - 1.) * xi.1)).sqrt();
+    let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+
+    let up = if n[2].abs() < 0.999 { [0., 0., 1.] } else { [1., 0., 0.] };
+    let tangent_x = vec_3::normalize(&vec_3::cross(&up, n));
+    let tangent_y = vec_3::cross(n, &tangent_x);
+    let h = vec_3::add(
+        &vec_3::add(
+            &vec_3::scale(&tangent_x, sin_theta * phi.cos()),
+            &vec_3::scale(&tangent_y, sin_theta * phi.sin()),
+        ),
+        &vec_3::scale(n, cos_theta),
+    );
+    vec_3::normalize(&h)
+}
+
+fn sh_basis(d: &[f32; 3]) -> [f32; 9] {
+    let (x, y, z) = (d[0], d[1], d[2]);
+    [
+        0.282_095,
+        0.488_603 * y,
+        0.488_603 * z,
+        0.488_603 * x,
+        1.092_548 * x * y,
+        1.092_548 * y * z,
+        0.315_392 * (3. * z * z - 1.),
+        1.092_548 * x * z,
+        0.546_274 * (x * x - y * y),
+    ]
+}
+
+//--Solid angle covered by texel (i, j) of a cube face--
+fn texel_solid_angle(i: usize, j: usize, size: usize) -> f32 {
+    let inv = 1. / size as f32;
+    let x0 = 2. * i as f32 * inv - 1.;
+    let y0 = 2. * j as f32 * inv - 1.;
+    let x1 = x0 + 2. * inv;
+    let y1 = y0 + 2. * inv;
+    let area = |x: f32, y: f32| (x * y).atan2((x * x + y * y + 1.).sqrt());
+    area(x0, y0) - area(x0, y1) - area(x1, y0) + area(x1, y1)
+}
+
+//--Direction through (s, t) of a cube face, same convention as GL--
+fn cube_direction(face: usize, s: f32, t: f32) -> [f32; 3] {
+    let sc = 2. * s - 1.;
+    let tc = 2. * t - 1.;
+    let d = match face {
+        0 => [1., -tc, -sc],
+        1 => [-1., -tc, sc],
+        2 => [sc, 1., tc],
+        3 => [sc, -1., -tc],
+        4 => [sc, -tc, 1.],
+        _ => [-sc, -tc, -1.],
+    };
+    vec_3::normalize(&d)
+}
+
+//--Cube face and (s, t) hit by a direction, same convention as GL--
+fn cube_lookup(d: &[f32; 3]) -> (usize, f32, f32) {
+    let (ax, ay, az) = (d[0].abs(), d[1].abs(), d[2].abs());
+    let (face, sc, tc, ma) = if ax >= ay && ax >= az {
+        if d[0] > 0. {
+            (0, -d[2], -d[1], ax)
+        } else {
+            (1, d[2], -d[1], ax)
+        }
+    } else if ay >= az {
+        if d[1] > 0. {
+            (2, d[0], d[2], ay)
+        } else {
+            (3, d[0], -d[2], ay)
+        }
+    } else if d[2] > 0. {
+        (4, d[0], -d[1], az)
+    } else {
+        (5, -d[0], -d[1], az)
+    };
+    (face, (sc / ma + 1.) / 2., (tc / ma + 1.) / 2.)
+}
+
+//--Linear radiance to gamma encoded RGBM, clamped to RGBM_RANGE--
+fn encode_rgbm(c: &[f32; 3]) -> [u8; 4] {
+    let g = [
+        c[0].max(0.).powf(1. / 2.2) / RGBM_RANGE,
+        c[1].max(0.).powf(1. / 2.2) / RGBM_RANGE,
+        c[2].max(0.).powf(1. / 2.2) / RGBM_RANGE,
+    ];
+    // Rounding the multiplier up keeps rgb within 0..1
+    let m = (g[0].max(g[1]).max(g[2]).clamp(1. / 255., 1.) * 255.).ceil() / 255.;
+    let byte = |v: f32| ((v / m).min(1.) * 255.).round() as u8;
+    [byte(g[0]), byte(g[1]), byte(g[2]), (m * 255.).round() as u8]
+}
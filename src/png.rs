@@ -0,0 +1,501 @@
+use crate::error::RenderError;
+
+//--PNG encoding of 8-bit RGBA images--
+//  <note>
+//      Pure Rust without web APIs, so it runs natively as well as in the browser.
+//      Rows are filtered adaptively and compressed with fixed-Huffman deflate,
+//      which is far from optimal but keeps renders with flat areas small.
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+//  Deflate window and longest match
+const WINDOW: usize = 32768;
+const MAX_MATCH: usize = 258;
+const MIN_MATCH: usize = 3;
+//  Match candidates visited per position, trades speed for size
+const MAX_CHAIN: usize = 48;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+//--Encode an image as PNG--
+//  <argument>
+//      width, height u32  : image size in pixels
+//      rgba          &[u8] : 4 bytes per pixel, rows from top to bottom
+//  <return> Result<Vec<u8>, RenderError>  file contents
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Result<Vec<u8>, RenderError> {
+    let stride = width as usize * 4;
+    if width == 0 || height == 0 || rgba.len() != stride * height as usize {
+        return Err(RenderError::Resource(format!(
+            "{} bytes don't make a {}x{} RGBA image",
+            rgba.len(),
+            width,
+            height
+        )));
+    }
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, adaptive filtering, no interlace
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib(&filter_rows(rgba, stride)));
+    write_chunk(&mut png, b"IEND", &[]);
+    Ok(png)
+}
+
+//--Reverse the row order in place, e.g. for readPixels output which starts at the bottom--
+pub fn flip_rows(pixels: &mut [u8], stride: usize) {
+    if stride == 0 {
+        return;
+    }
+    let rows = pixels.len() / stride;
+    for row in 0..rows / 2 {
+        let (top, bottom) = pixels.split_at_mut((rows - 1 - row) * stride);
+        top[row * stride..(row + 1) * stride].swap_with_slice(&mut bottom[..stride]);
+    }
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+//--Prefix every row with the filter that leaves the smallest residuals--
+//  <note>
+//      Minimum sum of absolute differences, the heuristic the PNG spec suggests.
+fn filter_rows(rgba: &[u8], stride: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rgba.len() + rgba.len() / stride);
+    let zero = vec![0u8; stride];
+    let mut candidate = vec![0u8; stride];
+    let mut best = vec![0u8; stride];
+    for (y, row) in rgba.chunks_exact(stride).enumerate() {
+        let above = if y == 0 { &zero[..] } else { &rgba[(y - 1) * stride..y * stride] };
+        let mut best_filter = 0;
+        let mut best_cost = u64::MAX;
+        for filter in 0..5u8 {
+            for i in 0..stride {
+                let left = if i >= 4 { row[i - 4] } else { 0 };
+                let upper_left = if i >= 4 { above[i - 4] } else { 0 };
+                let predicted = match filter {
+                    0 => 0,
+                    1 => left,
+                    2 => above[i],
+                    3 => ((left as u16 + above[i] as u16) / 2) as u8,
+                    _ => paeth(left, above[i], upper_left),
+                };
+                candidate[i] = row[i].wrapping_sub(predicted);
+            }
+            let cost: u64 = candidate.iter().map(|&b| (b as i8).unsigned_abs() as u64).sum();
+            if cost < best_cost {
+                best_cost = cost;
+                best_filter = filter;
+                std::mem::swap(&mut best, &mut candidate);
+            }
+        }
+        out.push(best_filter);
+        out.extend_from_slice(&best);
+    }
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = ((p - a as i16).abs(), (p - b as i16).abs(), (p - c as i16).abs());
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+//--zlib stream: header, one fixed-Huffman deflate block, Adler-32--
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    // Default compression, no dictionary, header checksum divisible by 31
+    bits.bytes.extend_from_slice(&[0x78, 0x9c]);
+    // Final block, fixed Huffman codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut head = vec![u32::MAX; 1 << HASH_BITS];
+    let mut prev = vec![u32::MAX; WINDOW];
+    let hash = |i: usize| {
+        let v = (data[i] as u32) << 16 | (data[i + 1] as u32) << 8 | data[i + 2] as u32;
+        (v.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    };
+    let insert = |i: usize, head: &mut Vec<u32>, prev: &mut Vec<u32>| {
+        if i + MIN_MATCH <= data.len() {
+            let h = hash(i);
+            prev[i % WINDOW] = head[h];
+            head[h] = i as u32;
+        }
+    };
+
+    let mut i = 0;
+    while i < data.len() {
+        let (mut best_len, mut best_distance) = (0, 0);
+        if i + MIN_MATCH <= data.len() {
+            let max_len = MAX_MATCH.min(data.len() - i);
+            let mut candidate = head[hash(i)];
+            let mut chain = 0;
+            while candidate != u32::MAX && chain < MAX_CHAIN {
+                let j = candidate as usize;
+                if i - j > WINDOW - 1 {
+                    break;
+                }
+                let len = data[j..j + max_len].iter().zip(&data[i..i + max_len]).take_while(|(a, b)| a == b).count();
+                if len > best_len {
+                    best_len = len;
+                    best_distance = i - j;
+                    if len == max_len {
+                        break;
+                    }
+                }
+                let next = prev[j % WINDOW];
+                // Older entries of the slot were overwritten by newer positions
+                if next == u32::MAX || next as usize >= j {
+                    break;
+                }
+                candidate = next;
+                chain += 1;
+            }
+        }
+
+        if best_len >= MIN_MATCH {
+            bits.length(best_len);
+            bits.distance(best_distance);
+            for k in i..i + best_len {
+                insert(k, &mut head, &mut prev);
+            }
+            i += best_len;
+        } else {
+            bits.literal(data[i] as u16);
+            insert(i, &mut head, &mut prev);
+            i += 1;
+        }
+    }
+    bits.literal(256);
+
+    let mut out = bits.finish();
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+//--Deflate bit stream, least significant bit first--
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.buffer |= (value as u64) << self.count;
+        self.count += bits;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    //  Huffman codes are defined most significant bit first
+    fn code(&mut self, code: u32, bits: u32) {
+        self.write(code.reverse_bits() >> (32 - bits), bits);
+    }
+
+    //--Literal byte or end of block (256) with the fixed code table--
+    fn literal(&mut self, symbol: u16) {
+        let symbol = symbol as u32;
+        match symbol {
+            0..=143 => self.code(0x30 + symbol, 8),
+            144..=255 => self.code(0x190 + symbol - 144, 9),
+            256..=279 => self.code(symbol - 256, 7),
+            _ => self.code(0xc0 + symbol - 280, 8),
+        }
+    }
+
+    fn length(&mut self, length: usize) {
+        let index = LENGTH_BASE.iter().rposition(|&base| base as usize <= length).unwrap_or(0);
+        self.literal(257 + index as u16);
+        self.write((length - LENGTH_BASE[index] as usize) as u32, LENGTH_EXTRA[index] as u32);
+    }
+
+    fn distance(&mut self, distance: usize) {
+        let index = DISTANCE_BASE.iter().rposition(|&base| base as usize <= distance).unwrap_or(0);
+        self.code(index as u32, 5);
+        self.write((distance - DISTANCE_BASE[index] as usize) as u32, DISTANCE_EXTRA[index] as u32);
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+//--CRC-32 as used by PNG chunks and ZIP entries--
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut table = [0u32; 256];
+    for (n, entry) in table.iter_mut().enumerate() {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 != 0 { (c >> 1) ^ 0xedb8_8320 } else { c >> 1 };
+        }
+        *entry = c;
+    }
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = table[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryInto;
+
+    //--Reads a deflate stream LSB first--
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        position: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bits(&mut self, count: u32) -> u32 {
+            let mut value = 0;
+            for i in 0..count {
+                let byte = self.bytes[self.position / 8];
+                value |= ((byte >> (self.position % 8)) as u32 & 1) << i;
+                self.position += 1;
+            }
+            value
+        }
+
+        //  Huffman codes are stored most significant bit first
+        fn code(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |code, _| code << 1 | self.bits(1))
+        }
+
+        //--Symbol of the fixed literal/length table--
+        fn literal(&mut self) -> u16 {
+            let code = self.code(7);
+            if code < 0x18 {
+                return 256 + code as u16;
+            }
+            let code = code << 1 | self.bits(1);
+            match code {
+                0x30..=0xbf => (code - 0x30) as u16,
+                0xc0..=0xc7 => (280 + code - 0xc0) as u16,
+                _ => (144 + (code << 1 | self.bits(1)) - 0x190) as u16,
+            }
+        }
+    }
+
+    //--Inflate a zlib stream of fixed-Huffman or stored blocks, checking the Adler-32--
+    fn inflate(zlib: &[u8]) -> Vec<u8> {
+        assert_eq!(zlib[0] & 0x0f, 8, "deflate");
+        assert_eq!((zlib[0] as u16 * 256 + zlib[1] as u16) % 31, 0, "header check");
+        let mut bits = BitReader {
+            bytes: &zlib[2..],
+            position: 0,
+        };
+        let mut out: Vec<u8> = Vec::new();
+        loop {
+            let last = bits.bits(1) == 1;
+            match bits.bits(2) {
+                0 => {
+                    bits.position = bits.position.div_ceil(8) * 8;
+                    let len = bits.bits(16) as usize;
+                    assert_eq!(bits.bits(16) as usize, !len & 0xffff);
+                    for _ in 0..len {
+                        out.push(bits.bits(8) as u8);
+                    }
+                }
+                1 => loop {
+                    let symbol = bits.literal() as usize;
+                    match symbol {
+                        0..=255 => out.push(symbol as u8),
+                        256 => break,
+                        _ => {
+                            let index = symbol - 257;
+                            let length = LENGTH_BASE[index] as usize + bits.bits(LENGTH_EXTRA[index] as u32) as usize;
+                            let index = bits.code(5) as usize;
+                            let distance = DISTANCE_BASE[index] as usize + bits.bits(DISTANCE_EXTRA[index] as u32) as usize;
+                            assert!(distance <= out.len() && distance <= WINDOW);
+                            let start = out.len() - distance;
+                            // Byte by byte, matches may overlap their own output
+                            for k in 0..length {
+                                out.push(out[start + k]);
+                            }
+                        }
+                    }
+                },
+                kind => panic!("unexpected block type {}", kind),
+            }
+            if last {
+                break;
+            }
+        }
+        let end = 2 + bits.position.div_ceil(8);
+        assert_eq!(&zlib[end..], &adler32(&out).to_be_bytes(), "Adler-32");
+        out
+    }
+
+    //--Decode what encode_rgba writes: signature, IHDR, IDAT chunks and IEND--
+    fn decode(png: &[u8]) -> (u32, u32, Vec<u8>) {
+        assert_eq!(png[..8], SIGNATURE);
+        let mut at = 8;
+        let mut size = None;
+        let mut idat = Vec::new();
+        loop {
+            let len = u32::from_be_bytes(png[at..at + 4].try_into().unwrap()) as usize;
+            let kind = &png[at + 4..at + 8];
+            let data = &png[at + 8..at + 8 + len];
+            let crc = u32::from_be_bytes(png[at + 8 + len..at + 12 + len].try_into().unwrap());
+            assert_eq!(crc, crc32(&png[at + 4..at + 8 + len]), "CRC of {:?}", kind);
+            at += 12 + len;
+            match kind {
+                b"IHDR" => {
+                    assert_eq!(&data[8..], &[8, 6, 0, 0, 0]);
+                    let word = |i: usize| u32::from_be_bytes(data[i..i + 4].try_into().unwrap());
+                    size = Some((word(0), word(4)));
+                }
+                b"IDAT" => idat.extend_from_slice(data),
+                b"IEND" => break,
+                _ => panic!("unexpected chunk {:?}", kind),
+            }
+        }
+        assert_eq!(at, png.len(), "trailing bytes");
+
+        let (width, height) = size.expect("IHDR");
+        let stride = width as usize * 4;
+        let filtered = inflate(&idat);
+        assert_eq!(filtered.len(), (stride + 1) * height as usize);
+        let mut pixels = vec![0u8; stride * height as usize];
+        for (y, row) in filtered.chunks_exact(stride + 1).enumerate() {
+            for i in 0..stride {
+                let left = if i >= 4 { pixels[y * stride + i - 4] } else { 0 };
+                let above = if y > 0 { pixels[(y - 1) * stride + i] } else { 0 };
+                let upper_left = if y > 0 && i >= 4 { pixels[(y - 1) * stride + i - 4] } else { 0 };
+                let predicted = match row[0] {
+                    0 => 0,
+                    1 => left,
+                    2 => above,
+                    3 => ((left as u16 + above as u16) / 2) as u8,
+                    4 => paeth(left, above, upper_left),
+                    filter => panic!("unknown filter {}", filter),
+                };
+                pixels[y * stride + i] = row[1 + i].wrapping_add(predicted);
+            }
+        }
+        (width, height, pixels)
+    }
+
+    //  Gradients, flat areas and noise, so every filter and long matches show up
+    fn test_image(width: u32, height: u32) -> Vec<u8> {
+        let mut seed: u32 = 1;
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let noise = (seed >> 16) as u8;
+                let pixel = match (x * 3 / width, y * 2 / height) {
+                    (0, _) => [x as u8, y as u8, (x + y) as u8, 255],
+                    (1, 0) => [40, 80, 120, 200],
+                    (1, _) => [noise, noise / 2, 255 - noise, 255],
+                    _ => [(x * y) as u8, noise & 0xf0, 128, (y * 4) as u8],
+                };
+                pixels.extend_from_slice(&pixel);
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn checksums_match_reference_values() {
+        assert_eq!(crc32(b"IEND"), 0xae42_6082);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn decodes_to_the_same_pixels() {
+        // Past the 32K window, a single pixel and odd sizes
+        for &(width, height) in &[(1, 1), (3, 2), (67, 45), (256, 160)] {
+            let pixels = test_image(width, height);
+            let png = encode_rgba(width, height, &pixels).unwrap();
+            let (w, h, decoded) = decode(&png);
+            assert_eq!((w, h), (width, height));
+            assert!(decoded == pixels, "{}x{} pixels differ", width, height);
+        }
+
+        // Flat images compress to almost nothing
+        let flat = [7u8, 8, 9, 255].repeat(128 * 128);
+        let png = encode_rgba(128, 128, &flat).unwrap();
+        assert!(png.len() < 1024, "{} bytes", png.len());
+        assert_eq!(decode(&png).2, flat);
+    }
+
+    #[test]
+    fn matches_the_golden_image() {
+        // Written by testdata/png_golden.py from the same test_image, through zlib
+        let golden = include_bytes!("testdata/png_golden.png");
+        let (width, height, reference) = decode(golden);
+        assert_eq!((width, height), (67, 45));
+        let pixels = test_image(width, height);
+        assert!(reference == pixels, "the decoder disagrees with the reference file");
+
+        let (_, _, decoded) = decode(&encode_rgba(width, height, &pixels).unwrap());
+        for (i, (ours, theirs)) in decoded.chunks(4).zip(reference.chunks(4)).enumerate() {
+            let (x, y) = (i % width as usize, i / width as usize);
+            assert_eq!(ours, theirs, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn rejects_mismatched_sizes() {
+        assert!(encode_rgba(0, 1, &[]).is_err());
+        assert!(encode_rgba(2, 2, &[0; 15]).is_err());
+    }
+
+    #[test]
+    fn flips_rows() {
+        let mut pixels: Vec<u8> = (0..12).collect();
+        flip_rows(&mut pixels, 4);
+        assert_eq!(pixels, [8, 9, 10, 11, 4, 5, 6, 7, 0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,89 @@
+use crate::vec_3;
+
+//--Helpers for unit quaternions stored as [x, y, z, w]--
+
+pub fn identity() -> [f32; 4] {
+    [0., 0., 0., 1.]
+}
+
+//--Rotation of `rad` radians around `axis`--
+pub fn from_axis_angle(axis: &[f32; 3], rad: f32) -> [f32; 4] {
+    let a = vec_3::normalize(axis);
+    let s = (rad / 2.).sin();
+    [a[0] * s, a[1] * s, a[2] * s, (rad / 2.).cos()]
+}
+
+//--Shortest rotation taking direction `from` to direction `to`--
+pub fn from_rotation_arc(from: &[f32; 3], to: &[f32; 3]) -> [f32; 4] {
+    let f = vec_3::normalize(from);
+    let t = vec_3::normalize(to);
+    let d = vec_3::dot(&f, &t);
+    if d < -0.999_999 {
+        // Opposite directions, rotate half a turn around any perpendicular axis
+        let mut axis = vec_3::cross(&[1., 0., 0.], &f);
+        if vec_3::length(&axis) < 1e-6 {
+            axis = vec_3::cross(&[0., 1., 0.], &f);
+        }
+        return from_axis_angle(&axis, std::f32::consts::PI);
+    }
+    let c = vec_3::cross(&f, &t);
+    normalize(&[c[0], c[1], c[2], 1. + d])
+}
+
+//--Hamilton product, applies `b` first and then `a`--
+pub fn multiply(a: &[f32; 4], b: &[f32; 4]) -> [f32; 4] {
+    [
+        a[3] * b[0] + a[0] * b[3] + a[1] * b[2] - a[2] * b[1],
+        a[3] * b[1] - a[0] * b[2] + a[1] * b[3] + a[2] * b[0],
+        a[3] * b[2] + a[0] * b[1] - a[1] * b[0] + a[2] * b[3],
+        a[3] * b[3] - a[0] * b[0] - a[1] * b[1] - a[2] * b[2],
+    ]
+}
+
+pub fn dot(a: &[f32; 4], b: &[f32; 4]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3]
+}
+
+pub fn normalize(q: &[f32; 4]) -> [f32; 4] {
+    let l = dot(q, q).sqrt();
+    if l == 0. {
+        return identity();
+    }
+    [q[0] / l, q[1] / l, q[2] / l, q[3] / l]
+}
+
+//--Rotate a vector--
+pub fn rotate(q: &[f32; 4], v: &[f32; 3]) -> [f32; 3] {
+    let u = [q[0], q[1], q[2]];
+    let t = vec_3::scale(&vec_3::cross(&u, v), 2.);
+    vec_3::add(&vec_3::add(v, &vec_3::scale(&t, q[3])), &vec_3::cross(&u, &t))
+}
+
+//--Spherical linear interpolation along the shortest arc--
+pub fn slerp(a: &[f32; 4], b: &[f32; 4], t: f32) -> [f32; 4] {
+    let mut b = *b;
+    let mut d = dot(a, &b);
+    if d < 0. {
+        b = [-b[0], -b[1], -b[2], -b[3]];
+        d = -d;
+    }
+    if d > 0.9995 {
+        // Nearly parallel, lerp avoids dividing by sin(0)
+        return normalize(&[
+            a[0] + (b[0] - a[0]) * t,
+            a[1] + (b[1] - a[1]) * t,
+            a[2] + (b[2] - a[2]) * t,
+            a[3] + (b[3] - a[3]) * t,
+        ]);
+    }
+    let theta = d.acos();
+    let s = theta.sin();
+    let wa = ((1. - t) * theta).sin() / s;
+    let wb = (t * theta).sin() / s;
+    [
+        a[0] * wa + b[0] * wb,
+        a[1] * wa + b[1] * wb,
+        a[2] * wa + b[2] * wb,
+        a[3] * wa + b[3] * wb,
+    ]
+}
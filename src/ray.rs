@@ -0,0 +1,228 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::error::RenderError;
+use crate::mat_4::Matrix;
+use crate::vec_3;
+
+//--Half line origin + t * direction, t >= 0--
+//  <note>
+//      The direction isn't required to be unit length. Rays built from the
+//      screen have a unit direction so t is a world distance, and transformed()
+//      keeps t meaning the same point on both sides of the transform.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: [f32; 3],
+    pub direction: [f32; 3],
+}
+
+//--Where a ray crosses a triangle--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TriangleHit {
+    pub t: f32,
+    //  Weights of the triangle's first, second and third vertex
+    pub barycentric: [f32; 3],
+}
+
+impl Ray {
+    pub fn new(origin: [f32; 3], direction: [f32; 3]) -> Self {
+        Self { origin, direction }
+    }
+
+    //--Ray from the near plane through a point in normalized device coordinates--
+    //  <argument>
+    //      ndc        [f32; 2] : x and y in -1..1, y pointing up
+    //      view       &Matrix  : view matrix
+    //      projection &Matrix  : projection matrix
+    pub fn from_ndc(ndc: [f32; 2], view: &Matrix, projection: &Matrix) -> Result<Self, RenderError> {
+        let mut inverse = *projection;
+        inverse
+            .multiply(view)
+            .inverse()
+            .map_err(|_| RenderError::Math("view projection matrix is not invertible".into()))?;
+        let near = inverse.transform_point(&[ndc[0], ndc[1], -1.]);
+        let far = inverse.transform_point(&[ndc[0], ndc[1], 1.]);
+        Ok(Self::new(near, vec_3::normalize(&vec_3::sub(&far, &near))))
+    }
+
+    //--Ray through a pixel--
+    //  <argument>
+    //      x, y          f32 : position from the top left corner
+    //      width, height f32 : viewport size in the same units as x and y
+    pub fn from_screen(x: f32, y: f32, width: f32, height: f32, view: &Matrix, projection: &Matrix) -> Result<Self, RenderError> {
+        let ndc = [x / width * 2. - 1., 1. - y / height * 2.];
+        Self::from_ndc(ndc, view, projection)
+    }
+
+    pub fn at(&self, t: f32) -> [f32; 3] {
+        vec_3::add(&self.origin, &vec_3::scale(&self.direction, t))
+    }
+
+    //--Same ray in another space, e.g. a mesh's local space with the inverse model matrix--
+    pub fn transformed(&self, m: &Matrix) -> Self {
+        Self::new(m.transform_point(&self.origin), m.transform_direction(&self.direction))
+    }
+
+    //--Distance to where the ray enters the box, 0 when it starts inside--
+    pub fn intersect_aabb(&self, aabb: &Aabb) -> Option<f32> {
+        // Slab test, IEEE infinities take care of axis-parallel rays
+        let mut near = 0f32;
+        let mut far = f32::INFINITY;
+        for axis in 0..3 {
+            let inv = 1. / self.direction[axis];
+            let mut t0 = (aabb.min[axis] - self.origin[axis]) * inv;
+            let mut t1 = (aabb.max[axis] - self.origin[axis]) * inv;
+            if t0 > t1 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            // max/min drop the NaN of 0 * inf when the origin lies on a slab
+            near = near.max(t0);
+            far = far.min(t1);
+            if near > far {
+                return None;
+            }
+        }
+        Some(near)
+    }
+
+    //--Distance to where the ray enters the sphere, 0 when it starts inside--
+    pub fn intersect_sphere(&self, sphere: &BoundingSphere) -> Option<f32> {
+        let oc = vec_3::sub(&self.origin, &sphere.center);
+        let a = vec_3::dot(&self.direction, &self.direction);
+        let b = vec_3::dot(&oc, &self.direction);
+        let c = vec_3::dot(&oc, &oc) - sphere.radius * sphere.radius;
+        if c <= 0. {
+            return Some(0.);
+        }
+        let discriminant = b * b - a * c;
+        if a == 0. || discriminant < 0. {
+            return None;
+        }
+        let t = (-b - discriminant.sqrt()) / a;
+        if t >= 0. {
+            Some(t)
+        } else {
+            None
+        }
+    }
+
+    //--Hit on a triangle from either side (Möller-Trumbore)--
+    pub fn intersect_triangle(&self, a: &[f32; 3], b: &[f32; 3], c: &[f32; 3]) -> Option<TriangleHit> {
+        let e1 = vec_3::sub(b, a);
+        let e2 = vec_3::sub(c, a);
+        let p = vec_3::cross(&self.direction, &e2);
+        let det = vec_3::dot(&e1, &p);
+        if det.abs() < 1e-12 {
+            // Parallel to the triangle's plane, or a degenerate triangle
+            return None;
+        }
+        let inv_det = 1. / det;
+        let s = vec_3::sub(&self.origin, a);
+        let u = vec_3::dot(&s, &p) * inv_det;
+        if !(0. ..=1.).contains(&u) {
+            return None;
+        }
+        let q = vec_3::cross(&s, &e1);
+        let v = vec_3::dot(&self.direction, &q) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
+        }
+        let t = vec_3::dot(&e2, &q) * inv_det;
+        if t < 0. {
+            return None;
+        }
+        Some(TriangleHit {
+            t,
+            barycentric: [1. - u - v, u, v],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    const A: [f32; 3] = [0., 0., 0.];
+    const B: [f32; 3] = [1., 0., 0.];
+    const C: [f32; 3] = [0., 1., 0.];
+
+    #[test]
+    fn triangle_hits_with_barycentrics() {
+        let ray = Ray::new([0.25, 0.5, 2.], [0., 0., -1.]);
+        let hit = ray.intersect_triangle(&A, &B, &C).unwrap();
+        assert!((hit.t - 2.).abs() < EPSILON);
+        for (w, expected) in hit.barycentric.iter().zip([0.25, 0.25, 0.5]) {
+            assert!((w - expected).abs() < EPSILON, "{:?}", hit);
+        }
+        // The weights reproduce the hit point
+        let weighted = [A, B, C]
+            .iter()
+            .zip(hit.barycentric.iter())
+            .fold([0.; 3], |p, (v, &w)| vec_3::add(&p, &vec_3::scale(v, w)));
+        let at = ray.at(hit.t);
+        for i in 0..3 {
+            assert!((weighted[i] - at[i]).abs() < EPSILON);
+        }
+
+        // Both sides hit, t scales with the direction's length
+        let back = Ray::new([0.25, 0.5, -1.], [0., 0., 2.]);
+        assert!((back.intersect_triangle(&A, &B, &C).unwrap().t - 0.5).abs() < EPSILON);
+    }
+
+    #[test]
+    fn triangle_misses() {
+        let down = [0., 0., -1.];
+        // Outside each edge
+        assert!(Ray::new([-0.1, 0.5, 1.], down).intersect_triangle(&A, &B, &C).is_none());
+        assert!(Ray::new([0.5, -0.1, 1.], down).intersect_triangle(&A, &B, &C).is_none());
+        assert!(Ray::new([0.6, 0.6, 1.], down).intersect_triangle(&A, &B, &C).is_none());
+        // Behind the origin, parallel, degenerate
+        assert!(Ray::new([0.2, 0.2, -1.], down).intersect_triangle(&A, &B, &C).is_none());
+        assert!(Ray::new([0.2, 0.2, 0.], [1., 0., 0.]).intersect_triangle(&A, &B, &C).is_none());
+        assert!(Ray::new([0.2, 0., 1.], down).intersect_triangle(&A, &B, &B).is_none());
+    }
+
+    #[test]
+    fn aabb_entry_distance() {
+        let aabb = Aabb {
+            min: [-1., -1., -1.],
+            max: [1., 1., 1.],
+        };
+        assert_eq!(Ray::new([0., 0., 5.], [0., 0., -1.]).intersect_aabb(&aabb), Some(4.));
+        assert_eq!(Ray::new([0., 0., 0.], [1., 0., 0.]).intersect_aabb(&aabb), Some(0.));
+        let diagonal = Ray::new([-3., -3., -3.], vec_3::normalize(&[1., 1., 1.]));
+        assert!((diagonal.intersect_aabb(&aabb).unwrap() - 2. * 3f32.sqrt()).abs() < EPSILON);
+        // Axis-parallel rays, including one running along a face
+        assert!(Ray::new([2., 0., 5.], [0., 0., -1.]).intersect_aabb(&aabb).is_none());
+        assert_eq!(Ray::new([1., 0., 5.], [0., 0., -1.]).intersect_aabb(&aabb), Some(4.));
+        // Pointing away
+        assert!(Ray::new([0., 0., 5.], [0., 0., 1.]).intersect_aabb(&aabb).is_none());
+    }
+
+    #[test]
+    fn sphere_entry_distance() {
+        let sphere = BoundingSphere {
+            center: [1., 2., 3.],
+            radius: 2.,
+        };
+        assert_eq!(Ray::new([1., 2., 10.], [0., 0., -1.]).intersect_sphere(&sphere), Some(5.));
+        assert_eq!(Ray::new([1., 2., 10.], [0., 0., -2.]).intersect_sphere(&sphere), Some(2.5));
+        assert_eq!(Ray::new([1., 2., 3.5], [0., 1., 0.]).intersect_sphere(&sphere), Some(0.));
+        // Tangent rays graze, slightly off ones miss
+        assert!((Ray::new([3., 2., 10.], [0., 0., -1.]).intersect_sphere(&sphere).unwrap() - 7.).abs() < EPSILON);
+        assert!(Ray::new([3.01, 2., 10.], [0., 0., -1.]).intersect_sphere(&sphere).is_none());
+        assert!(Ray::new([1., 2., 10.], [0., 0., 1.]).intersect_sphere(&sphere).is_none());
+    }
+
+    #[test]
+    fn transformed_keeps_t() {
+        let mut m = Matrix::new();
+        m.set_trs(&[1., 2., 3.], &crate::quat::from_axis_angle(&[0., 1., 0.], 0.5), &[2., 2., 2.]);
+        let ray = Ray::new([0.25, 0.5, 2.], [0., 0., -1.]);
+        let hit = ray.intersect_triangle(&A, &B, &C).unwrap();
+        let moved = ray.transformed(&m);
+        let corners = [A, B, C].map(|v| m.transform_point(&v));
+        let moved_hit = moved.intersect_triangle(&corners[0], &corners[1], &corners[2]).unwrap();
+        assert!((moved_hit.t - hit.t).abs() < EPSILON);
+    }
+}
@@ -0,0 +1,273 @@
+use web_sys::WebGlRenderingContext as GL;
+
+//--Blend equation presets--
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlendMode {
+    Opaque,
+    Alpha,
+    PremultipliedAlpha,
+    Additive,
+    Multiply,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CompareFunc {
+    LessEqual,
+    NotEqual,
+    Always,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullFace {
+    None,
+    Back,
+    Front,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Winding {
+    CounterClockwise,
+    Clockwise,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StencilOp {
+    Keep,
+    Replace,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StencilState {
+    pub func: CompareFunc,
+    pub reference: i32,
+    pub read_mask: u32,
+    pub write_mask: u32,
+    pub fail: StencilOp,
+    pub depth_fail: StencilOp,
+    pub pass: StencilOp,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PolygonOffset {
+    pub factor: f32,
+    pub units: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scissor {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+//--Fixed-function state of one draw--
+//  <note>
+//      Values are immutable, the with_* methods return a modified copy.
+//      Default is opaque, depth tested with LEQUAL, back faces culled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderState {
+    pub blend: BlendMode,
+    pub depth_test: bool,
+    pub depth_write: bool,
+    pub depth_func: CompareFunc,
+    pub cull_face: CullFace,
+    pub front_face: Winding,
+    pub color_mask: [bool; 4],
+    pub stencil: Option<StencilState>,
+    pub polygon_offset: Option<PolygonOffset>,
+    pub scissor: Option<Scissor>,
+}
+
+impl Default for RenderState {
+    fn default() -> Self {
+        Self::OPAQUE
+    }
+}
+
+impl RenderState {
+    pub const OPAQUE: Self = Self {
+        blend: BlendMode::Opaque,
+        depth_test: true,
+        depth_write: true,
+        depth_func: CompareFunc::LessEqual,
+        cull_face: CullFace::Back,
+        front_face: Winding::CounterClockwise,
+        color_mask: [true; 4],
+        stencil: None,
+        polygon_offset: None,
+        scissor: None,
+    };
+
+    //--Alpha blended, depth tested but not written--
+    pub const TRANSPARENT: Self = Self {
+        blend: BlendMode::Alpha,
+        depth_write: false,
+        ..Self::OPAQUE
+    };
+
+    //--Alpha blended on top of everything--
+    pub const OVERLAY: Self = Self {
+        blend: BlendMode::Alpha,
+        depth_test: false,
+        depth_write: false,
+        cull_face: CullFace::None,
+        ..Self::OPAQUE
+    };
+
+    pub fn with_blend(self, blend: BlendMode) -> Self {
+        Self { blend, ..self }
+    }
+
+    pub fn with_depth(self, test: bool, write: bool, func: CompareFunc) -> Self {
+        Self {
+            depth_test: test,
+            depth_write: write,
+            depth_func: func,
+            ..self
+        }
+    }
+
+    pub fn with_cull_face(self, cull_face: CullFace, front_face: Winding) -> Self {
+        Self {
+            cull_face,
+            front_face,
+            ..self
+        }
+    }
+
+    pub fn with_color_mask(self, color_mask: [bool; 4]) -> Self {
+        Self { color_mask, ..self }
+    }
+
+    pub fn with_stencil(self, stencil: Option<StencilState>) -> Self {
+        Self { stencil, ..self }
+    }
+
+    pub fn with_polygon_offset(self, polygon_offset: Option<PolygonOffset>) -> Self {
+        Self {
+            polygon_offset,
+            ..self
+        }
+    }
+
+    pub fn with_scissor(self, scissor: Option<Scissor>) -> Self {
+        Self { scissor, ..self }
+    }
+
+    pub fn is_transparent(&self) -> bool {
+        self.blend != BlendMode::Opaque
+    }
+}
+
+//--Last state sent to the context--
+//  <note>
+//      Only the parts that differ from the previous draw are issued.
+//      Call invalidate() after touching GL state behind the cache's back.
+pub struct StateCache {
+    current: Option<RenderState>,
+}
+
+impl StateCache {
+    pub fn new() -> Self {
+        Self { current: None }
+    }
+
+    pub fn invalidate(&mut self) {
+        self.current = None;
+    }
+
+    pub fn apply(&mut self, gl: &GL, state: &RenderState) {
+        let all = self.current.is_none();
+        let old = self.current.unwrap_or(*state);
+
+        if all || old.blend != state.blend {
+            set_blend(gl, state.blend);
+        }
+        if all || old.depth_test != state.depth_test {
+            toggle(gl, GL::DEPTH_TEST, state.depth_test);
+        }
+        if all || old.depth_write != state.depth_write {
+            gl.depth_mask(state.depth_write);
+        }
+        if all || old.depth_func != state.depth_func {
+            gl.depth_func(compare_func(state.depth_func));
+        }
+        if all || old.cull_face != state.cull_face {
+            toggle(gl, GL::CULL_FACE, state.cull_face != CullFace::None);
+            match state.cull_face {
+                CullFace::Back => gl.cull_face(GL::BACK),
+                CullFace::Front => gl.cull_face(GL::FRONT),
+                        CullFace::None => (),
+            }
+        }
+        if all || old.front_face != state.front_face {
+            gl.front_face(match state.front_face {
+                Winding::CounterClockwise => GL::CCW,
+                Winding::Clockwise => GL::CW,
+            });
+        }
+        if all || old.color_mask != state.color_mask {
+            let m = state.color_mask;
+            gl.color_mask(m[0], m[1], m[2], m[3]);
+        }
+        if all || old.stencil != state.stencil {
+            toggle(gl, GL::STENCIL_TEST, state.stencil.is_some());
+            if let Some(s) = state.stencil {
+                gl.stencil_func(compare_func(s.func), s.reference, s.read_mask);
+                gl.stencil_mask(s.write_mask);
+                gl.stencil_op(stencil_op(s.fail), stencil_op(s.depth_fail), stencil_op(s.pass));
+            }
+        }
+        if all || old.polygon_offset != state.polygon_offset {
+            toggle(gl, GL::POLYGON_OFFSET_FILL, state.polygon_offset.is_some());
+            if let Some(p) = state.polygon_offset {
+                gl.polygon_offset(p.factor, p.units);
+            }
+        }
+        if all || old.scissor != state.scissor {
+            toggle(gl, GL::SCISSOR_TEST, state.scissor.is_some());
+            if let Some(s) = state.scissor {
+                gl.scissor(s.x, s.y, s.width, s.height);
+            }
+        }
+
+        self.current = Some(*state);
+    }
+}
+
+fn toggle(gl: &GL, cap: u32, on: bool) {
+    if on {
+        gl.enable(cap);
+    } else {
+        gl.disable(cap);
+    }
+}
+
+fn set_blend(gl: &GL, blend: BlendMode) {
+    toggle(gl, GL::BLEND, blend != BlendMode::Opaque);
+    match blend {
+        BlendMode::Opaque => (),
+        BlendMode::Alpha => {
+            gl.blend_func_separate(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA, GL::ONE, GL::ONE_MINUS_SRC_ALPHA)
+        }
+        BlendMode::PremultipliedAlpha => gl.blend_func(GL::ONE, GL::ONE_MINUS_SRC_ALPHA),
+        BlendMode::Additive => gl.blend_func(GL::SRC_ALPHA, GL::ONE),
+        BlendMode::Multiply => gl.blend_func(GL::DST_COLOR, GL::ZERO),
+    }
+}
+
+fn compare_func(func: CompareFunc) -> u32 {
+    match func {
+        CompareFunc::LessEqual => GL::LEQUAL,
+        CompareFunc::NotEqual => GL::NOTEQUAL,
+        CompareFunc::Always => GL::ALWAYS,
+    }
+}
+
+fn stencil_op(op: StencilOp) -> u32 {
+    match op {
+        StencilOp::Keep => GL::KEEP,
+        StencilOp::Replace => GL::REPLACE,
+    }
+}
@@ -0,0 +1,344 @@
+use crate::bounds::Frustum;
+use crate::error::RenderError;
+use crate::instancing::{InstanceBatch, Instancing};
+use crate::mat_4::Matrix;
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::render_state::{RenderState, StateCache};
+use crate::shading::ShadingMode;
+use crate::skin::Skin;
+use std::cmp::Ordering;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+//--One mesh drawn with one material, optionally once per instance--
+pub struct DrawCall {
+    pub mesh: Rc<Mesh>,
+    pub material: Rc<Material>,
+    pub model: Matrix,
+    pub instances: Option<Rc<InstanceBatch>>,
+    pub skin: Option<Rc<Skin>>,
+    depth: f32,
+}
+
+//--Draw calls of the last flush--
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    pub drawn: usize,
+    //  Skipped because their bounds were outside the view frustum,
+    //  or their model matrix was singular
+    pub culled: usize,
+}
+
+//--Collects draw calls for a frame and submits them sorted--
+//  <note>
+//      Opaque draws are grouped by program and material, then drawn front to back.
+//      Transparent draws follow, back to front.
+//      Every draw gets mvpMatrix, modelMatrix, invMatrix (inverse of the model)
+//      and normalMatrix (inverse transpose of the model). Instanced draws also
+//      get vpMatrix, and invMatrix is the identity as they light in world space.
+//      projectionMatrix and viewportSize (drawing buffer pixels) are globals.
+//      Draws whose mesh or instance bounds are outside the view frustum are skipped,
+//      as are non-instanced draws with a singular model matrix (zero scale).
+//      Shading modes other than Solid draw every non-instanced, non-skinned
+//      call with the shading program instead of its material, opaque and depth tested.
+pub struct Renderer {
+    queue: Vec<DrawCall>,
+    frustum_culling: bool,
+    stats: RenderStats,
+    globals: Vec<(String, Uniform)>,
+    enabled: Vec<u32>,
+    state_cache: StateCache,
+    //  Detected on the first flush
+    instancing: Option<Instancing>,
+    shading: ShadingMode,
+    shading_program: Option<Rc<ShaderProgram>>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Self {
+            queue: Vec::new(),
+            frustum_culling: true,
+            stats: RenderStats::default(),
+            globals: Vec::new(),
+            enabled: Vec::new(),
+            state_cache: StateCache::new(),
+            instancing: None,
+            shading: ShadingMode::Solid,
+            shading_program: None,
+        }
+    }
+
+    //--Clear color, depth and stencil--
+    //  <note>
+    //      Masks and scissor affect clears, so the default state is applied first.
+    pub fn clear(&mut self, gl: &GL, color: &[f32; 4]) {
+        self.state_cache.apply(gl, &RenderState::default());
+        gl.clear_color(color[0], color[1], color[2], color[3]);
+        gl.clear_depth(1.);
+        gl.clear_stencil(0);
+        // The stencil clear is masked like stencil writes, a stencilled draw may have narrowed it
+        gl.stencil_mask(0xff);
+        gl.clear(GL::COLOR_BUFFER_BIT | GL::DEPTH_BUFFER_BIT | GL::STENCIL_BUFFER_BIT);
+    }
+
+    //--Forget the cached state after GL state was changed elsewhere--
+    //  <note>
+    //      Also needed after a context restore, which resets every state.
+    pub fn invalidate_state(&mut self) {
+        self.state_cache.invalidate();
+        self.enabled.clear();
+        self.instancing = None;
+    }
+
+    //--Set a uniform shared by every program, e.g. light direction--
+    pub fn set_global(&mut self, name: &str, value: Uniform) -> &mut Self {
+        match self.globals.iter_mut().find(|(n, _)| n == name) {
+            Some(global) => global.1 = value,
+            None => self.globals.push((name.to_string(), value)),
+        }
+        self
+    }
+
+    //--Queue a draw for this frame--
+    pub fn submit(&mut self, mesh: &Rc<Mesh>, material: &Rc<Material>, model: &Matrix) {
+        self.queue.push(DrawCall {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            model: *model,
+            instances: None,
+            skin: None,
+            depth: 0.,
+        });
+    }
+
+    //--Queue a skinned draw for this frame--
+    //  <note>
+    //      The material's program has to be a skinned one, see skin::program.
+    //      Skinned draws aren't frustum culled as the pose moves the mesh
+    //      outside its bind pose bounds.
+    pub fn submit_skinned(&mut self, mesh: &Rc<Mesh>, material: &Rc<Material>, model: &Matrix, skin: &Rc<Skin>) {
+        self.queue.push(DrawCall {
+            mesh: mesh.clone(),
+            material: material.clone(),
+            model: *model,
+            instances: None,
+            skin: Some(skin.clone()),
+            depth: 0.,
+        });
+    }
+
+    //--Queue an instanced draw for this frame--
+    //  <argument>
+    //      model &Matrix : applied on top of every instance's own model matrix
+    pub fn submit_instances(&mut self, batch: &Rc<InstanceBatch>, model: &Matrix) {
+        self.queue.push(DrawCall {
+            mesh: batch.mesh.clone(),
+            material: batch.material.clone(),
+            model: *model,
+            instances: Some(batch.clone()),
+            skin: None,
+            depth: 0.,
+        });
+    }
+
+    //--Turn frustum culling on or off, on by default--
+    pub fn set_frustum_culling(&mut self, enabled: bool) -> &mut Self {
+        self.frustum_culling = enabled;
+        self
+    }
+
+    //--Switch the viewport shading, takes effect on the next flush--
+    //  <note>
+    //      Modes other than Solid need set_shading_program first.
+    pub fn set_shading(&mut self, mode: ShadingMode) -> &mut Self {
+        self.shading = mode;
+        self
+    }
+
+    pub fn shading(&self) -> ShadingMode {
+        self.shading
+    }
+
+    //--Program the non-solid shading modes draw with, see shading::program--
+    //  <note>
+    //      The caller owns and tracks it for context restore.
+    pub fn set_shading_program(&mut self, program: Rc<ShaderProgram>) -> &mut Self {
+        self.shading_program = Some(program);
+        self
+    }
+
+    //--Drawn and culled counts of the last flush--
+    pub fn stats(&self) -> RenderStats {
+        self.stats
+    }
+
+    //--Sort and draw every queued call, then clear the queue--
+    //  <argument>
+    //      view       &Matrix : view matrix, used for the depth sort
+    //      projection &Matrix : projection matrix
+    //  <note>
+    //      A failing draw doesn't stop the others, the first error is returned
+    //      once every call was drawn. The queue is cleared and the attribute
+    //      arrays disabled either way.
+    pub fn flush(&mut self, gl: &GL, view: &Matrix, projection: &Matrix) -> Result<(), RenderError> {
+        let result = self.draw_queued(gl, view, projection);
+
+        for &location in self.enabled.iter() {
+            gl.disable_vertex_attrib_array(location);
+        }
+        self.enabled.clear();
+        self.queue.clear();
+        result
+    }
+
+    fn draw_queued(&mut self, gl: &GL, view: &Matrix, projection: &Matrix) -> Result<(), RenderError> {
+        let shading = match (self.shading, &self.shading_program) {
+            (ShadingMode::Solid, _) => None,
+            (mode, Some(program)) => Some((mode, program.clone())),
+            (mode, None) => {
+                return Err(RenderError::Resource(format!("{} shading needs a shading program", mode.name())));
+            }
+        };
+        if let Some((mode, _)) = &shading {
+            self.set_global("shadingMode", Uniform::Int(mode.index()));
+        }
+
+        let mut vp_matrix = *projection;
+        vp_matrix.multiply(view);
+        self.set_global("projectionMatrix", Uniform::Mat4(projection.get_value()))
+            .set_global(
+                "viewportSize",
+                Uniform::Vec2([gl.drawing_buffer_width() as f32, gl.drawing_buffer_height() as f32]),
+            );
+
+        let submitted = self.queue.len();
+        if self.frustum_culling {
+            let frustum = Frustum::from_matrix(&vp_matrix);
+            self.queue.retain(|call| is_visible(call, &frustum));
+        }
+        self.stats = RenderStats {
+            drawn: self.queue.len(),
+            culled: submitted - self.queue.len(),
+        };
+
+        let mut mv_matrix = Matrix::new();
+        for call in self.queue.iter_mut() {
+            mv_matrix.substitution(view).multiply(&call.model);
+            call.depth = -mv_matrix.get_value()[14];
+        }
+        self.queue.sort_by(draw_order);
+        let instancing = self.instancing.get_or_insert_with(|| Instancing::detect(gl)).clone();
+
+        let mut mvp_matrix = Matrix::new();
+        let mut inv_matrix = Matrix::new();
+        let mut normal_matrix = Matrix::new();
+        let mut program = None;
+        let mut material = None;
+        let mut first_error = None;
+        for call in self.queue.iter() {
+            // Instanced and skinned draws keep their material, their vertex shader places the geometry
+            let shading = shading.as_ref().filter(|_| call.instances.is_none() && call.skin.is_none());
+            let shader = match shading {
+                Some((_, program)) => program,
+                None => &call.material.program,
+            };
+            if program != Some(shader.id) {
+                gl.use_program(Some(&shader.program()));
+                for (name, value) in self.globals.iter() {
+                    shader.set_uniform(gl, name, value);
+                }
+                program = Some(shader.id);
+                material = None;
+            }
+            if shading.is_some() {
+                self.state_cache.apply(gl, &RenderState::OPAQUE);
+            } else if material != Some(call.material.id) {
+                self.state_cache.apply(gl, &call.material.state);
+                call.material.apply(gl);
+                material = Some(call.material.id);
+            }
+
+            if let Some(batch) = &call.instances {
+                shader.set_uniform(gl, "vpMatrix", &Uniform::Mat4(vp_matrix.get_value()));
+                shader.set_uniform(gl, "modelMatrix", &Uniform::Mat4(call.model.get_value()));
+                shader.set_uniform(gl, "invMatrix", &Uniform::Mat4(Matrix::new().get_value()));
+                if let Err(e) = batch.draw(gl, shader, &instancing, &mut self.enabled) {
+                    first_error.get_or_insert(e);
+                }
+                continue;
+            }
+
+            // Zero scale collapses the mesh, there is nothing to draw
+            if inv_matrix.substitution(&call.model).inverse().is_err() {
+                self.stats.drawn -= 1;
+                self.stats.culled += 1;
+                continue;
+            }
+            mvp_matrix.substitution(&vp_matrix).multiply(&call.model);
+            normal_matrix.substitution(&inv_matrix).transpose();
+            shader.set_uniform(gl, "mvpMatrix", &Uniform::Mat4(mvp_matrix.get_value()));
+            shader.set_uniform(gl, "modelMatrix", &Uniform::Mat4(call.model.get_value()));
+            shader.set_uniform(gl, "invMatrix", &Uniform::Mat4(inv_matrix.get_value()));
+            shader.set_uniform(gl, "normalMatrix", &Uniform::Mat4(normal_matrix.get_value()));
+
+            let mesh = match shading {
+                Some((ShadingMode::Wireframe, _)) => call.mesh.unwelded(gl),
+                _ => Ok(call.mesh.clone()),
+            };
+            let skinned = match &call.skin {
+                Some(skin) => skin.apply(gl, shader, call.material.textures.len() as u32),
+                None => Ok(()),
+            };
+            match (mesh, skinned) {
+                (Ok(mesh), Ok(())) => {
+                    mesh.bind(gl, shader, &mut self.enabled);
+                    mesh.draw(gl);
+                }
+                (Err(e), _) | (_, Err(e)) => {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+}
+
+//--Conservative frustum test, draws without bounds are always visible--
+fn is_visible(call: &DrawCall, frustum: &Frustum) -> bool {
+    if call.skin.is_some() {
+        return true;
+    }
+    match &call.instances {
+        Some(batch) => batch
+            .bounds()
+            .is_none_or(|aabb| frustum.intersects_aabb(&aabb.transformed(&call.model))),
+        None => call
+            .mesh
+            .bounds
+            .as_ref()
+            .is_none_or(|bounds| frustum.intersects_bounds(bounds, &call.model)),
+    }
+}
+
+fn draw_order(a: &DrawCall, b: &DrawCall) -> Ordering {
+    let (ma, mb) = (&a.material, &b.material);
+    match (ma.is_transparent(), mb.is_transparent()) {
+        (false, true) => Ordering::Less,
+        (true, false) => Ordering::Greater,
+        (false, false) => ma
+            .program
+            .id
+            .cmp(&mb.program.id)
+            .then(ma.id.cmp(&mb.id))
+            .then(a.depth.partial_cmp(&b.depth).unwrap_or(Ordering::Equal)),
+        (true, true) => b
+            .depth
+            .partial_cmp(&a.depth)
+            .unwrap_or(Ordering::Equal)
+            .then(ma.program.id.cmp(&mb.program.id))
+            .then(ma.id.cmp(&mb.id)),
+    }
+}
@@ -0,0 +1,654 @@
+use crate::camera::Camera;
+use crate::debug_draw::DebugDraw;
+use crate::error::RenderError;
+use crate::id_pass::IdPass;
+use crate::instancing::InstanceBatch;
+use crate::mat_4::Matrix;
+use crate::material::{Material, Uniform};
+use crate::mesh::Mesh;
+use crate::quat;
+use crate::ray::Ray;
+use crate::renderer::Renderer;
+use crate::skin::Skin;
+use crate::text::Label;
+use crate::vec_3;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+//--Handle of a node in a scene--
+//  <note>
+//      Slots of removed nodes are reused, the generation tells a stale
+//      handle from the node that took its slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+//  Storage of one node, the generation is bumped whenever the node is removed
+struct Slot {
+    generation: u32,
+    node: Option<Node>,
+}
+
+//  <note>
+//      Only directional lights are lit by the shaders so far.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    //  Shines along the node's -Z axis
+    Directional,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+//--Result of picking a scene with a ray--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PickHit {
+    pub node: NodeId,
+    //  Triangle index into the mesh's index buffer
+    pub triangle: usize,
+    //  Weights of the triangle's three vertices at the hit
+    pub barycentric: [f32; 3],
+    //  World space position of the hit
+    pub position: [f32; 3],
+    //  Distance from the ray origin, in units of the ray direction
+    pub distance: f32,
+}
+
+//--Result of picking a scene through an ID pass--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdPickHit {
+    pub node: NodeId,
+    //  Index into the node's instance batch, None for its renderable
+    pub instance: Option<usize>,
+    //  World space position read back from the depth buffer
+    pub position: [f32; 3],
+}
+
+//--Mesh drawn with a material at the node's world transform--
+#[derive(Clone)]
+pub struct Renderable {
+    pub mesh: Rc<Mesh>,
+    pub material: Rc<Material>,
+}
+
+pub struct Node {
+    pub name: String,
+    translation: [f32; 3],
+    rotation: [f32; 4],
+    scale: [f32; 3],
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    local: Matrix,
+    world: Matrix,
+    dirty: bool,
+    pub renderable: Option<Renderable>,
+    //  Poses the renderable's mesh, whose material needs a skinned program
+    pub skin: Option<Rc<Skin>>,
+    //  Drawn relative to the node's world transform
+    pub instances: Option<Rc<InstanceBatch>>,
+    pub light: Option<Light>,
+    pub camera: Option<Camera>,
+    //  Text drawn at the node's origin
+    pub label: Option<Rc<Label>>,
+}
+
+impl Node {
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            translation: [0., 0., 0.],
+            rotation: quat::identity(),
+            scale: [1., 1., 1.],
+            parent: None,
+            children: Vec::new(),
+            local: Matrix::new(),
+            world: Matrix::new(),
+            dirty: true,
+            renderable: None,
+            skin: None,
+            instances: None,
+            light: None,
+            camera: None,
+            label: None,
+        }
+    }
+
+    pub fn translation(&self) -> [f32; 3] {
+        self.translation
+    }
+
+    pub fn rotation(&self) -> [f32; 4] {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> [f32; 3] {
+        self.scale
+    }
+
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+//--Node hierarchy with local TRS transforms--
+//  <note>
+//      World matrices are cached and only recomputed for nodes whose
+//      transform, or an ancestor's transform, changed since the last update.
+pub struct Scene {
+    nodes: Vec<Slot>,
+    roots: Vec<NodeId>,
+}
+
+impl Scene {
+    pub fn new() -> Self {
+        Self {
+            nodes: Vec::new(),
+            roots: Vec::new(),
+        }
+    }
+
+    //--Add an empty root node--
+    pub fn add_node(&mut self, name: &str) -> NodeId {
+        let index = match self.nodes.iter().position(|slot| slot.node.is_none()) {
+            Some(i) => i,
+            None => {
+                self.nodes.push(Slot { generation: 0, node: None });
+                self.nodes.len() - 1
+            }
+        };
+        let slot = &mut self.nodes[index];
+        slot.node = Some(Node::new(name));
+        let id = NodeId {
+            index,
+            generation: slot.generation,
+        };
+        self.roots.push(id);
+        id
+    }
+
+    //--Remove a node and its whole subtree--
+    pub fn remove_node(&mut self, id: NodeId) {
+        self.detach(id);
+        self.roots.retain(|&r| r != id);
+        if self.node(id).is_none() {
+            return;
+        }
+        let slot = &mut self.nodes[id.index];
+        slot.generation = slot.generation.wrapping_add(1);
+        let children = match slot.node.take() {
+            Some(node) => node.children,
+            None => return,
+        };
+        for child in children {
+            if let Some(node) = self.node_mut(child) {
+                node.parent = None;
+            }
+            self.remove_node(child);
+        }
+    }
+
+    //--None for ids of removed nodes, even if their slot was reused--
+    pub fn node(&self, id: NodeId) -> Option<&Node> {
+        self.nodes
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    //--Access a node's attachments, use the setters below for its transform--
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    //--Live nodes with their ids, in slot order--
+    fn live(&self) -> impl Iterator<Item = (NodeId, &Node)> {
+        self.nodes.iter().enumerate().filter_map(|(index, slot)| {
+            let id = NodeId {
+                index,
+                generation: slot.generation,
+            };
+            slot.node.as_ref().map(|node| (id, node))
+        })
+    }
+
+    //--Re-parent a node, None makes it a root--
+    //  <note>
+    //      Fails if the new parent is the node itself or one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<(), RenderError> {
+        if self.node(id).is_none() {
+            return Err(RenderError::Resource("node doesn't exist".into()));
+        }
+        if let Some(p) = parent {
+            if self.node(p).is_none() {
+                return Err(RenderError::Resource("parent doesn't exist".into()));
+            }
+            let mut ancestor = Some(p);
+            while let Some(a) = ancestor {
+                if a == id {
+                    return Err(RenderError::Resource("a node can't be its own ancestor".into()));
+                }
+                ancestor = self.node(a).and_then(|n| n.parent);
+            }
+        }
+
+        self.detach(id);
+        self.roots.retain(|&r| r != id);
+        match parent {
+            Some(p) => {
+                if let Some(node) = self.node_mut(p) {
+                    node.children.push(id);
+                }
+            }
+            None => self.roots.push(id),
+        }
+        if let Some(node) = self.node_mut(id) {
+            node.parent = parent;
+            node.dirty = true;
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self, id: NodeId) {
+        let parent = self.node(id).and_then(|n| n.parent);
+        if let Some(node) = parent.and_then(|p| self.node_mut(p)) {
+            node.children.retain(|&c| c != id);
+        }
+    }
+
+    pub fn set_translation(&mut self, id: NodeId, translation: &[f32; 3]) {
+        if let Some(node) = self.node_mut(id) {
+            node.translation = *translation;
+            node.dirty = true;
+        }
+    }
+
+    pub fn set_rotation(&mut self, id: NodeId, rotation: &[f32; 4]) {
+        if let Some(node) = self.node_mut(id) {
+            node.rotation = quat::normalize(rotation);
+            node.dirty = true;
+        }
+    }
+
+    pub fn set_scale(&mut self, id: NodeId, scale: &[f32; 3]) {
+        if let Some(node) = self.node_mut(id) {
+            node.scale = *scale;
+            node.dirty = true;
+        }
+    }
+
+    //--Recompute world matrices of dirty subtrees--
+    pub fn update(&mut self) {
+        let identity = Matrix::new();
+        for i in 0..self.roots.len() {
+            let root = self.roots[i];
+            self.update_node(root, &identity, false);
+        }
+    }
+
+    fn update_node(&mut self, id: NodeId, parent_world: &Matrix, parent_changed: bool) {
+        let node = match self.node_mut(id) {
+            Some(node) => node,
+            None => return,
+        };
+        if node.dirty {
+            node.local.set_trs(&node.translation, &node.rotation, &node.scale);
+        }
+        let changed = node.dirty || parent_changed;
+        if changed {
+            node.world.substitution(parent_world).multiply(&node.local);
+            node.dirty = false;
+        }
+        let world = node.world;
+        let children = node.children.clone();
+        for child in children {
+            self.update_node(child, &world, changed);
+        }
+    }
+
+    //--World matrix, up to date--
+    pub fn world_matrix(&mut self, id: NodeId) -> Option<Matrix> {
+        self.update();
+        self.node(id).map(|n| n.world)
+    }
+
+    //--Eye position, view and projection matrices of a camera node, world matrices must be up to date--
+    fn camera_matrices(&self, camera: NodeId, aspect: f32) -> Result<([f32; 3], Matrix, Matrix), RenderError> {
+        let camera_node = self.node(camera).ok_or_else(|| RenderError::Resource("camera node doesn't exist".into()))?;
+        let projection = camera_node
+            .camera
+            .ok_or_else(|| RenderError::Resource("node has no camera".into()))?
+            .projection_matrix(aspect);
+        let eye = camera_node.world.get_translation();
+        let view = Camera::view_from_world(&camera_node.world)?;
+        Ok((eye, view, projection))
+    }
+
+    //--Direction towards and color of the first directional light, world matrices must be up to date--
+    //  <note>
+    //      Without one the color is black and the direction +Z, an unrotated
+    //      light's, so globals of a removed light don't linger.
+    fn sun(&self) -> ([f32; 3], [f32; 3]) {
+        self.live()
+            .find_map(|(_, n)| n.light.filter(|l| l.kind == LightKind::Directional).map(|l| (n, l)))
+            .map_or(([0., 0., 1.], [0., 0., 0.]), |(node, light)| {
+                (
+                    vec_3::normalize(&node.world.transform_direction(&[0., 0., 1.])),
+                    vec_3::scale(&light.color, light.intensity),
+                )
+            })
+    }
+
+    //--Draw every renderable node as seen from a camera node--
+    //  <argument>
+    //      renderer &mut Renderer : renderer the draws are submitted to
+    //      camera   NodeId        : node with a camera attached
+    //      aspect   f32           : viewport width divided by height
+    //  <note>
+    //      The first directional light sets the lightDirection and lightColor globals,
+    //      the camera position is passed as eyeDirection and cameraPosition.
+    pub fn render(&mut self, gl: &GL, renderer: &mut Renderer, camera: NodeId, aspect: f32) -> Result<(), RenderError> {
+        self.update();
+        let (eye, view, projection) = self.camera_matrices(camera, aspect)?;
+
+        let (direction, color) = self.sun();
+        renderer
+            .set_global("eyeDirection", Uniform::Vec3(eye))
+            .set_global("cameraPosition", Uniform::Vec3(eye))
+            .set_global("lightDirection", Uniform::Vec3(direction))
+            .set_global("lightColor", Uniform::Vec3(color));
+
+        for (_, node) in self.live() {
+            match (&node.renderable, &node.skin) {
+                (Some(r), Some(skin)) => renderer.submit_skinned(&r.mesh, &r.material, &node.world, skin),
+                (Some(r), None) => renderer.submit(&r.mesh, &r.material, &node.world),
+                _ => {}
+            }
+            if let Some(batch) = &node.instances {
+                renderer.submit_instances(batch, &node.world);
+            }
+            if let Some(label) = &node.label {
+                renderer.submit(&label.mesh(), label.material(), &node.world);
+            }
+        }
+        renderer.flush(gl, &view, &projection)
+    }
+
+    //--Queue the vertex normals of every renderable, e.g. to check transforms--
+    pub fn debug_normals(&mut self, debug: &mut DebugDraw, length: f32, color: [f32; 4]) {
+        self.update();
+        for (_, node) in self.live() {
+            if let Some(r) = &node.renderable {
+                debug.normals(&r.mesh, &node.world, length, color);
+            }
+        }
+    }
+
+    //--Draw the queued debug lines as seen from a camera node--
+    //  <note>
+    //      Call after render() so lines are depth tested against the scene,
+    //      then invalidate the renderer's state.
+    pub fn flush_debug(&mut self, gl: &GL, debug: &mut DebugDraw, camera: NodeId, aspect: f32) -> Result<(), RenderError> {
+        self.update();
+        let (_, view, projection) = self.camera_matrices(camera, aspect)?;
+        debug.flush(gl, &view, &projection)
+    }
+
+    //--Closest renderable under a pixel as seen from a camera node--
+    //  <argument>
+    //      camera        NodeId : node with a camera attached
+    //      x, y          f32    : position from the top left corner of the viewport
+    //      width, height f32    : viewport size in the same units as x and y
+    pub fn pick(&mut self, camera: NodeId, x: f32, y: f32, width: f32, height: f32) -> Result<Option<PickHit>, RenderError> {
+        self.update();
+        let (_, view, projection) = self.camera_matrices(camera, width / height)?;
+        let ray = Ray::from_screen(x, y, width, height, &view, &projection)?;
+        Ok(self.pick_ray(&ray))
+    }
+
+    //--Closest renderable hit by a world space ray--
+    //  <note>
+    //      Each mesh is tested in its local space against its triangle BVH,
+    //      after a cheap rejection with its world bounding sphere. Instance
+    //      batches aren't pickable. World matrices must be up to date.
+    pub fn pick_ray(&self, ray: &Ray) -> Option<PickHit> {
+        let mut best: Option<PickHit> = None;
+        for (id, node) in self.live() {
+            let renderable = match &node.renderable {
+                Some(r) => r,
+                None => continue,
+            };
+            let (bounds, bvh) = match (renderable.mesh.bounds, renderable.mesh.bvh()) {
+                (Some(bounds), Some(bvh)) => (bounds, bvh),
+                _ => continue,
+            };
+            let limit = best.map_or(f32::INFINITY, |b| b.distance);
+            match ray.intersect_sphere(&bounds.sphere.transformed(&node.world)) {
+                Some(t) if t <= limit => {}
+                _ => continue,
+            }
+            let mut to_local = node.world;
+            if to_local.inverse().is_err() {
+                continue;
+            }
+            // The local ray keeps t, so distances compare across nodes
+            if let Some(hit) = bvh.intersect(&ray.transformed(&to_local), limit) {
+                best = Some(PickHit {
+                    node: id,
+                    triangle: hit.triangle,
+                    barycentric: hit.barycentric,
+                    position: ray.at(hit.t),
+                    distance: hit.t,
+                });
+            }
+        }
+        best
+    }
+
+    //--Closest renderable or instance under a pixel, found on the GPU--
+    //  <argument>
+    //      pass          &mut IdPass : ID pass, its radius applies
+    //      camera        NodeId      : node with a camera attached
+    //      x, y          f32         : position from the top left corner of the viewport
+    //      width, height f32         : viewport size in the same units as x and y
+    //  <note>
+    //      Unlike pick(), instances are told apart and no BVH is built, which
+    //      suits dense scenes. Costs a GPU round trip, and the renderer's state
+    //      has to be invalidated afterwards.
+    #[allow(clippy::too_many_arguments)]
+    pub fn pick_gpu(
+        &mut self,
+        gl: &GL,
+        pass: &mut IdPass,
+        camera: NodeId,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+    ) -> Result<Option<IdPickHit>, RenderError> {
+        self.update();
+        let (_, view, projection) = self.camera_matrices(camera, width / height)?;
+
+        // First ID of every submission in increasing order, with its node and whether it's a batch
+        let mut ranges: Vec<(u32, NodeId, bool)> = Vec::new();
+        for (node_id, node) in self.live() {
+            if let Some(r) = &node.renderable {
+                if let Some(id) = pass.submit(&r.mesh, &r.material, &node.world) {
+                    ranges.push((id, node_id, false));
+                }
+            }
+            if let Some(batch) = node.instances.as_ref().filter(|b| !b.is_empty()) {
+                if let Some(id) = pass.submit_instances(batch, &node.world) {
+                    ranges.push((id, node_id, true));
+                }
+            }
+        }
+
+        let hit = match pass.pick(gl, &view, &projection, x, y, width, height)? {
+            Some(hit) => hit,
+            None => return Ok(None),
+        };
+        let range = ranges.partition_point(|&(first, _, _)| first <= hit.id);
+        Ok(range.checked_sub(1).map(|i| {
+            let (first, node, batch) = ranges[i];
+            IdPickHit {
+                node,
+                instance: if batch { Some((hit.id - first) as usize) } else { None },
+                position: hit.position,
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stale_ids_miss_reused_slots() {
+        let mut scene = Scene::new();
+        let parent = scene.add_node("parent");
+        let child = scene.add_node("child");
+        scene.set_parent(child, Some(parent)).unwrap();
+        scene.remove_node(parent);
+        assert!(scene.node(parent).is_none());
+        assert!(scene.node(child).is_none());
+
+        let reused = scene.add_node("reused");
+        assert_eq!(reused.index, parent.index);
+        assert!(scene.node(parent).is_none());
+        assert!(scene.node_mut(parent).is_none());
+        assert_eq!(scene.node(reused).map(|n| n.name.as_str()), Some("reused"));
+
+        // Removing through a stale id leaves the new node alone
+        scene.remove_node(parent);
+        assert!(scene.node(reused).is_some());
+        assert_eq!(scene.roots, [reused]);
+    }
+
+    fn assert_close(a: &Matrix, b: &Matrix) {
+        for (x, y) in a.get_value().iter().zip(b.get_value().iter()) {
+            assert!((x - y).abs() < 1e-5, "{:?} vs {:?}", a, b);
+        }
+    }
+
+    fn trs(t: [f32; 3], r: [f32; 4], s: [f32; 3]) -> Matrix {
+        let mut m = Matrix::new();
+        m.set_trs(&t, &r, &s);
+        m
+    }
+
+    #[test]
+    fn world_matrices_compose_down_the_hierarchy() {
+        let mut scene = Scene::new();
+        let (parent, child, grandchild) = (scene.add_node("parent"), scene.add_node("child"), scene.add_node("grandchild"));
+        scene.set_parent(child, Some(parent)).unwrap();
+        scene.set_parent(grandchild, Some(child)).unwrap();
+
+        let turn = quat::from_axis_angle(&[0., 1., 0.], std::f32::consts::FRAC_PI_2);
+        scene.set_translation(parent, &[1., 0., 0.]);
+        scene.set_rotation(parent, &turn);
+        scene.set_translation(child, &[0., 0., 1.]);
+        scene.set_scale(child, &[2., 2., 2.]);
+        scene.set_translation(grandchild, &[0., 1., 0.]);
+
+        let mut expected = trs([1., 0., 0.], turn, [1., 1., 1.]);
+        expected.multiply(&trs([0., 0., 1.], quat::identity(), [2., 2., 2.]));
+        assert_close(&scene.world_matrix(child).unwrap(), &expected);
+        expected.multiply(&trs([0., 1., 0.], quat::identity(), [1., 1., 1.]));
+        assert_close(&scene.world_matrix(grandchild).unwrap(), &expected);
+
+        // The child's +Z is turned onto the parent's +X
+        let origin = scene.world_matrix(child).unwrap().transform_point(&[0., 0., 0.]);
+        assert!(vec_3::length(&vec_3::sub(&origin, &[2., 0., 0.])) < 1e-5, "{:?}", origin);
+    }
+
+    #[test]
+    fn ancestor_changes_reach_cached_descendants() {
+        let mut scene = Scene::new();
+        let (root, child, other) = (scene.add_node("root"), scene.add_node("child"), scene.add_node("other"));
+        scene.set_parent(child, Some(root)).unwrap();
+        scene.set_translation(child, &[0., 1., 0.]);
+        assert_eq!(scene.world_matrix(child).unwrap().get_translation(), [0., 1., 0.]);
+
+        scene.set_translation(root, &[5., 0., 0.]);
+        assert_eq!(scene.world_matrix(child).unwrap().get_translation(), [5., 1., 0.]);
+        scene.set_scale(root, &[2., 2., 2.]);
+        assert_eq!(scene.world_matrix(child).unwrap().get_translation(), [5., 2., 0.]);
+
+        // Moving to another parent picks up its transform
+        scene.set_translation(other, &[0., 0., -3.]);
+        scene.set_parent(child, Some(other)).unwrap();
+        assert_eq!(scene.world_matrix(child).unwrap().get_translation(), [0., 1., -3.]);
+        scene.set_parent(child, None).unwrap();
+        assert_eq!(scene.world_matrix(child).unwrap().get_translation(), [0., 1., 0.]);
+    }
+
+    #[test]
+    fn set_parent_rejects_cycles() {
+        let mut scene = Scene::new();
+        let (a, b, c) = (scene.add_node("a"), scene.add_node("b"), scene.add_node("c"));
+        scene.set_parent(b, Some(a)).unwrap();
+        scene.set_parent(c, Some(b)).unwrap();
+
+        assert!(scene.set_parent(a, Some(a)).is_err());
+        assert!(scene.set_parent(a, Some(c)).is_err());
+        assert!(scene.set_parent(b, Some(c)).is_err());
+        // Failed calls leave the hierarchy as it was
+        assert_eq!(scene.roots, [a]);
+        assert_eq!(scene.node(a).unwrap().children(), [b]);
+        assert_eq!(scene.node(c).unwrap().parent(), Some(b));
+
+        let removed = scene.add_node("removed");
+        scene.remove_node(removed);
+        assert!(scene.set_parent(c, Some(removed)).is_err());
+        assert!(scene.set_parent(removed, Some(a)).is_err());
+    }
+
+    #[test]
+    fn remove_node_takes_the_subtree() {
+        let mut scene = Scene::new();
+        let (root, arm, hand, leg) = (scene.add_node("root"), scene.add_node("arm"), scene.add_node("hand"), scene.add_node("leg"));
+        scene.set_parent(arm, Some(root)).unwrap();
+        scene.set_parent(hand, Some(arm)).unwrap();
+        scene.set_parent(leg, Some(root)).unwrap();
+
+        scene.remove_node(arm);
+        assert!(scene.node(arm).is_none());
+        assert!(scene.node(hand).is_none());
+        assert_eq!(scene.node(root).unwrap().children(), [leg]);
+        assert_eq!(scene.live().count(), 2);
+        assert_eq!(scene.roots, [root]);
+    }
+
+    #[test]
+    fn removing_the_light_resets_its_globals() {
+        let mut scene = Scene::new();
+        let light = scene.add_node("light");
+        scene.set_rotation(light, &quat::from_axis_angle(&[1., 0., 0.], -std::f32::consts::FRAC_PI_2));
+        scene.node_mut(light).unwrap().light = Some(Light {
+            kind: LightKind::Directional,
+            color: [1., 0.5, 0.],
+            intensity: 2.,
+        });
+        scene.update();
+        let (direction, color) = scene.sun();
+        assert!(vec_3::length(&vec_3::sub(&direction, &[0., 1., 0.])) < 1e-5, "{:?}", direction);
+        assert_eq!(color, [2., 1., 0.]);
+
+        scene.remove_node(light);
+        assert_eq!(scene.sun(), ([0., 0., 1.], [0., 0., 0.]));
+    }
+}
@@ -0,0 +1,8 @@
+precision mediump float;
+
+varying vec4 vColor;
+
+// Debug colors are written as given, no lighting or encoding
+void main(void){
+    gl_FragColor = vColor;
+}
@@ -0,0 +1,10 @@
+attribute vec3 position;
+attribute vec4 color;
+uniform mat4 vpMatrix;
+varying vec4 vColor;
+
+// Pairs with debug.frag, lines are batched in world space
+void main(void) {
+    vColor = color;
+    gl_Position = vpMatrix * vec4(position, 1.0);
+}
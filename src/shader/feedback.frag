@@ -0,0 +1,6 @@
+#version 300 es
+precision mediump float;
+
+// Transform feedback passes run with RASTERIZER_DISCARD, nothing reaches this
+void main(void) {
+}
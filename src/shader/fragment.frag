@@ -4,15 +4,22 @@ uniform mat4 invMatrix;
 uniform vec3 lightDirection;
 uniform vec3 eyeDirection;
 uniform vec4 ambientColor;
+uniform vec4 baseColor;
 varying vec3 vNormal;
 varying vec4 vColor;
 
+// Lighting happens in linear space, colors and uniforms are expected linear
+vec3 toSrgb(vec3 c) {
+    c = clamp(c, 0.0, 1.0);
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
 void main(void){
     vec3  invLight  = normalize(invMatrix * vec4(lightDirection, 0.0)).xyz;
     vec3  invEye    = normalize(invMatrix * vec4(eyeDirection, 0.0)).xyz;
     vec3  halfLE    = normalize(invLight + invEye);
     float diffuse   = clamp(dot(vNormal, invLight), 0.0, 1.0);
     float specular  = pow(clamp(dot(vNormal, halfLE), 0.0, 1.0), 50.0);
-    vec4  destColor = vColor * vec4(vec3(diffuse), 1.0) + vec4(vec3(specular), 1.0) + ambientColor;
-    gl_FragColor    = destColor;
+    vec4  destColor = vColor * baseColor * vec4(vec3(diffuse), 1.0) + vec4(vec3(specular), 1.0) + ambientColor;
+    gl_FragColor    = vec4(toSrgb(destColor.rgb), destColor.a);
 }
\ No newline at end of file
@@ -0,0 +1,22 @@
+precision highp float;
+
+uniform vec4 idColor;
+uniform float instanced;
+uniform float depthPass;
+varying vec4 vColor;
+
+// Pairs with vertex.vert (idColor per object) and id_instanced.vert (vColor per instance).
+// The depth pass packs gl_FragCoord.z into 8 bit channels, most significant first.
+vec4 packDepth(float depth) {
+    vec4 enc = fract(depth * vec4(1.0, 255.0, 65025.0, 16581375.0));
+    enc -= enc.yzww * vec4(1.0 / 255.0, 1.0 / 255.0, 1.0 / 255.0, 0.0);
+    return enc;
+}
+
+void main(void){
+    if(depthPass > 0.5){
+        gl_FragColor = packDepth(gl_FragCoord.z);
+    }else{
+        gl_FragColor = instanced > 0.5 ? vColor : idColor;
+    }
+}
@@ -0,0 +1,20 @@
+attribute vec3 position;
+attribute vec4 instanceModel0;
+attribute vec4 instanceModel1;
+attribute vec4 instanceModel2;
+attribute vec4 instanceModel3;
+attribute float instanceIndex;
+uniform mat4 vpMatrix;
+uniform mat4 modelMatrix;
+uniform float baseId;
+varying vec4 vColor;
+
+// Pairs with id.frag, writes baseId + instanceIndex as 24 bit RGB, low byte in red.
+// The half offset keeps the divisions from landing just below an integer.
+void main(void) {
+    float id = baseId + instanceIndex + 0.5;
+    vec3 bytes = floor(mod(vec3(id) / vec3(1.0, 256.0, 65536.0), 256.0));
+    vColor = vec4(bytes / 255.0, 1.0);
+    mat4 model = modelMatrix * mat4(instanceModel0, instanceModel1, instanceModel2, instanceModel3);
+    gl_Position = vpMatrix * model * vec4(position, 1.0);
+}
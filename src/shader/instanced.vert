@@ -0,0 +1,21 @@
+attribute vec3 position;
+attribute vec3 normal;
+attribute vec4 color;
+attribute vec4 instanceModel0;
+attribute vec4 instanceModel1;
+attribute vec4 instanceModel2;
+attribute vec4 instanceModel3;
+attribute vec4 instanceColor;
+uniform mat4 vpMatrix;
+uniform mat4 modelMatrix;
+varying vec3 vNormal;
+varying vec4 vColor;
+
+// Pairs with fragment.frag, the renderer sets invMatrix to identity for
+// instanced draws so lighting happens in world space.
+void main(void) {
+    mat4 model = modelMatrix * mat4(instanceModel0, instanceModel1, instanceModel2, instanceModel3);
+    vNormal = normalize((model * vec4(normal, 0.0)).xyz);
+    vColor = color * instanceColor;
+    gl_Position = vpMatrix * model * vec4(position, 1.0);
+}
@@ -0,0 +1,14 @@
+precision mediump float;
+
+varying vec2 vTexCoord;
+varying vec4 vColor;
+
+// Round sprite with a soft edge, unlit
+void main(void){
+    float d      = length(vTexCoord * 2.0 - 1.0);
+    float alpha  = vColor.a * (1.0 - smoothstep(0.5, 1.0, d));
+    if (alpha <= 0.0) {
+        discard;
+    }
+    gl_FragColor = vec4(vColor.rgb, alpha);
+}
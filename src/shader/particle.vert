@@ -0,0 +1,19 @@
+attribute vec3 position;
+attribute vec2 texCoord;
+attribute vec4 instanceModel0;
+attribute vec4 instanceModel1;
+attribute vec4 instanceModel2;
+attribute vec4 instanceModel3;
+attribute vec4 instanceColor;
+uniform mat4 vpMatrix;
+uniform mat4 modelMatrix;
+varying vec2 vTexCoord;
+varying vec4 vColor;
+
+// Quad in the XY plane, the instance matrix already faces the camera
+void main(void) {
+    mat4 model = modelMatrix * mat4(instanceModel0, instanceModel1, instanceModel2, instanceModel3);
+    vTexCoord = texCoord;
+    vColor = instanceColor;
+    gl_Position = vpMatrix * model * vec4(position, 1.0);
+}
@@ -0,0 +1,29 @@
+#version 300 es
+in vec3 position;
+in float age;
+in float lifetime;
+// Camera rotation, the quads take it over
+uniform mat4 billboard;
+// Size and color sampled evenly over the particle's life, LIFE_SAMPLES in particles.rs
+uniform float lifeSize[16];
+uniform vec4 lifeColor[16];
+out vec4 outModel0;
+out vec4 outModel1;
+out vec4 outModel2;
+out vec4 outModel3;
+out vec4 outColor;
+
+// Writes one instance per particle in InstanceBatch's layout, dead particles
+// collapse to zero size.
+void main(void) {
+    float life = lifetime > 0.0 ? clamp(age / lifetime, 0.0, 1.0) : 1.0;
+    float x = life * 15.0;
+    int i = int(min(floor(x), 14.0));
+    float f = x - float(i);
+    float size = age < lifetime ? mix(lifeSize[i], lifeSize[i + 1], f) : 0.0;
+    outModel0 = billboard[0] * size;
+    outModel1 = billboard[1] * size;
+    outModel2 = billboard[2] * size;
+    outModel3 = vec4(position, 1.0);
+    outColor = mix(lifeColor[i], lifeColor[i + 1], f);
+}
@@ -0,0 +1,26 @@
+#version 300 es
+in vec3 position;
+in vec3 velocity;
+in float age;
+in float lifetime;
+uniform float dt;
+uniform vec3 gravity;
+uniform float damping;
+out vec3 outPosition;
+out vec3 outVelocity;
+out float outAge;
+out float outLifetime;
+
+// One particle per vertex, captured by transform feedback. Mirrors
+// ParticleSystem::update, dead particles keep their state until reused.
+void main(void) {
+    outAge = age + dt;
+    outLifetime = lifetime;
+    if (outAge >= lifetime) {
+        outPosition = position;
+        outVelocity = velocity;
+        return;
+    }
+    outVelocity = (velocity + gravity * dt) * damping;
+    outPosition = position + outVelocity * dt;
+}
@@ -0,0 +1,148 @@
+#ifdef GL_EXT_shader_texture_lod
+#extension GL_EXT_shader_texture_lod : enable
+#endif
+#ifdef GL_FRAGMENT_PRECISION_HIGH
+precision highp float;
+#else
+precision mediump float;
+#endif
+
+const float PI = 3.14159265359;
+// Brightest gamma encoded environment value, RGBM_RANGE in pbr.rs
+const float RGBM_RANGE = 8.0;
+
+// glTF metallic-roughness material
+uniform vec4      baseColorFactor;
+uniform float     metallicFactor;
+uniform float     roughnessFactor;
+uniform float     normalScale;
+uniform float     occlusionStrength;
+uniform vec3      emissiveFactor;
+uniform float     alphaMode;        // 0: opaque, 1: mask, 2: blend
+uniform float     alphaCutoff;
+uniform vec4      texCoordSets;     // base color, metallic-roughness, normal, occlusion
+uniform float     emissiveTexCoord;
+uniform sampler2D baseColorTexture;
+uniform sampler2D metallicRoughnessTexture;
+uniform sampler2D normalTexture;
+uniform sampler2D occlusionTexture;
+uniform sampler2D emissiveTexture;
+
+// Image based lighting
+uniform samplerCube environmentMap;
+uniform sampler2D   brdfLut;
+uniform float       environmentMaxLod;
+uniform vec3        irradianceSH[9];
+
+uniform vec3 lightDirection;
+uniform vec3 lightColor;
+uniform vec3 cameraPosition;
+
+varying vec3 vPosition;
+varying vec3 vNormal;
+varying vec4 vTangent;
+varying vec2 vTexCoord0;
+varying vec2 vTexCoord1;
+
+vec2 texCoord(float set) {
+    return mix(vTexCoord0, vTexCoord1, set);
+}
+
+vec3 toLinear(vec3 c) {
+    return pow(c, vec3(2.2));
+}
+
+vec3 sampleEnvironment(vec3 dir, float lod) {
+#ifdef GL_EXT_shader_texture_lod
+    vec4 rgbm = textureCubeLodEXT(environmentMap, dir, lod);
+#else
+    vec4 rgbm = textureCube(environmentMap, dir, lod);
+#endif
+    return toLinear(rgbm.rgb * rgbm.a * RGBM_RANGE);
+}
+
+vec3 irradiance(vec3 n) {
+    return irradianceSH[0] * 0.282095
+         + irradianceSH[1] * 0.488603 * n.y
+         + irradianceSH[2] * 0.488603 * n.z
+         + irradianceSH[3] * 0.488603 * n.x
+         + irradianceSH[4] * 1.092548 * n.x * n.y
+         + irradianceSH[5] * 1.092548 * n.y * n.z
+         + irradianceSH[6] * 0.315392 * (3.0 * n.z * n.z - 1.0)
+         + irradianceSH[7] * 1.092548 * n.x * n.z
+         + irradianceSH[8] * 0.546274 * (n.x * n.x - n.y * n.y);
+}
+
+float distributionGGX(float nDotH, float alpha) {
+    float a2 = alpha * alpha;
+    float d  = nDotH * nDotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+float visibilitySmithGGX(float nDotL, float nDotV, float alpha) {
+    float a2   = alpha * alpha;
+    float ggxV = nDotL * sqrt(nDotV * nDotV * (1.0 - a2) + a2);
+    float ggxL = nDotV * sqrt(nDotL * nDotL * (1.0 - a2) + a2);
+    float ggx  = ggxV + ggxL;
+    return ggx > 0.0 ? 0.5 / ggx : 0.0;
+}
+
+vec3 fresnelSchlick(vec3 f0, float vDotH) {
+    return f0 + (1.0 - f0) * pow(1.0 - vDotH, 5.0);
+}
+
+void main(void){
+    vec4 baseColor = texture2D(baseColorTexture, texCoord(texCoordSets.x));
+    baseColor = vec4(toLinear(baseColor.rgb), baseColor.a) * baseColorFactor;
+    if (alphaMode == 1.0 && baseColor.a < alphaCutoff) {
+        discard;
+    }
+
+    vec4  mr        = texture2D(metallicRoughnessTexture, texCoord(texCoordSets.y));
+    float metallic  = clamp(metallicFactor * mr.b, 0.0, 1.0);
+    float roughness = clamp(roughnessFactor * mr.g, 0.04, 1.0);
+    float alpha     = roughness * roughness;
+
+    // Meshes without normals read zero, face the viewer instead of producing NaN
+    vec3 n = dot(vNormal, vNormal) > 0.0 ? normalize(vNormal) : normalize(cameraPosition - vPosition);
+    if (!gl_FrontFacing) {
+        n = -n;
+    }
+    vec3 t = vTangent.xyz - n * dot(n, vTangent.xyz);
+    if (dot(t, t) > 1e-6) {
+        t = normalize(t);
+        vec3 b  = cross(n, t) * vTangent.w;
+        vec3 tn = texture2D(normalTexture, texCoord(texCoordSets.z)).xyz * 2.0 - 1.0;
+        tn.xy  *= normalScale;
+        n = normalize(mat3(t, b, n) * tn);
+    }
+
+    vec3  v     = normalize(cameraPosition - vPosition);
+    vec3  l     = normalize(lightDirection);
+    vec3  h     = normalize(l + v);
+    float nDotV = clamp(abs(dot(n, v)), 0.001, 1.0);
+    float nDotL = clamp(dot(n, l), 0.0, 1.0);
+    float nDotH = clamp(dot(n, h), 0.0, 1.0);
+    float vDotH = clamp(dot(v, h), 0.0, 1.0);
+
+    vec3 f0           = mix(vec3(0.04), baseColor.rgb, metallic);
+    vec3 diffuseColor = baseColor.rgb * (1.0 - metallic);
+
+    // Cook-Torrance with GGX distribution
+    vec3 f        = fresnelSchlick(f0, vDotH);
+    vec3 specular = f * distributionGGX(nDotH, alpha) * visibilitySmithGGX(nDotL, nDotV, alpha);
+    vec3 diffuse  = (1.0 - f) * diffuseColor / PI;
+    vec3 color    = (diffuse + specular) * lightColor * nDotL;
+
+    // Split-sum image based lighting
+    // Half a texel in from the edges, the repeating lookup table would wrap around
+    vec2  brdf        = texture2D(brdfLut, clamp(vec2(nDotV, roughness), 0.5 / 64.0, 63.5 / 64.0)).rg;
+    vec3  prefiltered = sampleEnvironment(reflect(-v, n), roughness * environmentMaxLod);
+    vec3  ambient     = irradiance(n) * diffuseColor + prefiltered * (f0 * brdf.x + brdf.y);
+    float occlusion   = 1.0 + occlusionStrength * (texture2D(occlusionTexture, texCoord(texCoordSets.w)).r - 1.0);
+    color += ambient * occlusion;
+
+    color += emissiveFactor * toLinear(texture2D(emissiveTexture, texCoord(emissiveTexCoord)).rgb);
+
+    gl_FragColor = vec4(pow(color, vec3(1.0 / 2.2)), alphaMode == 2.0 ? baseColor.a : 1.0);
+}
@@ -0,0 +1,22 @@
+attribute vec3 position;
+attribute vec3 normal;
+attribute vec4 tangent;
+attribute vec2 texCoord0;
+attribute vec2 texCoord1;
+uniform mat4 mvpMatrix;
+uniform mat4 modelMatrix;
+uniform mat4 normalMatrix;
+varying vec3 vPosition;
+varying vec3 vNormal;
+varying vec4 vTangent;
+varying vec2 vTexCoord0;
+varying vec2 vTexCoord1;
+
+void main(void) {
+    vPosition   = (modelMatrix * vec4(position, 1.0)).xyz;
+    vNormal     = (normalMatrix * vec4(normal, 0.0)).xyz;
+    vTangent    = vec4((modelMatrix * vec4(tangent.xyz, 0.0)).xyz, tangent.w);
+    vTexCoord0  = texCoord0;
+    vTexCoord1  = texCoord1;
+    gl_Position = mvpMatrix * vec4(position, 1.0);
+}
@@ -0,0 +1,70 @@
+#extension GL_OES_standard_derivatives : enable
+precision mediump float;
+
+uniform int shadingMode;
+uniform vec3 lightDirection;
+uniform vec3 cameraPosition;
+uniform mat4 projectionMatrix;
+// Depth mapped to black..white, derived from the projection when empty
+uniform vec2 depthRange;
+varying vec3 vNormal;
+varying vec4 vColor;
+varying vec2 vTexCoord;
+varying vec3 vBarycentric;
+varying vec3 vWorldPosition;
+varying float vDepth;
+
+// Modes follow shading::ShadingMode, colors are written without a material
+vec3 toSrgb(vec3 c) {
+    c = clamp(c, 0.0, 1.0);
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+vec3 blinnPhong(vec3 normal) {
+    vec3  light    = normalize(lightDirection);
+    vec3  eye      = normalize(cameraPosition - vWorldPosition);
+    float diffuse  = clamp(dot(normal, light), 0.0, 1.0);
+    float specular = pow(clamp(dot(normal, normalize(light + eye)), 0.0, 1.0), 50.0);
+    return vec3(0.6) * diffuse + vec3(specular * 0.5) + vec3(0.1);
+}
+
+float edgeDistance() {
+#ifdef GL_OES_standard_derivatives
+    // About one pixel wide whatever the triangle's size on screen
+    vec3 d = fwidth(vBarycentric);
+    vec3 a = smoothstep(vec3(0.0), d * 1.5, vBarycentric);
+#else
+    vec3 a = smoothstep(vec3(0.0), vec3(0.02), vBarycentric);
+#endif
+    return min(min(a.x, a.y), a.z);
+}
+
+void main(void){
+    vec3 normal = normalize(vNormal);
+    if (shadingMode == 1) {
+        vec3 lit = toSrgb(blinnPhong(normal));
+        gl_FragColor = vec4(mix(vec3(1.0, 0.6, 0.1), lit, edgeDistance()), 1.0);
+    } else if (shadingMode == 2) {
+        gl_FragColor = vec4(normal * 0.5 + 0.5, 1.0);
+    } else if (shadingMode == 3) {
+        vec2  cell    = floor(vTexCoord * 8.0);
+        float checker = mod(cell.x + cell.y, 2.0);
+        // Tinted by U in red and V in green to show orientation
+        vec3  tint    = vec3(0.5) + 0.5 * vec3(fract(vTexCoord), 0.0);
+        gl_FragColor  = vec4(mix(vec3(0.2), vec3(0.9), checker) * tint, 1.0);
+    } else if (shadingMode == 4) {
+        vec2 range = depthRange;
+        if (range.y <= range.x) {
+            // Near and far planes of Matrix::perspective, which maps depth to 0..1
+            float a = projectionMatrix[2][2];
+            float b = projectionMatrix[3][2];
+            range = vec2(b / a, b / (a + 1.0));
+        }
+        float depth = clamp((vDepth - range.x) / (range.y - range.x), 0.0, 1.0);
+        gl_FragColor = vec4(vec3(1.0 - depth), 1.0);
+    } else if (shadingMode == 5) {
+        gl_FragColor = vec4(toSrgb(vColor.rgb), 1.0);
+    } else {
+        gl_FragColor = vec4(toSrgb(blinnPhong(normal)), 1.0);
+    }
+}
@@ -0,0 +1,26 @@
+attribute vec3 position;
+attribute vec3 normal;
+attribute vec4 color;
+attribute vec2 texCoord0;
+attribute vec3 barycentric;
+uniform mat4 mvpMatrix;
+uniform mat4 modelMatrix;
+uniform mat4 normalMatrix;
+varying vec3 vNormal;
+varying vec4 vColor;
+varying vec2 vTexCoord;
+varying vec3 vBarycentric;
+varying vec3 vWorldPosition;
+varying float vDepth;
+
+// Pairs with shading.frag, missing attributes read as (0, 0, 0, 1)
+void main(void) {
+    vNormal = (normalMatrix * vec4(normal, 0.0)).xyz;
+    vColor = color;
+    vTexCoord = texCoord0;
+    vBarycentric = barycentric;
+    vWorldPosition = (modelMatrix * vec4(position, 1.0)).xyz;
+    gl_Position = mvpMatrix * vec4(position, 1.0);
+    // View space distance for perspective projections
+    vDepth = gl_Position.w;
+}
@@ -0,0 +1,42 @@
+attribute vec3 position;
+attribute vec3 normal;
+attribute vec4 color;
+attribute vec4 joints;
+attribute vec4 weights;
+uniform mat4 mvpMatrix;
+varying vec3 vNormal;
+varying vec4 vColor;
+
+// vertex.vert with 4-influence skinning, MAX_BONES or BONE_TEXTURE is defined by skin::program
+#ifdef BONE_TEXTURE
+uniform sampler2D boneTexture;
+uniform float boneTextureWidth;
+
+mat4 boneMatrix(float bone) {
+    // Four texels per bone, one column each, sampled at texel centers
+    float x = (bone * 4.0 + 0.5) / boneTextureWidth;
+    float dx = 1.0 / boneTextureWidth;
+    return mat4(
+        texture2D(boneTexture, vec2(x, 0.5)),
+        texture2D(boneTexture, vec2(x + dx, 0.5)),
+        texture2D(boneTexture, vec2(x + dx * 2.0, 0.5)),
+        texture2D(boneTexture, vec2(x + dx * 3.0, 0.5)));
+}
+#else
+uniform mat4 boneMatrices[MAX_BONES];
+
+mat4 boneMatrix(float bone) {
+    return boneMatrices[int(bone)];
+}
+#endif
+
+void main(void) {
+    mat4 skin = boneMatrix(joints.x) * weights.x
+              + boneMatrix(joints.y) * weights.y
+              + boneMatrix(joints.z) * weights.z
+              + boneMatrix(joints.w) * weights.w;
+    // Lighting uses invMatrix like vertex.vert, so normals stay in mesh space
+    vNormal = normalize((skin * vec4(normal, 0.0)).xyz);
+    vColor = color;
+    gl_Position = mvpMatrix * skin * vec4(position, 1.0);
+}
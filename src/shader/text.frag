@@ -0,0 +1,24 @@
+precision mediump float;
+
+uniform sampler2D atlas;
+uniform vec4 textColor;
+uniform float distanceRange;
+varying vec2 vTexCoord;
+varying float vPixelsPerTexel;
+
+// textColor is linear like every other color uniform, output is sRGB as in fragment.frag
+vec3 toSrgb(vec3 c) {
+    c = clamp(c, 0.0, 1.0);
+    return mix(c * 12.92, 1.055 * pow(c, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, c));
+}
+
+void main(void){
+    float texel = texture2D(atlas, vTexCoord).a;
+    float alpha = texel;
+    if(distanceRange > 0.0){
+        // Signed distance to the outline in screen pixels, antialiased over one pixel
+        float distance = (texel - 0.5) * distanceRange * vPixelsPerTexel;
+        alpha = clamp(distance + 0.5, 0.0, 1.0);
+    }
+    gl_FragColor = vec4(toSrgb(textColor.rgb), textColor.a * alpha);
+}
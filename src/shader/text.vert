@@ -0,0 +1,31 @@
+attribute vec3 position;
+attribute vec2 texCoord0;
+uniform mat4 mvpMatrix;
+uniform mat4 projectionMatrix;
+uniform vec2 viewportSize;
+uniform float screenSpace;
+uniform vec2 labelOffset;
+uniform float texelsPerUnit;
+varying vec2 vTexCoord;
+varying float vPixelsPerTexel;
+
+// Pairs with text.frag. The node's origin is projected with the MVP and the
+// glyph quads are offset from it in clip space, so the text faces the camera.
+// Screen space offsets are pixels, billboard offsets are view space units
+// scaled like the projection would.
+void main(void) {
+    vec4 anchor = mvpMatrix * vec4(0.0, 0.0, 0.0, 1.0);
+    vec2 offset = position.xy + labelOffset;
+    float pixelsPerUnit = 1.0;
+    if(screenSpace > 0.5){
+        anchor.xy += offset * 2.0 / viewportSize * anchor.w;
+    }else{
+        vec2 scale = vec2(projectionMatrix[0][0], projectionMatrix[1][1]);
+        anchor.xy += offset * scale;
+        pixelsPerUnit = scale.y * viewportSize.y * 0.5 / max(anchor.w, 1e-6);
+    }
+    vTexCoord = texCoord0;
+    vPixelsPerTexel = pixelsPerUnit / texelsPerUnit;
+    // Origins behind the camera would mirror the text, push it out of the clip volume
+    gl_Position = anchor.w > 0.0 ? anchor : vec4(0.0, 0.0, 2.0, 1.0);
+}
@@ -0,0 +1,75 @@
+use crate::error::RenderError;
+use crate::material::ShaderProgram;
+use web_sys::WebGlRenderingContext as GL;
+
+//--Viewport shading, Solid draws every material as authored--
+//  <note>
+//      The other modes replace the material of every non-instanced draw with
+//      one inspection program, see Renderer::set_shading.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ShadingMode {
+    #[default]
+    Solid,
+    //  Lit gray with triangle edges drawn over it
+    Wireframe,
+    //  World space normal as color, x in red
+    Normals,
+    //  Checkerboard of 8x8 cells per UV unit
+    UvChecker,
+    //  View distance from white at the near plane to black at the far one
+    Depth,
+    //  Color attribute, unlit
+    VertexColor,
+}
+
+impl ShadingMode {
+    pub const ALL: [ShadingMode; 6] = [
+        ShadingMode::Solid,
+        ShadingMode::Wireframe,
+        ShadingMode::Normals,
+        ShadingMode::UvChecker,
+        ShadingMode::Depth,
+        ShadingMode::VertexColor,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ShadingMode::Solid => "solid",
+            ShadingMode::Wireframe => "wireframe",
+            ShadingMode::Normals => "normals",
+            ShadingMode::UvChecker => "uv",
+            ShadingMode::Depth => "depth",
+            ShadingMode::VertexColor => "vertexColor",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+
+    //--Following mode, wrapping around, for a toggle key--
+    pub fn next(self) -> Self {
+        let index = Self::ALL.iter().position(|&mode| mode == self).unwrap_or(0);
+        Self::ALL[(index + 1) % Self::ALL.len()]
+    }
+
+    //--Value of the shadingMode uniform--
+    pub fn index(self) -> i32 {
+        self as i32
+    }
+}
+
+//--Compile the program the non-solid modes draw with--
+//  <note>
+//      Enables OES_standard_derivatives when available for one pixel wide
+//      wireframe edges, without it edges scale with the triangles. Uses
+//      lightDirection, cameraPosition and projectionMatrix from the renderer's
+//      globals, a depthRange global overrides the planes of the depth mode.
+pub fn program(gl: &GL) -> Result<ShaderProgram, RenderError> {
+    ShaderProgram::with_extensions(
+        gl,
+        include_str!("shader/shading.vert"),
+        include_str!("shader/shading.frag"),
+        &["OES_standard_derivatives"],
+    )
+}
@@ -1,3 +1,5 @@
+use crate::color::Rgba;
+
 pub fn torus(row: u16, column: u16, i_rad: f32, o_rad: f32) -> (Vec<f32>, Vec<f32>, Vec<f32>, Vec<u16>) {
     let mut pos = Vec::new();
     let mut nor = Vec::new();
@@ -22,7 +24,7 @@ pub fn torus(row: u16, column: u16, i_rad: f32, o_rad: f32) -> (Vec<f32>, Vec<f3
             nor.push(rx);
             nor.push(ry);
             nor.push(rz);
-            let tc = hsva(360. / column as f32 * ii as f32, 1., 1., 1.).unwrap();
+            let tc = Rgba::from_hsv(360. / column as f32 * ii as f32, 1., 1., 1.).linear();
             for c in tc {
                 col.push(c);
             }
@@ -43,26 +45,3 @@ pub fn torus(row: u16, column: u16, i_rad: f32, o_rad: f32) -> (Vec<f32>, Vec<f3
 
     (pos, nor, col, idx)
 }
-
-fn hsva(h: f32, s: f32, v: f32, a: f32) -> Result<[f32; 4], String> {
-    if s > 1. || v > 1. || a > 1. {
-        return Err("invalid value".to_string());
-    }
-
-    if s == 0. {
-        return Ok([v, v, v, a]);
-    }
-
-    let th = h % 360.;
-    let i = (th / 60.).floor() as usize;
-    let f = th / 60. - i as f32;
-    let m = v * (1. - s);
-    let n = v * (1. - s * f);
-    let k = v * (1. - s * (1. - f));
-
-    let r = [v, n, m, m, k, v];
-    let g = [k, v, v, n, m, m];
-    let b = [m, m, k, v, v, n];
-
-    Ok([r[i], g[i], b[i], a])
-}
\ No newline at end of file
@@ -0,0 +1,414 @@
+use crate::animation::Clip;
+use crate::context::Restorable;
+use crate::error::RenderError;
+use crate::mat_4::Matrix;
+use crate::material::{ShaderProgram, Uniform};
+use crate::quat;
+use crate::webgl;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::WebGlTexture;
+
+//  Uniform vectors skinned.vert needs besides the bone array
+const RESERVED_VECTORS: usize = 16;
+//  Largest bone array compiled into the uniform variant, long arrays slow down linking
+const MAX_UNIFORM_BONES: usize = 128;
+
+//--Local translation, rotation and scale of one joint--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: [f32; 3],
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self {
+            translation: [0., 0., 0.],
+            rotation: quat::identity(),
+            scale: [1., 1., 1.],
+        }
+    }
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix {
+        let mut m = Matrix::new();
+        m.set_trs(&self.translation, &self.rotation, &self.scale);
+        m
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Joint {
+    pub name: String,
+    //  Index of the parent joint, None for roots
+    pub parent: Option<usize>,
+    //  Local transform when no clip drives the joint
+    pub rest: Transform,
+    //  Mesh space to joint space at bind time
+    pub inverse_bind: Matrix,
+}
+
+//--Joint hierarchy a skinned mesh is bound to--
+//  <note>
+//      Joint indices match the joints attribute of the mesh. Parents may
+//      come after their children, evaluation follows a sorted order.
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    //  Joint root space to mesh space, for transforms above the root joints
+    pub root: Matrix,
+    //  Joint indices with every parent before its children
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>, root: Matrix) -> Result<Self, RenderError> {
+        let count = joints.len();
+        let mut depth = vec![0usize; count];
+        for (i, joint) in joints.iter().enumerate() {
+            let mut parent = joint.parent;
+            while let Some(p) = parent {
+                if p >= count {
+                    return Err(RenderError::Resource(format!("joint '{}' has a missing parent", joint.name)));
+                }
+                depth[i] += 1;
+                if depth[i] > count {
+                    return Err(RenderError::Resource(format!("joint '{}' is its own ancestor", joint.name)));
+                }
+                parent = joints[p].parent;
+            }
+        }
+        let mut order: Vec<usize> = (0..count).collect();
+        order.sort_by_key(|&i| depth[i]);
+        Ok(Self { joints, root, order })
+    }
+
+    pub fn len(&self) -> usize {
+        self.joints.len()
+    }
+
+    pub fn rest_pose(&self) -> Vec<Transform> {
+        self.joints.iter().map(|j| j.rest).collect()
+    }
+
+    //--Skinning matrices of a pose, mesh space at bind time to mesh space now--
+    //  <argument>
+    //      pose &[Transform] : local transform per joint
+    //      out  &mut [f32]   : 16 floats per joint, column-major
+    pub fn joint_matrices(&self, pose: &[Transform], out: &mut [f32]) {
+        let mut global = vec![Matrix::new(); self.joints.len()];
+        for &i in self.order.iter() {
+            let local = pose.get(i).unwrap_or(&self.joints[i].rest).matrix();
+            let mut m = match self.joints[i].parent {
+                Some(p) => global[p],
+                None => self.root,
+            };
+            m.multiply(&local);
+            global[i] = m;
+        }
+        for (i, (joint, chunk)) in self.joints.iter().zip(out.chunks_exact_mut(16)).enumerate() {
+            let mut m = global[i];
+            m.multiply(&joint.inverse_bind);
+            chunk.copy_from_slice(&m.get_value());
+        }
+    }
+}
+
+//--Where the vertex shader reads bone matrices from--
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BoneStorage {
+    //  mat4 uniform array with room for max_bones
+    Uniforms { max_bones: usize },
+    //  Float RGBA texture, four texels per bone, for skeletons too large for uniforms
+    Texture,
+}
+
+impl BoneStorage {
+    //--Pick uniforms when `joints` bones fit the vertex uniform limit, a bone texture otherwise--
+    //  <note>
+    //      Bone textures need vertex texture fetch and float textures, core in
+    //      WebGL2 and OES_texture_float in WebGL1, the extension is enabled
+    //      again by Skin::apply whenever it creates the texture.
+    pub fn detect(gl: &GL, joints: usize) -> Result<Self, RenderError> {
+        let parameter = |name: u32| gl.get_parameter(name).ok().and_then(|v| v.as_f64()).unwrap_or(0.) as usize;
+        let max_bones = (parameter(GL::MAX_VERTEX_UNIFORM_VECTORS).saturating_sub(RESERVED_VECTORS) / 4).min(MAX_UNIFORM_BONES);
+        if joints <= max_bones {
+            return Ok(BoneStorage::Uniforms { max_bones });
+        }
+        let float_textures =
+            webgl::webgl2(gl).is_some() || gl.get_extension("OES_texture_float").ok().flatten().is_some();
+        if parameter(GL::MAX_VERTEX_TEXTURE_IMAGE_UNITS) > 0 && float_textures {
+            return Ok(BoneStorage::Texture);
+        }
+        Err(RenderError::Resource(format!(
+            "{} joints exceed the {} bone uniforms and bone textures are unsupported",
+            joints, max_bones
+        )))
+    }
+
+    //--Whether a skeleton of `joints` bones can use this storage--
+    pub fn fits(self, joints: usize) -> bool {
+        match self {
+            BoneStorage::Uniforms { max_bones } => joints <= max_bones,
+            BoneStorage::Texture => true,
+        }
+    }
+}
+
+//--Compile the skinned variant of vertex.vert with fragment.frag--
+//  <note>
+//      The program reads bones from the given storage, skins of any skeleton
+//      that fits it can share the program.
+pub fn program(gl: &GL, storage: BoneStorage) -> Result<ShaderProgram, RenderError> {
+    let defines = match storage {
+        BoneStorage::Uniforms { max_bones } => format!("#define MAX_BONES {}\n", max_bones.max(1)),
+        BoneStorage::Texture => "#define BONE_TEXTURE\n".to_string(),
+    };
+    ShaderProgram::new(
+        gl,
+        &(defines + include_str!("shader/skinned.vert")),
+        include_str!("shader/fragment.frag"),
+    )
+}
+
+//--Pose of one skinned mesh and its bone matrices on the GPU--
+//  <note>
+//      The renderer uploads the matrices before drawing a node with a skin.
+//      The bone texture is rebuilt after a context restore, track the skin.
+pub struct Skin {
+    skeleton: Rc<Skeleton>,
+    storage: BoneStorage,
+    pose: RefCell<Vec<Transform>>,
+    //  16 floats per joint
+    matrices: RefCell<Vec<f32>>,
+    texture: RefCell<Option<WebGlTexture>>,
+    //  Matrices changed since the last texture upload
+    dirty: Cell<bool>,
+}
+
+impl Skin {
+    //--Skin in the skeleton's rest pose--
+    pub fn new(skeleton: Rc<Skeleton>, storage: BoneStorage) -> Result<Self, RenderError> {
+        if !storage.fits(skeleton.len()) {
+            return Err(RenderError::Resource(format!(
+                "{} joints don't fit the bone uniforms",
+                skeleton.len()
+            )));
+        }
+        let skin = Self {
+            pose: RefCell::new(skeleton.rest_pose()),
+            matrices: RefCell::new(vec![0.; skeleton.len() * 16]),
+            skeleton,
+            storage,
+            texture: RefCell::new(None),
+            dirty: Cell::new(true),
+        };
+        skin.update();
+        Ok(skin)
+    }
+
+    pub fn skeleton(&self) -> &Rc<Skeleton> {
+        &self.skeleton
+    }
+
+    pub fn storage(&self) -> BoneStorage {
+        self.storage
+    }
+
+    pub fn set_pose(&self, pose: &[Transform]) {
+        let mut current = self.pose.borrow_mut();
+        for (dst, src) in current.iter_mut().zip(pose.iter()) {
+            *dst = *src;
+        }
+        drop(current);
+        self.update();
+    }
+
+    //--Pose the skin with a clip at a time, starting from the rest pose--
+    pub fn play(&self, clip: &Clip, time: f32) {
+        let mut pose = self.skeleton.rest_pose();
+        clip.sample(time, &mut pose);
+        *self.pose.borrow_mut() = pose;
+        self.update();
+    }
+
+    fn update(&self) {
+        self.skeleton.joint_matrices(&self.pose.borrow(), &mut self.matrices.borrow_mut());
+        self.dirty.set(true);
+    }
+
+    //--Hand the bone matrices to a program that is in use--
+    //  <argument>
+    //      unit u32 : texture unit for the bone texture, one the material doesn't use
+    pub fn apply(&self, gl: &GL, program: &ShaderProgram, unit: u32) -> Result<(), RenderError> {
+        let matrices = self.matrices.borrow();
+        match self.storage {
+            BoneStorage::Uniforms { .. } => {
+                if let Some(location) = program.uniform_location(gl, "boneMatrices") {
+                    gl.uniform_matrix4fv_with_f32_array(Some(&location), false, &matrices);
+                }
+            }
+            BoneStorage::Texture => {
+                let width = (self.skeleton.len() * 4).max(1) as i32;
+                let mut texture = self.texture.borrow_mut();
+                if texture.is_none() {
+                    // Extensions are per context, a restored context starts without it
+                    if webgl::webgl2(gl).is_none() {
+                        gl.get_extension("OES_texture_float")
+                            .ok()
+                            .flatten()
+                            .ok_or_else(|| RenderError::Resource("OES_texture_float is unsupported".into()))?;
+                    }
+                    let created = gl
+                        .create_texture()
+                        .ok_or_else(|| RenderError::Resource("failed to create bone texture".into()))?;
+                    gl.bind_texture(GL::TEXTURE_2D, Some(&created));
+                    // Float textures can't be filtered without another extension
+                    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
+                    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
+                    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+                    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+                    *texture = Some(created);
+                    self.dirty.set(true);
+                }
+                gl.active_texture(GL::TEXTURE0 + unit);
+                gl.bind_texture(GL::TEXTURE_2D, texture.as_ref());
+                if self.dirty.get() {
+                    let mut texels = matrices.clone();
+                    texels.resize(width as usize * 4, 0.);
+                    let view = js_sys::Float32Array::from(&texels[..]);
+                    // WebGL2 only takes float texels into a sized internal format
+                    let format = match webgl::webgl2(gl) {
+                        Some(_) => web_sys::WebGl2RenderingContext::RGBA32F,
+                        None => GL::RGBA,
+                    };
+                    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_array_buffer_view(
+                        GL::TEXTURE_2D,
+                        0,
+                        format as i32,
+                        width,
+                        1,
+                        0,
+                        GL::RGBA,
+                        GL::FLOAT,
+                        Some(&view),
+                    )
+                    .map_err(|_| RenderError::Resource("failed to upload bone texture".into()))?;
+                    self.dirty.set(false);
+                }
+                program.set_uniform(gl, "boneTexture", &Uniform::Int(unit as i32));
+                program.set_uniform(gl, "boneTextureWidth", &Uniform::Float(width as f32));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Restorable for Skin {
+    fn restore(&self, _gl: &GL) -> Result<(), RenderError> {
+        // Recreated and uploaded on the next apply
+        *self.texture.borrow_mut() = None;
+        self.dirty.set(true);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vec_3;
+    use std::convert::TryInto;
+
+    fn joint(name: &str, parent: Option<usize>, rest: Transform) -> Joint {
+        Joint {
+            name: name.into(),
+            parent,
+            rest,
+            inverse_bind: Matrix::new(),
+        }
+    }
+
+    fn transform(translation: [f32; 3], angle: f32, scale: f32) -> Transform {
+        Transform {
+            translation,
+            rotation: quat::from_axis_angle(&vec_3::normalize(&[1., 2., 3.]), angle),
+            scale: [scale; 3],
+        }
+    }
+
+    fn matrices(skeleton: &Skeleton, pose: &[Transform]) -> Vec<[f32; 16]> {
+        let mut out = vec![0.; skeleton.len() * 16];
+        skeleton.joint_matrices(pose, &mut out);
+        out.chunks(16).map(|c| c.try_into().unwrap()).collect()
+    }
+
+    fn assert_close(a: &[f32; 16], b: &[f32; 16]) {
+        for (x, y) in a.iter().zip(b.iter()) {
+            assert!((x - y).abs() < 1e-4, "{:?} vs {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn bind_pose_skins_to_identity() {
+        let mut root = Matrix::new();
+        root.set_trs(&[0., -1., 0.], &quat::identity(), &[1., 1., 1.]);
+        let rests = [
+            transform([0., 1., 0.], 0.3, 1.),
+            transform([0., 2., 0.5], -0.7, 0.5),
+            transform([1., 0., 0.], 1.1, 2.),
+        ];
+        let mut joints = vec![
+            joint("hips", None, rests[0]),
+            joint("spine", Some(0), rests[1]),
+            joint("head", Some(1), rests[2]),
+        ];
+        // Inverse of each joint's mesh space transform at bind time
+        let mut global = root;
+        for joint in joints.iter_mut() {
+            global.multiply(&joint.rest.matrix());
+            let mut inverse = global;
+            inverse.inverse().unwrap();
+            joint.inverse_bind = inverse;
+        }
+        let skeleton = Skeleton::new(joints, root).unwrap();
+        for m in matrices(&skeleton, &skeleton.rest_pose()) {
+            assert_close(&m, &Matrix::new().get_value());
+        }
+        // A short pose falls back to the rest transforms
+        for m in matrices(&skeleton, &[]) {
+            assert_close(&m, &Matrix::new().get_value());
+        }
+    }
+
+    #[test]
+    fn children_listed_before_their_parents_compose() {
+        let (upper, lower) = (transform([0., 1., 0.], 0.5, 1.), transform([0., 0., 2.], -0.4, 1.5));
+        let ordered = Skeleton::new(
+            vec![joint("upper", None, upper), joint("lower", Some(0), lower)],
+            Matrix::new(),
+        )
+        .unwrap();
+        let reversed = Skeleton::new(
+            vec![joint("lower", Some(1), lower), joint("upper", None, upper)],
+            Matrix::new(),
+        )
+        .unwrap();
+
+        let mut expected = upper.matrix();
+        expected.multiply(&lower.matrix());
+        assert_close(&matrices(&ordered, &ordered.rest_pose())[1], &expected.get_value());
+        assert_close(&matrices(&reversed, &reversed.rest_pose())[0], &expected.get_value());
+        assert_close(&matrices(&reversed, &reversed.rest_pose())[1], &upper.matrix().get_value());
+    }
+
+    #[test]
+    fn rejects_cycles_and_missing_parents() {
+        let rest = Transform::default();
+        assert!(Skeleton::new(vec![joint("a", Some(1), rest), joint("b", Some(0), rest)], Matrix::new()).is_err());
+        assert!(Skeleton::new(vec![joint("a", Some(0), rest)], Matrix::new()).is_err());
+        assert!(Skeleton::new(vec![joint("a", None, rest), joint("b", Some(2), rest)], Matrix::new()).is_err());
+    }
+}
@@ -0,0 +1,65 @@
+#!/usr/bin/env python3
+# Writes png_golden.png, the reference for png::tests::matches_the_golden_image.
+# Uses zlib with fixed Huffman codes, which the test's inflate understands,
+# and cycles through all five row filters.
+import struct
+import zlib
+
+WIDTH, HEIGHT = 67, 45
+
+
+def test_image(width, height):
+    seed = 1
+    pixels = bytearray()
+    for y in range(height):
+        for x in range(width):
+            seed = (seed * 1103515245 + 12345) & 0xFFFFFFFF
+            noise = (seed >> 16) & 0xFF
+            column, row = x * 3 // width, y * 2 // height
+            if column == 0:
+                pixel = [x, y, x + y, 255]
+            elif column == 1 and row == 0:
+                pixel = [40, 80, 120, 200]
+            elif column == 1:
+                pixel = [noise, noise // 2, 255 - noise, 255]
+            else:
+                pixel = [x * y, noise & 0xF0, 128, y * 4]
+            pixels.extend(c & 0xFF for c in pixel)
+    return pixels
+
+
+def paeth(a, b, c):
+    p = a + b - c
+    pa, pb, pc = abs(p - a), abs(p - b), abs(p - c)
+    if pa <= pb and pa <= pc:
+        return a
+    return b if pb <= pc else c
+
+
+def filtered(pixels, width, height):
+    stride = width * 4
+    out = bytearray()
+    for y in range(height):
+        kind = y % 5
+        out.append(kind)
+        for i in range(stride):
+            at = y * stride + i
+            left = pixels[at - 4] if i >= 4 else 0
+            above = pixels[at - stride] if y > 0 else 0
+            upper_left = pixels[at - stride - 4] if y > 0 and i >= 4 else 0
+            predicted = [0, left, above, (left + above) // 2, paeth(left, above, upper_left)][kind]
+            out.append((pixels[at] - predicted) & 0xFF)
+    return bytes(out)
+
+
+def chunk(kind, data):
+    return struct.pack(">I", len(data)) + kind + data + struct.pack(">I", zlib.crc32(kind + data))
+
+
+compressor = zlib.compressobj(9, zlib.DEFLATED, 15, 9, zlib.Z_FIXED)
+idat = compressor.compress(filtered(test_image(WIDTH, HEIGHT), WIDTH, HEIGHT)) + compressor.flush()
+with open(__file__.replace(".py", ".png"), "wb") as f:
+    f.write(b"\x89PNG\r\n\x1a\n")
+    f.write(chunk(b"IHDR", struct.pack(">IIBBBBB", WIDTH, HEIGHT, 8, 6, 0, 0, 0)))
+    f.write(chunk(b"IDAT", idat))
+    f.write(chunk(b"IEND", b""))
@@ -0,0 +1,625 @@
+use crate::bounds::{Aabb, BoundingSphere};
+use crate::color::Rgba;
+use crate::context::{ResourceTracker, Restorable};
+use crate::error::RenderError;
+use crate::loader::MeshData;
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::render_state::{CullFace, RenderState, Winding};
+use crate::texture::Texture;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use web_sys::WebGlRenderingContext as GL;
+
+//  Quads per text mesh, four vertices each have to fit u16 indices
+const MAX_GLYPHS: usize = 16383;
+
+//--Where a glyph sits in the atlas and how it's placed on a line, in atlas pixels--
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Glyph {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    //  From the pen position to the quad's left edge
+    pub x_offset: f32,
+    //  From the top of the line down to the quad's top edge
+    pub y_offset: f32,
+    pub advance: f32,
+}
+
+//--Single channel glyph atlas with metrics and kerning--
+//  <note>
+//      Either a coverage bitmap or a signed distance field, where 0.5 is the
+//      outline and distance_range atlas pixels span 0 to 1. SDF atlases stay
+//      sharp at any size, bitmaps look best near their nominal size.
+pub struct FontAtlas {
+    //  Nominal size in pixels the atlas was made for
+    pub size: f32,
+    pub line_height: f32,
+    //  From the top of a line to the baseline
+    pub base: f32,
+    pub width: u32,
+    pub height: u32,
+    //  One byte per pixel, rows top to bottom
+    pub pixels: Vec<u8>,
+    //  Some for SDF atlases
+    pub distance_range: Option<f32>,
+    glyphs: HashMap<char, Glyph>,
+    kerning: HashMap<(char, char), f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+impl TextAlign {
+    //--Parse "left", "center" or "right"--
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(TextAlign::Left),
+            "center" => Some(TextAlign::Center),
+            "right" => Some(TextAlign::Right),
+            _ => None,
+        }
+    }
+}
+
+//--Which part of the text block lands on the origin vertically--
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAnchor {
+    Top,
+    Middle,
+    //  Baseline of the first line
+    Baseline,
+    Bottom,
+}
+
+impl TextAnchor {
+    //--Parse "top", "middle", "baseline" or "bottom"--
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "top" => Some(TextAnchor::Top),
+            "middle" => Some(TextAnchor::Middle),
+            "baseline" => Some(TextAnchor::Baseline),
+            "bottom" => Some(TextAnchor::Bottom),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TextLayout {
+    //  Output units per nominal font size, a line is about this tall
+    pub size: f32,
+    pub align: TextAlign,
+    pub anchor: TextAnchor,
+    //  Multiplier for the font's line height
+    pub line_spacing: f32,
+}
+
+impl Default for TextLayout {
+    fn default() -> Self {
+        Self {
+            size: 1.,
+            align: TextAlign::Left,
+            anchor: TextAnchor::Baseline,
+            line_spacing: 1.,
+        }
+    }
+}
+
+impl FontAtlas {
+    //--Read an AngelCode BMFont text descriptor and its page--
+    //  <argument>
+    //      descriptor &str    : .fnt contents in the text format, one page
+    //      pixels     Vec<u8> : the page, one byte per pixel or RGBA of which alpha is used
+    //  <note>
+    //      A "distanceField fieldType=sdf distanceRange=N" line, as written by
+    //      msdf-bmfont and examples/font_atlas.rs, marks the page as an SDF.
+    pub fn from_bmfont(descriptor: &str, pixels: Vec<u8>) -> Result<Self, RenderError> {
+        let mut atlas = FontAtlas {
+            size: 0.,
+            line_height: 0.,
+            base: 0.,
+            width: 0,
+            height: 0,
+            pixels: Vec::new(),
+            distance_range: None,
+            glyphs: HashMap::new(),
+            kerning: HashMap::new(),
+        };
+        for line in descriptor.lines() {
+            let mut words = line.split_whitespace();
+            let tag = match words.next() {
+                Some(tag) => tag,
+                None => continue,
+            };
+            let values: HashMap<&str, &str> = words.filter_map(|w| w.split_once('=')).collect();
+            let number = |key: &str| -> Result<f32, RenderError> {
+                values
+                    .get(key)
+                    .and_then(|v| v.trim_matches('"').parse().ok())
+                    .ok_or_else(|| RenderError::Resource(format!("font descriptor: '{}' line without a valid {}", tag, key)))
+            };
+            match tag {
+                "info" => atlas.size = number("size")?.abs(),
+                "common" => {
+                    atlas.line_height = number("lineHeight")?;
+                    atlas.base = number("base")?;
+                    atlas.width = number("scaleW")? as u32;
+                    atlas.height = number("scaleH")? as u32;
+                    if number("pages").unwrap_or(1.) > 1. {
+                        return Err(RenderError::Resource("font descriptor: only single page fonts are supported".into()));
+                    }
+                }
+                "distanceField" => atlas.distance_range = Some(number("distanceRange")?),
+                "char" => {
+                    let c = char::from_u32(number("id")? as u32)
+                        .ok_or_else(|| RenderError::Resource("font descriptor: invalid char id".into()))?;
+                    atlas.glyphs.insert(
+                        c,
+                        Glyph {
+                            x: number("x")?,
+                            y: number("y")?,
+                            width: number("width")?,
+                            height: number("height")?,
+                            x_offset: number("xoffset")?,
+                            y_offset: number("yoffset")?,
+                            advance: number("xadvance")?,
+                        },
+                    );
+                }
+                "kerning" => {
+                    let pair = (char::from_u32(number("first")? as u32), char::from_u32(number("second")? as u32));
+                    if let (Some(a), Some(b)) = pair {
+                        atlas.kerning.insert((a, b), number("amount")?);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let area = (atlas.width * atlas.height) as usize;
+        if area == 0 || atlas.glyphs.is_empty() {
+            return Err(RenderError::Resource("font descriptor has no common line or no chars".into()));
+        }
+        atlas.pixels = if pixels.len() == area {
+            pixels
+        } else if pixels.len() == area * 4 {
+            pixels.chunks_exact(4).map(|p| p[3]).collect()
+        } else {
+            return Err(RenderError::Resource(format!(
+                "font page has {} bytes, expected {} or {}",
+                pixels.len(),
+                area,
+                area * 4
+            )));
+        };
+        if atlas.size == 0. {
+            atlas.size = atlas.line_height;
+        }
+        Ok(atlas)
+    }
+
+    //--DejaVu Sans, printable ASCII as a 32 px SDF, see src/font--
+    pub fn builtin() -> Self {
+        Self::from_bmfont(
+            include_str!("font/dejavu_sans.fnt"),
+            include_bytes!("font/dejavu_sans.sdf").to_vec(),
+        )
+        .expect("bundled font is valid")
+    }
+
+    pub fn glyph(&self, c: char) -> Option<&Glyph> {
+        self.glyphs.get(&c)
+    }
+
+    //--Advance adjustment between two characters, in atlas pixels--
+    pub fn kerning(&self, a: char, b: char) -> f32 {
+        self.kerning.get(&(a, b)).copied().unwrap_or(0.)
+    }
+
+    //--Width of one line in atlas pixels, kerning included--
+    fn line_width(&self, line: &str) -> f32 {
+        let mut width = 0.;
+        let mut previous = None;
+        for c in line.chars() {
+            if let Some(glyph) = self.glyph(c) {
+                width += glyph.advance + previous.map_or(0., |p| self.kerning(p, c));
+                previous = Some(c);
+            }
+        }
+        width
+    }
+
+    //--Size of the laid out text block in output units--
+    pub fn measure(&self, text: &str, layout: &TextLayout) -> [f32; 2] {
+        let scale = layout.size / self.size;
+        let width = text.lines().map(|line| self.line_width(line)).fold(0., f32::max);
+        let lines = text.lines().count().max(1) as f32;
+        [width * scale, lines * self.line_height * layout.line_spacing * scale]
+    }
+
+    //--Lay out text as one quad per glyph in the xy plane, y up--
+    //  <return> MeshData  positions, tex_coords and indices; no normals or colors
+    //  <note>
+    //      Lines break at '\n' and are aligned around x = 0, the anchor decides
+    //      what lands on y = 0. Characters missing from the atlas are skipped,
+    //      text past MAX_GLYPHS is dropped.
+    pub fn build(&self, text: &str, layout: &TextLayout) -> MeshData {
+        let scale = layout.size / self.size;
+        let line_advance = self.line_height * layout.line_spacing;
+        let lines: Vec<&str> = text.lines().collect();
+        let block_height = lines.len().max(1) as f32 * line_advance;
+        // Top of the first line, in atlas pixels with y up
+        let top = match layout.anchor {
+            TextAnchor::Top => 0.,
+            TextAnchor::Middle => block_height / 2.,
+            TextAnchor::Baseline => self.base,
+            TextAnchor::Bottom => block_height,
+        };
+
+        let mut data = MeshData::default();
+        let mut quads = 0;
+        'lines: for (row, line) in lines.iter().enumerate() {
+            let width = self.line_width(line);
+            let mut pen = match layout.align {
+                TextAlign::Left => 0.,
+                TextAlign::Center => -width / 2.,
+                TextAlign::Right => -width,
+            };
+            let line_top = top - row as f32 * line_advance;
+            let mut previous = None;
+            for c in line.chars() {
+                let glyph = match self.glyph(c) {
+                    Some(glyph) => glyph,
+                    None => continue,
+                };
+                pen += previous.map_or(0., |p| self.kerning(p, c));
+                previous = Some(c);
+                if glyph.width > 0. && glyph.height > 0. {
+                    if quads == MAX_GLYPHS {
+                        break 'lines;
+                    }
+                    let (x0, y0) = (pen + glyph.x_offset, line_top - glyph.y_offset);
+                    let (x1, y1) = (x0 + glyph.width, y0 - glyph.height);
+                    let (u0, v0) = (glyph.x / self.width as f32, glyph.y / self.height as f32);
+                    let (u1, v1) = (
+                        (glyph.x + glyph.width) / self.width as f32,
+                        (glyph.y + glyph.height) / self.height as f32,
+                    );
+                    let first = (quads * 4) as u16;
+                    for (x, y, u, v) in [(x0, y0, u0, v0), (x0, y1, u0, v1), (x1, y1, u1, v1), (x1, y0, u1, v0)] {
+                        data.positions.extend_from_slice(&[x * scale, y * scale, 0.]);
+                        data.tex_coords.extend_from_slice(&[u, v]);
+                    }
+                    data.indices
+                        .extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+                    quads += 1;
+                }
+                pen += glyph.advance;
+            }
+        }
+        data
+    }
+}
+
+//--Font atlas uploaded with the text program--
+pub struct Font {
+    pub atlas: FontAtlas,
+    texture: Rc<Texture>,
+    program: Rc<ShaderProgram>,
+}
+
+impl Font {
+    pub fn new(gl: &GL, atlas: FontAtlas) -> Result<Self, RenderError> {
+        // White with the atlas in alpha, text.frag reads alpha only
+        let pixels = atlas.pixels.iter().flat_map(|&a| [255, 255, 255, a]).collect();
+        Ok(Self {
+            texture: Rc::new(Texture::image_2d(gl, atlas.width, atlas.height, pixels)?),
+            program: Rc::new(ShaderProgram::new(
+                gl,
+                include_str!("shader/text.vert"),
+                include_str!("shader/text.frag"),
+            )?),
+            atlas,
+        })
+    }
+
+    //--Upload the bundled font--
+    pub fn builtin(gl: &GL) -> Result<Self, RenderError> {
+        Self::new(gl, FontAtlas::builtin())
+    }
+
+    //--Register the GPU resources for context restore--
+    pub fn track(&self, resources: &mut ResourceTracker) {
+        resources.track(&self.texture);
+        resources.track(&self.program);
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LabelMode {
+    //  Sized in world units, always facing the camera
+    Billboard,
+    //  Sized in drawing buffer pixels, the same size at any distance
+    Screen,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LabelStyle {
+    pub mode: LabelMode,
+    //  World units or pixels per line, depending on the mode
+    pub size: f32,
+    pub color: Rgba,
+    pub align: TextAlign,
+    pub anchor: TextAnchor,
+    //  Shift from the node's origin, in the same units as size
+    pub offset: [f32; 2],
+    //  Hidden behind geometry when true, drawn on top otherwise
+    pub depth_test: bool,
+}
+
+impl Default for LabelStyle {
+    fn default() -> Self {
+        Self {
+            mode: LabelMode::Billboard,
+            size: 0.5,
+            color: Rgba::WHITE,
+            align: TextAlign::Center,
+            anchor: TextAnchor::Bottom,
+            offset: [0., 0.],
+            depth_test: true,
+        }
+    }
+}
+
+//--Text attached to a scene node--
+//  <note>
+//      The node's origin is projected with its MVP and the text is laid out
+//      around it in clip space, so rotation and scale of the node don't
+//      apply. Track the label itself for context restore, the mesh changes
+//      with the text.
+pub struct Label {
+    pub font: Rc<Font>,
+    pub style: LabelStyle,
+    text: RefCell<String>,
+    mesh: RefCell<Rc<Mesh>>,
+    material: Rc<Material>,
+}
+
+impl Label {
+    pub fn new(gl: &GL, font: &Rc<Font>, text: &str, style: LabelStyle) -> Result<Self, RenderError> {
+        let mut material = Material::new(font.program.clone());
+        let state = if style.depth_test {
+            RenderState::TRANSPARENT
+        } else {
+            RenderState::OVERLAY
+        };
+        let screen_space = style.mode == LabelMode::Screen;
+        material
+            .set("textColor", Uniform::Vec4(style.color.linear()))
+            .set("distanceRange", Uniform::Float(font.atlas.distance_range.unwrap_or(0.)))
+            .set("texelsPerUnit", Uniform::Float(font.atlas.size / style.size))
+            .set("screenSpace", Uniform::Float(if screen_space { 1. } else { 0. }))
+            .set("labelOffset", Uniform::Vec2(style.offset))
+            .set_texture("atlas", font.texture.clone())
+            .set_state(state.with_cull_face(CullFace::None, Winding::CounterClockwise));
+
+        let label = Self {
+            font: font.clone(),
+            style,
+            text: RefCell::new(String::new()),
+            mesh: RefCell::new(Rc::new(Self::build_mesh(gl, font, "", &style)?)),
+            material: Rc::new(material),
+        };
+        label.set_text(gl, text)?;
+        Ok(label)
+    }
+
+    fn build_mesh(gl: &GL, font: &Font, text: &str, style: &LabelStyle) -> Result<Mesh, RenderError> {
+        let layout = TextLayout {
+            size: style.size,
+            align: style.align,
+            anchor: style.anchor,
+            ..Default::default()
+        };
+        let data = font.atlas.build(text, &layout);
+        let mut mesh = Mesh::new(
+            gl,
+            &[("position", &data.positions, 3), ("texCoord0", &data.tex_coords, 2)],
+            &data.indices,
+        )?;
+        mesh.bounds = match style.mode {
+            // Billboards turn with the camera, so bound every orientation around the origin
+            LabelMode::Billboard => mesh.bounds.map(|mut bounds| {
+                let reach = data
+                    .positions
+                    .chunks_exact(3)
+                    .map(|p| (p[0] + style.offset[0]).hypot(p[1] + style.offset[1]))
+                    .fold(0., f32::max);
+                bounds.aabb = Aabb {
+                    min: [-reach; 3],
+                    max: [reach; 3],
+                };
+                bounds.sphere = BoundingSphere {
+                    center: [0.; 3],
+                    radius: reach,
+                };
+                bounds
+            }),
+            // Pixel sized, no meaningful bounds in model space
+            LabelMode::Screen => None,
+        };
+        Ok(mesh)
+    }
+
+    pub fn text(&self) -> String {
+        self.text.borrow().clone()
+    }
+
+    //--Replace the text, rebuilding the mesh--
+    pub fn set_text(&self, gl: &GL, text: &str) -> Result<(), RenderError> {
+        *self.mesh.borrow_mut() = Rc::new(Self::build_mesh(gl, &self.font, text, &self.style)?);
+        *self.text.borrow_mut() = text.to_string();
+        Ok(())
+    }
+
+    pub fn mesh(&self) -> Rc<Mesh> {
+        self.mesh.borrow().clone()
+    }
+
+    pub fn material(&self) -> &Rc<Material> {
+        &self.material
+    }
+}
+
+impl Restorable for Label {
+    fn restore(&self, gl: &GL) -> Result<(), RenderError> {
+        self.mesh.borrow().restore(gl)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FONT: &str = "info face=\"Test\" size=16
+common lineHeight=20 base=15 scaleW=16 scaleH=8 pages=1
+distanceField fieldType=sdf distanceRange=4
+chars count=3
+char id=65 x=0 y=0 width=8 height=8 xoffset=1 yoffset=4 xadvance=10
+char id=86 x=8 y=0 width=8 height=8 xoffset=0 yoffset=4 xadvance=9
+char id=32 x=0 y=0 width=0 height=0 xoffset=0 yoffset=0 xadvance=5
+kernings count=1
+kerning first=65 second=86 amount=-2
+";
+
+    fn atlas() -> FontAtlas {
+        let pixels = (0..16 * 8).flat_map(|i| [0, 0, 0, i as u8]).collect();
+        FontAtlas::from_bmfont(FONT, pixels).unwrap()
+    }
+
+    fn layout(align: TextAlign, anchor: TextAnchor) -> TextLayout {
+        TextLayout {
+            size: 16.,
+            align,
+            anchor,
+            ..TextLayout::default()
+        }
+    }
+
+    //  Top left corner of each quad
+    fn corners(data: &MeshData) -> Vec<[f32; 2]> {
+        data.positions.chunks(12).map(|q| [q[0], q[1]]).collect()
+    }
+
+    #[test]
+    fn parses_a_bmfont_descriptor() {
+        let atlas = atlas();
+        assert_eq!((atlas.size, atlas.line_height, atlas.base), (16., 20., 15.));
+        assert_eq!((atlas.width, atlas.height), (16, 8));
+        assert_eq!(atlas.distance_range, Some(4.));
+        assert_eq!(atlas.glyph('V').unwrap().advance, 9.);
+        assert_eq!(atlas.glyph('B'), None);
+        assert_eq!(atlas.kerning('A', 'V'), -2.);
+        assert_eq!(atlas.kerning('V', 'A'), 0.);
+        // RGBA pages keep the alpha channel
+        assert_eq!(atlas.pixels.len(), 128);
+        assert_eq!(atlas.pixels[127], 127);
+
+        assert!(FontAtlas::from_bmfont(FONT, vec![0; 100]).is_err());
+        assert!(FontAtlas::from_bmfont(&FONT.replace("pages=1", "pages=2"), vec![0; 128]).is_err());
+        assert!(FontAtlas::from_bmfont(&FONT.replace("xadvance=9", ""), vec![0; 128]).is_err());
+        let no_chars: String = FONT.lines().filter(|l| !l.starts_with("char ")).map(|l| l.to_owned() + "\n").collect();
+        assert!(FontAtlas::from_bmfont(&no_chars, vec![0; 128]).is_err());
+    }
+
+    #[test]
+    fn measure_applies_kerning() {
+        let atlas = atlas();
+        let unit = layout(TextAlign::Left, TextAnchor::Baseline);
+        assert_eq!(atlas.measure("AV", &unit), [17., 20.]);
+        assert_eq!(atlas.measure("VA", &unit), [19., 20.]);
+        // A space between them breaks the pair, missing characters don't
+        assert_eq!(atlas.measure("A V", &unit), [24., 20.]);
+        assert_eq!(atlas.measure("A?V", &unit), [17., 20.]);
+        assert_eq!(atlas.measure("AV\nVAV", &unit), [26., 40.]);
+        assert_eq!(atlas.measure("", &unit), [0., 20.]);
+
+        let large = TextLayout {
+            size: 32.,
+            line_spacing: 1.5,
+            ..unit
+        };
+        assert_eq!(atlas.measure("AV\nA", &large), [34., 120.]);
+    }
+
+    #[test]
+    fn alignment_shifts_lines_around_the_origin() {
+        let atlas = atlas();
+        let x = |align| -> Vec<f32> {
+            corners(&atlas.build("AV", &layout(align, TextAnchor::Baseline)))
+                .iter()
+                .map(|c| c[0])
+                .collect()
+        };
+        // V starts at the kerned pen position, 10 - 2
+        assert_eq!(x(TextAlign::Left), [1., 8.]);
+        assert_eq!(x(TextAlign::Center), [-7.5, -0.5]);
+        assert_eq!(x(TextAlign::Right), [-16., -9.]);
+
+        // Each line is aligned by its own width
+        let lines = corners(&atlas.build("AV\nA", &layout(TextAlign::Right, TextAnchor::Baseline)));
+        assert_eq!(lines[2][0], -10. + 1.);
+    }
+
+    #[test]
+    fn anchor_places_the_block_vertically() {
+        let atlas = atlas();
+        let y = |text, anchor| -> Vec<f32> {
+            corners(&atlas.build(text, &layout(TextAlign::Left, anchor)))
+                .iter()
+                .map(|c| c[1])
+                .collect()
+        };
+        // Glyph tops sit 4 below the line top, the baseline 15
+        assert_eq!(y("A", TextAnchor::Top), [-4.]);
+        assert_eq!(y("A", TextAnchor::Baseline), [11.]);
+        assert_eq!(y("A", TextAnchor::Middle), [6.]);
+        assert_eq!(y("A", TextAnchor::Bottom), [16.]);
+        assert_eq!(y("A\nA", TextAnchor::Middle), [16., -4.]);
+        assert_eq!(y("A\nA", TextAnchor::Bottom), [36., 16.]);
+
+        let spaced = TextLayout {
+            line_spacing: 1.5,
+            ..layout(TextAlign::Left, TextAnchor::Top)
+        };
+        assert_eq!(corners(&atlas.build("A\nA", &spaced))[1][1], -34.);
+
+        // Scaled to output units, with texture coordinates of the atlas cell
+        let data = atlas.build("V", &TextLayout::default());
+        assert_eq!(&data.positions[..6], &[0., 11. / 16., 0., 0., 3. / 16., 0.]);
+        assert_eq!(data.tex_coords, [0.5, 0., 0.5, 1., 1., 1., 1., 0.]);
+        assert_eq!(data.indices, [0, 1, 2, 0, 2, 3]);
+    }
+
+    #[test]
+    fn text_past_max_glyphs_is_dropped() {
+        let atlas = atlas();
+        let unit = layout(TextAlign::Left, TextAnchor::Baseline);
+        // Spaces advance the pen without a quad
+        assert_eq!(atlas.build("A A", &unit).vertex_count(), 8);
+
+        let text = "AV A\n".repeat(MAX_GLYPHS / 3 + 10);
+        let data = atlas.build(&text, &unit);
+        assert_eq!(data.vertex_count(), MAX_GLYPHS * 4);
+        assert_eq!(data.indices.len(), MAX_GLYPHS * 6);
+        assert_eq!(data.indices.iter().copied().max(), Some((MAX_GLYPHS * 4 - 1) as u16));
+    }
+}
@@ -0,0 +1,75 @@
+use crate::context::Restorable;
+use crate::error::RenderError;
+use crate::webgl;
+use std::cell::RefCell;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{ImageBitmap, WebGlTexture};
+
+//--Pixels a texture was created from--
+pub enum TextureSource {
+    //  RGBA8, mipmapped when both sides are powers of two
+    Image2d { width: u32, height: u32, pixels: Vec<u8> },
+    //  RGBA8 faces per mip level in +X, -X, +Y, -Y, +Z, -Z order
+    Cube { size: u32, levels: Vec<[Vec<u8>; 6]> },
+    //  Decoded image, e.g. a PNG or JPEG of a glTF asset
+    Bitmap(ImageBitmap),
+}
+
+//--Texture that keeps its pixels to survive a context loss--
+pub struct Texture {
+    source: RefCell<TextureSource>,
+    texture: RefCell<WebGlTexture>,
+}
+
+impl Texture {
+    pub fn new(gl: &GL, source: TextureSource) -> Result<Self, RenderError> {
+        let texture = create(gl, &source)?;
+        Ok(Self {
+            source: RefCell::new(source),
+            texture: RefCell::new(texture),
+        })
+    }
+
+    //--2D texture from RGBA8 pixels--
+    pub fn image_2d(gl: &GL, width: u32, height: u32, pixels: Vec<u8>) -> Result<Self, RenderError> {
+        Self::new(gl, TextureSource::Image2d { width, height, pixels })
+    }
+
+    //--TEXTURE_2D or TEXTURE_CUBE_MAP--
+    pub fn target(&self) -> u32 {
+        match *self.source.borrow() {
+            TextureSource::Cube { .. } => GL::TEXTURE_CUBE_MAP,
+            _ => GL::TEXTURE_2D,
+        }
+    }
+
+    //--Replace the pixels in place, e.g. a placeholder once its image is decoded--
+    //  <note>
+    //      Materials sharing the texture pick up the new pixels on their next draw.
+    //      The source is kept even if the upload fails, so a restore uses it.
+    pub fn set_source(&self, gl: &GL, source: TextureSource) -> Result<(), RenderError> {
+        *self.source.borrow_mut() = source;
+        let texture = create(gl, &self.source.borrow())?;
+        gl.delete_texture(Some(&self.texture.replace(texture)));
+        Ok(())
+    }
+
+    pub fn bind(&self, gl: &GL) {
+        gl.bind_texture(self.target(), Some(&self.texture.borrow()));
+    }
+}
+
+impl Restorable for Texture {
+    fn restore(&self, gl: &GL) -> Result<(), RenderError> {
+        *self.texture.borrow_mut() = create(gl, &self.source.borrow())?;
+        Ok(())
+    }
+}
+
+fn create(gl: &GL, source: &TextureSource) -> Result<WebGlTexture, RenderError> {
+    match source {
+        TextureSource::Image2d { width, height, pixels } => webgl::create_texture(gl, *width, *height, pixels),
+        TextureSource::Cube { size, levels } => webgl::create_cube_texture(gl, *size, levels),
+        TextureSource::Bitmap(bitmap) => webgl::create_texture_from_bitmap(gl, bitmap),
+    }
+}
@@ -0,0 +1,50 @@
+//--Helpers for 3-component vectors--
+//  <note>
+//      Vectors are plain [f32; 3] so they can be passed straight to uniform3fv.
+
+pub fn add(a: &[f32; 3], b: &[f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+pub fn sub(a: &[f32; 3], b: &[f32; 3]) -> [f32; 3] {
+    [a[0] - b[0], a[1] - b[1], a[2] - b[2]]
+}
+
+pub fn scale(a: &[f32; 3], s: f32) -> [f32; 3] {
+    [a[0] * s, a[1] * s, a[2] * s]
+}
+
+pub fn dot(a: &[f32; 3], b: &[f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+pub fn cross(a: &[f32; 3], b: &[f32; 3]) -> [f32; 3] {
+    [
+        a[1] * b[2] - a[2] * b[1],
+        a[2] * b[0] - a[0] * b[2],
+        a[0] * b[1] - a[1] * b[0],
+    ]
+}
+
+pub fn length(a: &[f32; 3]) -> f32 {
+    dot(a, a).sqrt()
+}
+
+//--Normalize the vector--
+//  <note>
+//      A zero-length vector is returned unchanged.
+pub fn normalize(a: &[f32; 3]) -> [f32; 3] {
+    let l = length(a);
+    if l == 0. {
+        return *a;
+    }
+    scale(a, l.recip())
+}
+
+pub fn lerp(a: &[f32; 3], b: &[f32; 3], t: f32) -> [f32; 3] {
+    [
+        a[0] + (b[0] - a[0]) * t,
+        a[1] + (b[1] - a[1]) * t,
+        a[2] + (b[2] - a[2]) * t,
+    ]
+}
@@ -0,0 +1,1135 @@
+use crate::animation::Clip;
+use crate::camera::{Camera, CameraController, FirstPersonController, FlyController, OrbitController};
+use crate::canvas::{CanvasTarget, ResponsiveCanvas};
+use crate::capture::{self, Capture, FrameSequence};
+use crate::color::Rgba;
+use crate::context::{ContextEvent, ContextMonitor, ResourceTracker};
+use crate::error::{ErrorReporter, RenderError};
+use crate::frame_loop::FrameLoop;
+use crate::id_pass::IdPass;
+use crate::input::Input;
+use crate::loader::{self, EncodedImage, MeshData, Model};
+use crate::material::{Material, ShaderProgram, Uniform};
+use crate::mesh::Mesh;
+use crate::pbr::{Environment, PbrMaterial, PbrResources};
+use crate::quat;
+use crate::renderer::Renderer;
+use crate::scene::{IdPickHit, Light, LightKind, NodeId, PickHit, Renderable, Scene};
+use crate::shading::{self, ShadingMode};
+use crate::skin::{self, BoneStorage, Skin};
+use crate::text::{Font, Label, LabelMode, LabelStyle, TextAlign, TextAnchor};
+use crate::texture::{Texture, TextureSource};
+use crate::vec_3;
+use crate::webgl;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use web_sys::WebGlRenderingContext as GL;
+use web_sys::{Blob, BlobPropertyBag, ColorSpaceConversion, HtmlCanvasElement, ImageBitmap, ImageBitmapOptions, PremultiplyAlpha};
+
+//--Controller driving the viewer camera--
+enum CameraMode {
+    Orbit(OrbitController),
+    FirstPerson(FirstPersonController),
+    Fly(FlyController),
+}
+
+impl CameraMode {
+    //--Controller of a mode name placed at `eye`, looking at `target`--
+    //  <argument>
+    //      name : "orbit", "firstPerson" or "fly"
+    fn from_name(name: &str, eye: &[f32; 3], target: &[f32; 3]) -> Option<Self> {
+        let mut mode = match name {
+            "orbit" => CameraMode::Orbit(OrbitController::new(target, 10.)),
+            "firstPerson" => CameraMode::FirstPerson(FirstPersonController::new(eye)),
+            "fly" => CameraMode::Fly(FlyController::new(eye)),
+            _ => return None,
+        };
+        mode.look_at(eye, target);
+        Some(mode)
+    }
+
+    fn look_at(&mut self, eye: &[f32; 3], target: &[f32; 3]) {
+        match self {
+            CameraMode::Orbit(orbit) => {
+                orbit.look_at(eye, target);
+            }
+            CameraMode::FirstPerson(walk) => {
+                walk.position = *eye;
+                walk.look_at(target);
+            }
+            CameraMode::Fly(fly) => {
+                fly.position = *eye;
+                fly.look_at(target);
+            }
+        }
+    }
+
+    //--Point the camera looks at, the orbit target or 10 units ahead--
+    fn target(&self) -> [f32; 3] {
+        match self {
+            CameraMode::Orbit(orbit) => orbit.target,
+            _ => {
+                let forward = quat::rotate(&self.controller().rotation(), &[0., 0., -1.]);
+                vec_3::add(&self.controller().position(), &vec_3::scale(&forward, 10.))
+            }
+        }
+    }
+
+    //--Look around without holding a button, while the pointer is locked--
+    fn set_always_look(&mut self, always_look: bool) {
+        match self {
+            CameraMode::Orbit(_) => (),
+            CameraMode::FirstPerson(walk) => walk.always_look = always_look,
+            CameraMode::Fly(fly) => fly.always_look = always_look,
+        }
+    }
+
+    fn controller(&self) -> &dyn CameraController {
+        match self {
+            CameraMode::Orbit(orbit) => orbit,
+            CameraMode::FirstPerson(walk) => walk,
+            CameraMode::Fly(fly) => fly,
+        }
+    }
+
+    fn controller_mut(&mut self) -> &mut dyn CameraController {
+        match self {
+            CameraMode::Orbit(orbit) => orbit,
+            CameraMode::FirstPerson(walk) => walk,
+            CameraMode::Fly(fly) => fly,
+        }
+    }
+}
+
+struct ViewerState {
+    gl: GL,
+    canvas: HtmlCanvasElement,
+    resources: ResourceTracker,
+    scene: Scene,
+    renderer: Renderer,
+    id_pass: IdPass,
+    input: Input,
+    responsive: ResponsiveCanvas,
+    controller: CameraMode,
+    camera: NodeId,
+    light: NodeId,
+    meshes: HashMap<String, NodeId>,
+    //  Skins and clips of the skinned meshes, by mesh name
+    animations: HashMap<String, Animation>,
+    //  PBR program and image based lighting, built with the first model
+    pbr: Option<Rc<PbrResources>>,
+    //  Skinned programs, compiled with the first mesh using each bone storage
+    skinned_programs: HashMap<BoneStorage, Rc<ShaderProgram>>,
+    //  Bundled font, uploaded with the first label
+    font: Option<Rc<Font>>,
+    //  Program of the non-solid shading modes, compiled with the first one
+    shading_program: Option<Rc<ShaderProgram>>,
+    clear_color: [f32; 4],
+}
+
+impl ViewerState {
+    fn new(canvas: HtmlCanvasElement) -> Result<Self, RenderError> {
+        let gl = webgl::get_webgl_context(&canvas)?;
+        let mut resources = ResourceTracker::new();
+        let id_pass = IdPass::new(&gl, 0)?;
+        id_pass.track(&mut resources);
+
+        let mut scene = Scene::new();
+        let camera = scene.add_node("camera");
+        scene.node_mut(camera).unwrap().camera = Some(Camera::perspective(45f32.to_radians(), 0.1, 1000.));
+        let light = scene.add_node("light");
+        scene.set_rotation(light, &quat::from_rotation_arc(&[0., 0., 1.], &[-0.5, 0.5, 0.5]));
+        scene.node_mut(light).unwrap().light = Some(Light {
+            kind: LightKind::Directional,
+            color: [1., 1., 1.],
+            intensity: 1.,
+        });
+
+        Ok(Self {
+            input: Input::attach(&canvas)?,
+            responsive: ResponsiveCanvas::new(&canvas, 2.)?,
+            gl,
+            canvas,
+            resources,
+            scene,
+            renderer: Renderer::new(),
+            id_pass,
+            controller: CameraMode::Orbit(OrbitController::new(&[0., 0., 0.], 10.)),
+            camera,
+            light,
+            meshes: HashMap::new(),
+            animations: HashMap::new(),
+            pbr: None,
+            skinned_programs: HashMap::new(),
+            font: None,
+            shading_program: None,
+            clear_color: [0., 0., 0., 1.],
+        })
+    }
+
+    //--Add or replace a glTF model drawn with its PBR materials--
+    //  <return> Vec<(Rc<Texture>, EncodedImage)>  placeholder textures waiting for their image
+    //  <note>
+    //      The first part goes on the node named `name`, further parts on its children.
+    //      Textures stay white, or a flat normal, until decode_images fills them in.
+    fn add_model(&mut self, name: &str, model: Model) -> Result<Vec<(Rc<Texture>, EncodedImage)>, RenderError> {
+        let pbr = self.pbr()?;
+        let Model {
+            parts,
+            materials,
+            images,
+            textures,
+        } = model;
+        let mut pending = Vec::with_capacity(images.len());
+        for (index, image) in images.into_iter().enumerate() {
+            let normal_map = materials
+                .iter()
+                .filter_map(|m| m.normal_texture)
+                .any(|t| textures.get(t.index) == Some(&index));
+            let pixel = if normal_map { vec![128, 128, 255, 255] } else { vec![255, 255, 255, 255] };
+            let texture = Rc::new(Texture::image_2d(&self.gl, 1, 1, pixel)?);
+            self.resources.track(&texture);
+            pending.push((texture, image));
+        }
+        // Normal maps are ignored without tangents, generate the ones the file left out
+        let normal_mapped: Vec<bool> = materials.iter().map(|m| m.normal_texture.is_some()).collect();
+        let textures: Vec<Rc<Texture>> = textures
+            .iter()
+            .filter_map(|&image| pending.get(image).map(|(texture, _)| texture.clone()))
+            .collect();
+        let materials: Vec<Rc<Material>> = materials
+            .iter()
+            .map(|m| Rc::new(m.to_material(&pbr, &textures)))
+            .collect();
+        let default_material = Rc::new(PbrMaterial::default().to_material(&pbr, &textures));
+
+        let mut renderables = Vec::with_capacity(parts.len());
+        for mut part in parts.into_iter() {
+            let normal_mapped = part.material.and_then(|i| normal_mapped.get(i)) == Some(&true);
+            if normal_mapped && part.mesh.tangents.is_empty() && !part.mesh.tex_coords.is_empty() {
+                part.mesh.generate_tangents()?;
+            }
+            part.mesh.optimize();
+            renderables.push(Renderable {
+                mesh: self.upload(&part.mesh)?,
+                material: part
+                    .material
+                    .and_then(|i| materials.get(i))
+                    .unwrap_or(&default_material)
+                    .clone(),
+            });
+        }
+        self.insert_mesh(name, renderables)?;
+        Ok(pending)
+    }
+
+    //--PBR program and image based lighting, built with the first model--
+    fn pbr(&mut self) -> Result<Rc<PbrResources>, RenderError> {
+        if let Some(pbr) = &self.pbr {
+            return Ok(pbr.clone());
+        }
+        // Sun where the default light shines from
+        let environment = Environment::sky(32, &[-0.5, 0.5, 0.5]);
+        let pbr = Rc::new(PbrResources::new(&self.gl, &environment, 64)?);
+        pbr.track(&mut self.resources);
+        self.pbr = Some(pbr.clone());
+        Ok(pbr)
+    }
+
+    fn upload(&mut self, data: &MeshData) -> Result<Rc<Mesh>, RenderError> {
+        let mesh = Rc::new(Mesh::from_data(&self.gl, data)?);
+        self.resources.track(&mesh);
+        Ok(mesh)
+    }
+
+    //--Replace the mesh called `name` by a node with the first part and children with the rest--
+    fn insert_mesh(&mut self, name: &str, parts: Vec<Renderable>) -> Result<NodeId, RenderError> {
+        self.remove_mesh(name);
+        let root = self.scene.add_node(name);
+        self.meshes.insert(name.to_string(), root);
+        for (i, part) in parts.into_iter().enumerate() {
+            let node = if i == 0 {
+                root
+            } else {
+                let child = self.scene.add_node(&format!("{}/{}", name, i));
+                self.scene.set_parent(child, Some(root))?;
+                child
+            };
+            self.scene.node_mut(node).unwrap().renderable = Some(part);
+        }
+        Ok(root)
+    }
+
+    //--Add or replace a skinned mesh, posed in its rest pose until a clip plays--
+    //  <return> Vec<String>  names of the clips animating its joints
+    fn add_skinned_mesh(&mut self, name: &str, model: loader::SkinnedModel) -> Result<Vec<String>, RenderError> {
+        let storage = BoneStorage::detect(&self.gl, model.skeleton.len())?;
+        let program = match self.skinned_programs.get(&storage) {
+            Some(program) => program.clone(),
+            None => {
+                let program = Rc::new(skin::program(&self.gl, storage)?);
+                self.resources.track(&program);
+                self.skinned_programs.insert(storage, program.clone());
+                program
+            }
+        };
+        let skin = Rc::new(Skin::new(Rc::new(model.skeleton), storage)?);
+        self.resources.track(&skin);
+
+        let mut data = model.mesh;
+        data.optimize();
+        let mut material = Material::new(program);
+        material
+            .set("ambientColor", Uniform::Vec4(Rgba::rgb(0.1, 0.1, 0.1).linear()))
+            .set("baseColor", Uniform::Vec4(Rgba::WHITE.linear()));
+        let part = Renderable {
+            mesh: self.upload(&data)?,
+            material: Rc::new(material),
+        };
+        let node = self.insert_mesh(name, vec![part])?;
+        if let Some(node) = self.scene.node_mut(node) {
+            node.skin = Some(skin.clone());
+        }
+        let clips: Vec<String> = model.clips.iter().map(|c| c.name.clone()).collect();
+        self.animations.insert(
+            name.to_string(),
+            Animation {
+                skin,
+                clips: model.clips,
+                playing: None,
+                time: 0.,
+            },
+        );
+        Ok(clips)
+    }
+
+    //--Loop a clip on a skinned mesh from its start, None returns to the rest pose--
+    fn play_animation(&mut self, name: &str, clip: Option<&str>) -> Result<(), RenderError> {
+        let animation = self
+            .animations
+            .get_mut(name)
+            .ok_or_else(|| RenderError::Resource(format!("mesh '{}' isn't skinned", name)))?;
+        animation.playing = match clip {
+            Some(clip) => Some(
+                animation
+                    .clips
+                    .iter()
+                    .position(|c| c.name == clip)
+                    .ok_or_else(|| RenderError::Resource(format!("mesh '{}' has no clip '{}'", name, clip)))?,
+            ),
+            None => None,
+        };
+        animation.time = 0.;
+        match animation.playing {
+            Some(index) => animation.skin.play(&animation.clips[index], 0.),
+            None => animation.skin.set_pose(&animation.skin.skeleton().rest_pose()),
+        }
+        Ok(())
+    }
+
+    fn remove_mesh(&mut self, name: &str) -> bool {
+        self.animations.remove(name);
+        match self.meshes.remove(name) {
+            Some(node) => {
+                self.scene.remove_node(node);
+                true
+            }
+            None => false,
+        }
+    }
+
+    //--Change the material of every part of a mesh--
+    fn update_materials<F>(&mut self, name: &str, mut update: F) -> Result<(), RenderError>
+    where
+        F: FnMut(&mut Material) -> Result<(), RenderError>,
+    {
+        let mut stack = vec![self.mesh_node(name)?];
+        while let Some(id) = stack.pop() {
+            let node = match self.scene.node_mut(id) {
+                Some(node) => node,
+                None => continue,
+            };
+            stack.extend_from_slice(node.children());
+            if let Some(renderable) = node.renderable.as_mut() {
+                // Materials are shared through Rc, swap in a modified copy
+                let mut material = (*renderable.material).clone();
+                update(&mut material)?;
+                renderable.material = Rc::new(material);
+            }
+        }
+        Ok(())
+    }
+
+    //--Replace the base color of every part of a mesh--
+    fn set_mesh_color(&mut self, name: &str, color: [f32; 4]) -> Result<(), RenderError> {
+        self.update_materials(name, |material| {
+            let param = if material.get("baseColorFactor").is_some() {
+                "baseColorFactor"
+            } else {
+                "baseColor"
+            };
+            material.set(param, Uniform::Vec4(Rgba::from(color).linear()));
+            Ok(())
+        })
+    }
+
+    //--Show text above a mesh's origin, None removes it--
+    //  <argument>
+    //      align, anchor : layout of the text block, the label's current one without them
+    fn set_mesh_label(
+        &mut self,
+        name: &str,
+        text: Option<&str>,
+        align: Option<TextAlign>,
+        anchor: Option<TextAnchor>,
+    ) -> Result<(), RenderError> {
+        let node = self.mesh_node(name)?;
+        let existing = self.scene.node(node).and_then(|n| n.label.clone());
+        let restyled = |label: &Label| {
+            align.is_some_and(|a| a != label.style.align) || anchor.is_some_and(|a| a != label.style.anchor)
+        };
+        let label = match (text, existing) {
+            (None, _) => None,
+            (Some(text), Some(label)) if !restyled(&label) => {
+                if label.text() != text {
+                    label.set_text(&self.gl, text)?;
+                }
+                Some(label)
+            }
+            (Some(text), Some(label)) => {
+                // Layout is baked into the mesh and the style is shared, build a new label
+                let style = LabelStyle {
+                    align: align.unwrap_or(label.style.align),
+                    anchor: anchor.unwrap_or(label.style.anchor),
+                    ..label.style
+                };
+                let label = Rc::new(Label::new(&self.gl, &label.font, text, style)?);
+                self.resources.track(&label);
+                Some(label)
+            }
+            (Some(text), None) => {
+                let font = match &self.font {
+                    Some(font) => font.clone(),
+                    None => {
+                        let font = Rc::new(Font::builtin(&self.gl)?);
+                        font.track(&mut self.resources);
+                        self.font = Some(font.clone());
+                        font
+                    }
+                };
+                // Sized in CSS pixels, the label is drawn in drawing buffer pixels
+                let ratio = self.responsive.pixel_ratio().max(1.) as f32;
+                let style = LabelStyle {
+                    mode: LabelMode::Screen,
+                    size: 16. * ratio,
+                    offset: [0., 8. * ratio],
+                    depth_test: false,
+                    ..Default::default()
+                };
+                let style = LabelStyle {
+                    align: align.unwrap_or(style.align),
+                    anchor: anchor.unwrap_or(style.anchor),
+                    ..style
+                };
+                let label = Rc::new(Label::new(&self.gl, &font, text, style)?);
+                self.resources.track(&label);
+                Some(label)
+            }
+        };
+        if let Some(node) = self.scene.node_mut(node) {
+            node.label = label;
+        }
+        Ok(())
+    }
+
+    //--Mesh under a point in CSS pixels from the canvas' top left corner--
+    fn pick(&mut self, x: f32, y: f32) -> Result<Option<(String, PickHit)>, RenderError> {
+        let (width, height) = (self.canvas.client_width() as f32, self.canvas.client_height() as f32);
+        if width <= 0. || height <= 0. {
+            return Ok(None);
+        }
+        let hit = match self.scene.pick(self.camera, x, y, width, height)? {
+            Some(hit) => hit,
+            None => return Ok(None),
+        };
+        Ok(self.mesh_name(hit.node).map(|name| (name, hit)))
+    }
+
+    //--Like pick(), through the GPU ID pass--
+    fn pick_gpu(&mut self, x: f32, y: f32, radius: u32) -> Result<Option<(String, IdPickHit)>, RenderError> {
+        let (width, height) = (self.canvas.client_width() as f32, self.canvas.client_height() as f32);
+        if width <= 0. || height <= 0. {
+            return Ok(None);
+        }
+        self.id_pass.set_radius(radius);
+        let hit = self
+            .scene
+            .pick_gpu(&self.gl, &mut self.id_pass, self.camera, x, y, width, height);
+        self.renderer.invalidate_state();
+        Ok(hit?.and_then(|hit| self.mesh_name(hit.node).map(|name| (name, hit))))
+    }
+
+    //--Name of the mesh a node belongs to, parts of a model report the model--
+    fn mesh_name(&self, node: NodeId) -> Option<String> {
+        let mut current = Some(node);
+        while let Some(id) = current {
+            let node = self.scene.node(id)?;
+            // Mesh roots are named after their mesh, parts are named "mesh/index"
+            if self.meshes.get(&node.name) == Some(&id) {
+                return Some(node.name.clone());
+            }
+            current = node.parent();
+        }
+        None
+    }
+
+    fn mesh_node(&self, name: &str) -> Result<NodeId, RenderError> {
+        self.meshes
+            .get(name)
+            .copied()
+            .ok_or_else(|| RenderError::Resource(format!("mesh '{}' doesn't exist", name)))
+    }
+
+    fn set_shading(&mut self, mode: ShadingMode) -> Result<(), RenderError> {
+        if mode != ShadingMode::Solid && self.shading_program.is_none() {
+            let program = Rc::new(shading::program(&self.gl)?);
+            self.resources.track(&program);
+            self.renderer.set_shading_program(program.clone());
+            self.shading_program = Some(program);
+        }
+        self.renderer.set_shading(mode);
+        Ok(())
+    }
+
+    //--Rebuild GPU resources on a restored context--
+    fn restore(&mut self) -> Result<(), RenderError> {
+        self.resources.restore_all(&self.gl)?;
+        self.renderer.invalidate_state();
+        self.id_pass.invalidate();
+        Ok(())
+    }
+
+    fn frame(&mut self, delta: f32) -> Result<(), RenderError> {
+        self.update(delta);
+        self.draw(self.responsive.aspect())?;
+        self.gl.flush();
+        Ok(())
+    }
+
+    fn update(&mut self, delta: f32) {
+        self.responsive.update(&self.gl);
+        let events = self.input.poll();
+        self.controller.set_always_look(self.input.state().is_pointer_locked());
+        let controller = self.controller.controller_mut();
+        for event in events.iter() {
+            controller.handle_event(event);
+        }
+        controller.update(delta);
+        self.scene.set_translation(self.camera, &controller.position());
+        self.scene.set_rotation(self.camera, &controller.rotation());
+        for animation in self.animations.values_mut() {
+            if let Some(index) = animation.playing {
+                let clip = &animation.clips[index];
+                animation.time += delta;
+                if clip.duration > 0. {
+                    animation.time %= clip.duration;
+                }
+                animation.skin.play(clip, animation.time);
+            }
+        }
+    }
+
+    fn draw(&mut self, aspect: f32) -> Result<(), RenderError> {
+        self.renderer.clear(&self.gl, &self.clear_color);
+        self.scene.render(&self.gl, &mut self.renderer, self.camera, aspect)
+    }
+
+    //--Advance by `delta` and render the frame offscreen, at the drawing buffer size by default--
+    fn capture(&mut self, delta: f32, width: Option<u32>, height: Option<u32>) -> Result<Capture, RenderError> {
+        self.update(delta);
+        let gl = self.gl.clone();
+        let width = width.unwrap_or(gl.drawing_buffer_width() as u32);
+        let height = height.unwrap_or(gl.drawing_buffer_height() as u32);
+        capture::capture_frame(&gl, width, height, || self.draw(width as f32 / height.max(1) as f32))
+    }
+}
+
+//--Clip playback on a skinned mesh--
+struct Animation {
+    skin: Rc<Skin>,
+    clips: Vec<Clip>,
+    //  Index of the looping clip, None holds the current pose
+    playing: Option<usize>,
+    //  Seconds into the playing clip
+    time: f32,
+}
+
+//--Renderer handle for JavaScript--
+//  <note>
+//      Owns a canvas, a scene and an animation loop. Call dispose() (or free())
+//      when the canvas goes away, the loop keeps the viewer alive otherwise.
+#[wasm_bindgen]
+pub struct Viewer {
+    state: Rc<RefCell<ViewerState>>,
+    frame_loop: FrameLoop,
+    context: ContextMonitor,
+    errors: ErrorReporter,
+}
+
+#[wasm_bindgen]
+impl Viewer {
+    //--Create a viewer on a canvas--
+    //  <argument>
+    //      target : canvas element, element id or CSS selector
+    #[wasm_bindgen(constructor)]
+    pub fn new(target: JsValue) -> Result<Viewer, JsValue> {
+        let canvas = CanvasTarget::from_js(&target)?.resolve()?;
+        let state = Rc::new(RefCell::new(ViewerState::new(canvas.clone())?));
+
+        let errors = ErrorReporter::new();
+
+        let s = state.clone();
+        let reporter = errors.clone();
+        let frame_loop = FrameLoop::start(0., move |time| {
+            // Release the state before the error callback, which may call back into the viewer
+            let result = s.borrow_mut().frame(time.delta);
+            if let Err(e) = result {
+                reporter.report(&e);
+            }
+        });
+
+        //Suspend the loop while the context is lost, pause() and resume() still apply meanwhile
+        let context = ContextMonitor::attach(&canvas, &state.borrow().gl)?;
+        let s = state.clone();
+        let l = frame_loop.clone();
+        let reporter = errors.clone();
+        context.set_handler(move |event| match event {
+            ContextEvent::Lost => l.set_suspended(true),
+            ContextEvent::Restored => {
+                let result = s.borrow_mut().restore();
+                if let Err(e) = result {
+                    reporter.report(&e);
+                }
+                l.set_suspended(false);
+            }
+        });
+
+        Ok(Viewer {
+            state,
+            frame_loop,
+            context,
+            errors,
+        })
+    }
+
+    //--Add or replace a mesh from .glb bytes, drawn with its glTF materials--
+    //  <note>
+    //      Embedded textures are decoded in the background and show up a few
+    //      frames later, decoding errors go to onError.
+    #[wasm_bindgen(js_name = loadMesh)]
+    pub fn load_mesh(&self, name: &str, bytes: &[u8]) -> Result<(), JsValue> {
+        let model = loader::load_gltf_model(bytes)?;
+        let pending = self.state.borrow_mut().add_model(name, model)?;
+        decode_images(&self.state.borrow().gl, &self.errors, pending);
+        Ok(())
+    }
+
+    //--Add or replace a skinned mesh and its animation clips from .glb bytes--
+    //  <return> Array of string  clip names, for playAnimation()
+    //  <note>
+    //      The first mesh with a skin is loaded, in its rest pose.
+    #[wasm_bindgen(js_name = loadSkinnedMesh)]
+    pub fn load_skinned_mesh(&self, name: &str, bytes: &[u8]) -> Result<js_sys::Array, JsValue> {
+        let model = loader::load_gltf_skinned(bytes)?;
+        let clips = self.state.borrow_mut().add_skinned_mesh(name, model)?;
+        Ok(clips.iter().map(|c| JsValue::from_str(c)).collect())
+    }
+
+    //--Loop an animation clip on a skinned mesh, null returns it to its rest pose--
+    #[wasm_bindgen(js_name = playAnimation)]
+    pub fn play_animation(&self, name: &str, clip: Option<String>) -> Result<(), JsValue> {
+        self.state.borrow_mut().play_animation(name, clip.as_deref())?;
+        Ok(())
+    }
+
+    //--Joints of a skinned mesh and where its bone matrices live--
+    //  <return> { joints: [name, ...], boneStorage: "uniforms" or "texture" }
+    pub fn skeleton(&self, name: &str) -> Result<JsValue, JsValue> {
+        let state = self.state.borrow();
+        let skin = &state
+            .animations
+            .get(name)
+            .ok_or_else(|| RenderError::Resource(format!("mesh '{}' isn't skinned", name)))?
+            .skin;
+        let joints: js_sys::Array = skin.skeleton().joints.iter().map(|j| JsValue::from_str(&j.name)).collect();
+        let storage = match skin.storage() {
+            BoneStorage::Uniforms { .. } => "uniforms",
+            BoneStorage::Texture => "texture",
+        };
+        js_object(&[("joints", joints.into()), ("boneStorage", JsValue::from_str(storage))])
+    }
+
+    //--Fetch a .glb file and add it as a mesh--
+    //  <return> Promise  resolves once the mesh is in the scene
+    #[wasm_bindgen(js_name = loadMeshFromUrl)]
+    pub fn load_mesh_from_url(&self, name: String, url: String) -> js_sys::Promise {
+        let state = self.state.clone();
+        let errors = self.errors.clone();
+        wasm_bindgen_futures::future_to_promise(async move {
+            let window = web_sys::window().ok_or_else(|| RenderError::Context("window doesn't exist".into()))?;
+            let response: web_sys::Response = JsFuture::from(window.fetch_with_str(&url)).await?.dyn_into()?;
+            if !response.ok() {
+                let message = format!("failed to fetch '{}': {}", url, response.status());
+                return Err(RenderError::Resource(message).into());
+            }
+            let buffer = JsFuture::from(response.array_buffer()?).await?;
+            let model = loader::load_gltf_model(&js_sys::Uint8Array::new(&buffer).to_vec())?;
+            let pending = state.borrow_mut().add_model(&name, model)?;
+            decode_images(&state.borrow().gl, &errors, pending);
+            Ok(JsValue::UNDEFINED)
+        })
+    }
+
+    //--Remove a mesh--
+    //  <return> bool  false if no mesh had that name
+    #[wasm_bindgen(js_name = removeMesh)]
+    pub fn remove_mesh(&self, name: &str) -> bool {
+        self.state.borrow_mut().remove_mesh(name)
+    }
+
+    //--Tint a mesh, sRGB components in 0..1 as in CSS--
+    #[wasm_bindgen(js_name = setMeshColor)]
+    pub fn set_mesh_color(&self, name: &str, r: f32, g: f32, b: f32, a: f32) -> Result<(), JsValue> {
+        self.state.borrow_mut().set_mesh_color(name, [r, g, b, a])?;
+        Ok(())
+    }
+
+    //--Tint a mesh with a CSS color such as "#ff8800" or "hsl(200, 80%, 50%)"--
+    #[wasm_bindgen(js_name = setMeshColorCss)]
+    pub fn set_mesh_color_css(&self, name: &str, css: &str) -> Result<(), JsValue> {
+        let color = Rgba::parse_css(css)?;
+        self.state.borrow_mut().set_mesh_color(name, color.to_array())?;
+        Ok(())
+    }
+
+    //--Show a text label above a mesh, null or undefined removes it--
+    //  <argument>
+    //      align  : "left", "center" or "right" of the text around the mesh's origin
+    //      anchor : "top", "middle", "baseline" or "bottom" of the text block on it,
+    //               both keep the current layout when left out, centered above by default
+    #[wasm_bindgen(js_name = setMeshLabel)]
+    pub fn set_mesh_label(&self, name: &str, text: Option<String>, align: Option<String>, anchor: Option<String>) -> Result<(), JsValue> {
+        let align = align
+            .map(|name| TextAlign::from_name(&name).ok_or_else(|| RenderError::Resource(format!("unknown text align '{}'", name))))
+            .transpose()?;
+        let anchor = anchor
+            .map(|name| TextAnchor::from_name(&name).ok_or_else(|| RenderError::Resource(format!("unknown text anchor '{}'", name))))
+            .transpose()?;
+        self.state.borrow_mut().set_mesh_label(name, text.as_deref(), align, anchor)?;
+        Ok(())
+    }
+
+    //--Move a mesh, its parts move with it--
+    #[wasm_bindgen(js_name = setMeshPosition)]
+    pub fn set_mesh_position(&self, name: &str, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        let mut state = self.state.borrow_mut();
+        let node = state.mesh_node(name)?;
+        state.scene.set_translation(node, &[x, y, z]);
+        Ok(())
+    }
+
+    //--Orient a mesh with a unit quaternion, normalized if it isn't--
+    #[wasm_bindgen(js_name = setMeshRotation)]
+    pub fn set_mesh_rotation(&self, name: &str, x: f32, y: f32, z: f32, w: f32) -> Result<(), JsValue> {
+        let mut state = self.state.borrow_mut();
+        let node = state.mesh_node(name)?;
+        state.scene.set_rotation(node, &[x, y, z, w]);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = setMeshScale)]
+    pub fn set_mesh_scale(&self, name: &str, x: f32, y: f32, z: f32) -> Result<(), JsValue> {
+        let mut state = self.state.borrow_mut();
+        let node = state.mesh_node(name)?;
+        state.scene.set_scale(node, &[x, y, z]);
+        Ok(())
+    }
+
+    //--Local transform of a mesh--
+    //  <return> { position: [x, y, z], rotation: [x, y, z, w], scale: [x, y, z] }
+    #[wasm_bindgen(js_name = meshTransform)]
+    pub fn mesh_transform(&self, name: &str) -> Result<JsValue, JsValue> {
+        let state = self.state.borrow();
+        let node = state.mesh_node(name)?;
+        let node = state
+            .scene
+            .node(node)
+            .ok_or_else(|| RenderError::Resource(format!("mesh '{}' doesn't exist", name)))?;
+        js_object(&[
+            ("position", js_array(&node.translation())),
+            ("rotation", js_array(&node.rotation())),
+            ("scale", js_array(&node.scale())),
+        ])
+    }
+
+    //--Place the camera at an eye position looking at a target--
+    #[wasm_bindgen(js_name = setCamera)]
+    pub fn set_camera(&self, eye_x: f32, eye_y: f32, eye_z: f32, target_x: f32, target_y: f32, target_z: f32) {
+        self.state
+            .borrow_mut()
+            .controller
+            .look_at(&[eye_x, eye_y, eye_z], &[target_x, target_y, target_z]);
+    }
+
+    //--Switch the camera controller, keeping the current view--
+    //  <argument>
+    //      mode : "orbit" (drag to rotate, wheel to zoom), "firstPerson" (WASD walks
+    //             on the ground plane, drag to look) or "fly" (WASD moves along the
+    //             view, Q and E down and up, Shift speeds up)
+    #[wasm_bindgen(js_name = setCameraMode)]
+    pub fn set_camera_mode(&self, mode: &str) -> Result<(), JsValue> {
+        let mut state = self.state.borrow_mut();
+        let eye = state.controller.controller().position();
+        let target = state.controller.target();
+        state.controller = CameraMode::from_name(mode, &eye, &target)
+            .ok_or_else(|| RenderError::Resource(format!("unknown camera mode '{}'", mode)))?;
+        Ok(())
+    }
+
+    //--Lock the pointer to the canvas, first-person and fly cameras then look without dragging--
+    //  <note>
+    //      Browsers only grant the lock from a user gesture, call it from a click handler.
+    //      Escape releases the lock.
+    #[wasm_bindgen(js_name = requestPointerLock)]
+    pub fn request_pointer_lock(&self) {
+        self.state.borrow().input.request_pointer_lock();
+    }
+
+    #[wasm_bindgen(js_name = exitPointerLock)]
+    pub fn exit_pointer_lock(&self) {
+        self.state.borrow().input.exit_pointer_lock();
+    }
+
+    //--Use a perspective projection--
+    //  <argument>
+    //      fovy : vertical field of view in radians
+    #[wasm_bindgen(js_name = setPerspective)]
+    pub fn set_perspective(&self, fovy: f32, near: f32, far: f32) {
+        let mut state = self.state.borrow_mut();
+        let camera = state.camera;
+        if let Some(node) = state.scene.node_mut(camera) {
+            node.camera = Some(Camera::perspective(fovy, near, far));
+        }
+    }
+
+    //--Use an orthographic projection, e.g. for technical views without foreshortening--
+    //  <argument>
+    //      height : visible extent along Y in world units, the width follows the canvas
+    #[wasm_bindgen(js_name = setOrthographic)]
+    pub fn set_orthographic(&self, height: f32, near: f32, far: f32) {
+        let mut state = self.state.borrow_mut();
+        let camera = state.camera;
+        if let Some(node) = state.scene.node_mut(camera) {
+            node.camera = Some(Camera::orthographic(height, near, far));
+        }
+    }
+
+    //--Set the directional light--
+    //  <argument>
+    //      x, y, z   : direction towards the light
+    //      r, g, b   : color in 0..1
+    //      intensity : multiplier for the color
+    #[wasm_bindgen(js_name = setLight)]
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_light(&self, x: f32, y: f32, z: f32, r: f32, g: f32, b: f32, intensity: f32) {
+        let mut state = self.state.borrow_mut();
+        let light = state.light;
+        state
+            .scene
+            .set_rotation(light, &quat::from_rotation_arc(&[0., 0., 1.], &[x, y, z]));
+        if let Some(node) = state.scene.node_mut(light) {
+            node.light = Some(Light {
+                kind: LightKind::Directional,
+                color: [r, g, b],
+                intensity,
+            });
+        }
+    }
+
+    #[wasm_bindgen(js_name = setClearColor)]
+    pub fn set_clear_color(&self, r: f32, g: f32, b: f32, a: f32) {
+        self.state.borrow_mut().clear_color = [r, g, b, a];
+    }
+
+    //--Stop the animation loop, the last frame stays on screen--
+    pub fn pause(&self) {
+        self.frame_loop.pause();
+    }
+
+    pub fn resume(&self) {
+        self.frame_loop.resume();
+    }
+
+    //--Advance a paused viewer by a single frame--
+    pub fn step(&self) {
+        self.frame_loop.step();
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn paused(&self) -> bool {
+        self.frame_loop.is_paused()
+    }
+
+    //--Cap the device pixel ratio the drawing buffer follows, 2 by default--
+    //  <note>
+    //      Lower it to trade sharpness for fill rate on high density screens.
+    #[wasm_bindgen(js_name = setMaxPixelRatio)]
+    pub fn set_max_pixel_ratio(&self, max_pixel_ratio: f64) {
+        self.state.borrow_mut().responsive.set_max_pixel_ratio(max_pixel_ratio);
+    }
+
+    //--Drawing buffer width in device pixels--
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.state.borrow().responsive.width()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.state.borrow().responsive.height()
+    }
+
+    //--Meshes drawn in the last frame--
+    #[wasm_bindgen(getter, js_name = drawnCount)]
+    pub fn drawn_count(&self) -> usize {
+        self.state.borrow().renderer.stats().drawn
+    }
+
+    //--Meshes skipped in the last frame for being outside the view--
+    #[wasm_bindgen(getter, js_name = culledCount)]
+    pub fn culled_count(&self) -> usize {
+        self.state.borrow().renderer.stats().culled
+    }
+
+    //--Skip meshes outside the view, on by default--
+    #[wasm_bindgen(js_name = setFrustumCulling)]
+    pub fn set_frustum_culling(&self, enabled: bool) {
+        self.state.borrow_mut().renderer.set_frustum_culling(enabled);
+    }
+
+    //--Switch how meshes are shaded, for inspecting geometry--
+    //  <argument>
+    //      mode : "solid", "wireframe", "normals", "uv", "depth" or "vertexColor"
+    #[wasm_bindgen(js_name = setShading)]
+    pub fn set_shading(&self, mode: &str) -> Result<(), JsValue> {
+        let mode = ShadingMode::from_name(mode)
+            .ok_or_else(|| RenderError::Resource(format!("unknown shading mode '{}'", mode)))?;
+        self.state.borrow_mut().set_shading(mode)?;
+        Ok(())
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn shading(&self) -> String {
+        self.state.borrow().renderer.shading().name().to_string()
+    }
+
+    //--Mesh under a point of the canvas--
+    //  <argument>
+    //      x, y : CSS pixels from the canvas' top left corner, e.g. offsetX and offsetY of a pointer event
+    //  <return> null, or { name, triangle, barycentric: [u, v, w], position: [x, y, z], distance }
+    pub fn pick(&self, x: f32, y: f32) -> Result<JsValue, JsValue> {
+        let (name, hit) = match self.state.borrow_mut().pick(x, y)? {
+            Some(found) => found,
+            None => return Ok(JsValue::NULL),
+        };
+        js_object(&[
+            ("name", JsValue::from_str(&name)),
+            ("triangle", JsValue::from_f64(hit.triangle as f64)),
+            ("barycentric", js_array(&hit.barycentric)),
+            ("position", js_array(&hit.position)),
+            ("distance", JsValue::from_f64(hit.distance as f64)),
+        ])
+    }
+
+    //--Mesh under a point of the canvas, found by drawing IDs on the GPU--
+    //  <argument>
+    //      x, y   : CSS pixels from the canvas' top left corner
+    //      radius : CSS pixels around the point that count, the closest mesh pixel wins
+    //  <return> null, or { name, position: [x, y, z] } with the position read from the depth buffer
+    //  <note>
+    //      Faster than pick() on dense meshes and forgiving with a radius, less precise in depth.
+    #[wasm_bindgen(js_name = pickGpu)]
+    pub fn pick_gpu(&self, x: f32, y: f32, radius: u32) -> Result<JsValue, JsValue> {
+        if self.context.is_lost() {
+            return Err(RenderError::Context("WebGL context is lost".into()).into());
+        }
+        let (name, hit) = match self.state.borrow_mut().pick_gpu(x, y, radius)? {
+            Some(found) => found,
+            None => return Ok(JsValue::NULL),
+        };
+        js_object(&[("name", JsValue::from_str(&name)), ("position", js_array(&hit.position))])
+    }
+
+    //--Receive errors raised while rendering frames--
+    //  <argument>
+    //      callback : called with a RenderError (an Error with a kind property),
+    //                 null restores logging to the console
+    #[wasm_bindgen(js_name = onError)]
+    pub fn on_error(&self, callback: Option<js_sys::Function>) {
+        match callback {
+            Some(callback) => self.errors.set_callback(move |e: &RenderError| {
+                let _ = callback.call1(&JsValue::NULL, &e.clone().into());
+            }),
+            None => self.errors.clear_callback(),
+        }
+    }
+
+    #[wasm_bindgen(getter, js_name = contextLost)]
+    pub fn context_lost(&self) -> bool {
+        self.context.is_lost()
+    }
+
+    //--Lose the WebGL context through WEBGL_lose_context, for testing--
+    #[wasm_bindgen(js_name = loseContext)]
+    pub fn lose_context(&self) -> Result<(), JsValue> {
+        self.context.simulate_loss()?;
+        Ok(())
+    }
+
+    //--Restore a context lost through loseContext--
+    #[wasm_bindgen(js_name = restoreContext)]
+    pub fn restore_context(&self) -> Result<(), JsValue> {
+        self.context.simulate_restore()?;
+        Ok(())
+    }
+
+    //--Render a frame offscreen and encode it as PNG--
+    //  <argument>
+    //      width, height : pixels, the drawing buffer size when omitted
+    //  <return> Uint8Array  PNG file contents
+    #[wasm_bindgen(js_name = captureFrame)]
+    pub fn capture_frame(&self, width: Option<u32>, height: Option<u32>) -> Result<js_sys::Uint8Array, JsValue> {
+        if self.context.is_lost() {
+            return Err(RenderError::Context("WebGL context is lost".into()).into());
+        }
+        let png = self.state.borrow_mut().capture(0., width, height)?.to_png()?;
+        Ok(js_sys::Uint8Array::from(&png[..]))
+    }
+
+    //--Like captureFrame(), saved through the browser's download prompt--
+    #[wasm_bindgen(js_name = downloadFrame)]
+    pub fn download_frame(&self, filename: Option<String>) -> Result<(), JsValue> {
+        let png = self.capture_frame(None, None)?.to_vec();
+        capture::download(&png, "image/png", filename.as_deref().unwrap_or("frame.png"))?;
+        Ok(())
+    }
+
+    //--Capture consecutive frames at a fixed virtual time step--
+    //  <argument>
+    //      frames    : number of frames
+    //      step      : seconds between frames, e.g. 1 / 30
+    //      turntable : orbit the camera one full turn over the sequence, in orbit mode
+    //  <return> Array of Uint8Array  one PNG per frame
+    //  <note>
+    //      Runs synchronously, so frames are evenly spaced whatever they cost to render.
+    #[wasm_bindgen(js_name = captureSequence)]
+    pub fn capture_sequence(&self, frames: u32, step: f64, turntable: bool) -> Result<js_sys::Array, JsValue> {
+        if self.context.is_lost() {
+            return Err(RenderError::Context("WebGL context is lost".into()).into());
+        }
+        let mut state = self.state.borrow_mut();
+        let mut sequence = FrameSequence::new(frames, step);
+        let images = js_sys::Array::new();
+        while let Some(time) = sequence.next_frame() {
+            if turntable && time.frame > 1 {
+                if let CameraMode::Orbit(orbit) = &mut state.controller {
+                    orbit.rotate(std::f32::consts::PI * 2. / frames as f32, 0.);
+                }
+            }
+            let png = state.capture(time.delta, None, None)?.to_png()?;
+            images.push(&js_sys::Uint8Array::from(&png[..]));
+        }
+        Ok(images)
+    }
+
+    //--Render a frame and return it as a PNG data URL--
+    //  <note>
+    //      The frame is drawn and read back in the same task, so this works
+    //      without preserveDrawingBuffer and while paused. Nothing is advanced
+    //      and input stays queued for the next frame of the loop.
+    pub fn screenshot(&self) -> Result<String, JsValue> {
+        if self.context.is_lost() {
+            return Err(RenderError::Context("WebGL context is lost".into()).into());
+        }
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.responsive.update(&state.gl);
+        let aspect = state.responsive.aspect();
+        state.draw(aspect)?;
+        state.gl.flush();
+        state.canvas.to_data_url()
+    }
+
+    //--Stop the loop and release the scene, the viewer can't be used afterwards--
+    pub fn dispose(self) {}
+}
+
+impl Drop for Viewer {
+    fn drop(&mut self) {
+        // Frees the frame callback, and with it the state and input listeners
+        self.frame_loop.cancel();
+    }
+}
+
+//--Decode embedded images in the background and swap them into their textures--
+//  <note>
+//      Failures go to the error reporter, the texture keeps its placeholder.
+fn decode_images(gl: &GL, errors: &ErrorReporter, pending: Vec<(Rc<Texture>, EncodedImage)>) {
+    for (texture, image) in pending {
+        let (gl, errors) = (gl.clone(), errors.clone());
+        wasm_bindgen_futures::spawn_local(async move {
+            let decoded = decode_image(&image).await;
+            errors.check(decoded.and_then(|bitmap| texture.set_source(&gl, TextureSource::Bitmap(bitmap))));
+        });
+    }
+}
+
+//--PNG or JPEG bytes to an ImageBitmap, without premultiplying or color conversion--
+async fn decode_image(image: &EncodedImage) -> Result<ImageBitmap, RenderError> {
+    let fail = |what: &str| RenderError::Resource(format!("failed to decode {} image: {}", image.mime_type, what));
+    let window = web_sys::window().ok_or_else(|| RenderError::Context("window doesn't exist".into()))?;
+    let parts = js_sys::Array::of1(&js_sys::Uint8Array::from(&image.bytes[..]));
+    let blob_options = BlobPropertyBag::new();
+    blob_options.set_type(&image.mime_type);
+    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &blob_options).map_err(|_| fail("blob"))?;
+
+    // Textures hold raw data such as normals and roughness, keep the bytes as stored
+    let options = ImageBitmapOptions::new();
+    options.set_premultiply_alpha(PremultiplyAlpha::None);
+    options.set_color_space_conversion(ColorSpaceConversion::None);
+    let promise = window
+        .create_image_bitmap_with_blob_and_image_bitmap_options(&blob, &options)
+        .map_err(|_| fail("createImageBitmap"))?;
+    let bitmap = JsFuture::from(promise).await.map_err(|_| fail("unsupported data"))?;
+    bitmap.dyn_into::<ImageBitmap>().map_err(|_| fail("not an image"))
+}
+
+fn js_array(values: &[f32]) -> JsValue {
+    values
+        .iter()
+        .map(|&v| JsValue::from_f64(v as f64))
+        .collect::<js_sys::Array>()
+        .into()
+}
+
+fn js_object(fields: &[(&str, JsValue)]) -> Result<JsValue, JsValue> {
+    let object = js_sys::Object::new();
+    for (key, value) in fields.iter() {
+        js_sys::Reflect::set(&object, &JsValue::from_str(key), value)?;
+    }
+    Ok(object.into())
+}
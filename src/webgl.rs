@@ -1,50 +1,80 @@
-use wasm_bindgen::JsCast;
+use crate::error::RenderError;
+use wasm_bindgen::{JsCast, JsValue};
 use web_sys::WebGlRenderingContext as GL;
 use web_sys::*;
 
-pub fn get_webgl_context(height: u32, width: u32) -> Result<WebGlRenderingContext, String> {
-    //Get WebGLContext
-    let document = window().unwrap().document().unwrap();
-    let canvas = document
-        .get_element_by_id("canvas")
-        .ok_or_else(|| String::from("canvas doesn't exist :("))?;
-    let canvas: web_sys::HtmlCanvasElement =
-        canvas.dyn_into::<web_sys::HtmlCanvasElement>().unwrap();
-
-    canvas.set_height(height);
-    canvas.set_width(width);
-
-    let gl: WebGlRenderingContext = canvas
-        .get_context("webgl")
-        .unwrap()
-        .ok_or_else(|| String::from("webgl is not supported in this browser :("))?
-        .dyn_into()
-        .unwrap();
+//--Create a WebGL context on a canvas--
+//  <argument>
+//      canvas &HtmlCanvasElement : target, see canvas::CanvasTarget for lookups
+//  <note>
+//      WebGL2 is preferred and handed out as a WebGlRenderingContext: it runs
+//      the same GLSL ES 1.0 shaders, and the extensions they use degrade
+//      through #ifdef where WebGL2 folds them into GLSL ES 3.0 instead.
+//      Code that needs WebGL2 features reaches them through webgl2().
+pub fn get_webgl_context(canvas: &HtmlCanvasElement) -> Result<WebGlRenderingContext, RenderError> {
+    //Request a stencil buffer, WebGL leaves it out by default
+    let attributes = WebGlContextAttributes::new();
+    attributes.set_stencil(true);
+
+    //Get WebGL2Context, its WebGL1 methods are called by name so it can stand in for a WebGLContext
+    let context = |kind: &str| {
+        canvas
+            .get_context_with_context_options(kind, &attributes)
+            .map_err(|_| RenderError::Context("canvas already has a different context".into()))
+    };
+    let gl: WebGlRenderingContext = match context("webgl2")?.and_then(|c| c.dyn_into::<WebGl2RenderingContext>().ok()) {
+        Some(gl2) => gl2.unchecked_into(),
+        None => context("webgl")?
+            .ok_or_else(|| RenderError::Context("webgl is not supported in this browser".into()))?
+            .dyn_into()
+            .map_err(|_| RenderError::Context("context is not a WebGlRenderingContext".into()))?,
+    };
 
     //Initialize WebGLContext
-    gl.enable(GL::BLEND);
-    gl.blend_func(GL::SRC_ALPHA, GL::ONE_MINUS_SRC_ALPHA);
     gl.clear_color(0.0, 0.0, 0.0, 1.0); //RGBA
     gl.clear_depth(1.);
+    gl.clear_stencil(0);
 
     Ok(gl)
 }
 
+//--The context as WebGL2, None on WebGL1--
+pub fn webgl2(gl: &GL) -> Option<&WebGl2RenderingContext> {
+    gl.dyn_ref::<WebGl2RenderingContext>()
+}
+
 pub fn link_program(
     gl: &WebGlRenderingContext,
     vert_source: &str,
     frag_source: &str,
-) -> Result<WebGlProgram, String> {
+) -> Result<WebGlProgram, RenderError> {
+    link_feedback_program(gl, vert_source, frag_source, &[])
+}
+
+//--Link a program whose vertex shader outputs are captured by transform feedback--
+//  <argument>
+//      varyings &[&str] : outputs written interleaved in this order, WebGL2 only
+pub fn link_feedback_program(
+    gl: &WebGlRenderingContext,
+    vert_source: &str,
+    frag_source: &str,
+    varyings: &[&str],
+) -> Result<WebGlProgram, RenderError> {
     let program = gl
         .create_program()
-        .ok_or_else(|| String::from("Error creating program"))?;
+        .ok_or_else(|| RenderError::Shader("failed to create program".into()))?;
 
-    let vert_shader = compile_shader(&gl, GL::VERTEX_SHADER, vert_source).unwrap();
+    let vert_shader = compile_shader(gl, GL::VERTEX_SHADER, vert_source)?;
 
-    let frag_shader = compile_shader(&gl, GL::FRAGMENT_SHADER, frag_source).unwrap();
+    let frag_shader = compile_shader(gl, GL::FRAGMENT_SHADER, frag_source)?;
 
     gl.attach_shader(&program, &vert_shader);
     gl.attach_shader(&program, &frag_shader);
+    if !varyings.is_empty() {
+        let gl2 = webgl2(gl).ok_or_else(|| RenderError::Shader("transform feedback needs WebGL2".into()))?;
+        let names: js_sys::Array = varyings.iter().map(|&name| JsValue::from_str(name)).collect();
+        gl2.transform_feedback_varyings(&program, &names, WebGl2RenderingContext::INTERLEAVED_ATTRIBS);
+    }
     gl.link_program(&program);
 
     if gl
@@ -55,9 +85,10 @@ pub fn link_program(
         gl.use_program(Some(&program));
         Ok(program)
     } else {
-        Err(gl
-            .get_program_info_log(&program)
-            .unwrap_or_else(|| String::from("Unknown error creating program object")))
+        Err(RenderError::Shader(
+            gl.get_program_info_log(&program)
+                .unwrap_or_else(|| String::from("Unknown error creating program object")),
+        ))
     }
 }
 
@@ -65,10 +96,10 @@ fn compile_shader(
     gl: &WebGlRenderingContext,
     shader_type: u32,
     source: &str,
-) -> Result<WebGlShader, String> {
+) -> Result<WebGlShader, RenderError> {
     let shader = gl
         .create_shader(shader_type)
-        .ok_or_else(|| String::from("Error creating shader"))?;
+        .ok_or_else(|| RenderError::Shader("failed to create shader".into()))?;
     gl.shader_source(&shader, source);
     gl.compile_shader(&shader);
 
@@ -79,15 +110,17 @@ fn compile_shader(
     {
         Ok(shader)
     } else {
-        Err(gl
+        let stage = if shader_type == GL::VERTEX_SHADER { "vertex" } else { "fragment" };
+        let log = gl
             .get_shader_info_log(&shader)
-            .unwrap_or_else(|| String::from("Unable to get shader info log")))
+            .unwrap_or_else(|| String::from("Unable to get shader info log"));
+        Err(RenderError::Shader(format!("{} shader: {}", stage, log)))
     }
 }
 
 #[allow(dead_code)]
-pub fn create_vbo_array(gl: &GL, data: &[f32]) -> Result<WebGlBuffer, String> {
-    let vbo = gl.create_buffer().ok_or("Failed to create buffer :(")?;
+pub fn create_vbo_array(gl: &GL, data: &[f32]) -> Result<WebGlBuffer, RenderError> {
+    let vbo = gl.create_buffer().ok_or_else(|| RenderError::Buffer("failed to create buffer".into()))?;
     gl.bind_buffer(GL::ARRAY_BUFFER, Some(&vbo));
     unsafe {
         let f32_array = js_sys::Float32Array::view(data);
@@ -98,11 +131,11 @@ pub fn create_vbo_array(gl: &GL, data: &[f32]) -> Result<WebGlBuffer, String> {
     Ok(vbo)
 }
 
-pub fn create_vbo_vector(gl: &GL, data: &Vec<f32>) -> Result<WebGlBuffer, String> {
-    let vbo = gl.create_buffer().ok_or("Failed to create buffer :(")?;
+pub fn create_vbo_vector(gl: &GL, data: &[f32]) -> Result<WebGlBuffer, RenderError> {
+    let vbo = gl.create_buffer().ok_or_else(|| RenderError::Buffer("failed to create buffer".into()))?;
     gl.bind_buffer(GL::ARRAY_BUFFER, Some(&vbo));
     unsafe {
-        let f32_array = js_sys::Float32Array::view(&(*data));
+        let f32_array = js_sys::Float32Array::view(data);
         gl.buffer_data_with_array_buffer_view(GL::ARRAY_BUFFER, &f32_array, GL::STATIC_DRAW)
     }
     gl.bind_buffer(GL::ARRAY_BUFFER, None);
@@ -111,8 +144,8 @@ pub fn create_vbo_vector(gl: &GL, data: &Vec<f32>) -> Result<WebGlBuffer, String
 }
 
 #[allow(dead_code)]
-pub fn create_ibo_array(gl: &GL, data: &[u16]) -> Result<WebGlBuffer, String> {
-    let ibo = gl.create_buffer().ok_or("Failed to create buffer :(")?;
+pub fn create_ibo_array(gl: &GL, data: &[u16]) -> Result<WebGlBuffer, RenderError> {
+    let ibo = gl.create_buffer().ok_or_else(|| RenderError::Buffer("failed to create buffer".into()))?;
 
     gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&ibo));
     unsafe {
@@ -128,12 +161,12 @@ pub fn create_ibo_array(gl: &GL, data: &[u16]) -> Result<WebGlBuffer, String> {
     Ok(ibo)
 }
 
-pub fn create_ibo_vector(gl: &GL, data: &Vec<u16>) -> Result<WebGlBuffer, String> {
-    let ibo = gl.create_buffer().ok_or("Failed to create buffer")?;
+pub fn create_ibo_vector(gl: &GL, data: &[u16]) -> Result<WebGlBuffer, RenderError> {
+    let ibo = gl.create_buffer().ok_or_else(|| RenderError::Buffer("failed to create buffer".into()))?;
 
     gl.bind_buffer(GL::ELEMENT_ARRAY_BUFFER, Some(&ibo));
     unsafe {
-        let ui16_array = js_sys::Uint16Array::view(&(*data));
+        let ui16_array = js_sys::Uint16Array::view(data);
         gl.buffer_data_with_array_buffer_view(
             GL::ELEMENT_ARRAY_BUFFER,
             &ui16_array,
@@ -145,11 +178,155 @@ pub fn create_ibo_vector(gl: &GL, data: &Vec<u16>) -> Result<WebGlBuffer, String
     Ok(ibo)
 }
 
-pub fn set_attribute(gl: &GL, vbo: &[WebGlBuffer], att_location: &[u32], att_stride: &[i32]) {
-    for i in 0..vbo.len() {
-        gl.bind_buffer(GL::ARRAY_BUFFER, Some(&vbo[i]));
-        gl.enable_vertex_attrib_array(att_location[i]);
-        gl.vertex_attrib_pointer_with_i32(att_location[i], att_stride[i], GL::FLOAT, false, 0, 0);
+
+//--Create a 2D RGBA texture from 8-bit pixels--
+//  <argument>
+//      width  u32    texture width
+//      height u32    texture height
+//      data   &[u8]  tightly packed RGBA rows, bottom row first
+//  <note>
+//      Power-of-two textures get mipmaps and REPEAT wrapping,
+//      others fall back to CLAMP_TO_EDGE as WebGL1 requires.
+pub fn create_texture(gl: &GL, width: u32, height: u32, data: &[u8]) -> Result<WebGlTexture, RenderError> {
+    let texture = gl.create_texture().ok_or_else(|| RenderError::Resource("failed to create texture".into()))?;
+    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        GL::TEXTURE_2D,
+        0,
+        GL::RGBA as i32,
+        width as i32,
+        height as i32,
+        0,
+        GL::RGBA,
+        GL::UNSIGNED_BYTE,
+        Some(data),
+    )
+    .map_err(|_| RenderError::Resource("failed to upload texture".into()))?;
+    set_sampling_2d(gl, width, height);
+    Ok(texture)
+}
+
+//--Create a 2D texture from a decoded image--
+//  <note>
+//      Rows are uploaded top first, as glTF texture coordinates expect.
+//      Same mipmap and wrap rules as create_texture.
+pub fn create_texture_from_bitmap(gl: &GL, bitmap: &ImageBitmap) -> Result<WebGlTexture, RenderError> {
+    let texture = gl.create_texture().ok_or_else(|| RenderError::Resource("failed to create texture".into()))?;
+    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+    gl.tex_image_2d_with_u32_and_u32_and_image_bitmap(GL::TEXTURE_2D, 0, GL::RGBA as i32, GL::RGBA, GL::UNSIGNED_BYTE, bitmap)
+        .map_err(|_| RenderError::Resource("failed to upload texture".into()))?;
+    set_sampling_2d(gl, bitmap.width(), bitmap.height());
+    Ok(texture)
+}
+
+//  Expects the texture bound to TEXTURE_2D, unbinds it
+fn set_sampling_2d(gl: &GL, width: u32, height: u32) {
+    if width.is_power_of_two() && height.is_power_of_two() {
+        gl.generate_mipmap(GL::TEXTURE_2D);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR_MIPMAP_LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::REPEAT as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::REPEAT as i32);
+    } else {
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::LINEAR as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+        gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
     }
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
+    gl.bind_texture(GL::TEXTURE_2D, None);
+}
+
+//--Create a cube map texture with an explicit mip chain--
+//  <argument>
+//      size   u32              edge length of mip level 0
+//      levels &[[Vec<u8>; 6]]  RGBA faces per mip level in +X, -X, +Y, -Y, +Z, -Z order
+//  <note>
+//      The chain has to go down to 1x1 for the texture to be complete.
+pub fn create_cube_texture(gl: &GL, size: u32, levels: &[[Vec<u8>; 6]]) -> Result<WebGlTexture, RenderError> {
+    let texture = gl.create_texture().ok_or_else(|| RenderError::Resource("failed to create texture".into()))?;
+    gl.bind_texture(GL::TEXTURE_CUBE_MAP, Some(&texture));
+    for (level, faces) in levels.iter().enumerate() {
+        let level_size = (size >> level).max(1) as i32;
+        for (face, data) in faces.iter().enumerate() {
+            gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+                GL::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32,
+                level as i32,
+                GL::RGBA as i32,
+                level_size,
+                level_size,
+                0,
+                GL::RGBA,
+                GL::UNSIGNED_BYTE,
+                Some(data),
+            )
+            .map_err(|_| RenderError::Resource("failed to upload cube map".into()))?;
+        }
+    }
+
+    let min_filter = if levels.len() > 1 {
+        GL::LINEAR_MIPMAP_LINEAR
+    } else {
+        GL::LINEAR
+    };
+    gl.tex_parameteri(GL::TEXTURE_CUBE_MAP, GL::TEXTURE_MIN_FILTER, min_filter as i32);
+    gl.tex_parameteri(GL::TEXTURE_CUBE_MAP, GL::TEXTURE_MAG_FILTER, GL::LINEAR as i32);
+    gl.tex_parameteri(GL::TEXTURE_CUBE_MAP, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+    gl.tex_parameteri(GL::TEXTURE_CUBE_MAP, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+    gl.bind_texture(GL::TEXTURE_CUBE_MAP, None);
+
+    Ok(texture)
 }
 
+//--Create an offscreen RGBA8 color target with a 16 bit depth buffer--
+//  <argument>
+//      width  u32  target width
+//      height u32  target height
+//  <return> (WebGlFramebuffer, WebGlTexture, WebGlRenderbuffer)
+//  <note>
+//      WebGL1 has no RGBA8 renderbuffers, so color goes to a texture.
+//      Nothing is left bound afterwards.
+pub fn create_render_target(
+    gl: &GL,
+    width: u32,
+    height: u32,
+) -> Result<(WebGlFramebuffer, WebGlTexture, WebGlRenderbuffer), RenderError> {
+    let texture = gl.create_texture().ok_or_else(|| RenderError::Resource("failed to create texture".into()))?;
+    gl.bind_texture(GL::TEXTURE_2D, Some(&texture));
+    gl.tex_image_2d_with_i32_and_i32_and_i32_and_format_and_type_and_opt_u8_array(
+        GL::TEXTURE_2D,
+        0,
+        GL::RGBA as i32,
+        width as i32,
+        height as i32,
+        0,
+        GL::RGBA,
+        GL::UNSIGNED_BYTE,
+        None,
+    )
+    .map_err(|_| RenderError::Resource("failed to allocate render target".into()))?;
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MIN_FILTER, GL::NEAREST as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_MAG_FILTER, GL::NEAREST as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_S, GL::CLAMP_TO_EDGE as i32);
+    gl.tex_parameteri(GL::TEXTURE_2D, GL::TEXTURE_WRAP_T, GL::CLAMP_TO_EDGE as i32);
+    gl.bind_texture(GL::TEXTURE_2D, None);
+
+    let depth = gl
+        .create_renderbuffer()
+        .ok_or_else(|| RenderError::Resource("failed to create renderbuffer".into()))?;
+    gl.bind_renderbuffer(GL::RENDERBUFFER, Some(&depth));
+    gl.renderbuffer_storage(GL::RENDERBUFFER, GL::DEPTH_COMPONENT16, width as i32, height as i32);
+    gl.bind_renderbuffer(GL::RENDERBUFFER, None);
+
+    let framebuffer = gl
+        .create_framebuffer()
+        .ok_or_else(|| RenderError::Resource("failed to create framebuffer".into()))?;
+    gl.bind_framebuffer(GL::FRAMEBUFFER, Some(&framebuffer));
+    gl.framebuffer_texture_2d(GL::FRAMEBUFFER, GL::COLOR_ATTACHMENT0, GL::TEXTURE_2D, Some(&texture), 0);
+    gl.framebuffer_renderbuffer(GL::FRAMEBUFFER, GL::DEPTH_ATTACHMENT, GL::RENDERBUFFER, Some(&depth));
+    let status = gl.check_framebuffer_status(GL::FRAMEBUFFER);
+    gl.bind_framebuffer(GL::FRAMEBUFFER, None);
+    if status != GL::FRAMEBUFFER_COMPLETE {
+        return Err(RenderError::Resource(format!("render target is incomplete (status 0x{:x})", status)));
+    }
+
+    Ok((framebuffer, texture, depth))
+}